@@ -1,8 +1,16 @@
 use crate::activities::Activity;
 use crate::config::{BridgeConfig, ValidatedBridge};
+use crate::hue;
+use crate::hue::{DiscoveredBridge, Discovery, UnauthBridge, HUE_HTTP_PORT};
+use crate::shutdown;
 use crate::widgets::center_rect;
 use crossbeam_channel::{Receiver, Sender};
-use hueclient::{Bridge, UnauthBridge};
+use crossterm::event::{Event, KeyCode, KeyEvent, KeyModifiers};
+use hueclient::HueError;
+use std::collections::VecDeque;
+use std::mem;
+use std::net::{IpAddr, SocketAddr};
+use std::time::{Duration, Instant};
 
 use tui::backend::Backend;
 use tui::buffer::Buffer;
@@ -12,27 +20,78 @@ use crate::tasks::Task;
 use crate::widgets::log_block::LogVariant::{TaskComplete, TaskFailed};
 use crate::widgets::log_block::{Log, LogEvent, LogItem};
 use crate::widgets::rainbow_border::RainbowBorderWidget;
-use crate::AppMsg;
 use crate::AppMsg::Next;
+use crate::{AppMsg, Outcome};
 use tui::layout::Rect;
-use tui::widgets::{Block, Borders, Widget};
+use tui::style::{Color, Modifier, Style};
+use tui::text::{Span, Spans};
+use tui::widgets::{
+    Block, Borders, List, ListItem, ListState, Paragraph, StatefulWidget, Widget, Wrap,
+};
 use tui::Frame;
 
+/// the bridge answers with this error code until its link button has been pressed
+const LINK_BUTTON_NOT_PRESSED: usize = 101;
+const REGISTER_POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// the bridge stays open for new users for 30 seconds after the link button is pressed
+const REGISTER_TIMEOUT: Duration = Duration::from_secs(30);
+
 pub struct DiscoverBridgeTask {
     log_tx: Sender<LogEvent>,
+    discovery: Discovery,
+}
+
+/// Checks whether there's a Hue bridge listening at a manually entered address.
+pub struct ProbeBridgeTask {
+    log_tx: Sender<LogEvent>,
+    address: SocketAddr,
+    entry: String,
+    attempts: u32,
 }
 
+/// Polls the bridge for a new user until the link button is pressed, the time runs out,
+/// or the user cancels.
 pub struct RegisterClientTask {
     log_tx: Sender<LogEvent>,
     device_type: String,
-    unauth_bridge: Option<UnauthBridge>,
+    bridge: DiscoveredBridge,
+    poll_interval: Duration,
+    timeout: Duration,
+    cancel_ch: (Sender<()>, Receiver<()>),
+}
+
+impl RegisterClientTask {
+    pub fn new(log_tx: Sender<LogEvent>, bridge: DiscoveredBridge) -> Self {
+        Self {
+            log_tx,
+            device_type: BridgeConfig::generate_device_type(),
+            bridge,
+            poll_interval: REGISTER_POLL_INTERVAL,
+            timeout: REGISTER_TIMEOUT,
+            cancel_ch: crossbeam_channel::bounded(1),
+        }
+    }
+
+    /// stops the task after its current request when sent to
+    pub fn canceller(&self) -> Sender<()> {
+        self.cancel_ch.0.clone()
+    }
+
+    /// Stopped rather than failed, so it can be retried or skipped, and any other chosen
+    /// bridges still get their turn.
+    fn failed(self, id: String, e: anyhow::Error) -> State {
+        let _ = self.log_tx.send(LogEvent::SetMessage(
+            id.clone(),
+            format!("Failed to register with {}: {:#}", self.bridge.info.name, e),
+        ));
+        let _ = self.log_tx.send(LogEvent::SetVariant(id, TaskFailed));
+        State::RegistrationStopped(self.bridge)
+    }
 }
 
 fn on_complete_default(r: anyhow::Result<State>, p: Sender<State>) {
-    match r {
-        Ok(state) => p.send(state).unwrap(),
-        Err(e) => p.send(State::Failed(e)).unwrap(),
-    };
+    // the activity may have gone by now, and nobody's waiting for this any more
+    let _ = p.send(r.unwrap_or_else(State::Failed));
 }
 
 impl Task for DiscoverBridgeTask {
@@ -40,38 +99,107 @@ impl Task for DiscoverBridgeTask {
     type OnCompleteParams = Sender<State>;
 
     fn run_task(self) -> anyhow::Result<State> {
-        let (log, id) = LogItem::task_waiting("Discovering Philips Hue bridge...");
-        self.log_tx.send(LogEvent::PushItem(log)).unwrap();
-
-        match Bridge::discover() {
-            None => {
-                self.log_tx
-                    .send(LogEvent::SetVariant(id, TaskFailed))
-                    .unwrap();
-                self.log_tx
-                    .send(LogEvent::PushItem(
-                        LogItem::info(
-                            "Failed to discover bridge. Enter the bridge's IP address manually.",
-                        )
-                        .0,
-                    ))
-                    .unwrap();
+        let (log, id) = LogItem::task_waiting("Discovering Philips Hue bridges...");
+        let _ = self.log_tx.send(LogEvent::PushItem(log));
+
+        let mut bridges = self.discovery.discover_bridges();
+        match bridges.len() {
+            0 => {
+                let _ = self.log_tx.send(LogEvent::SetVariant(id, TaskFailed));
+                let _ = self.log_tx.send(LogEvent::PushItem(
+                    LogItem::info(
+                        "Failed to discover bridge. Enter the bridge's IP address manually.",
+                    )
+                    .0,
+                ));
                 Ok(State::ManualEntry {
                     attempts: 0,
                     current_entry: "".to_string(),
+                    error: None,
+                })
+            }
+
+            // nothing to choose between
+            1 => {
+                let _ = self.log_tx.send(LogEvent::SetVariant(id, TaskComplete));
+
+                Ok(State::RegisteringClient(Some(RegisterClientTask::new(
+                    self.log_tx,
+                    bridges.remove(0),
+                ))))
+            }
+
+            n => {
+                let _ = self.log_tx.send(LogEvent::SetVariant(id, TaskComplete));
+                let _ = self.log_tx.send(LogEvent::PushItem(
+                    LogItem::info(format!("Found {} bridges.", n)).0,
+                ));
+
+                let mut highlighted = ListState::default();
+                highlighted.select(Some(0));
+                Ok(State::ChoosingBridges {
+                    chosen: vec![false; n],
+                    bridges,
+                    highlighted,
                 })
             }
+        }
+    }
 
-            Some(unauth_bridge) => {
-                self.log_tx
-                    .send(LogEvent::SetVariant(id, TaskComplete))
-                    .unwrap();
+    fn on_complete(r: anyhow::Result<Self::Result>, p: Self::OnCompleteParams) {
+        on_complete_default(r, p)
+    }
+}
+
+/// Parses `192.168.1.2` or `192.168.1.2:80` (or `[::1]:80`) into an address to probe.
+fn parse_bridge_address(entry: &str) -> Result<SocketAddr, String> {
+    let entry = entry.trim();
+    if entry.is_empty() {
+        return Err("Enter an IP address, e.g. 192.168.1.2".to_string());
+    }
+
+    let address = match entry.parse::<IpAddr>() {
+        Ok(ip) => SocketAddr::new(ip, HUE_HTTP_PORT),
+        Err(_) => entry
+            .parse::<SocketAddr>()
+            .map_err(|_| format!("\"{}\" is not a valid IP address", entry))?,
+    };
+
+    Ok(address)
+}
+
+impl Task for ProbeBridgeTask {
+    type Result = State;
+    type OnCompleteParams = Sender<State>;
+
+    fn run_task(self) -> anyhow::Result<State> {
+        let (log, id) =
+            LogItem::task_waiting(format!("Looking for a Hue bridge at {}...", self.address));
+        let _ = self.log_tx.send(LogEvent::PushItem(log));
+
+        match hue::fetch_bridge_info(&hue::base_url(self.address)) {
+            Ok(info) => {
+                let _ = self.log_tx.send(LogEvent::SetVariant(id, TaskComplete));
+                let _ = self.log_tx.send(LogEvent::PushItem(
+                    LogItem::info(format!("Found {} ({})", info.name, info.bridgeid)).0,
+                ));
+
+                Ok(State::RegisteringClient(Some(RegisterClientTask::new(
+                    self.log_tx,
+                    DiscoveredBridge {
+                        address: self.address,
+                        info,
+                    },
+                ))))
+            }
+            Err(_) => {
+                let _ = self.log_tx.send(LogEvent::SetVariant(id, TaskFailed));
 
-                Ok(State::RegisteringClient(Some(RegisterClientTask {
-                    log_tx: self.log_tx,
-                    device_type: BridgeConfig::generate_device_type(),
-                    unauth_bridge: Some(unauth_bridge),
-                })))
+                Ok(State::ManualEntry {
+                    attempts: self.attempts + 1,
+                    current_entry: self.entry,
+                    error: Some(format!("No Hue bridge answered at {}", self.address)),
+                })
             }
         }
     }
@@ -85,24 +213,78 @@ impl Task for RegisterClientTask {
     type Result = State;
     type OnCompleteParams = Sender<State>;
 
-    fn run_task(mut self) -> anyhow::Result<Self::Result> {
-        let unauth_bridge = self.unauth_bridge.take().unwrap();
+    fn run_task(self) -> anyhow::Result<Self::Result> {
+        let unauth_bridge = UnauthBridge::new(hue::base_url(self.bridge.address));
 
-        let (log, id) = LogItem::task_waiting(
-            "Registering client. Press the button on your bridge to continue.",
-        );
-        self.log_tx.send(LogEvent::PushItem(log)).unwrap();
+        let (log, id) = LogItem::task_waiting("");
+        let _ = self.log_tx.send(LogEvent::PushItem(log));
 
+        let deadline = Instant::now() + self.timeout;
         loop {
-            match unauth_bridge.clone().register_user(&self.device_type) {
+            if shutdown::requested() {
+                return Ok(State::RegistrationStopped(self.bridge));
+            }
+
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                let _ = self.log_tx.send(LogEvent::SetMessage(
+                    id.clone(),
+                    "Timed out waiting for the link button.".to_string(),
+                ));
+                let _ = self.log_tx.send(LogEvent::SetVariant(id, TaskFailed));
+                return Ok(State::RegistrationStopped(self.bridge));
+            }
+
+            let _ = self.log_tx.send(LogEvent::SetMessage(
+                id.clone(),
+                format!(
+                    "Press the link button on {}. {}s left, c to cancel.",
+                    self.bridge.info.name,
+                    remaining.as_secs_f32().ceil()
+                ),
+            ));
+
+            match unauth_bridge.register_user(&self.device_type) {
                 Ok(bridge) => {
-                    self.log_tx
-                        .send(LogEvent::SetVariant(id, TaskComplete))
-                        .unwrap();
-                    let bridge = ValidatedBridge::from_bridge(bridge, self.device_type)?;
+                    let bridge = match ValidatedBridge::from_bridge(
+                        bridge,
+                        self.bridge.address,
+                        self.device_type.clone(),
+                        self.bridge.info.bridgeid.clone(),
+                    ) {
+                        Ok(bridge) => bridge,
+                        Err(e) => return Ok(self.failed(id, e)),
+                    };
+                    let _ = self.log_tx.send(LogEvent::SetMessage(
+                        id.clone(),
+                        format!("Registered with {}.", self.bridge.info.name),
+                    ));
+                    let _ = self.log_tx.send(LogEvent::SetVariant(id, TaskComplete));
                     return Ok(State::Complete(bridge));
                 }
-                Err(_) => {}
+
+                // nobody has pressed the button yet, so try again in a bit
+                Err(HueError::BridgeError {
+                    code: LINK_BUTTON_NOT_PRESSED,
+                    ..
+                }) => {}
+
+                Err(e) => return Ok(self.failed(id, e.into())),
+            }
+
+            // wait for the next poll, or bail early if the user cancels
+            if self
+                .cancel_ch
+                .1
+                .recv_timeout(self.poll_interval.min(remaining))
+                .is_ok()
+            {
+                let _ = self.log_tx.send(LogEvent::SetMessage(
+                    id.clone(),
+                    "Registration cancelled.".to_string(),
+                ));
+                let _ = self.log_tx.send(LogEvent::SetVariant(id, TaskFailed));
+                return Ok(State::RegistrationStopped(self.bridge));
             }
         }
     }
@@ -114,16 +296,29 @@ impl Task for RegisterClientTask {
 
 pub enum State {
     DiscoveringBridge(Option<DiscoverBridgeTask>),
+    ProbingBridge(Option<ProbeBridgeTask>),
     RegisteringClient(Option<RegisterClientTask>),
+    /// registration was cancelled or timed out, and can be restarted
+    RegistrationStopped(DiscoveredBridge),
+
+    /// more than one bridge turned up, so the user picks which to register with
+    ChoosingBridges {
+        bridges: Vec<DiscoveredBridge>,
+        chosen: Vec<bool>,
+        highlighted: ListState,
+    },
 
     ManualEntry {
         attempts: u32,
         current_entry: String,
+        error: Option<String>,
     },
 
     Waiting(Box<State>),
     Failed(anyhow::Error),
     Complete(ValidatedBridge),
+    /// the bridges have been handed over to the app
+    Done,
 }
 
 impl State {
@@ -135,13 +330,17 @@ impl State {
                 Waiting(Box::new(self))
             }
 
-            State::RegisteringClient(ref mut task) => {
+            State::ProbingBridge(ref mut task) => {
                 let task = task.take().unwrap();
                 task.spawn(state_tx);
                 Waiting(Box::new(self))
             }
 
-            State::ManualEntry { .. } => self, // TODO: should this be a different activity?
+            State::RegisteringClient(ref mut task) => {
+                let task = task.take().unwrap();
+                task.spawn(state_tx);
+                Waiting(Box::new(self))
+            }
 
             _ => self,
         }
@@ -153,6 +352,98 @@ pub struct BridgeConnect {
     log: Log,
     state_ch: (Sender<State>, Receiver<State>),
     app_tx: Sender<AppMsg>,
+    cancel_registration: Option<Sender<()>>,
+    /// chosen bridges still waiting for their turn to be registered
+    queue: VecDeque<DiscoveredBridge>,
+    registered: Vec<ValidatedBridge>,
+}
+
+/// The text box shown while the user types in the bridge's address.
+pub struct ManualEntryWidget<'a> {
+    attempts: u32,
+    current_entry: &'a str,
+    error: Option<&'a str>,
+    ticks: u64,
+}
+
+impl<'a> Widget for ManualEntryWidget<'a> {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        let title = match self.attempts {
+            0 => " enter bridge address ".to_string(),
+            n => format!(" enter bridge address (attempt {}) ", n + 1),
+        };
+        let block = Block::default().borders(Borders::ALL).title(title);
+        let inner = block.inner(area);
+        block.render(area, buf);
+
+        // blink the cursor at ~2 fps
+        let cursor = if (self.ticks / 30).is_multiple_of(2) {
+            "_"
+        } else {
+            " "
+        };
+        let mut text = vec![
+            Spans::from("Type your bridge's IP address and press enter."),
+            Spans::from(""),
+            Spans::from(vec![
+                Span::styled("> ", Style::default().add_modifier(Modifier::BOLD)),
+                Span::raw(self.current_entry.to_string()),
+                Span::raw(cursor),
+            ]),
+            Spans::from(""),
+        ];
+        if let Some(error) = self.error {
+            text.push(Spans::from(Span::styled(
+                format!("[!] {}", error),
+                Style::default().fg(Color::Red).add_modifier(Modifier::BOLD),
+            )));
+        }
+
+        Paragraph::new(text).wrap(Wrap { trim: false }).render(
+            center_rect(
+                inner,
+                inner.width.saturating_sub(4),
+                inner.height.saturating_sub(2),
+            ),
+            buf,
+        );
+    }
+}
+
+/// A checklist of the bridges found on the network.
+pub struct BridgeChoiceWidget<'a> {
+    bridges: &'a [DiscoveredBridge],
+    chosen: &'a [bool],
+}
+
+impl<'a> StatefulWidget for BridgeChoiceWidget<'a> {
+    type State = ListState;
+
+    fn render(self, area: Rect, buf: &mut Buffer, state: &mut Self::State) {
+        let items: Vec<ListItem> = self
+            .bridges
+            .iter()
+            .zip(self.chosen)
+            .map(|(bridge, chosen)| {
+                ListItem::new(format!(
+                    "[{}] {} ({}) at {}",
+                    if *chosen { "x" } else { " " },
+                    bridge.info.name,
+                    bridge.info.bridgeid,
+                    bridge.address.ip()
+                ))
+            })
+            .collect();
+
+        let list = List::new(items)
+            .block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .title(" choose bridges to connect to "),
+            )
+            .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+        StatefulWidget::render(list, area, buf, state);
+    }
 }
 
 pub struct BridgeConnectWidget {
@@ -180,14 +471,30 @@ impl<B: Backend> Activity<B> for BridgeConnect {
             f.size(),
         );
 
-        if let Some(state) = &self.state {
+        if let Some(state) = &mut self.state {
             match state {
                 // Manual IP entry needs special rendering
-                State::ManualEntry { attempts, .. } => {
-                    let block = Block::default()
-                        .borders(Borders::ALL)
-                        .title(format!(" oog! : {} ", attempts));
-                    f.render_widget(block, center_rect(f.size(), 72, 20));
+                State::ManualEntry {
+                    attempts,
+                    current_entry,
+                    error,
+                } => {
+                    let entry = ManualEntryWidget {
+                        attempts: *attempts,
+                        current_entry,
+                        error: error.as_deref(),
+                        ticks,
+                    };
+                    f.render_widget(entry, center_rect(f.size(), 72, 20));
+                }
+
+                State::ChoosingBridges {
+                    bridges,
+                    chosen,
+                    highlighted,
+                } => {
+                    let choice = BridgeChoiceWidget { bridges, chosen };
+                    f.render_stateful_widget(choice, center_rect(f.size(), 72, 20), highlighted);
                 }
 
                 // print the log in any other case.
@@ -202,19 +509,76 @@ impl<B: Backend> Activity<B> for BridgeConnect {
         let (state_tx, state_rx) = self.state_ch.clone();
 
         if let Ok(state) = state_rx.try_recv() {
+            // each bridge only arrives here once, so it's only ever sent once
+            if let Complete(bridge) = state {
+                self.registered.push(bridge);
+                self.register_next();
+                self.log.update();
+                return;
+            }
+
+            match &state {
+                State::Failed(e) => {
+                    let (log, _) = LogItem::error(format!("{:#}", e));
+                    self.log.sender().send(LogEvent::PushItem(log)).unwrap();
+                }
+                State::RegisteringClient(Some(task)) => {
+                    self.cancel_registration = Some(task.canceller());
+                }
+                State::RegistrationStopped(_) => {
+                    let (log, _) = LogItem::info("Press r to try registering again.");
+                    self.log.sender().send(LogEvent::PushItem(log)).unwrap();
+                }
+                _ => {}
+            }
             self.state = Some(state.update(state_tx));
         }
 
-        if let Some(Complete(bridge)) = &self.state {
-            self.app_tx.send(Next);
+        self.log.update();
+    }
+
+    fn handle_event(&mut self, event: &Event) -> bool {
+        match event {
+            Event::Key(key) => self.handle_key(*key),
+            _ => false,
         }
+    }
 
-        self.log.update();
+    fn keybindings(&self) -> Vec<(&'static str, &'static str)> {
+        match &self.state {
+            Some(State::ManualEntry { .. }) => vec![
+                ("enter", "look for a bridge at this address"),
+                ("ctrl+u", "clear the address"),
+            ],
+            Some(Waiting(waiting)) if matches!(**waiting, State::RegisteringClient(_)) => {
+                vec![("c", "cancel registration")]
+            }
+            Some(State::RegistrationStopped(_)) => {
+                vec![("r", "retry registration"), ("s", "skip this bridge")]
+            }
+            Some(State::ChoosingBridges { .. }) => vec![
+                ("up, down", "highlight a bridge"),
+                ("space", "choose the highlighted bridge"),
+                ("enter", "connect to the chosen bridges"),
+                ("m", "enter an address manually"),
+            ],
+            _ => vec![],
+        }
+    }
+}
+
+/// Going back or finishing setup drops the activity, so the registration it started stops
+/// polling the bridge instead of carrying on for nobody.
+impl Drop for BridgeConnect {
+    fn drop(&mut self) {
+        if let Some(cancel) = self.cancel_registration.take() {
+            let _ = cancel.try_send(());
+        }
     }
 }
 
 impl BridgeConnect {
-    pub fn init(app_tx: Sender<AppMsg>) -> Self {
+    pub fn with_discovery(app_tx: Sender<AppMsg>, discovery: Discovery) -> Self {
         let mut log = Log::default();
         log.set_title(String::from(" welcome to twitchbrite "));
 
@@ -223,6 +587,7 @@ impl BridgeConnect {
             .0
             .send(State::DiscoveringBridge(Some(DiscoverBridgeTask {
                 log_tx: log.sender(),
+                discovery,
             })))
             .unwrap();
 
@@ -231,6 +596,468 @@ impl BridgeConnect {
             log,
             state_ch,
             app_tx,
+            cancel_registration: None,
+            queue: VecDeque::new(),
+            registered: vec![],
+        }
+    }
+
+    fn register(&mut self, bridge: DiscoveredBridge) {
+        let task = RegisterClientTask::new(self.log.sender(), bridge);
+        self.cancel_registration = Some(task.canceller());
+        let registering = State::RegisteringClient(Some(task));
+        self.state = Some(registering.update(self.state_ch.0.clone()));
+    }
+
+    /// Moves on to the next chosen bridge, or hands every registered bridge to the app
+    /// once the queue is empty. If every bridge was skipped, there's nothing to hand over, so
+    /// it asks for one's address instead.
+    fn register_next(&mut self) {
+        if let Some(bridge) = self.queue.pop_front() {
+            self.register(bridge);
+        } else if !self.registered.is_empty() {
+            let bridges = mem::take(&mut self.registered);
+            self.app_tx.send(Next(Outcome::Bridges(bridges))).unwrap();
+            self.state = Some(State::Done);
+        } else {
+            self.state = Some(State::ManualEntry {
+                attempts: 0,
+                current_entry: "".to_string(),
+                error: Some("There are no bridges left to register with.".to_string()),
+            });
+        }
+    }
+
+    /// Returns true if the key was used in the current state.
+    fn handle_key(&mut self, key: KeyEvent) -> bool {
+        match &mut self.state {
+            Some(State::ManualEntry {
+                attempts,
+                current_entry,
+                error,
+            }) => match key.code {
+                KeyCode::Char('u') if key.modifiers == KeyModifiers::CONTROL => {
+                    current_entry.clear();
+                    *error = None;
+                }
+                // anything that can show up in an IPv4/IPv6 address, with or without a port
+                KeyCode::Char(c)
+                    if !key.modifiers.contains(KeyModifiers::CONTROL)
+                        && (c.is_ascii_hexdigit() || ".:[]".contains(c)) =>
+                {
+                    current_entry.push(c);
+                    *error = None;
+                }
+                KeyCode::Backspace => {
+                    current_entry.pop();
+                }
+                KeyCode::Enter => match parse_bridge_address(current_entry) {
+                    Ok(address) => {
+                        let probe = State::ProbingBridge(Some(ProbeBridgeTask {
+                            log_tx: self.log.sender(),
+                            address,
+                            entry: current_entry.clone(),
+                            attempts: *attempts,
+                        }));
+                        self.state = Some(probe.update(self.state_ch.0.clone()));
+                    }
+                    Err(e) => {
+                        *attempts += 1;
+                        *error = Some(e);
+                    }
+                },
+                _ => return false,
+            },
+
+            Some(Waiting(waiting)) if matches!(**waiting, State::RegisteringClient(_)) => {
+                match key.code {
+                    KeyCode::Char('c') => {
+                        if let Some(cancel) = self.cancel_registration.take() {
+                            let _ = cancel.try_send(());
+                        }
+                    }
+                    _ => return false,
+                }
+            }
+
+            Some(State::RegistrationStopped(bridge)) => match key.code {
+                KeyCode::Char('r') => {
+                    let bridge = bridge.clone();
+                    self.register(bridge);
+                }
+                KeyCode::Char('s') => self.register_next(),
+                _ => return false,
+            },
+
+            Some(State::ChoosingBridges {
+                bridges,
+                chosen,
+                highlighted,
+            }) => {
+                let current = highlighted.selected().unwrap_or(0);
+                match key.code {
+                    KeyCode::Up => highlighted.select(Some(current.saturating_sub(1))),
+                    KeyCode::Down => highlighted.select(Some((current + 1).min(bridges.len() - 1))),
+                    KeyCode::Char(' ') => chosen[current] = !chosen[current],
+                    KeyCode::Enter => {
+                        // nothing ticked means just the highlighted one
+                        if !chosen.contains(&true) {
+                            chosen[current] = true;
+                        }
+                        self.queue = bridges
+                            .iter()
+                            .zip(chosen.iter())
+                            .filter(|(_, chosen)| **chosen)
+                            .map(|(bridge, _)| bridge.clone())
+                            .collect();
+                        self.register_next();
+                    }
+                    KeyCode::Char('m') => {
+                        self.state = Some(State::ManualEntry {
+                            attempts: 0,
+                            current_entry: "".to_string(),
+                            error: None,
+                        })
+                    }
+                    _ => return false,
+                }
+            }
+
+            _ => return false,
+        }
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::harness::{lines, wait_until, Harness, PATIENCE};
+    use crate::hue::mock::MockBridge;
+    use crate::Mode;
+    use crossterm::event::KeyEvent;
+    use tui::backend::TestBackend;
+    use tui::Terminal;
+
+    fn update(activity: &mut BridgeConnect) {
+        <BridgeConnect as Activity<TestBackend>>::update(activity, 0);
+    }
+
+    fn press(activity: &mut BridgeConnect, code: KeyCode) {
+        let event = Event::Key(KeyEvent::new(code, KeyModifiers::NONE));
+        assert!(<BridgeConnect as Activity<TestBackend>>::handle_event(
+            activity, &event
+        ));
+    }
+
+    /// updates the activity until `done` says so, giving up after a few seconds
+    fn update_until(activity: &mut BridgeConnect, done: impl Fn(&BridgeConnect) -> bool) {
+        let finished = wait_until(|| {
+            if done(activity) {
+                return true;
+            }
+            update(activity);
+            false
+        });
+        assert!(finished, "gave up waiting for the activity");
+    }
+
+    fn registered_bridges(app_rx: &Receiver<AppMsg>) -> Vec<ValidatedBridge> {
+        match app_rx.recv_timeout(PATIENCE) {
+            Ok(Next(Outcome::Bridges(bridges))) => bridges,
+            _ => panic!("expected the registered bridges"),
         }
     }
+
+    #[test]
+    fn registers_with_a_single_discovered_bridge() {
+        let mock = MockBridge::start();
+        mock.press_link_button();
+
+        let (app_tx, app_rx) = crossbeam_channel::unbounded();
+        let mut activity =
+            BridgeConnect::with_discovery(app_tx, Discovery::Addresses(vec![mock.address()]));
+        update_until(&mut activity, |a| matches!(a.state, Some(State::Done)));
+
+        let bridges = registered_bridges(&app_rx);
+        assert_eq!(bridges.len(), 1);
+        assert_eq!(bridges[0].id(), mock.info().bridgeid);
+        assert_eq!(bridges[0].address(), mock.address());
+        assert!(mock.is_user(&bridges[0].username));
+        // for streaming effects later
+        assert!(bridges[0].clientkey.is_some());
+        assert!(app_rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn stops_registering_once_dropped() {
+        let mock = MockBridge::start();
+        let (app_tx, _app_rx) = crossbeam_channel::unbounded();
+        let mut activity =
+            BridgeConnect::with_discovery(app_tx, Discovery::Addresses(vec![mock.address()]));
+        update_until(&mut activity, |a| a.cancel_registration.is_some());
+
+        let states = activity.state_ch.1.clone();
+        drop(activity);
+        // long before the link button would have timed out
+        assert!(matches!(
+            states.recv_timeout(PATIENCE),
+            Ok(State::RegistrationStopped(_))
+        ));
+    }
+
+    #[test]
+    fn chooses_between_several_bridges() {
+        let a = MockBridge::start();
+        let b = MockBridge::start();
+        a.press_link_button();
+        b.press_link_button();
+
+        let (app_tx, app_rx) = crossbeam_channel::unbounded();
+        let discovery = Discovery::Addresses(vec![a.address(), b.address()]);
+        let mut activity = BridgeConnect::with_discovery(app_tx, discovery);
+        update_until(&mut activity, |a| {
+            matches!(a.state, Some(State::ChoosingBridges { .. }))
+        });
+
+        press(&mut activity, KeyCode::Char(' '));
+        press(&mut activity, KeyCode::Down);
+        press(&mut activity, KeyCode::Char(' '));
+        press(&mut activity, KeyCode::Enter);
+        update_until(&mut activity, |a| matches!(a.state, Some(State::Done)));
+
+        let ids: Vec<String> = registered_bridges(&app_rx)
+            .iter()
+            .map(|bridge| bridge.id().to_string())
+            .collect();
+        assert_eq!(ids, [a.info().bridgeid, b.info().bridgeid]);
+    }
+
+    #[test]
+    fn carries_on_past_bridges_that_cant_be_reached() {
+        let a = MockBridge::start();
+        let b = MockBridge::start();
+        a.press_link_button();
+
+        let (app_tx, app_rx) = crossbeam_channel::unbounded();
+        let discovery = Discovery::Addresses(vec![a.address(), b.address()]);
+        let mut activity = BridgeConnect::with_discovery(app_tx, discovery);
+        update_until(&mut activity, |a| {
+            matches!(a.state, Some(State::ChoosingBridges { .. }))
+        });
+        // gone between being found and being registered with
+        drop(b);
+
+        press(&mut activity, KeyCode::Char(' '));
+        press(&mut activity, KeyCode::Down);
+        press(&mut activity, KeyCode::Char(' '));
+        press(&mut activity, KeyCode::Enter);
+        update_until(&mut activity, |a| {
+            matches!(a.state, Some(State::RegistrationStopped(_)))
+        });
+        assert!(app_rx.try_recv().is_err());
+
+        // the one that did register isn't lost by skipping the one that didn't
+        press(&mut activity, KeyCode::Char('s'));
+        let ids: Vec<String> = registered_bridges(&app_rx)
+            .iter()
+            .map(|bridge| bridge.id().to_string())
+            .collect();
+        assert_eq!(ids, [a.info().bridgeid]);
+    }
+
+    #[test]
+    fn falls_back_to_manual_entry() {
+        let (app_tx, _app_rx) = crossbeam_channel::unbounded();
+        let mut activity = BridgeConnect::with_discovery(app_tx, Discovery::Addresses(vec![]));
+        update_until(&mut activity, |a| {
+            matches!(a.state, Some(State::ManualEntry { .. }))
+        });
+
+        // nothing listens on this one
+        let unused = MockBridge::start().address();
+        for c in unused.to_string().chars() {
+            press(&mut activity, KeyCode::Char(c));
+        }
+        press(&mut activity, KeyCode::Enter);
+        update_until(&mut activity, |a| {
+            matches!(a.state, Some(State::ManualEntry { attempts: 1, .. }))
+        });
+
+        let mock = MockBridge::start();
+        mock.press_link_button();
+        let clear = Event::Key(KeyEvent::new(KeyCode::Char('u'), KeyModifiers::CONTROL));
+        assert!(<BridgeConnect as Activity<TestBackend>>::handle_event(
+            &mut activity,
+            &clear
+        ));
+        for c in mock.address().to_string().chars() {
+            press(&mut activity, KeyCode::Char(c));
+        }
+        press(&mut activity, KeyCode::Enter);
+        update_until(&mut activity, |a| matches!(a.state, Some(State::Done)));
+    }
+
+    /// Skips straight to registering with `mock` instead of discovering, giving up on the
+    /// link button quickly.
+    fn register_briefly(activity: &mut BridgeConnect, mock: &MockBridge) {
+        activity.state_ch.1.try_recv().unwrap();
+        let mut task = RegisterClientTask::new(
+            activity.log.sender(),
+            DiscoveredBridge {
+                address: mock.address(),
+                info: mock.info(),
+            },
+        );
+        task.poll_interval = Duration::from_millis(10);
+        task.timeout = Duration::from_millis(50);
+        activity.state =
+            Some(State::RegisteringClient(Some(task)).update(activity.state_ch.0.clone()));
+    }
+
+    #[test]
+    fn registration_can_be_retried_after_a_timeout() {
+        let mock = MockBridge::start();
+        let (app_tx, app_rx) = crossbeam_channel::unbounded();
+        let mut activity = BridgeConnect::with_discovery(app_tx, Discovery::Network);
+        register_briefly(&mut activity, &mock);
+        update_until(&mut activity, |a| {
+            matches!(a.state, Some(State::RegistrationStopped(_)))
+        });
+
+        mock.press_link_button();
+        press(&mut activity, KeyCode::Char('r'));
+        update_until(&mut activity, |a| matches!(a.state, Some(State::Done)));
+        assert_eq!(registered_bridges(&app_rx).len(), 1);
+    }
+
+    #[test]
+    fn skipping_every_bridge_asks_for_an_address() {
+        let mock = MockBridge::start();
+        let (app_tx, app_rx) = crossbeam_channel::unbounded();
+        let mut activity = BridgeConnect::with_discovery(app_tx, Discovery::Network);
+        register_briefly(&mut activity, &mock);
+        update_until(&mut activity, |a| {
+            matches!(a.state, Some(State::RegistrationStopped(_)))
+        });
+
+        press(&mut activity, KeyCode::Char('s'));
+        assert!(matches!(
+            activity.state,
+            Some(State::ManualEntry { error: Some(_), .. })
+        ));
+        assert!(app_rx.try_recv().is_err());
+    }
+
+    /// the top of the screen, which is where everything but the empty box is
+    fn render(activity: &mut BridgeConnect, ticks: u64) -> Vec<String> {
+        let mut terminal = Terminal::new(TestBackend::new(80, 24)).unwrap();
+        terminal
+            .draw(|f| <BridgeConnect as Activity<TestBackend>>::render(activity, ticks, f))
+            .unwrap();
+        lines(terminal.backend().buffer())
+            .iter()
+            .take(9)
+            .map(|line| line.trim_end().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn manual_entry_snapshot() {
+        let (app_tx, _app_rx) = crossbeam_channel::unbounded();
+        let mut activity = BridgeConnect::with_discovery(app_tx, Discovery::Addresses(vec![]));
+        activity.state = Some(State::ManualEntry {
+            attempts: 1,
+            current_entry: "192.168.1".to_string(),
+            error: Some("No Hue bridge answered at 192.168.1.2:80".to_string()),
+        });
+
+        assert_eq!(
+            render(&mut activity, 0),
+            [
+                "",
+                "",
+                "    ┌ enter bridge address (attempt 2) ────────────────────────────────────┐",
+                "    │                                                                      │",
+                "    │  Type your bridge's IP address and press enter.                      │",
+                "    │                                                                      │",
+                "    │  > 192.168.1_                                                        │",
+                "    │                                                                      │",
+                "    │  [!] No Hue bridge answered at 192.168.1.2:80                        │",
+            ]
+        );
+
+        // the cursor blinks
+        assert_eq!(
+            render(&mut activity, 30)[6],
+            "    │  > 192.168.1                                                         │"
+        );
+    }
+
+    #[test]
+    fn manual_entry_fits_tiny_terminals() {
+        for (width, height) in [(0, 0), (3, 2), (5, 3)] {
+            let mut buf = Buffer::empty(Rect::new(0, 0, width, height));
+            ManualEntryWidget {
+                attempts: 0,
+                current_entry: "192.168.1.2",
+                error: Some("nope"),
+                ticks: 0,
+            }
+            .render(buf.area, &mut buf);
+        }
+    }
+
+    #[test]
+    fn bridge_choice_snapshot() {
+        let (app_tx, _app_rx) = crossbeam_channel::unbounded();
+        let mut activity = BridgeConnect::with_discovery(app_tx, Discovery::Addresses(vec![]));
+        let bridge = |name: &str, id: &str, ip: &str| DiscoveredBridge {
+            address: SocketAddr::new(ip.parse().unwrap(), 80),
+            info: hue::BridgeInfo {
+                name: name.to_string(),
+                bridgeid: id.to_string(),
+            },
+        };
+        let mut highlighted = ListState::default();
+        highlighted.select(Some(1));
+        activity.state = Some(State::ChoosingBridges {
+            bridges: vec![
+                bridge("Upstairs", "001788FFFE000001", "192.168.1.2"),
+                bridge("Downstairs", "001788FFFE000002", "192.168.1.3"),
+            ],
+            chosen: vec![true, false],
+            highlighted,
+        });
+
+        assert_eq!(
+            render(&mut activity, 0)[..5],
+            [
+                "",
+                "",
+                "    ┌ choose bridges to connect to ────────────────────────────────────────┐",
+                "    │[x] Upstairs (001788FFFE000001) at 192.168.1.2                        │",
+                "    │[ ] Downstairs (001788FFFE000002) at 192.168.1.3                      │",
+            ]
+        );
+    }
+
+    #[test]
+    fn manual_entry_through_the_app() {
+        let mock = MockBridge::start();
+        mock.press_link_button();
+
+        let mut app = Harness::new(80, 24, Discovery::Addresses(vec![]));
+        app.tick_until(|app| app.shows("enter bridge address"));
+
+        app.type_str(&mock.address().to_string());
+        app.tick();
+        assert!(app.shows(&format!("> {}_", mock.address())));
+
+        app.key(KeyCode::Enter);
+        // logging in to Twitch comes next
+        app.tick_until(|app| app.shows("log in to twitch"));
+        assert_eq!(app.mode(), Mode::Setup);
+    }
 }
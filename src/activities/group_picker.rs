@@ -0,0 +1,276 @@
+use crate::activities::Activity;
+use crate::config::ValidatedBridge;
+use crate::hue::groups::{Group, Scene};
+use crate::lights::effect_queue::EffectTarget;
+use crate::tasks::Task;
+use crate::widgets::center_rect;
+use crate::widgets::rainbow_border::RainbowBorderWidget;
+use crate::AppMsg;
+use crossbeam_channel::{Receiver, Sender};
+use crossterm::event::{Event, KeyCode};
+
+use tui::backend::Backend;
+use tui::style::{Modifier, Style};
+use tui::widgets::{Block, Borders, List, ListItem, ListState};
+use tui::Frame;
+
+/// Something in the list to pick.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Choice {
+    /// effects play on every light, one by one
+    EveryLight,
+    Group {
+        bridge: String,
+        group: Group,
+    },
+    Scene(Scene),
+}
+
+impl Choice {
+    fn label(&self) -> String {
+        match self {
+            Choice::EveryLight => "Every light".to_string(),
+            Choice::Group { group, .. } => format!("{} · {}", group.name, group.kind),
+            Choice::Scene(scene) => format!("{} · scene", scene.name),
+        }
+    }
+}
+
+/// every bridge's groups and scenes, or why they couldn't be listed
+type Choices = Result<Vec<Choice>, String>;
+
+/// Asks every bridge for its groups and scenes.
+pub struct ListGroupsTask {
+    bridges: Vec<ValidatedBridge>,
+}
+
+impl Task for ListGroupsTask {
+    type Result = Vec<Choice>;
+    type OnCompleteParams = Sender<Choices>;
+
+    fn run_task(self) -> anyhow::Result<Vec<Choice>> {
+        let mut groups = vec![Choice::EveryLight];
+        let mut scenes = vec![];
+        for bridge in &self.bridges {
+            groups.extend(bridge.get_groups()?.into_iter().map(|group| Choice::Group {
+                bridge: bridge.id().to_string(),
+                group,
+            }));
+            scenes.extend(bridge.get_scenes()?.into_iter().map(Choice::Scene));
+        }
+        groups.extend(scenes);
+        Ok(groups)
+    }
+
+    fn on_complete(r: anyhow::Result<Vec<Choice>>, choices_tx: Self::OnCompleteParams) {
+        let _ = choices_tx.send(r.map_err(|e| format!("{:#}", e)));
+    }
+}
+
+/// Picks the room, zone or group effects play on, and recalls the bridges' scenes.
+pub struct GroupPicker {
+    app_tx: Sender<AppMsg>,
+    /// where effects play now
+    target: Option<EffectTarget>,
+    /// None until the bridges have answered
+    choices: Option<Choices>,
+    choices_ch: (Sender<Choices>, Receiver<Choices>),
+    selected: usize,
+}
+
+impl GroupPicker {
+    pub fn init(
+        app_tx: Sender<AppMsg>,
+        bridges: Vec<ValidatedBridge>,
+        target: Option<EffectTarget>,
+    ) -> Self {
+        let choices_ch = crossbeam_channel::unbounded();
+        ListGroupsTask { bridges }.spawn(choices_ch.0.clone());
+
+        Self {
+            app_tx,
+            target,
+            choices: None,
+            choices_ch,
+            selected: 0,
+        }
+    }
+
+    fn loaded(&self) -> &[Choice] {
+        match &self.choices {
+            Some(Ok(choices)) => choices,
+            _ => &[],
+        }
+    }
+
+    fn is_target(&self, choice: &Choice) -> bool {
+        match (choice, &self.target) {
+            (Choice::EveryLight, None) => true,
+            (Choice::Group { bridge, group }, Some(target)) => {
+                *bridge == target.bridge && group.id == target.group
+            }
+            _ => false,
+        }
+    }
+
+    fn list_items(&self) -> Vec<ListItem<'static>> {
+        let choices = match &self.choices {
+            None => return vec![ListItem::new("Asking the bridges...")],
+            Some(Err(e)) => {
+                return vec![ListItem::new(format!(
+                    "Couldn't list the groups and scenes: {}",
+                    e
+                ))]
+            }
+            Some(Ok(choices)) => choices,
+        };
+
+        choices
+            .iter()
+            .map(|choice| {
+                let mark = match self.is_target(choice) {
+                    true => "• ",
+                    false => "  ",
+                };
+                ListItem::new(format!("{}{}", mark, choice.label()))
+            })
+            .collect()
+    }
+
+    fn pick(&self) {
+        let msg = match self.loaded().get(self.selected) {
+            Some(Choice::EveryLight) => AppMsg::SetEffectTarget(None),
+            Some(Choice::Group { bridge, group }) => AppMsg::SetEffectTarget(Some(EffectTarget {
+                bridge: bridge.clone(),
+                group: group.id,
+                name: group.name.clone(),
+            })),
+            Some(Choice::Scene(scene)) => AppMsg::RecallScene(scene.name.clone()),
+            None => return,
+        };
+        self.app_tx.send(msg).unwrap();
+    }
+}
+
+impl<B: Backend> Activity<B> for GroupPicker {
+    fn render(&mut self, ticks: u64, f: &mut Frame<B>) {
+        f.render_widget(
+            RainbowBorderWidget {
+                border_animated: false,
+                ticks,
+            },
+            f.size(),
+        );
+
+        let list = List::new(self.list_items())
+            .block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .title(" where effects play "),
+            )
+            .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+        let mut selected = ListState::default();
+        if !self.loaded().is_empty() {
+            selected.select(Some(self.selected));
+        }
+        f.render_stateful_widget(list, center_rect(f.size(), 72, 20), &mut selected);
+    }
+
+    fn update(&mut self, _ticks: u64) {
+        if let Ok(choices) = self.choices_ch.1.try_recv() {
+            self.choices = Some(choices);
+        }
+    }
+
+    fn handle_event(&mut self, event: &Event) -> bool {
+        let key = match event {
+            Event::Key(key) => key,
+            _ => return false,
+        };
+
+        let count = self.loaded().len();
+        match key.code {
+            KeyCode::Up => self.selected = self.selected.saturating_sub(1),
+            KeyCode::Down if count > 0 => self.selected = (self.selected + 1).min(count - 1),
+            KeyCode::Enter => self.pick(),
+            _ => return false,
+        }
+
+        true
+    }
+
+    fn keybindings(&self) -> Vec<(&'static str, &'static str)> {
+        vec![
+            ("up, down", "choose"),
+            ("enter", "play effects there, or recall the scene"),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::harness::{lines, wait_until};
+    use crate::hue::mock::MockBridge;
+    use crossterm::event::{KeyEvent, KeyModifiers};
+    use std::collections::BTreeMap;
+    use tui::backend::TestBackend;
+    use tui::Terminal;
+
+    fn press(picker: &mut GroupPicker, code: KeyCode) {
+        let event = Event::Key(KeyEvent::new(code, KeyModifiers::NONE));
+        assert!(<GroupPicker as Activity<TestBackend>>::handle_event(
+            picker, &event
+        ));
+    }
+
+    #[test]
+    fn picks_groups_and_scenes() {
+        let mock = MockBridge::start();
+        let office = mock.add_group("Office", "Room", &[1, 2]);
+        mock.add_group("Desk", "Zone", &[1]);
+        mock.add_scene("Relax", Some(office), BTreeMap::new());
+
+        let (app_tx, app_rx) = crossbeam_channel::unbounded();
+        let bridge = mock.validated_bridge();
+        let mut picker = GroupPicker::init(app_tx, vec![bridge.clone()], None);
+        let loaded = wait_until(|| {
+            <GroupPicker as Activity<TestBackend>>::update(&mut picker, 0);
+            picker.choices.is_some()
+        });
+        assert!(loaded, "the groups never came");
+
+        let mut terminal = Terminal::new(TestBackend::new(80, 24)).unwrap();
+        terminal
+            .draw(|f| <GroupPicker as Activity<TestBackend>>::render(&mut picker, 0, f))
+            .unwrap();
+        let lines = lines(terminal.backend().buffer());
+        assert!(lines[3].contains("• Every light"));
+        assert!(lines[4].contains("Office · room"));
+        assert!(lines[5].contains("Desk · zone"));
+        assert!(lines[6].contains("Relax · scene"));
+
+        press(&mut picker, KeyCode::Down);
+        press(&mut picker, KeyCode::Enter);
+        match app_rx.try_recv() {
+            Ok(AppMsg::SetEffectTarget(Some(target))) => assert_eq!(
+                target,
+                EffectTarget {
+                    bridge: bridge.id().to_string(),
+                    group: office,
+                    name: "Office".to_string(),
+                }
+            ),
+            _ => panic!("expected effects to go to the office"),
+        }
+
+        press(&mut picker, KeyCode::Down);
+        press(&mut picker, KeyCode::Down);
+        press(&mut picker, KeyCode::Down);
+        press(&mut picker, KeyCode::Enter);
+        assert!(matches!(
+            app_rx.try_recv(),
+            Ok(AppMsg::RecallScene(name)) if name == "Relax"
+        ));
+    }
+}
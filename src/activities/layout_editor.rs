@@ -0,0 +1,428 @@
+use crate::activities::Activity;
+use crate::config::ValidatedBridge;
+use crate::lights::color::Gamut;
+use crate::lights::effects::Target;
+use crate::lights::layout::Layout;
+use crate::lights::Rgb;
+use crate::tasks::Task;
+use crate::widgets::center_rect;
+use crate::widgets::rainbow_border::RainbowBorderWidget;
+use crate::AppMsg;
+use crossbeam_channel::{Receiver, Sender};
+use crossterm::event::{Event, KeyCode, KeyModifiers};
+
+use tui::backend::Backend;
+use tui::buffer::Buffer;
+use tui::layout::Rect;
+use tui::style::{Color, Modifier, Style};
+use tui::text::{Span, Spans};
+use tui::widgets::{Block, Borders, List, ListItem, ListState, Widget};
+use tui::Frame;
+
+/// how many steps the arrow keys take to cross the room, each way
+const GRID_COLUMNS: f32 = 20.0;
+const GRID_ROWS: f32 = 10.0;
+/// how wide the list of lights is, next to the room
+const LIST_WIDTH: u16 = 30;
+
+/// One of the bridges' lights.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fixture {
+    pub bridge: String,
+    pub light: usize,
+    pub name: String,
+    /// what it was showing when the lights were listed, black if it was off
+    pub color: Rgb,
+}
+
+/// every bridge's lights, or why they couldn't be listed
+type Fixtures = Result<Vec<Fixture>, String>;
+
+/// Asks every bridge which lights it has, and what they're showing.
+pub struct ListLightsTask {
+    bridges: Vec<ValidatedBridge>,
+}
+
+impl Task for ListLightsTask {
+    type Result = Vec<Fixture>;
+    type OnCompleteParams = Sender<Fixtures>;
+
+    fn run_task(self) -> anyhow::Result<Vec<Fixture>> {
+        let mut fixtures = vec![];
+        for bridge in &self.bridges {
+            let mut lights = bridge.get_all_lights()?;
+            let states = bridge.get_light_states()?;
+            // in the same order as everything else numbers them
+            lights.sort_by_key(|light| light.id);
+            fixtures.extend(lights.into_iter().map(|light| {
+                let gamut = Gamut::for_model(&light.light.modelid);
+                Fixture {
+                    bridge: bridge.id().to_string(),
+                    light: light.id,
+                    name: light.light.name,
+                    color: states
+                        .get(&light.id)
+                        .map_or(Rgb(0, 0, 0), |state| Target::from_state(state, gamut).rgb()),
+                }
+            }));
+        }
+        Ok(fixtures)
+    }
+
+    fn on_complete(r: anyhow::Result<Vec<Fixture>>, lights_tx: Self::OnCompleteParams) {
+        let _ = lights_tx.send(r.map_err(|e| format!("{:#}", e)));
+    }
+}
+
+/// Places the lights around the room, so effects can sweep across it.
+pub struct LayoutEditor {
+    app_tx: Sender<AppMsg>,
+    layout: Layout,
+    /// None until the bridges have answered
+    lights: Option<Fixtures>,
+    lights_ch: (Sender<Fixtures>, Receiver<Fixtures>),
+    selected: usize,
+    /// the zone being typed in for the selected light
+    zone_entry: Option<String>,
+}
+
+impl LayoutEditor {
+    pub fn init(app_tx: Sender<AppMsg>, bridges: Vec<ValidatedBridge>, layout: Layout) -> Self {
+        let lights_ch = crossbeam_channel::unbounded();
+        ListLightsTask { bridges }.spawn(lights_ch.0.clone());
+
+        Self {
+            app_tx,
+            layout,
+            lights: None,
+            lights_ch,
+            selected: 0,
+            zone_entry: None,
+        }
+    }
+
+    fn fixtures(&self) -> &[Fixture] {
+        match &self.lights {
+            Some(Ok(fixtures)) => fixtures,
+            _ => &[],
+        }
+    }
+
+    /// Where fixture `i` is, placed or not, the same way effects work it out.
+    fn position(&self, i: usize) -> (f32, f32) {
+        let fixture = &self.fixtures()[i];
+        let same_bridge: Vec<&Fixture> = self
+            .fixtures()
+            .iter()
+            .filter(|other| other.bridge == fixture.bridge)
+            .collect();
+        let index = same_bridge
+            .iter()
+            .position(|other| other.light == fixture.light)
+            .unwrap_or(0);
+        self.layout
+            .position(&fixture.bridge, fixture.light, index, same_bridge.len())
+    }
+
+    /// Moves the selected light a step across and down the room, placing it if it wasn't.
+    fn nudge(&mut self, across: f32, down: f32) {
+        let fixture = match self.fixtures().get(self.selected) {
+            Some(fixture) => fixture.clone(),
+            None => return,
+        };
+        let position = self.position(self.selected);
+        let placed = self.layout.entry(&fixture.bridge, fixture.light, position);
+        placed.x = step(placed.x, across, GRID_COLUMNS);
+        placed.y = step(placed.y, down, GRID_ROWS);
+    }
+
+    fn set_zone(&mut self, zone: String) {
+        let fixture = match self.fixtures().get(self.selected) {
+            Some(fixture) => fixture.clone(),
+            None => return,
+        };
+        let position = self.position(self.selected);
+        let zone = zone.trim();
+        self.layout
+            .entry(&fixture.bridge, fixture.light, position)
+            .zone = Some(zone.to_string()).filter(|_| !zone.is_empty());
+    }
+
+    fn zone(&self, fixture: &Fixture) -> Option<&str> {
+        self.layout
+            .get(&fixture.bridge, fixture.light)
+            .and_then(|placed| placed.zone.as_deref())
+    }
+
+    fn list_items(&self) -> Vec<ListItem<'static>> {
+        let fixtures = match &self.lights {
+            None => return vec![ListItem::new("Asking the bridges...")],
+            Some(Err(e)) => return vec![ListItem::new(format!("Couldn't list the lights: {}", e))],
+            Some(Ok(fixtures)) => fixtures,
+        };
+
+        fixtures
+            .iter()
+            .enumerate()
+            .map(|(i, fixture)| {
+                let zone = match (&self.zone_entry, i == self.selected) {
+                    (Some(entry), true) => format!(" · zone: {}_", entry),
+                    _ => match self.zone(fixture) {
+                        Some(zone) => format!(" · {}", zone),
+                        None => "".to_string(),
+                    },
+                };
+                ListItem::new(Spans::from(vec![
+                    swatch(fixture.color),
+                    Span::raw(format!("{} {}{}", label(i), fixture.name, zone)),
+                ]))
+            })
+            .collect()
+    }
+}
+
+/// A dot in the light's colour, or a hollow one if it's off.
+fn swatch(color: Rgb) -> Span<'static> {
+    match color {
+        Rgb(0, 0, 0) => Span::styled("○ ", Style::default().fg(Color::DarkGray)),
+        Rgb(r, g, b) => Span::styled("● ", Style::default().fg(Color::Rgb(r, g, b))),
+    }
+}
+
+/// `at` moved `by` steps along a grid of `steps`, staying in the room.
+fn step(at: f32, by: f32, steps: f32) -> f32 {
+    ((at * steps).round() + by).clamp(0.0, steps) / steps
+}
+
+/// what fixture `i` is marked with in the room
+fn label(i: usize) -> char {
+    "123456789abcdefghijklmnopqrstuvwxyz"
+        .chars()
+        .nth(i)
+        .unwrap_or('*')
+}
+
+/// The room seen from above, with each light marked where it is.
+struct RoomWidget {
+    /// position, and whether it's been placed or is only where it'd be by default
+    lights: Vec<((f32, f32), bool)>,
+    selected: usize,
+}
+
+impl Widget for RoomWidget {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        let block = Block::default().borders(Borders::ALL).title(" room ");
+        let inner = block.inner(area);
+        block.render(area, buf);
+        if inner.width == 0 || inner.height == 0 {
+            return;
+        }
+
+        // the selected light last, so it's on top of any sharing its cell
+        let mut order: Vec<usize> = (0..self.lights.len()).collect();
+        order.sort_by_key(|&i| i == self.selected);
+        for i in order {
+            let ((x, y), placed) = self.lights[i];
+            let column = inner.x + (x * (inner.width - 1) as f32).round() as u16;
+            let row = inner.y + (y * (inner.height - 1) as f32).round() as u16;
+            let style = match (i == self.selected, placed) {
+                (true, _) => Style::default().add_modifier(Modifier::REVERSED),
+                (false, true) => Style::default(),
+                (false, false) => Style::default().fg(Color::DarkGray),
+            };
+            buf.set_string(column, row, label(i).to_string(), style);
+        }
+    }
+}
+
+impl<B: Backend> Activity<B> for LayoutEditor {
+    fn render(&mut self, ticks: u64, f: &mut Frame<B>) {
+        f.render_widget(
+            RainbowBorderWidget {
+                border_animated: false,
+                ticks,
+            },
+            f.size(),
+        );
+
+        let area = center_rect(f.size(), 72, 20);
+        let list_width = LIST_WIDTH.min(area.width);
+        let list_area = Rect::new(area.x, area.y, list_width, area.height);
+        let room_area = Rect::new(
+            area.x + list_width,
+            area.y,
+            area.width - list_width,
+            area.height,
+        );
+
+        let list = List::new(self.list_items())
+            .block(Block::default().borders(Borders::ALL).title(" lights "))
+            .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+        let mut selected = ListState::default();
+        if !self.fixtures().is_empty() {
+            selected.select(Some(self.selected));
+        }
+        f.render_stateful_widget(list, list_area, &mut selected);
+
+        let room = RoomWidget {
+            lights: (0..self.fixtures().len())
+                .map(|i| {
+                    let fixture = &self.fixtures()[i];
+                    let placed = self.layout.get(&fixture.bridge, fixture.light).is_some();
+                    (self.position(i), placed)
+                })
+                .collect(),
+            selected: self.selected,
+        };
+        f.render_widget(room, room_area);
+    }
+
+    fn update(&mut self, _ticks: u64) {
+        if let Ok(lights) = self.lights_ch.1.try_recv() {
+            self.lights = Some(lights);
+        }
+    }
+
+    fn handle_event(&mut self, event: &Event) -> bool {
+        let key = match event {
+            Event::Key(key) => key,
+            _ => return false,
+        };
+
+        if let Some(entry) = &mut self.zone_entry {
+            match key.code {
+                KeyCode::Char(c) if !key.modifiers.contains(KeyModifiers::CONTROL) => entry.push(c),
+                KeyCode::Backspace => {
+                    entry.pop();
+                }
+                KeyCode::Enter => {
+                    let zone = entry.clone();
+                    self.zone_entry = None;
+                    self.set_zone(zone);
+                }
+                _ => return false,
+            }
+            return true;
+        }
+
+        let count = self.fixtures().len();
+        match key.code {
+            KeyCode::Tab if count > 0 => self.selected = (self.selected + 1) % count,
+            KeyCode::BackTab if count > 0 => self.selected = (self.selected + count - 1) % count,
+            KeyCode::Left => self.nudge(-1.0, 0.0),
+            KeyCode::Right => self.nudge(1.0, 0.0),
+            KeyCode::Up => self.nudge(0.0, -1.0),
+            KeyCode::Down => self.nudge(0.0, 1.0),
+            KeyCode::Char('z') if count > 0 => {
+                let zone = self.zone(&self.fixtures()[self.selected]).unwrap_or("");
+                self.zone_entry = Some(zone.to_string());
+            }
+            KeyCode::Enter => self
+                .app_tx
+                .send(AppMsg::SaveLayout(self.layout.clone()))
+                .unwrap(),
+            _ => return false,
+        }
+
+        true
+    }
+
+    fn keybindings(&self) -> Vec<(&'static str, &'static str)> {
+        match self.zone_entry {
+            Some(_) => vec![("enter", "set the zone")],
+            None => vec![
+                ("tab, shift+tab", "choose a light"),
+                ("arrows", "move it"),
+                ("z", "name its zone"),
+                ("enter", "save"),
+            ],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::harness::{lines, wait_until};
+    use crate::hue::mock::MockBridge;
+    use crossterm::event::KeyEvent;
+    use tui::backend::TestBackend;
+    use tui::Terminal;
+
+    fn press(editor: &mut LayoutEditor, code: KeyCode) {
+        let event = Event::Key(KeyEvent::new(code, KeyModifiers::NONE));
+        assert!(<LayoutEditor as Activity<TestBackend>>::handle_event(
+            editor, &event
+        ));
+    }
+
+    fn loaded(mock: &MockBridge) -> (LayoutEditor, Receiver<AppMsg>) {
+        let (app_tx, app_rx) = crossbeam_channel::unbounded();
+        let mut editor =
+            LayoutEditor::init(app_tx, vec![mock.validated_bridge()], Layout::default());
+        let loaded = wait_until(|| {
+            <LayoutEditor as Activity<TestBackend>>::update(&mut editor, 0);
+            editor.lights.is_some()
+        });
+        assert!(loaded, "the lights never came");
+        (editor, app_rx)
+    }
+
+    #[test]
+    fn places_lights_and_names_their_zones() {
+        let mock = MockBridge::start();
+        let (mut editor, app_rx) = loaded(&mock);
+
+        // the backdrop starts out in the middle of the room, second of three
+        press(&mut editor, KeyCode::Tab);
+        press(&mut editor, KeyCode::Left);
+        press(&mut editor, KeyCode::Up);
+        press(&mut editor, KeyCode::Up);
+        press(&mut editor, KeyCode::Char('z'));
+        for c in "backdrop".chars() {
+            press(&mut editor, KeyCode::Char(c));
+        }
+        press(&mut editor, KeyCode::Enter);
+        press(&mut editor, KeyCode::Enter);
+
+        let layout = match app_rx.try_recv() {
+            Ok(AppMsg::SaveLayout(layout)) => layout,
+            _ => panic!("expected the layout to be saved"),
+        };
+        let bridge = mock.info().bridgeid;
+        let placed = layout.get(&bridge, 2).unwrap();
+        assert_eq!((placed.x, placed.y), (0.45, 0.3));
+        assert_eq!(placed.zone.as_deref(), Some("backdrop"));
+        assert_eq!(layout.lights.len(), 1);
+    }
+
+    #[test]
+    fn shows_the_room() {
+        let mock = MockBridge::start();
+        let (mut editor, _app_rx) = loaded(&mock);
+        press(&mut editor, KeyCode::Down);
+
+        let mut terminal = Terminal::new(TestBackend::new(80, 24)).unwrap();
+        terminal
+            .draw(|f| <LayoutEditor as Activity<TestBackend>>::render(&mut editor, 0, f))
+            .unwrap();
+        let lines = lines(terminal.backend().buffer());
+        assert!(lines[2].contains("┌ lights "));
+        assert!(lines[3].contains("● 1 Desk"));
+        assert!(lines[5].contains("○ 3 Hallway"));
+        // the desk light's showing a warm white by xy
+        let buffer = terminal.backend().buffer();
+        let swatch = (0..80)
+            .map(|x| buffer.get(x, 3))
+            .find(|cell| cell.symbol == "●")
+            .unwrap();
+        match swatch.fg {
+            Color::Rgb(r, g, b) => assert!(r == 255 && g > b && b > 0),
+            other => panic!("expected the desk light's colour, got {:?}", other),
+        }
+        // inside the room's border, 40 wide and 18 tall from (35, 3), the desk has been
+        // moved a row below the others, snapping to the grid on the way
+        let at = |x: usize, y: usize| lines[y].chars().nth(x).unwrap();
+        assert_eq!((at(41, 13), at(55, 12), at(68, 12)), ('1', '2', '3'));
+    }
+}
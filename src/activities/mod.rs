@@ -1,5 +1,14 @@
 pub mod bridge_connect;
+pub mod group_picker;
+pub mod layout_editor;
+pub mod settings;
+pub mod status;
+pub mod twitch_login;
 
+use crate::commands::CommandOutcome;
+use crate::lights::LightEvent;
+use crate::twitch::TwitchEvent;
+use crossterm::event::Event;
 use tui::backend::Backend;
 
 use tui::Frame;
@@ -7,4 +16,23 @@ use tui::Frame;
 pub trait Activity<B: Backend> {
     fn render(&mut self, ticks: u64, f: &mut Frame<B>);
     fn update(&mut self, ticks: u64);
+
+    /// Offered every input event the app-wide keybindings didn't claim.
+    /// Returns true if the event was consumed.
+    fn handle_event(&mut self, _event: &Event) -> bool {
+        false
+    }
+
+    /// Sees everything that happens on the Twitch channel while the activity is on screen.
+    fn handle_twitch_event(&mut self, _event: &TwitchEvent) {}
+
+    /// Sees every chat command, whether it went through or not.
+    fn handle_command(&mut self, _outcome: &CommandOutcome) {}
+
+    fn handle_light_event(&mut self, _event: &LightEvent) {}
+
+    /// (keys, description) pairs listed in the help overlay
+    fn keybindings(&self) -> Vec<(&'static str, &'static str)> {
+        vec![]
+    }
 }
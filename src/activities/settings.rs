@@ -0,0 +1,112 @@
+use crate::activities::Activity;
+use crate::widgets::center_rect;
+use crate::widgets::rainbow_border::RainbowBorderWidget;
+use crate::{AppMsg, SetupStep};
+use crossbeam_channel::Sender;
+use crossterm::event::{Event, KeyCode};
+
+use tui::backend::Backend;
+use tui::style::{Modifier, Style};
+use tui::widgets::{Block, Borders, List, ListItem, ListState};
+use tui::Frame;
+
+#[derive(Debug, Copy, Clone)]
+enum Entry {
+    BridgeSetup,
+    Layout,
+    Groups,
+    TwitchLogin,
+    Back,
+}
+
+impl Entry {
+    const ALL: [Entry; 5] = [
+        Entry::BridgeSetup,
+        Entry::Layout,
+        Entry::Groups,
+        Entry::TwitchLogin,
+        Entry::Back,
+    ];
+
+    fn label(&self) -> &'static str {
+        match self {
+            Entry::BridgeSetup => "Set up the Hue bridge again",
+            Entry::Layout => "Place the lights around the room",
+            Entry::Groups => "Choose where effects play, or recall a scene",
+            Entry::TwitchLogin => "Log in to Twitch again",
+            Entry::Back => "Back",
+        }
+    }
+
+    fn message(&self) -> AppMsg {
+        match self {
+            Entry::BridgeSetup => AppMsg::RerunSetup(SetupStep::Bridge),
+            Entry::Layout => AppMsg::OpenLayoutEditor,
+            Entry::Groups => AppMsg::OpenGroupPicker,
+            Entry::TwitchLogin => AppMsg::RerunSetup(SetupStep::TwitchLogin),
+            Entry::Back => AppMsg::Back,
+        }
+    }
+}
+
+pub struct Settings {
+    selected: ListState,
+    app_tx: Sender<AppMsg>,
+}
+
+impl<B: Backend> Activity<B> for Settings {
+    fn render(&mut self, ticks: u64, f: &mut Frame<B>) {
+        f.render_widget(
+            RainbowBorderWidget {
+                border_animated: false,
+                ticks,
+            },
+            f.size(),
+        );
+
+        let items: Vec<ListItem> = Entry::ALL
+            .iter()
+            .map(|entry| ListItem::new(entry.label()))
+            .collect();
+        let list = List::new(items)
+            .block(Block::default().borders(Borders::ALL).title(" settings "))
+            .highlight_style(Style::default().add_modifier(Modifier::REVERSED))
+            .highlight_symbol("> ");
+
+        f.render_stateful_widget(list, center_rect(f.size(), 72, 20), &mut self.selected);
+    }
+
+    fn update(&mut self, _ticks: u64) {}
+
+    fn handle_event(&mut self, event: &Event) -> bool {
+        let key = match event {
+            Event::Key(key) => key,
+            _ => return false,
+        };
+
+        let selected = self.selected.selected().unwrap_or(0);
+        match key.code {
+            KeyCode::Up => self.selected.select(Some(selected.saturating_sub(1))),
+            KeyCode::Down => self
+                .selected
+                .select(Some((selected + 1).min(Entry::ALL.len() - 1))),
+            KeyCode::Enter => self.app_tx.send(Entry::ALL[selected].message()).unwrap(),
+            _ => return false,
+        }
+
+        true
+    }
+
+    fn keybindings(&self) -> Vec<(&'static str, &'static str)> {
+        vec![("up, down", "choose"), ("enter", "select")]
+    }
+}
+
+impl Settings {
+    pub fn init(app_tx: Sender<AppMsg>) -> Self {
+        let mut selected = ListState::default();
+        selected.select(Some(0));
+
+        Self { selected, app_tx }
+    }
+}
@@ -0,0 +1,234 @@
+use crate::activities::Activity;
+use crate::commands::CommandOutcome;
+use crate::hue::queue::QueueStats;
+use crate::lights::effect_queue::EffectsStatus;
+use crate::lights::LightEvent;
+use crate::twitch::{HypeTrainStage, TwitchEvent};
+use crate::widgets::center_rect;
+use crate::widgets::effect_queue::EffectQueueWidget;
+use crate::widgets::log_block::{Log, LogEvent, LogItem};
+use crate::widgets::rainbow_border::RainbowBorderWidget;
+use crate::AppMsg;
+use crossbeam_channel::Sender;
+use crossterm::event::{Event, KeyCode};
+use std::collections::BTreeMap;
+
+use tui::backend::Backend;
+use tui::layout::{Alignment, Rect};
+use tui::widgets::Paragraph;
+use tui::Frame;
+
+/// The main screen while twitchbrite is running.
+pub struct Status {
+    log: Log,
+    app_tx: Sender<AppMsg>,
+    /// by bridge ID
+    queues: BTreeMap<String, QueueStats>,
+    effects: EffectsStatus,
+}
+
+impl<B: Backend> Activity<B> for Status {
+    fn render(&mut self, ticks: u64, f: &mut Frame<B>) {
+        f.render_widget(
+            RainbowBorderWidget {
+                border_animated: true,
+                ticks,
+            },
+            f.size(),
+        );
+
+        let log_area = center_rect(f.size(), 72, 20);
+        f.render_widget(self.log.clone(), log_area);
+
+        // inside the top of the log, over the oldest lines
+        let effects = EffectQueueWidget {
+            status: &self.effects,
+        };
+        let height = effects.height().min(log_area.height / 2);
+        if height > 0 {
+            let effects_area = Rect {
+                x: log_area.x + 1,
+                y: log_area.y + 1,
+                width: log_area.width.saturating_sub(2),
+                height,
+            };
+            f.render_widget(effects, effects_area);
+        }
+
+        let queue_area = Rect {
+            y: log_area.bottom(),
+            height: 1,
+            ..log_area
+        };
+        if !self.queues.is_empty() && queue_area.bottom() < f.size().bottom() {
+            let summary = Paragraph::new(self.queue_summary()).alignment(Alignment::Center);
+            f.render_widget(summary, queue_area);
+        }
+    }
+
+    fn update(&mut self, _ticks: u64) {
+        self.log.update();
+    }
+
+    fn handle_event(&mut self, event: &Event) -> bool {
+        match event {
+            Event::Key(key) if key.code == KeyCode::Char('s') => {
+                self.app_tx.send(AppMsg::OpenSettings).unwrap();
+                true
+            }
+            Event::Key(key) if key.code == KeyCode::Char('r') => {
+                self.app_tx.send(AppMsg::ToggleRainbow).unwrap();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn handle_twitch_event(&mut self, event: &TwitchEvent) {
+        if let Some(item) = log_item(event) {
+            self.log.sender().send(LogEvent::PushItem(item)).unwrap();
+        }
+    }
+
+    fn handle_command(&mut self, outcome: &CommandOutcome) {
+        let user = &outcome.message.display_name;
+        let item = match &outcome.result {
+            Ok(action) => {
+                LogItem::task_complete(format!("!{} from {}: {}", outcome.command, user, action))
+            }
+            Err(rejection) => LogItem::task_failed(format!(
+                "!{} from {} rejected: {}",
+                outcome.command, user, rejection
+            )),
+        };
+        self.log.sender().send(LogEvent::PushItem(item.0)).unwrap();
+    }
+
+    fn handle_light_event(&mut self, event: &LightEvent) {
+        let item = match event {
+            LightEvent::Failed { action, reason } => {
+                LogItem::error(format!("Couldn't {}: {}", action, reason))
+            }
+            LightEvent::BridgeFailed { bridge, reason } => {
+                LogItem::error(format!("Hue bridge {}: {}", bridge, reason))
+            }
+            LightEvent::Queue { bridge, stats } => {
+                self.queues.insert(bridge.clone(), *stats);
+                return;
+            }
+            LightEvent::Effects(status) => {
+                self.effects = status.clone();
+                return;
+            }
+            LightEvent::EffectDone { .. } => return,
+        };
+        self.log.sender().send(LogEvent::PushItem(item.0)).unwrap();
+    }
+
+    fn keybindings(&self) -> Vec<(&'static str, &'static str)> {
+        vec![("s", "settings"), ("r", "rainbow mode on/off")]
+    }
+}
+
+/// How an event shows up in the log, if at all.
+fn log_item(event: &TwitchEvent) -> Option<LogItem> {
+    let item = match event {
+        TwitchEvent::Joined { channel } => LogItem::task_complete(format!("Joined #{}", channel)),
+        TwitchEvent::Disconnected { reason } => {
+            LogItem::error(format!("Lost Twitch chat: {}", reason))
+        }
+        TwitchEvent::EventSubConnected { subscriptions } => LogItem::task_complete(format!(
+            "Listening for {} kinds of Twitch event",
+            subscriptions
+        )),
+        TwitchEvent::EventSubDisconnected { reason } => {
+            LogItem::error(format!("Lost Twitch events: {}", reason))
+        }
+        TwitchEvent::SubscriptionFailed { kind, reason } => {
+            LogItem::error(format!("Not getting {} events: {}", kind, reason))
+        }
+        TwitchEvent::Message(msg) => LogItem::info(format!("{}: {}", msg.display_name, msg.text)),
+        TwitchEvent::Subscribe {
+            user, tier, months, ..
+        } => match months {
+            1 => LogItem::info(format!("{} subscribed ({})", user, tier.name())),
+            n => LogItem::info(format!(
+                "{} resubscribed for {} months ({})",
+                user,
+                n,
+                tier.name()
+            )),
+        },
+        TwitchEvent::GiftSub {
+            gifter,
+            recipient,
+            tier,
+            count,
+        } => {
+            let gifter = gifter.as_deref().unwrap_or("Someone anonymous");
+            match recipient {
+                Some(recipient) => LogItem::info(format!(
+                    "{} gifted {} a sub ({})",
+                    gifter,
+                    recipient,
+                    tier.name()
+                )),
+                None => LogItem::info(format!(
+                    "{} gifted {} subs ({})",
+                    gifter,
+                    count,
+                    tier.name()
+                )),
+            }
+        }
+        TwitchEvent::Raid { from, viewers } => {
+            LogItem::info(format!("{} is raiding with {} viewers", from, viewers))
+        }
+        TwitchEvent::ClearChat { .. } => return None,
+        TwitchEvent::Follow { user } => LogItem::info(format!("{} followed", user)),
+        TwitchEvent::Cheer { user, bits, .. } => LogItem::info(format!(
+            "{} cheered {} bits",
+            user.as_deref().unwrap_or("Someone anonymous"),
+            bits
+        )),
+        TwitchEvent::Redemption { user, reward, .. } => {
+            LogItem::info(format!("{} redeemed {}", user, reward))
+        }
+        TwitchEvent::HypeTrain { stage, level, .. } => match stage {
+            HypeTrainStage::Began => LogItem::info("A hype train started!"),
+            HypeTrainStage::Progressed => return None,
+            HypeTrainStage::Ended => {
+                LogItem::info(format!("The hype train ended at level {}", level))
+            }
+        },
+        TwitchEvent::StreamOnline => LogItem::info("The stream is live"),
+        TwitchEvent::StreamOffline => LogItem::info("The stream ended"),
+    };
+    Some(item.0)
+}
+
+impl Status {
+    pub fn init<I: IntoIterator<Item = String>>(app_tx: Sender<AppMsg>, messages: I) -> Self {
+        let mut log = Log::default();
+        log.set_title(String::from(" twitchbrite "));
+        for message in messages {
+            log.sender()
+                .send(LogEvent::PushItem(LogItem::task_complete(message).0))
+                .unwrap();
+        }
+
+        Self {
+            log,
+            app_tx,
+            queues: BTreeMap::new(),
+            effects: EffectsStatus::default(),
+        }
+    }
+
+    /// How far behind the bridges are, over all of them.
+    fn queue_summary(&self) -> String {
+        let depth: usize = self.queues.values().map(|stats| stats.depth).sum();
+        let dropped: u64 = self.queues.values().map(|stats| stats.dropped).sum();
+        format!("Bridge queue: {} waiting, {} dropped", depth, dropped)
+    }
+}
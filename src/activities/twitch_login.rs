@@ -0,0 +1,526 @@
+use crate::activities::Activity;
+use crate::config::{TwitchConfig, TwitchTokens};
+use crate::shutdown;
+use crate::tasks::Task;
+use crate::twitch::auth::{AuthClient, DeviceCode, DevicePoll};
+use crate::widgets::center_rect;
+use crate::widgets::log_block::LogVariant::{TaskComplete, TaskFailed};
+use crate::widgets::log_block::{Log, LogEvent, LogItem};
+use crate::widgets::rainbow_border::RainbowBorderWidget;
+use crate::AppMsg::Next;
+use crate::{AppMsg, Outcome};
+use anyhow::anyhow;
+use crossbeam_channel::{Receiver, Sender};
+use crossterm::event::{Event, KeyCode};
+use std::thread;
+use std::time::{Duration, Instant};
+use tui::backend::Backend;
+use tui::Frame;
+
+/// how much to back off each time Twitch asks us to slow down, per RFC 8628
+const SLOW_DOWN_STEP: Duration = Duration::from_secs(5);
+
+/// Asks Twitch for a code the user can enter on another device.
+pub struct RequestCodeTask {
+    log_tx: Sender<LogEvent>,
+    auth: AuthClient,
+}
+
+/// Polls for a token until the user has entered the code, or the code expires.
+pub struct PollTokenTask {
+    log_tx: Sender<LogEvent>,
+    auth: AuthClient,
+    device: DeviceCode,
+    interval: Duration,
+    slow_down_step: Duration,
+}
+
+impl PollTokenTask {
+    pub fn new(log_tx: Sender<LogEvent>, auth: AuthClient, device: DeviceCode) -> Self {
+        Self {
+            log_tx,
+            auth,
+            interval: Duration::from_secs(device.interval),
+            device,
+            slow_down_step: SLOW_DOWN_STEP,
+        }
+    }
+}
+
+fn on_complete_default(r: anyhow::Result<State>, p: Sender<State>) {
+    // nobody's listening if the login was left before this finished
+    let _ = p.send(r.unwrap_or_else(State::Failed));
+}
+
+impl Task for RequestCodeTask {
+    type Result = State;
+    type OnCompleteParams = Sender<State>;
+
+    fn run_task(self) -> anyhow::Result<State> {
+        let (log, id) = LogItem::task_waiting("Asking Twitch for a login code...");
+        let _ = self.log_tx.send(LogEvent::PushItem(log));
+
+        let device = match self.auth.request_device_code() {
+            Ok(device) => device,
+            Err(e) => {
+                let _ = self.log_tx.send(LogEvent::SetVariant(id, TaskFailed));
+                return Err(e.context("Failed to get a login code from Twitch"));
+            }
+        };
+
+        let _ = self.log_tx.send(LogEvent::SetVariant(id, TaskComplete));
+        let _ = self.log_tx.send(LogEvent::PushItem(
+            LogItem::info(format!(
+                "Go to {} and enter the code {}",
+                // the full URI fills the code in too, but is too long to show
+                device
+                    .verification_uri
+                    .split('?')
+                    .next()
+                    .unwrap_or(&device.verification_uri),
+                device.user_code
+            ))
+            .0,
+        ));
+
+        Ok(State::WaitingForUser(Some(PollTokenTask::new(
+            self.log_tx,
+            self.auth,
+            device,
+        ))))
+    }
+
+    fn on_complete(r: anyhow::Result<Self::Result>, p: Self::OnCompleteParams) {
+        on_complete_default(r, p)
+    }
+}
+
+impl Task for PollTokenTask {
+    type Result = State;
+    type OnCompleteParams = Sender<State>;
+
+    fn run_task(mut self) -> anyhow::Result<State> {
+        let (log, id) = LogItem::task_waiting("");
+        let _ = self.log_tx.send(LogEvent::PushItem(log));
+
+        let deadline = Instant::now() + Duration::from_secs(self.device.expires_in);
+        let mut next_poll = Instant::now() + self.interval;
+        loop {
+            if shutdown::requested() {
+                return Ok(State::Expired);
+            }
+
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                let _ = self.log_tx.send(LogEvent::SetMessage(
+                    id.clone(),
+                    "The login code expired.".to_string(),
+                ));
+                let _ = self.log_tx.send(LogEvent::SetVariant(id, TaskFailed));
+                return Ok(State::Expired);
+            }
+
+            let waiting = self.log_tx.send(LogEvent::SetMessage(
+                id.clone(),
+                format!(
+                    "Waiting for you to log in. The code works for another {}m.",
+                    (remaining.as_secs_f32() / 60.0).ceil()
+                ),
+            ));
+            // the login's been left, so there's nobody to log in for any more
+            if waiting.is_err() {
+                return Ok(State::Expired);
+            }
+
+            // short naps, so quitting doesn't have to wait out the whole interval
+            if Instant::now() < next_poll {
+                thread::sleep(Duration::from_millis(100).min(next_poll - Instant::now()));
+                continue;
+            }
+            next_poll = Instant::now() + self.interval;
+
+            let tokens = match self.auth.poll_device_code(&self.device.device_code) {
+                Ok(DevicePoll::Pending) => continue,
+                Ok(DevicePoll::SlowDown) => {
+                    self.interval += self.slow_down_step;
+                    next_poll = Instant::now() + self.interval;
+                    continue;
+                }
+                Ok(DevicePoll::Expired) => {
+                    let _ = self.log_tx.send(LogEvent::SetMessage(
+                        id.clone(),
+                        "The login code expired.".to_string(),
+                    ));
+                    let _ = self.log_tx.send(LogEvent::SetVariant(id, TaskFailed));
+                    return Ok(State::Expired);
+                }
+                Ok(DevicePoll::Denied) => Err(anyhow!("The login was turned down on Twitch")),
+                Ok(DevicePoll::Done(tokens)) => Ok(tokens),
+                Err(e) => Err(e),
+            };
+
+            // the token alone doesn't say whose it is
+            let result = tokens.and_then(|tokens| {
+                let validation = self
+                    .auth
+                    .validate(&tokens.access_token)?
+                    .ok_or_else(|| anyhow!("Twitch didn't accept the token it just gave us"))?;
+                Ok(TwitchTokens {
+                    access_token: tokens.access_token,
+                    refresh_token: tokens.refresh_token,
+                    user_id: validation.user_id,
+                    login: validation.login,
+                })
+            });
+
+            return match result {
+                Ok(tokens) => {
+                    let _ = self.log_tx.send(LogEvent::SetMessage(
+                        id.clone(),
+                        format!("Logged in to Twitch as {}.", tokens.login),
+                    ));
+                    let _ = self.log_tx.send(LogEvent::SetVariant(id, TaskComplete));
+                    Ok(State::Complete(tokens))
+                }
+                Err(e) => {
+                    let _ = self.log_tx.send(LogEvent::SetVariant(id, TaskFailed));
+                    Err(e.context("Failed to log in to Twitch"))
+                }
+            };
+        }
+    }
+
+    fn on_complete(r: anyhow::Result<Self::Result>, p: Self::OnCompleteParams) {
+        on_complete_default(r, p)
+    }
+}
+
+pub enum State {
+    RequestingCode(Option<RequestCodeTask>),
+    WaitingForUser(Option<PollTokenTask>),
+    /// the code ran out before the user logged in, and a new one can be requested
+    Expired,
+
+    #[cfg_attr(not(test), allow(dead_code))]
+    Waiting(Box<State>),
+    Failed(anyhow::Error),
+    Complete(TwitchTokens),
+    /// the tokens have been handed over to the app
+    Done,
+}
+
+impl State {
+    fn update(mut self, state_tx: Sender<State>) -> Self {
+        match self {
+            State::RequestingCode(ref mut task) => {
+                let task = task.take().unwrap();
+                task.spawn(state_tx);
+                State::Waiting(Box::new(self))
+            }
+
+            State::WaitingForUser(ref mut task) => {
+                let task = task.take().unwrap();
+                task.spawn(state_tx);
+                State::Waiting(Box::new(self))
+            }
+
+            _ => self,
+        }
+    }
+}
+
+/// Logs in to Twitch with the device code flow, so nobody has to paste tokens around.
+pub struct TwitchLogin {
+    state: Option<State>,
+    log: Log,
+    state_ch: (Sender<State>, Receiver<State>),
+    app_tx: Sender<AppMsg>,
+    /// None when there's no client ID to log in with
+    auth: Option<AuthClient>,
+}
+
+impl<B: Backend> Activity<B> for TwitchLogin {
+    fn render(&mut self, ticks: u64, f: &mut Frame<B>) {
+        f.render_widget(
+            RainbowBorderWidget {
+                border_animated: false,
+                ticks,
+            },
+            f.size(),
+        );
+
+        f.render_widget(self.log.clone(), center_rect(f.size(), 72, 20));
+    }
+
+    fn update(&mut self, _ticks: u64) {
+        let (state_tx, state_rx) = self.state_ch.clone();
+
+        if let Ok(state) = state_rx.try_recv() {
+            if let State::Complete(tokens) = state {
+                self.app_tx
+                    .send(Next(Outcome::TwitchLogin(tokens)))
+                    .unwrap();
+                self.state = Some(State::Done);
+                self.log.update();
+                return;
+            }
+
+            match &state {
+                State::Failed(e) => {
+                    let (log, _) = LogItem::error(format!("{:#}", e));
+                    self.log.sender().send(LogEvent::PushItem(log)).unwrap();
+                    if self.auth.is_some() {
+                        let (log, _) = LogItem::info("Press r to try again.");
+                        self.log.sender().send(LogEvent::PushItem(log)).unwrap();
+                    }
+                }
+                State::Expired => {
+                    let (log, _) = LogItem::info("Press r to get a new code.");
+                    self.log.sender().send(LogEvent::PushItem(log)).unwrap();
+                }
+                _ => {}
+            }
+            self.state = Some(state.update(state_tx));
+        }
+
+        self.log.update();
+    }
+
+    fn handle_event(&mut self, event: &Event) -> bool {
+        let key = match event {
+            Event::Key(key) => key,
+            _ => return false,
+        };
+
+        match (&self.state, key.code) {
+            (Some(State::Expired | State::Failed(_)), KeyCode::Char('r')) => {
+                match &self.auth {
+                    Some(auth) => self.request_code(auth.clone()),
+                    None => return false,
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn keybindings(&self) -> Vec<(&'static str, &'static str)> {
+        match &self.state {
+            Some(State::Expired | State::Failed(_)) if self.auth.is_some() => {
+                vec![("r", "start the login over")]
+            }
+            _ => vec![],
+        }
+    }
+}
+
+impl TwitchLogin {
+    pub fn init(app_tx: Sender<AppMsg>, twitch_config: &TwitchConfig) -> Self {
+        let mut log = Log::default();
+        log.set_title(String::from(" log in to twitch "));
+
+        let auth = match twitch_config.client_id() {
+            Some(client_id) => AuthClient::new(&twitch_config.auth_url, client_id),
+            None => Err(anyhow!(
+                "twitchbrite needs a Twitch client ID. Register an application at \
+                 https://dev.twitch.tv/console and set client_id under [twitch_config] in config.toml."
+            )),
+        };
+
+        let mut login = Self {
+            state: None,
+            log,
+            state_ch: crossbeam_channel::unbounded(),
+            app_tx,
+            auth: None,
+        };
+        match auth {
+            Ok(auth) => {
+                login.auth = Some(auth.clone());
+                login.request_code(auth);
+            }
+            Err(e) => login.state_ch.0.send(State::Failed(e)).unwrap(),
+        }
+        login
+    }
+
+    /// Logging in again because the old login stopped working, for `reason`.
+    pub fn with_reason(
+        app_tx: Sender<AppMsg>,
+        twitch_config: &TwitchConfig,
+        reason: String,
+    ) -> Self {
+        let login = Self::init(app_tx, twitch_config);
+        let (log, _) = LogItem::error(reason);
+        login.log.sender().send(LogEvent::PushItem(log)).unwrap();
+        login
+    }
+
+    fn request_code(&mut self, auth: AuthClient) {
+        self.state = None;
+        self.state_ch
+            .0
+            .send(State::RequestingCode(Some(RequestCodeTask {
+                log_tx: self.log.sender(),
+                auth,
+            })))
+            .unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::harness::{lines, wait_until, Harness, PATIENCE};
+    use crate::hue::mock::MockBridge;
+    use crate::twitch::mock::{MockTwitchAuth, MOCK_CLIENT_ID, MOCK_LOGIN, MOCK_USER_ID};
+    use crate::Mode;
+    use crossterm::event::{KeyEvent, KeyModifiers};
+    use tui::backend::TestBackend;
+    use tui::Terminal;
+
+    fn login(auth: &MockTwitchAuth) -> (TwitchLogin, Receiver<AppMsg>) {
+        let (app_tx, app_rx) = crossbeam_channel::unbounded();
+        let config = TwitchConfig {
+            auth_url: auth.base_url(),
+            client_id: Some(MOCK_CLIENT_ID.to_string()),
+            ..TwitchConfig::default()
+        };
+        (TwitchLogin::init(app_tx, &config), app_rx)
+    }
+
+    fn update_until(login: &mut TwitchLogin, done: impl Fn(&TwitchLogin) -> bool) {
+        let finished = wait_until(|| {
+            if done(login) {
+                return true;
+            }
+            <TwitchLogin as Activity<TestBackend>>::update(login, 0);
+            false
+        });
+        assert!(finished, "gave up waiting for the login");
+    }
+
+    fn waiting_for_user(login: &TwitchLogin) -> bool {
+        matches!(&login.state, Some(State::Waiting(w)) if matches!(**w, State::WaitingForUser(_)))
+    }
+
+    fn screen(login: &mut TwitchLogin) -> String {
+        let mut terminal = Terminal::new(TestBackend::new(80, 24)).unwrap();
+        terminal
+            .draw(|f| <TwitchLogin as Activity<TestBackend>>::render(login, 0, f))
+            .unwrap();
+        lines(terminal.backend().buffer()).join("\n")
+    }
+
+    fn tokens(app_rx: &Receiver<AppMsg>) -> TwitchTokens {
+        match app_rx.recv_timeout(PATIENCE) {
+            Ok(Next(Outcome::TwitchLogin(tokens))) => tokens,
+            _ => panic!("expected the login's tokens"),
+        }
+    }
+
+    #[test]
+    fn logs_in_with_a_device_code() {
+        let auth = MockTwitchAuth::start();
+        let (mut login, app_rx) = login(&auth);
+
+        update_until(&mut login, waiting_for_user);
+        assert!(screen(&mut login).contains(&format!("enter the code {}", auth.user_code())));
+
+        auth.authorize();
+        update_until(&mut login, |l| matches!(l.state, Some(State::Done)));
+
+        let tokens = tokens(&app_rx);
+        assert_eq!(tokens.login, MOCK_LOGIN);
+        assert_eq!(tokens.user_id, MOCK_USER_ID);
+        assert!(auth.is_valid(&tokens.access_token));
+        assert!(screen(&mut login).contains("Logged in to Twitch as dwbrite."));
+    }
+
+    #[test]
+    fn backs_off_when_asked_to_slow_down() {
+        let auth = MockTwitchAuth::start();
+        let client = AuthClient::new(auth.base_url(), MOCK_CLIENT_ID).unwrap();
+        let device = client.request_device_code().unwrap();
+
+        let (log_tx, _log_rx) = crossbeam_channel::unbounded();
+        let mut task = PollTokenTask::new(log_tx, client, device);
+        task.slow_down_step = Duration::from_millis(200);
+
+        auth.fail_next_poll("slow_down");
+        auth.authorize();
+        let started = Instant::now();
+        assert!(matches!(task.run_task(), Ok(State::Complete(_))));
+        assert!(started.elapsed() >= Duration::from_millis(200));
+        assert_eq!(auth.polls(), 2);
+    }
+
+    #[test]
+    fn stops_polling_once_the_login_is_gone() {
+        let auth = MockTwitchAuth::start();
+        let client = AuthClient::new(auth.base_url(), MOCK_CLIENT_ID).unwrap();
+        let device = client.request_device_code().unwrap();
+
+        let (log_tx, log_rx) = crossbeam_channel::unbounded();
+        drop(log_rx);
+        let (done_tx, done_rx) = crossbeam_channel::bounded(1);
+        let task = PollTokenTask::new(log_tx, client, device);
+        thread::spawn(move || done_tx.send(task.run_task()).unwrap());
+
+        // rather than waiting out the half hour the code's good for
+        assert!(matches!(
+            done_rx.recv_timeout(PATIENCE),
+            Ok(Ok(State::Expired))
+        ));
+        assert_eq!(auth.polls(), 0);
+    }
+
+    #[test]
+    fn starts_over_once_the_code_expires() {
+        let auth = MockTwitchAuth::start();
+        let (mut login, app_rx) = login(&auth);
+
+        auth.fail_next_poll("expired_token");
+        update_until(&mut login, |l| matches!(l.state, Some(State::Expired)));
+        assert!(screen(&mut login).contains("Press r to get a new code."));
+
+        let r = Event::Key(KeyEvent::new(KeyCode::Char('r'), KeyModifiers::NONE));
+        assert!(<TwitchLogin as Activity<TestBackend>>::handle_event(
+            &mut login, &r
+        ));
+        update_until(&mut login, waiting_for_user);
+        auth.authorize();
+        update_until(&mut login, |l| matches!(l.state, Some(State::Done)));
+        assert_eq!(tokens(&app_rx).login, MOCK_LOGIN);
+    }
+
+    #[test]
+    fn setup_logs_in_after_the_bridge() {
+        let bridge = MockBridge::start();
+        bridge.press_link_button();
+
+        let mut app = Harness::new(
+            80,
+            24,
+            crate::hue::Discovery::Addresses(vec![bridge.address()]),
+        );
+        app.tick_until(|app| app.shows("log in to twitch"));
+        app.tick_until(|app| app.shows(&app.auth.user_code()));
+        assert_eq!(app.mode(), Mode::Setup);
+
+        app.auth.authorize();
+        app.tick_until(|app| app.mode() == Mode::Running);
+
+        let tokens = app.config().twitch_config().tokens.clone().unwrap();
+        assert!(app.auth.is_valid(&tokens.access_token));
+
+        // and chat is joined with the new login
+        app.chat.expect("CAP REQ");
+        assert_eq!(
+            app.chat.recv(),
+            format!("PASS oauth:{}", tokens.access_token)
+        );
+        assert_eq!(app.chat.recv(), format!("NICK {}", MOCK_LOGIN));
+        assert_eq!(app.chat.recv(), format!("JOIN #{}", MOCK_LOGIN));
+    }
+}
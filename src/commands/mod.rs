@@ -0,0 +1,429 @@
+//! Chat commands like `!color purple`: who may use them, how often, and what they do to the
+//! lights.
+
+pub mod replies;
+
+use crate::lights::effects::{Effect, EffectKind};
+use crate::lights::{LightAction, Rgb};
+use crate::twitch::ChatMessage;
+use std::collections::HashMap;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// Who someone is in the channel, as far as their badges say. Each role can do everything the
+/// ones before it can.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Role {
+    Everyone,
+    Subscriber,
+    Vip,
+    Moderator,
+    Broadcaster,
+}
+
+impl Role {
+    pub fn of(msg: &ChatMessage) -> Self {
+        let has = |badge: &str| msg.badges.contains_key(badge);
+        if has("broadcaster") {
+            Role::Broadcaster
+        } else if has("moderator") {
+            Role::Moderator
+        } else if has("vip") {
+            Role::Vip
+        } else if has("subscriber") || has("founder") {
+            Role::Subscriber
+        } else {
+            Role::Everyone
+        }
+    }
+
+    /// as in "you need to be ..."
+    pub fn name(&self) -> &'static str {
+        match self {
+            Role::Everyone => "in chat",
+            Role::Subscriber => "a subscriber",
+            Role::Vip => "a VIP",
+            Role::Moderator => "a moderator",
+            Role::Broadcaster => "the broadcaster",
+        }
+    }
+}
+
+/// Turns whatever follows the command name into an action, or says what's wrong with it.
+pub type Parser = fn(&str) -> Result<LightAction, String>;
+
+pub struct Command {
+    /// without the `!`
+    pub name: &'static str,
+    pub aliases: &'static [&'static str],
+    pub role: Role,
+    /// between uses by anyone
+    pub global_cooldown: Duration,
+    /// between uses by the same person
+    pub user_cooldown: Duration,
+    pub parse: Parser,
+}
+
+/// Why a command didn't go through.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Rejection {
+    NotAllowed {
+        needed: Role,
+    },
+    BadArguments(String),
+    /// with how long is left
+    GlobalCooldown(Duration),
+    UserCooldown(Duration),
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Rejection::NotAllowed { needed } => write!(f, "needs to be {}", needed.name()),
+            Rejection::BadArguments(reason) => write!(f, "{}", reason),
+            Rejection::GlobalCooldown(left) => write!(f, "on cooldown for {}s", secs(*left)),
+            Rejection::UserCooldown(left) => {
+                write!(f, "on cooldown for them for {}s", secs(*left))
+            }
+        }
+    }
+}
+
+/// rounded up, so nobody's told to wait 0s
+fn secs(duration: Duration) -> u64 {
+    (duration.as_millis() as u64).div_ceil(1000)
+}
+
+/// Someone used a command, and how that went.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommandOutcome {
+    pub command: &'static str,
+    pub message: ChatMessage,
+    pub result: Result<LightAction, Rejection>,
+}
+
+#[derive(Default)]
+struct Cooldowns {
+    last_used: Option<Instant>,
+    /// keyed by user ID
+    by_user: HashMap<String, Instant>,
+}
+
+/// The registered commands, and when each was last used.
+pub struct Commands {
+    commands: Vec<(Command, Cooldowns)>,
+}
+
+impl Default for Commands {
+    fn default() -> Self {
+        let mut commands = Self::empty();
+        commands.register(Command {
+            name: "color",
+            aliases: &["colour"],
+            role: Role::Everyone,
+            global_cooldown: Duration::from_secs(5),
+            user_cooldown: Duration::from_secs(30),
+            parse: parse_color,
+        });
+        commands.register(Command {
+            name: "lights",
+            aliases: &["light"],
+            role: Role::Subscriber,
+            global_cooldown: Duration::from_secs(10),
+            user_cooldown: Duration::from_secs(60),
+            parse: parse_lights,
+        });
+        commands.register(Command {
+            name: "effect",
+            aliases: &["fx"],
+            role: Role::Everyone,
+            global_cooldown: Duration::from_secs(10),
+            user_cooldown: Duration::from_secs(60),
+            parse: parse_effect,
+        });
+        commands.register(Command {
+            name: "scene",
+            aliases: &[],
+            role: Role::Subscriber,
+            global_cooldown: Duration::from_secs(10),
+            user_cooldown: Duration::from_secs(60),
+            parse: parse_scene,
+        });
+        commands
+    }
+}
+
+impl Commands {
+    pub fn empty() -> Self {
+        Self { commands: vec![] }
+    }
+
+    pub fn register(&mut self, command: Command) {
+        self.commands.push((command, Cooldowns::default()));
+    }
+
+    /// None if the message isn't one of our commands. Cooldowns only start once a command
+    /// goes through.
+    pub fn handle(&mut self, msg: &ChatMessage, now: Instant) -> Option<CommandOutcome> {
+        let text = msg.text.trim().strip_prefix('!')?;
+        let (name, args) = text.split_once(' ').unwrap_or((text, ""));
+        let name = name.to_lowercase();
+        let (command, cooldowns) = self
+            .commands
+            .iter_mut()
+            .find(|(command, _)| command.name == name || command.aliases.contains(&&*name))?;
+
+        let result = Self::check(command, cooldowns, msg, args.trim(), now);
+        if result.is_ok() {
+            cooldowns.last_used = Some(now);
+            cooldowns.by_user.insert(msg.user_id.clone(), now);
+        }
+        Some(CommandOutcome {
+            command: command.name,
+            message: msg.clone(),
+            result,
+        })
+    }
+
+    fn check(
+        command: &Command,
+        cooldowns: &Cooldowns,
+        msg: &ChatMessage,
+        args: &str,
+        now: Instant,
+    ) -> Result<LightAction, Rejection> {
+        if Role::of(msg) < command.role {
+            return Err(Rejection::NotAllowed {
+                needed: command.role,
+            });
+        }
+        let action = (command.parse)(args).map_err(Rejection::BadArguments)?;
+
+        let left = |since: Option<&Instant>, cooldown: Duration| {
+            since
+                .map(|since| (*since + cooldown).saturating_duration_since(now))
+                .filter(|left| !left.is_zero())
+        };
+        if let Some(left) = left(cooldowns.last_used.as_ref(), command.global_cooldown) {
+            return Err(Rejection::GlobalCooldown(left));
+        }
+        if let Some(left) = left(cooldowns.by_user.get(&msg.user_id), command.user_cooldown) {
+            return Err(Rejection::UserCooldown(left));
+        }
+        Ok(action)
+    }
+}
+
+fn parse_color(args: &str) -> Result<LightAction, String> {
+    match args {
+        "" => Err("which colour? e.g. !color purple or !color #ff8000".to_string()),
+        colour => Rgb::parse(colour)
+            .map(LightAction::Color)
+            .ok_or_else(|| format!("don't know the colour {}", colour)),
+    }
+}
+
+fn parse_lights(args: &str) -> Result<LightAction, String> {
+    match args.to_lowercase().as_str() {
+        "on" => Ok(LightAction::On),
+        "off" => Ok(LightAction::Off),
+        "party" => Ok(LightAction::Party),
+        "rainbow" => Ok(LightAction::Rainbow),
+        _ => Err("try !lights on, off, party or rainbow".to_string()),
+    }
+}
+
+/// an effect's name, then its colour for effects that take one
+pub fn parse_effect(args: &str) -> Result<LightAction, String> {
+    let (name, colour) = args.split_once(' ').unwrap_or((args, ""));
+    let color = match colour.trim() {
+        "" => Rgb(255, 255, 255),
+        colour => Rgb::parse(colour).ok_or_else(|| format!("don't know the colour {}", colour))?,
+    };
+    EffectKind::from_name(&name.to_lowercase(), color)
+        .map(|kind| LightAction::Effect(Effect::from(kind)))
+        .ok_or_else(|| format!("try !effect {} plus a colour", EffectKind::NAMES.join("/")))
+}
+
+/// a scene saved on the bridge, by name
+pub fn parse_scene(args: &str) -> Result<LightAction, String> {
+    match args.trim() {
+        "" => Err("which scene? e.g. !scene relax".to_string()),
+        name => Ok(LightAction::Scene(name.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    fn message(user: &str, badges: &[&str], text: &str) -> ChatMessage {
+        ChatMessage {
+            id: format!("{}-msg", user),
+            channel: "twitchbrite".to_string(),
+            user_id: format!("{}-id", user),
+            login: user.to_string(),
+            display_name: user.to_string(),
+            text: text.to_string(),
+            badges: badges
+                .iter()
+                .map(|badge| (badge.to_string(), "1".to_string()))
+                .collect::<BTreeMap<_, _>>(),
+            bits: 0,
+        }
+    }
+
+    fn result(
+        commands: &mut Commands,
+        msg: &ChatMessage,
+        now: Instant,
+    ) -> Result<LightAction, Rejection> {
+        commands.handle(msg, now).expect("not a command").result
+    }
+
+    fn later(now: Instant) -> Instant {
+        now + Duration::from_secs(60)
+    }
+
+    #[test]
+    fn roles_come_from_badges() {
+        assert_eq!(Role::of(&message("a", &[], "")), Role::Everyone);
+        assert_eq!(Role::of(&message("a", &["founder"], "")), Role::Subscriber);
+        assert_eq!(
+            Role::of(&message("a", &["vip", "subscriber"], "")),
+            Role::Vip
+        );
+        assert_eq!(
+            Role::of(&message("a", &["moderator", "subscriber"], "")),
+            Role::Moderator
+        );
+        assert_eq!(
+            Role::of(&message("a", &["broadcaster", "subscriber"], "")),
+            Role::Broadcaster
+        );
+    }
+
+    #[test]
+    fn matches_names_and_aliases() {
+        let mut commands = Commands::default();
+        let now = Instant::now();
+
+        let msg = message("a", &[], "!COLOUR purple");
+        let outcome = commands.handle(&msg, now).unwrap();
+        assert_eq!(outcome.command, "color");
+        assert_eq!(outcome.result, Ok(LightAction::Color(Rgb(128, 0, 255))));
+
+        assert!(commands
+            .handle(&message("a", &[], "!uptime"), now)
+            .is_none());
+        assert!(commands
+            .handle(&message("a", &[], "color purple"), now)
+            .is_none());
+    }
+
+    #[test]
+    fn checks_roles() {
+        let mut commands = Commands::default();
+        let now = Instant::now();
+
+        assert_eq!(
+            result(&mut commands, &message("a", &[], "!lights party"), now),
+            Err(Rejection::NotAllowed {
+                needed: Role::Subscriber
+            })
+        );
+        assert_eq!(
+            result(&mut commands, &message("b", &["vip"], "!lights party"), now),
+            Ok(LightAction::Party)
+        );
+    }
+
+    #[test]
+    fn reports_bad_arguments() {
+        let mut commands = Commands::default();
+        let now = Instant::now();
+
+        let rejection = result(&mut commands, &message("a", &[], "!color plaid"), now);
+        assert_eq!(
+            rejection,
+            Err(Rejection::BadArguments(
+                "don't know the colour plaid".to_string()
+            ))
+        );
+        // which doesn't count as a use
+        assert!(result(&mut commands, &message("a", &[], "!color red"), now).is_ok());
+    }
+
+    #[test]
+    fn enforces_cooldowns() {
+        let mut commands = Commands::default();
+        let start = Instant::now();
+        let later = |secs| start + Duration::from_secs(secs);
+
+        assert!(result(&mut commands, &message("a", &[], "!color red"), start).is_ok());
+        assert_eq!(
+            result(&mut commands, &message("b", &[], "!color blue"), later(2)),
+            Err(Rejection::GlobalCooldown(Duration::from_secs(3)))
+        );
+        assert!(result(&mut commands, &message("b", &[], "!color blue"), later(5)).is_ok());
+        assert_eq!(
+            result(&mut commands, &message("a", &[], "!color green"), later(18)),
+            Err(Rejection::UserCooldown(Duration::from_secs(12)))
+        );
+        assert!(result(&mut commands, &message("a", &[], "!color green"), later(30)).is_ok());
+    }
+
+    #[test]
+    fn parses_effects() {
+        let mut commands = Commands::default();
+        let now = Instant::now();
+
+        let action = result(&mut commands, &message("a", &[], "!fx Pulse #00ff00"), now);
+        assert_eq!(
+            action,
+            Ok(LightAction::Effect(Effect::from(EffectKind::Pulse(Rgb(
+                0, 255, 0
+            )))))
+        );
+        let cycle = result(
+            &mut commands,
+            &message("b", &[], "!effect cycle"),
+            later(now),
+        );
+        assert_eq!(
+            cycle,
+            Ok(LightAction::Effect(Effect::from(EffectKind::ColorCycle)))
+        );
+        assert!(result(&mut commands, &message("c", &[], "!effect wobble"), now).is_err());
+    }
+
+    #[test]
+    fn parses_scenes() {
+        let mut commands = Commands::default();
+        let now = Instant::now();
+
+        let scene = result(
+            &mut commands,
+            &message("a", &["subscriber"], "!scene  Relax "),
+            now,
+        );
+        assert_eq!(scene, Ok(LightAction::Scene("Relax".to_string())));
+        assert_eq!(
+            result(&mut commands, &message("b", &["vip"], "!scene"), later(now)),
+            Err(Rejection::BadArguments(
+                "which scene? e.g. !scene relax".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn explains_rejections() {
+        let cooldown = Rejection::GlobalCooldown(Duration::from_millis(11_200));
+        assert_eq!(cooldown.to_string(), "on cooldown for 12s");
+        let role = Rejection::NotAllowed {
+            needed: Role::Subscriber,
+        };
+        assert_eq!(role.to_string(), "needs to be a subscriber");
+    }
+}
@@ -0,0 +1,174 @@
+//! What the bot says in chat when someone uses a command.
+
+use crate::commands::{secs, CommandOutcome, Rejection};
+use serde::{Deserialize, Serialize};
+
+/// One template per outcome, with `{user}`, `{command}`, `{action}`, `{role}`, `{reason}` and
+/// `{seconds}` filled in where they apply. An empty template says nothing.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ReplyTemplates {
+    /// off to keep the bot quiet altogether
+    pub enabled: bool,
+    pub accepted: String,
+    pub not_allowed: String,
+    pub bad_arguments: String,
+    pub global_cooldown: String,
+    pub user_cooldown: String,
+}
+
+impl Default for ReplyTemplates {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            accepted: "effect queued: {action}".to_string(),
+            not_allowed: "you need to be {role} to use !{command}".to_string(),
+            bad_arguments: "{reason}".to_string(),
+            global_cooldown: "!{command} is on cooldown for {seconds}s".to_string(),
+            user_cooldown: "you can use !{command} again in {seconds}s".to_string(),
+        }
+    }
+}
+
+impl ReplyTemplates {
+    /// None if there's nothing to say.
+    pub fn render(&self, outcome: &CommandOutcome) -> Option<String> {
+        if !self.enabled {
+            return None;
+        }
+
+        let template = match &outcome.result {
+            Ok(_) => &self.accepted,
+            Err(Rejection::NotAllowed { .. }) => &self.not_allowed,
+            Err(Rejection::BadArguments(_)) => &self.bad_arguments,
+            Err(Rejection::GlobalCooldown(_)) => &self.global_cooldown,
+            Err(Rejection::UserCooldown(_)) => &self.user_cooldown,
+        };
+        if template.is_empty() {
+            return None;
+        }
+
+        let mut values = vec![
+            ("user", outcome.message.display_name.clone()),
+            ("command", outcome.command.to_string()),
+        ];
+        match &outcome.result {
+            Ok(action) => values.push(("action", action.to_string())),
+            Err(Rejection::NotAllowed { needed }) => {
+                values.push(("role", needed.name().to_string()))
+            }
+            Err(Rejection::BadArguments(reason)) => values.push(("reason", reason.clone())),
+            Err(Rejection::GlobalCooldown(left) | Rejection::UserCooldown(left)) => {
+                values.push(("seconds", secs(*left).to_string()))
+            }
+        }
+        Some(fill(template, &values))
+    }
+}
+
+/// Replaces each `{key}` in one pass, so values that happen to look like placeholders (say, in
+/// someone's name) stay as they are. Unknown placeholders are left alone.
+fn fill(template: &str, values: &[(&str, String)]) -> String {
+    let mut filled = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(open) = rest.find('{') {
+        filled.push_str(&rest[..open]);
+        rest = &rest[open..];
+        let value = rest.find('}').and_then(|close| {
+            let key = &rest[1..close];
+            let (_, value) = values.iter().find(|(k, _)| *k == key)?;
+            Some((value, close))
+        });
+        match value {
+            Some((value, close)) => {
+                filled.push_str(value);
+                rest = &rest[close + 1..];
+            }
+            None => {
+                filled.push('{');
+                rest = &rest[1..];
+            }
+        }
+    }
+    filled.push_str(rest);
+    filled
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::Role;
+    use crate::lights::{LightAction, Rgb};
+    use crate::twitch::ChatMessage;
+    use std::collections::BTreeMap;
+    use std::time::Duration;
+
+    fn outcome(result: Result<LightAction, Rejection>) -> CommandOutcome {
+        CommandOutcome {
+            command: "color",
+            message: ChatMessage {
+                id: "abc".to_string(),
+                channel: "dwbrite".to_string(),
+                user_id: "1234".to_string(),
+                login: "viewer".to_string(),
+                display_name: "Viewer".to_string(),
+                text: "!color purple".to_string(),
+                badges: BTreeMap::new(),
+                bits: 0,
+            },
+            result,
+        }
+    }
+
+    #[test]
+    fn fills_in_the_templates() {
+        let templates = ReplyTemplates::default();
+        let render = |result| templates.render(&outcome(result)).unwrap();
+
+        assert_eq!(
+            render(Ok(LightAction::Color(Rgb(128, 0, 255)))),
+            "effect queued: turn the lights purple"
+        );
+        assert_eq!(
+            render(Err(Rejection::GlobalCooldown(Duration::from_millis(
+                11_500
+            )))),
+            "!color is on cooldown for 12s"
+        );
+        assert_eq!(
+            render(Err(Rejection::NotAllowed {
+                needed: Role::Subscriber
+            })),
+            "you need to be a subscriber to use !color"
+        );
+    }
+
+    #[test]
+    fn can_be_quiet() {
+        let mut templates = ReplyTemplates {
+            accepted: String::new(),
+            ..ReplyTemplates::default()
+        };
+        let accepted = outcome(Ok(LightAction::On));
+        let rejected = outcome(Err(Rejection::BadArguments("nope".to_string())));
+        assert_eq!(templates.render(&accepted), None);
+        assert_eq!(templates.render(&rejected).as_deref(), Some("nope"));
+
+        templates.enabled = false;
+        assert_eq!(templates.render(&rejected), None);
+    }
+
+    #[test]
+    fn user_names_go_in_as_is() {
+        let templates = ReplyTemplates {
+            accepted: "thanks {user}, {action} {unknown}".to_string(),
+            ..ReplyTemplates::default()
+        };
+        let mut accepted = outcome(Ok(LightAction::On));
+        accepted.message.display_name = "{action}".to_string();
+        assert_eq!(
+            templates.render(&accepted).as_deref(),
+            Some("thanks {action}, turn the lights on {unknown}")
+        );
+    }
+}
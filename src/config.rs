@@ -1,29 +1,56 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
 
 use rand::distributions::Alphanumeric;
 use rand::{thread_rng, Rng};
 use serde::{Deserialize, Serialize};
 
+use std::collections::BTreeMap;
+use std::io;
+use std::net::SocketAddr;
 use std::path::PathBuf;
 
-use hueclient::Bridge;
+use crate::commands::replies::ReplyTemplates;
+use crate::hue;
+use crate::hue::{Bridge, UnauthBridge};
+use crate::lights::effect_queue::EffectsConfig;
+use crate::lights::layout::Layout;
+use crate::twitch;
 use std::ops::{Deref, DerefMut};
 use std::{env, fs};
 
+#[derive(Clone)]
 pub struct ValidatedBridge {
     device_type: String, // honestly this is entirely unnecessary
+    bridge_id: String,
+    address: SocketAddr,
     bridge: Bridge,
 }
 
 impl ValidatedBridge {
-    pub fn from_bridge(bridge: Bridge, device_type: String) -> Result<Self> {
+    pub fn from_bridge(
+        bridge: Bridge,
+        address: SocketAddr,
+        device_type: String,
+        bridge_id: String,
+    ) -> Result<Self> {
         bridge.get_all_lights()?;
 
         Ok(Self {
             bridge,
             device_type,
+            bridge_id,
+            address,
         })
     }
+
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    /// the bridge's own ID, as reported by `/api/config`
+    pub fn id(&self) -> &str {
+        &self.bridge_id
+    }
 }
 
 impl Deref for ValidatedBridge {
@@ -43,8 +70,15 @@ impl DerefMut for ValidatedBridge {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BridgeConfig {
     device_type: String, // honestly this is entirely unnecessary
+    /// empty for a bridge moved over from an older config, until it's been asked
+    bridge_id: String,
     bridge_ip: std::net::IpAddr,
+    #[serde(default = "BridgeConfig::default_port")]
+    bridge_port: u16,
     bridge_username: String,
+    /// for streaming effects, if the bridge handed one out
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    bridge_clientkey: Option<String>,
 }
 
 impl BridgeConfig {
@@ -54,23 +88,145 @@ impl BridgeConfig {
         format!("twitchbrite#{}", chars)
     }
 
+    fn default_port() -> u16 {
+        hue::HUE_HTTP_PORT
+    }
+
+    pub fn address(&self) -> SocketAddr {
+        SocketAddr::new(self.bridge_ip, self.bridge_port)
+    }
+
+    /// reconnect to the bridge with the saved credentials, checking that they're still accepted
+    pub fn validate(&self) -> Result<ValidatedBridge> {
+        let base_url = hue::base_url(self.address());
+        let bridge_id = match self.bridge_id.is_empty() {
+            true => hue::fetch_bridge_info(&base_url)?.bridgeid,
+            false => self.bridge_id.clone(),
+        };
+        let bridge = UnauthBridge::new(base_url)
+            .with_user(&self.bridge_username)
+            .with_clientkey(self.bridge_clientkey.clone());
+        ValidatedBridge::from_bridge(bridge, self.address(), self.device_type.clone(), bridge_id)
+    }
+
     /// create a bridge config from an _authorized_ Bridge
     pub fn from_validated_bridge(bridge: &ValidatedBridge) -> Self {
         BridgeConfig {
             device_type: bridge.device_type.clone(),
-            bridge_ip: bridge.ip,
+            bridge_id: bridge.bridge_id.clone(),
+            bridge_ip: bridge.address.ip(),
+            bridge_port: bridge.address.port(),
             bridge_username: bridge.username.clone(),
+            bridge_clientkey: bridge.clientkey.clone(),
+        }
+    }
+}
+
+/// How the one bridge was saved before there could be several.
+#[derive(Debug, Clone, Deserialize)]
+struct LegacyBridgeConfig {
+    device_type: String,
+    bridge_ip: std::net::IpAddr,
+    bridge_username: String,
+}
+
+impl From<LegacyBridgeConfig> for BridgeConfig {
+    fn from(legacy: LegacyBridgeConfig) -> Self {
+        BridgeConfig {
+            device_type: legacy.device_type,
+            bridge_id: String::new(),
+            bridge_ip: legacy.bridge_ip,
+            bridge_port: BridgeConfig::default_port(),
+            bridge_username: legacy.bridge_username,
+            bridge_clientkey: None,
         }
     }
 }
 
+/// What the device code login leaves us with.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TwitchTokens {
+    pub access_token: String,
+    pub refresh_token: String,
+    pub user_id: String,
+    pub login: String,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct TwitchConfig {}
+#[serde(default)]
+pub struct TwitchConfig {
+    /// the channel whose chat drives the lights, without the #.
+    /// Defaults to the logged in user's own channel
+    pub channel: Option<String>,
+    pub chat_host: String,
+    /// where the OAuth endpoints live
+    pub auth_url: String,
+    /// point these two at the Twitch CLI's mock server to test without going live, e.g.
+    /// `ws://127.0.0.1:8080/ws` and `http://127.0.0.1:8080`
+    pub eventsub_url: String,
+    pub helix_url: String,
+    /// only needed when twitchbrite wasn't built with a client ID of its own
+    pub client_id: Option<String>,
+    pub tokens: Option<TwitchTokens>,
+    /// what the bot says back to chat commands
+    pub replies: ReplyTemplates,
+}
+
+impl Default for TwitchConfig {
+    fn default() -> Self {
+        Self {
+            channel: None,
+            chat_host: twitch::chat::TMI_HOST.to_string(),
+            auth_url: twitch::auth::TWITCH_AUTH_URL.to_string(),
+            eventsub_url: twitch::eventsub::EVENTSUB_URL.to_string(),
+            helix_url: twitch::helix::HELIX_URL.to_string(),
+            client_id: None,
+            tokens: None,
+            replies: ReplyTemplates::default(),
+        }
+    }
+}
+
+impl TwitchConfig {
+    pub fn client_id(&self) -> Option<&str> {
+        self.client_id
+            .as_deref()
+            .or(twitch::auth::BUILT_IN_CLIENT_ID)
+    }
+
+    /// the configured channel, or else the logged in user's
+    pub fn channel(&self) -> Option<&str> {
+        self.channel
+            .as_deref()
+            .or_else(|| self.tokens.as_ref().map(|tokens| tokens.login.as_str()))
+    }
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
-    bridge_config: Option<BridgeConfig>,
-    twitch_config: Option<TwitchConfig>,
+    /// keyed by bridge ID
+    #[serde(default)]
+    bridges: BTreeMap<String, BridgeConfig>,
+    #[serde(default)]
+    twitch_config: TwitchConfig,
+    /// which effects Twitch events play, and which effects wait for which
+    #[serde(default)]
+    effects: EffectsConfig,
+    /// where the lights are in the room
+    #[serde(default)]
+    layout: Layout,
+    /// read from configs saved before `bridges`, and moved into it on load
+    #[serde(default, skip_serializing)]
+    bridge_config: Option<LegacyBridgeConfig>,
+    /// where `save` writes to, or nowhere for a config that only lives in memory
+    #[serde(skip)]
+    path: Option<PathBuf>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Config {
@@ -84,20 +240,181 @@ impl Config {
 
     pub fn new() -> Self {
         Self {
+            bridges: BTreeMap::new(),
+            twitch_config: TwitchConfig::default(),
+            effects: EffectsConfig::default(),
+            layout: Layout::default(),
             bridge_config: None,
-            twitch_config: None,
+            path: Some(Self::get_config_path()),
+        }
+    }
+
+    /// a config that's never written to disk, for tests
+    #[cfg(test)]
+    pub fn in_memory() -> Self {
+        Self {
+            path: None,
+            ..Self::new()
+        }
+    }
+
+    pub fn bridge_configs(&self) -> impl Iterator<Item = &BridgeConfig> {
+        self.bridges.values()
+    }
+
+    pub fn twitch_config(&self) -> &TwitchConfig {
+        &self.twitch_config
+    }
+
+    pub fn twitch_config_mut(&mut self) -> &mut TwitchConfig {
+        &mut self.twitch_config
+    }
+
+    pub fn effects_config(&self) -> &EffectsConfig {
+        &self.effects
+    }
+
+    pub fn effects_config_mut(&mut self) -> &mut EffectsConfig {
+        &mut self.effects
+    }
+
+    pub fn layout(&self) -> &Layout {
+        &self.layout
+    }
+
+    pub fn set_layout(&mut self, layout: Layout) {
+        self.layout = layout;
+    }
+
+    /// adds the bridge, or replaces the saved credentials for a bridge with the same ID
+    pub fn set_bridge_config(&mut self, bridge_config: BridgeConfig) {
+        self.bridges
+            .insert(bridge_config.bridge_id.clone(), bridge_config);
+    }
+
+    /// Saves a bridge moved over from an older config under its ID, once it's connected and
+    /// the ID is known. Returns true if there was one to save.
+    pub fn identify_bridges(&mut self, bridges: &BTreeMap<String, ValidatedBridge>) -> bool {
+        let address = match self.bridges.get("") {
+            Some(unidentified) => unidentified.address(),
+            None => return false,
+        };
+        match bridges.values().find(|bridge| bridge.address() == address) {
+            Some(bridge) => {
+                self.bridges.remove("");
+                self.set_bridge_config(BridgeConfig::from_validated_bridge(bridge));
+                true
+            }
+            None => false,
         }
     }
 
+    /// The saved config, or a fresh one if nothing's been saved yet. A config that's there
+    /// but can't be read is an error, rather than something the next save would overwrite,
+    /// bridge credentials and Twitch tokens and all.
     pub fn load() -> Result<Self> {
-        let config_path = Self::get_config_path();
-        let result = fs::read(&config_path)?;
-        Ok(toml::from_slice(result.as_slice())?)
+        Self::load_from(Self::get_config_path())
+    }
+
+    fn load_from(config_path: PathBuf) -> Result<Self> {
+        let result = match fs::read(&config_path) {
+            Ok(result) => result,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                return Ok(Self {
+                    path: Some(config_path),
+                    ..Self::new()
+                })
+            }
+            Err(e) => {
+                return Err(e).with_context(|| format!("Couldn't read {}", config_path.display()))
+            }
+        };
+        let mut config: Config = toml::from_slice(result.as_slice()).with_context(|| {
+            format!(
+                "{} isn't a config twitchbrite can read; fix it or move it out of the way",
+                config_path.display()
+            )
+        })?;
+        if let Some(legacy) = config.bridge_config.take() {
+            config.bridges.insert(String::new(), legacy.into());
+        }
+        Ok(Self {
+            path: Some(config_path),
+            ..config
+        })
     }
 
     pub fn save(&self) {
-        let config_path = Self::get_config_path();
+        let config_path = match &self.path {
+            Some(path) => path,
+            None => return,
+        };
         let bytes = toml::to_vec(&self).expect("Failed to serialize config.");
         fs::write(config_path, bytes).expect("Failed to save config to disk.");
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hue::mock::MockBridge;
+
+    fn scratch(name: &str) -> PathBuf {
+        let path =
+            env::temp_dir().join(format!("twitchbrite-{}-{}.toml", name, std::process::id()));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn only_starts_fresh_without_a_config() {
+        let missing = scratch("missing");
+        let config = Config::load_from(missing.clone()).unwrap();
+        assert!(config.bridges.is_empty());
+        assert_eq!(config.path, Some(missing));
+
+        let broken = scratch("broken");
+        fs::write(&broken, "[bridges.abc\nusername = ").unwrap();
+        let e = Config::load_from(broken.clone()).unwrap_err();
+        assert!(format!("{:#}", e).contains("isn't a config twitchbrite can read"));
+        // and it's still there to be fixed
+        assert!(fs::read(&broken).unwrap().starts_with(b"[bridges.abc"));
+        fs::remove_file(&broken).unwrap();
+
+        // a directory can't be read as a file, but it's not missing either
+        assert!(Config::load_from(env::temp_dir()).is_err());
+    }
+
+    #[test]
+    fn moves_the_bridge_over_from_older_configs() {
+        let mock = MockBridge::start();
+        let username = mock.registered_bridge().username;
+        let path = scratch("legacy");
+        let legacy = format!(
+            "[bridge_config]\ndevice_type = \"twitchbrite#abcde\"\nbridge_ip = \"127.0.0.1\"\n\
+             bridge_username = \"{}\"\n\n[twitch_config]\n",
+            username
+        );
+        fs::write(&path, legacy).unwrap();
+
+        let mut config = Config::load_from(path.clone()).unwrap();
+        fs::remove_file(&path).unwrap();
+        let migrated = config.bridges.get_mut("").unwrap();
+        assert_eq!(migrated.bridge_username, username);
+        assert_eq!(migrated.address().port(), hue::HUE_HTTP_PORT);
+        // the mock isn't on port 80
+        migrated.bridge_port = mock.address().port();
+
+        let bridge = config.bridge_configs().next().unwrap().validate().unwrap();
+        assert_eq!(bridge.id(), mock.info().bridgeid);
+        let bridges = BTreeMap::from([(bridge.id().to_string(), bridge)]);
+        assert!(config.identify_bridges(&bridges));
+        assert!(!config.identify_bridges(&bridges));
+
+        let saved = toml::to_string(&config).unwrap();
+        assert!(!saved.contains("[bridge_config]"));
+        let ids: Vec<_> = config.bridges.keys().cloned().collect();
+        assert_eq!(ids, [mock.info().bridgeid]);
+        assert_eq!(config.bridges[&ids[0]].bridge_username, username);
+    }
+}
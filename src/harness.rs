@@ -0,0 +1,230 @@
+//! Runs the whole app headless on tui's `TestBackend`. Ticks only advance when a test asks,
+//! input and app messages are injected directly, and the screen can be read back as text.
+//! Twitch is faked too: chat and login talk to servers the harness owns.
+//!
+//! Background tasks still run on real threads, so tests (here and in every other module)
+//! wait for them with `wait_for` and `wait_until`, up to `PATIENCE`.
+
+use crate::config::{Config, TwitchTokens, ValidatedBridge};
+use crate::hue::Discovery;
+use crate::twitch::mock::{
+    FakeIrcServer, MockEventSub, MockTwitchAuth, MOCK_CLIENT_ID, MOCK_LOGIN, MOCK_USER_ID,
+};
+use crate::twitch::TwitchEvent;
+use crate::{AppMsg, Mode, TwitchBrite};
+use crossterm::event::{Event, KeyCode, KeyEvent, KeyModifiers};
+use std::thread;
+use std::time::{Duration, Instant};
+use tui::backend::TestBackend;
+use tui::buffer::Buffer;
+use tui::Terminal;
+
+/// how long tests wait on background tasks before failing
+pub const PATIENCE: Duration = Duration::from_secs(5);
+/// how often a wait checks again
+const POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+/// Checks every few milliseconds until `ready` gives something back. `None` if that took
+/// longer than `PATIENCE`, for the caller to fail with whatever explains it best.
+#[must_use]
+pub fn wait_for<T>(mut ready: impl FnMut() -> Option<T>) -> Option<T> {
+    let deadline = Instant::now() + PATIENCE;
+    loop {
+        if let Some(found) = ready() {
+            return Some(found);
+        }
+        if Instant::now() >= deadline {
+            return None;
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+/// `wait_for` something that's either done or not, false if it never was.
+#[must_use]
+pub fn wait_until(mut done: impl FnMut() -> bool) -> bool {
+    wait_for(|| done().then_some(())).is_some()
+}
+
+pub struct Harness {
+    app: TwitchBrite<TestBackend>,
+    /// where the app's chat connection goes
+    pub chat: FakeIrcServer,
+    /// where the app logs in to Twitch
+    pub auth: MockTwitchAuth,
+    /// where the app listens for EventSub events once logged in
+    pub eventsub: MockEventSub,
+}
+
+impl Harness {
+    /// A fresh install: no saved bridges and nobody logged in, so the app starts in setup
+    /// and looks for bridges with `discovery`.
+    pub fn new(width: u16, height: u16, discovery: Discovery) -> Self {
+        Self::build(width, height, vec![], discovery, false)
+    }
+
+    /// An app that's already set up with these bridges and logged in to Twitch, so it starts
+    /// out running.
+    pub fn with_bridges(width: u16, height: u16, bridges: Vec<ValidatedBridge>) -> Self {
+        Self::build(width, height, bridges, Discovery::Addresses(vec![]), true)
+    }
+
+    fn build(
+        width: u16,
+        height: u16,
+        bridges: Vec<ValidatedBridge>,
+        discovery: Discovery,
+        logged_in: bool,
+    ) -> Self {
+        let terminal = Terminal::new(TestBackend::new(width, height)).unwrap();
+        let bridges = bridges
+            .into_iter()
+            .map(|bridge| (bridge.id().to_string(), bridge))
+            .collect();
+
+        let chat = FakeIrcServer::start();
+        let auth = MockTwitchAuth::start();
+        let eventsub = MockEventSub::start();
+
+        let mut config = Config::in_memory();
+        let twitch_config = config.twitch_config_mut();
+        twitch_config.chat_host = chat.address().to_string();
+        twitch_config.auth_url = auth.base_url();
+        twitch_config.client_id = Some(MOCK_CLIENT_ID.to_string());
+        twitch_config.eventsub_url = eventsub.ws_url();
+        twitch_config.helix_url = eventsub.helix_url();
+        if logged_in {
+            let (access_token, refresh_token) = auth.issue_tokens();
+            twitch_config.tokens = Some(TwitchTokens {
+                access_token,
+                refresh_token,
+                user_id: MOCK_USER_ID.to_string(),
+                login: MOCK_LOGIN.to_string(),
+            });
+        }
+
+        let mut app = TwitchBrite::new(terminal, config, bridges, discovery);
+        app.connect_twitch();
+
+        Self {
+            app,
+            chat,
+            auth,
+            eventsub,
+        }
+    }
+
+    /// One pass of the app loop, minus the input polling and the sleep.
+    pub fn tick(&mut self) {
+        self.app.tick();
+        self.app.draw().unwrap();
+    }
+
+    pub fn ticks(&mut self, n: u64) {
+        for _ in 0..n {
+            self.tick();
+        }
+    }
+
+    /// Ticks until `done` holds. Background tasks run on real threads, so this is how a
+    /// test waits for them, failing if they take unreasonably long.
+    pub fn tick_until(&mut self, done: impl Fn(&Self) -> bool) {
+        let finished = wait_until(|| {
+            if done(self) {
+                return true;
+            }
+            self.tick();
+            false
+        });
+        assert!(
+            finished,
+            "gave up waiting, the screen was:\n{}",
+            self.screen().join("\n")
+        );
+    }
+
+    pub fn input(&mut self, event: Event) {
+        self.app.input(&event);
+    }
+
+    pub fn key(&mut self, code: KeyCode) {
+        self.input(Event::Key(KeyEvent::new(code, KeyModifiers::NONE)));
+    }
+
+    pub fn ctrl(&mut self, c: char) {
+        self.input(Event::Key(KeyEvent::new(
+            KeyCode::Char(c),
+            KeyModifiers::CONTROL,
+        )));
+    }
+
+    pub fn type_str(&mut self, text: &str) {
+        for c in text.chars() {
+            self.key(KeyCode::Char(c));
+        }
+    }
+
+    /// Queues a Twitch event as if it had come in over chat. It's handled on the next tick.
+    pub fn twitch(&self, event: TwitchEvent) {
+        self.app.twitch_ch.0.send(event).unwrap();
+    }
+
+    /// Hands the app an event as if it came in over EventSub.
+    pub fn eventsub_event(&self, event: TwitchEvent) {
+        self.app.eventsub_ch.0.send(event).unwrap();
+    }
+
+    /// Queues a message as if an activity had sent it. It's handled on the next tick.
+    pub fn send(&self, msg: AppMsg) {
+        self.app.channel.0.send(msg).unwrap();
+    }
+
+    /// Connects to Twitch again, as the app does on startup.
+    pub fn restart_twitch(&mut self) {
+        self.app.connect_twitch();
+    }
+
+    pub fn mode(&self) -> Mode {
+        self.app.mode
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.app.state.config
+    }
+
+    pub fn should_stop(&self) -> bool {
+        self.app.state.should_stop
+    }
+
+    pub fn buffer(&self) -> &Buffer {
+        self.app.terminal.backend().buffer()
+    }
+
+    /// what was on screen after the last tick
+    pub fn screen(&self) -> Vec<String> {
+        lines(self.buffer())
+    }
+
+    pub fn shows(&self, text: &str) -> bool {
+        self.screen().iter().any(|line| line.contains(text))
+    }
+}
+
+impl Drop for Harness {
+    fn drop(&mut self) {
+        // hang up on the fake Twitch before it goes away
+        self.app.disconnect_twitch();
+    }
+}
+
+/// A buffer's symbols, one string per row, for comparing against what a widget should draw.
+pub fn lines(buf: &Buffer) -> Vec<String> {
+    let area = buf.area;
+    (area.top()..area.bottom())
+        .map(|y| {
+            (area.left()..area.right())
+                .map(|x| buf.get(x, y).symbol.as_str())
+                .collect()
+        })
+        .collect()
+}
@@ -0,0 +1,322 @@
+//! The Entertainment API, which streams colours straight to the lights over DTLS instead of
+//! queueing REST commands. REST manages about 10 commands a second; a stream can change every
+//! light in an area 25 to 50 times a second, which is what strobes need to look right.
+//!
+//! This is the v1 flavour, to match the rest of the client: an entertainment area is a group
+//! of type "Entertainment", streaming is switched on through the group's `stream` attribute,
+//! and the stream itself is DTLS 1.2 with the username as the PSK identity and the clientkey
+//! handed out at registration as the key.
+//! https://developers.meethue.com/develop/hue-entertainment/hue-entertainment-api/
+
+use crate::config::ValidatedBridge;
+use crate::hue::groups::GroupKind;
+use crate::hue::Bridge;
+use crate::lights::Rgb;
+use anyhow::{anyhow, Context};
+use openssl::ssl::{Ssl, SslContext, SslMethod, SslOptions, SslStream, SslVersion};
+use serde_json::{json, Value};
+use std::collections::BTreeSet;
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// 25 frames a second, the slow end of what the bridge expects
+pub const STREAM_INTERVAL: Duration = Duration::from_millis(40);
+/// the only cipher suite the bridge offers
+pub const CIPHER: &str = "PSK-AES128-GCM-SHA256";
+/// how long the bridge gets to answer each step of the handshake
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(3);
+/// well under any MTU, and far more than a frame for 10 lights needs
+const MTU: u32 = 1200;
+const HEADER: &[u8] = b"HueStream";
+
+/// The areas being streamed to, by bridge ID and area ID. The bridge only takes one stream
+/// per area, so a second one would fail or knock the first off.
+static STREAMING: Mutex<BTreeSet<(String, usize)>> = Mutex::new(BTreeSet::new());
+
+/// A group of lights set up for streaming in the Hue app.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EntertainmentArea {
+    pub id: usize,
+    pub name: String,
+    pub lights: Vec<usize>,
+}
+
+impl Bridge {
+    /// sorted by ID
+    pub fn get_entertainment_areas(&self) -> hueclient::Result<Vec<EntertainmentArea>> {
+        Ok(self
+            .get_groups()?
+            .into_iter()
+            .filter(|group| group.kind == GroupKind::Entertainment)
+            .map(|group| EntertainmentArea {
+                id: group.id,
+                name: group.name,
+                lights: group.lights,
+            })
+            .collect())
+    }
+
+    /// While an area is streaming, its lights ignore REST commands.
+    pub fn set_streaming(&self, area: usize, active: bool) -> hueclient::Result<Value> {
+        let body = json!({ "stream": { "active": active } });
+        self.put(&format!("groups/{}", area), &body)
+    }
+}
+
+/// One frame: every light's colour, in 16 bits a channel.
+pub fn message(sequence: u8, colors: &[(usize, Rgb)]) -> Vec<u8> {
+    let mut message = HEADER.to_vec();
+    // version 1.0, the sequence number, two reserved bytes, RGB, another reserved byte
+    message.extend([0x01, 0x00, sequence, 0x00, 0x00, 0x00, 0x00]);
+    for (light, Rgb(r, g, b)) in colors {
+        // a light rather than a gradient strip's segment
+        message.push(0x00);
+        message.extend((*light as u16).to_be_bytes());
+        for channel in [r, g, b] {
+            message.extend((*channel as u16 * 257).to_be_bytes());
+        }
+    }
+    message
+}
+
+/// A UDP socket that reads and writes datagrams to and from one peer, which is what DTLS
+/// expects underneath it.
+#[derive(Debug)]
+pub struct Datagrams {
+    socket: UdpSocket,
+    peer: SocketAddr,
+}
+
+impl Datagrams {
+    pub fn new(socket: UdpSocket, peer: SocketAddr) -> Self {
+        Self { socket, peer }
+    }
+}
+
+impl Read for Datagrams {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let (len, from) = self.socket.recv_from(buf)?;
+            if from == self.peer {
+                return Ok(len);
+            }
+        }
+    }
+}
+
+impl Write for Datagrams {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.socket.send_to(buf, self.peer)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// An area's place in `STREAMING`, given up when dropped.
+struct Claim((String, usize));
+
+impl Claim {
+    fn take(bridge: &ValidatedBridge, area: &EntertainmentArea) -> anyhow::Result<Self> {
+        let key = (bridge.id().to_string(), area.id);
+        match STREAMING.lock().unwrap().insert(key.clone()) {
+            true => Ok(Self(key)),
+            false => Err(anyhow!("{} is already being streamed to", area.name)),
+        }
+    }
+}
+
+impl Drop for Claim {
+    fn drop(&mut self) {
+        STREAMING.lock().unwrap().remove(&self.0);
+    }
+}
+
+/// A stream to one entertainment area. The area goes back to taking REST commands when it's
+/// dropped.
+pub struct EntertainmentStream {
+    bridge: Bridge,
+    area: EntertainmentArea,
+    stream: SslStream<Datagrams>,
+    sequence: u8,
+    /// dropped last, once the area's been switched back
+    _claim: Claim,
+}
+
+impl EntertainmentStream {
+    /// Switches streaming on for `area` and shakes hands with the bridge. Fails if something
+    /// else is already streaming to it.
+    pub fn open(bridge: &ValidatedBridge, area: EntertainmentArea) -> anyhow::Result<Self> {
+        let key = bridge
+            .clientkey
+            .as_deref()
+            .ok_or_else(|| anyhow!("set the bridge up again to stream to it"))?;
+        let key = decode_hex(key).ok_or_else(|| anyhow!("the clientkey isn't hex"))?;
+        let claim = Claim::take(bridge, &area)?;
+
+        bridge
+            .set_streaming(area.id, true)
+            .with_context(|| format!("Couldn't start streaming to {}", area.name))?;
+        let address = SocketAddr::new(bridge.address().ip(), bridge.stream_port);
+        match handshake(address, &bridge.username, key) {
+            Ok(stream) => Ok(Self {
+                bridge: (**bridge).clone(),
+                area,
+                stream,
+                sequence: 0,
+                _claim: claim,
+            }),
+            Err(e) => {
+                let _ = bridge.set_streaming(area.id, false);
+                Err(e.context(format!("Couldn't stream to {}", area.name)))
+            }
+        }
+    }
+
+    pub fn area(&self) -> &EntertainmentArea {
+        &self.area
+    }
+
+    /// Sends a frame. Lights in the area that aren't in `colors` keep what they had.
+    pub fn send(&mut self, colors: &[(usize, Rgb)]) -> anyhow::Result<()> {
+        self.stream.write_all(&message(self.sequence, colors))?;
+        self.sequence = self.sequence.wrapping_add(1);
+        Ok(())
+    }
+}
+
+impl Drop for EntertainmentStream {
+    fn drop(&mut self) {
+        let _ = self.stream.shutdown();
+        let _ = self.bridge.set_streaming(self.area.id, false);
+    }
+}
+
+fn handshake(
+    address: SocketAddr,
+    identity: &str,
+    key: Vec<u8>,
+) -> anyhow::Result<SslStream<Datagrams>> {
+    let mut context = SslContext::builder(SslMethod::dtls())?;
+    context.set_min_proto_version(Some(SslVersion::DTLS1_2))?;
+    context.set_cipher_list(CIPHER)?;
+    // the MTU's set below, since there's no real datagram BIO to ask
+    context.set_options(SslOptions::NO_QUERY_MTU);
+    let identity = identity.as_bytes().to_vec();
+    context.set_psk_client_callback(move |_, _, identity_out, psk_out| {
+        // the identity goes out NUL-terminated
+        if identity.len() >= identity_out.len() || key.len() > psk_out.len() {
+            return Ok(0);
+        }
+        identity_out[..identity.len()].copy_from_slice(&identity);
+        identity_out[identity.len()] = 0;
+        psk_out[..key.len()].copy_from_slice(&key);
+        Ok(key.len())
+    });
+
+    let mut ssl = Ssl::new(&context.build())?;
+    ssl.set_mtu(MTU)?;
+    let unspecified = match address.ip() {
+        IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+    };
+    let socket = UdpSocket::bind(SocketAddr::new(unspecified, 0))?;
+    socket.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+    ssl.connect(Datagrams::new(socket, address))
+        .map_err(|e| anyhow!("DTLS handshake failed: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hue::mock::MockBridge;
+
+    #[test]
+    fn lays_out_frames() {
+        let message = message(7, &[(2, Rgb(255, 0, 128)), (10, Rgb(0, 1, 0))]);
+        assert_eq!(&message[..16], b"HueStream\x01\x00\x07\x00\x00\x00\x00");
+        assert_eq!(
+            &message[16..25],
+            [0x00, 0x00, 0x02, 0xff, 0xff, 0x00, 0x00, 0x80, 0x80]
+        );
+        assert_eq!(
+            &message[25..],
+            [0x00, 0x00, 0x0a, 0x00, 0x00, 0x01, 0x01, 0x00, 0x00]
+        );
+    }
+
+    #[test]
+    fn streams_to_an_entertainment_area() {
+        let mut mock = MockBridge::start();
+        let id = mock.add_entertainment_area("TV area", &[1, 2]);
+        let bridge = mock.validated_bridge();
+
+        let areas = bridge.get_entertainment_areas().unwrap();
+        assert_eq!(
+            areas,
+            [EntertainmentArea {
+                id,
+                name: "TV area".to_string(),
+                lights: vec![1, 2],
+            }]
+        );
+
+        let mut stream = EntertainmentStream::open(&bridge, areas[0].clone()).unwrap();
+        assert!(mock.is_streaming(id));
+        stream.send(&[(1, Rgb(255, 0, 0))]).unwrap();
+        stream.send(&[(2, Rgb(0, 0, 255))]).unwrap();
+        let frames = mock.wait_for_frames(2);
+        assert_eq!(frames[0], [(1, [65535, 0, 0])]);
+        assert_eq!(frames[1], [(2, [0, 0, 65535])]);
+
+        drop(stream);
+        assert!(!mock.is_streaming(id));
+    }
+
+    #[test]
+    fn streams_to_an_area_once_at_a_time() {
+        let mut mock = MockBridge::start();
+        mock.add_entertainment_area("TV area", &[1, 2]);
+        let bridge = mock.validated_bridge();
+        let area = bridge.get_entertainment_areas().unwrap().remove(0);
+
+        let first = EntertainmentStream::open(&bridge, area.clone()).unwrap();
+        let second = EntertainmentStream::open(&bridge, area.clone());
+        assert_eq!(
+            second.err().unwrap().to_string(),
+            "TV area is already being streamed to"
+        );
+
+        drop(first);
+        assert!(EntertainmentStream::open(&bridge, area).is_ok());
+    }
+
+    #[test]
+    fn needs_the_right_key() {
+        let mut mock = MockBridge::start();
+        let id = mock.add_entertainment_area("TV area", &[1, 2]);
+        let mut bridge = mock.validated_bridge();
+        let area = bridge.get_entertainment_areas().unwrap().remove(0);
+
+        bridge.clientkey = None;
+        assert!(EntertainmentStream::open(&bridge, area.clone()).is_err());
+
+        bridge.clientkey = Some("00".repeat(16));
+        assert!(EntertainmentStream::open(&bridge, area).is_err());
+        // and it doesn't leave the area stuck streaming
+        assert!(!mock.is_streaming(id));
+    }
+}
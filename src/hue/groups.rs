@@ -0,0 +1,190 @@
+//! Groups and scenes, as set up in the Hue app. To the v1 API, rooms and zones are groups
+//! like any other, and a scene is a look saved on the bridge for some of its lights.
+//! https://developers.meethue.com/develop/hue-api/groupds-api/
+
+use crate::hue::{protocol_err, Bridge, BridgeResponse};
+use hueclient::CommandLight;
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+/// the group bridges keep with every light in it
+pub const ALL_LIGHTS: usize = 0;
+
+/// What a group is for, going by its type.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum GroupKind {
+    Room,
+    Zone,
+    /// lights grouped by some other app
+    LightGroup,
+    Entertainment,
+    /// the bridge's own, like a multi-source luminaire's
+    Other,
+}
+
+impl GroupKind {
+    fn of(r#type: &str) -> Self {
+        match r#type {
+            "Room" => GroupKind::Room,
+            "Zone" => GroupKind::Zone,
+            "LightGroup" => GroupKind::LightGroup,
+            "Entertainment" => GroupKind::Entertainment,
+            _ => GroupKind::Other,
+        }
+    }
+}
+
+impl fmt::Display for GroupKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            GroupKind::Room => "room",
+            GroupKind::Zone => "zone",
+            GroupKind::LightGroup | GroupKind::Other => "group",
+            GroupKind::Entertainment => "entertainment area",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Group {
+    pub id: usize,
+    pub name: String,
+    pub kind: GroupKind,
+    pub lights: Vec<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Scene {
+    /// made up by the bridge, e.g. "4e1c6b20e-on-0"
+    pub id: String,
+    pub name: String,
+    /// the group it was saved for, if it was saved for one rather than a handful of lights
+    pub group: Option<usize>,
+    pub lights: Vec<usize>,
+}
+
+fn number(id: &str) -> hueclient::Result<usize> {
+    usize::from_str(id).map_err(|_| protocol_err("ID isn't a number"))
+}
+
+fn numbers(ids: &[String]) -> hueclient::Result<Vec<usize>> {
+    ids.iter().map(|id| number(id)).collect()
+}
+
+impl Bridge {
+    /// Rooms, zones and the rest, sorted by ID. Group 0 isn't listed, since it's always there.
+    pub fn get_groups(&self) -> hueclient::Result<Vec<Group>> {
+        #[derive(Deserialize)]
+        struct Listed {
+            name: String,
+            r#type: String,
+            lights: Vec<String>,
+        }
+
+        let resp: BridgeResponse<HashMap<String, Listed>> =
+            self.client.get(&self.url("groups")).send()?.json()?;
+
+        let mut groups = vec![];
+        for (id, group) in resp.get()? {
+            groups.push(Group {
+                id: number(&id)?,
+                name: group.name,
+                kind: GroupKind::of(&group.r#type),
+                lights: numbers(&group.lights)?,
+            });
+        }
+        groups.sort_by_key(|group| group.id);
+        Ok(groups)
+    }
+
+    /// sorted by name
+    pub fn get_scenes(&self) -> hueclient::Result<Vec<Scene>> {
+        #[derive(Deserialize)]
+        struct Listed {
+            name: String,
+            /// only for scenes of type "GroupScene"
+            group: Option<String>,
+            lights: Vec<String>,
+        }
+
+        let resp: BridgeResponse<HashMap<String, Listed>> =
+            self.client.get(&self.url("scenes")).send()?.json()?;
+
+        let mut scenes = vec![];
+        for (id, scene) in resp.get()? {
+            scenes.push(Scene {
+                id,
+                name: scene.name,
+                group: scene.group.as_deref().map(number).transpose()?,
+                lights: numbers(&scene.lights)?,
+            });
+        }
+        scenes.sort_by(|a, b| a.name.cmp(&b.name).then_with(|| a.id.cmp(&b.id)));
+        Ok(scenes)
+    }
+
+    /// Puts the scene's lights back how it was saved, through its group, or through group 0
+    /// for scenes that don't have one.
+    pub fn recall_scene(&self, scene: &Scene) -> hueclient::Result<Value> {
+        let command = CommandLight {
+            scene: Some(scene.id.clone()),
+            ..CommandLight::default()
+        };
+        self.set_group_state(scene.group.unwrap_or(ALL_LIGHTS), &command)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hue::mock::MockBridge;
+    use hueclient::LightState;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn lists_groups_and_recalls_scenes() {
+        let mock = MockBridge::start();
+        let office = mock.add_group("Office", "Room", &[1, 2]);
+        let desk = mock.add_group("Desk", "Zone", &[1]);
+        let relax = LightState {
+            on: true,
+            bri: Some(144),
+            hue: None,
+            sat: None,
+            ct: Some(447),
+            xy: None,
+        };
+        let off = LightState { on: false, ..relax };
+        mock.add_scene(
+            "Relax",
+            Some(office),
+            BTreeMap::from([(1, relax), (2, off)]),
+        );
+        mock.add_scene("Night light", None, BTreeMap::from([(3, relax)]));
+        let bridge = mock.registered_bridge();
+
+        let groups = bridge.get_groups().unwrap();
+        let kinds: Vec<_> = groups.iter().map(|group| (group.id, group.kind)).collect();
+        assert_eq!(kinds, [(office, GroupKind::Room), (desk, GroupKind::Zone)]);
+        assert_eq!(groups[0].lights, [1, 2]);
+
+        let scenes = bridge.get_scenes().unwrap();
+        let names: Vec<_> = scenes.iter().map(|scene| scene.name.as_str()).collect();
+        assert_eq!(names, ["Night light", "Relax"]);
+        assert_eq!((scenes[0].group, scenes[1].group), (None, Some(office)));
+
+        bridge.recall_scene(&scenes[1]).unwrap();
+        let desk_light = mock.light_state(1).unwrap();
+        assert_eq!((desk_light.on, desk_light.ct), (true, Some(447)));
+        assert!(!mock.light_state(2).unwrap().on);
+        // scenes only touch their own lights
+        assert!(!mock.light_state(3).unwrap().on);
+
+        bridge.recall_scene(&scenes[0]).unwrap();
+        assert!(mock.light_state(3).unwrap().on);
+    }
+}
@@ -0,0 +1,692 @@
+//! An in-process stand-in for a Hue bridge's v1 REST API, so the bridge code can be tested
+//! without hardware. It keeps just enough state to be convincing: a link button, registered
+//! users, a few lights and whatever groups and scenes a test adds, plus knobs for injecting
+//! errors and latency. Entertainment areas come with a DTLS server standing in for the
+//! bridge's streaming endpoint.
+
+use crate::config::ValidatedBridge;
+use crate::harness::wait_for;
+use crate::hue::entertainment::{self, Datagrams};
+use crate::hue::{BridgeError, BridgeErrorInner, BridgeInfo, UnauthBridge};
+use hueclient::{CommandLight, Light, LightState};
+use openssl::ssl::{HandshakeError, Ssl, SslContext, SslMethod, SslStream};
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashSet};
+use std::io::{ErrorKind, Read};
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use tiny_http::{Header, Method, Request, Response, Server};
+
+/// how long the bridge accepts new users after the link button is pressed
+const LINK_BUTTON_WINDOW: Duration = Duration::from_secs(30);
+/// every user gets the same key, hex encoded like the real thing
+const CLIENTKEY: &str = "0123456789ABCDEF0123456789ABCDEF";
+/// how often the stream server checks whether it should stop
+const STREAM_POLL: Duration = Duration::from_millis(20);
+
+/// A light's colour in a stream frame, 16 bits a channel.
+pub type StreamedColor = (usize, [u16; 3]);
+
+struct MockGroup {
+    name: String,
+    r#type: &'static str,
+    lights: Vec<usize>,
+    streaming: bool,
+}
+
+struct MockScene {
+    name: String,
+    group: Option<usize>,
+    /// what each of its lights is set to when it's recalled
+    lightstates: BTreeMap<usize, LightState>,
+}
+
+struct MockState {
+    info: BridgeInfo,
+    link_button_until: Option<Instant>,
+    users: HashSet<String>,
+    lights: BTreeMap<usize, Light>,
+    /// by light ID, for lights that have one
+    color_modes: BTreeMap<usize, &'static str>,
+    /// by group ID, not counting group 0
+    groups: BTreeMap<usize, MockGroup>,
+    /// by scene ID
+    scenes: BTreeMap<String, MockScene>,
+    /// every frame streamed while an area was streaming
+    frames: Vec<Vec<StreamedColor>>,
+    next_error: Option<(usize, String)>,
+    latency: Duration,
+    light_writes: usize,
+    group_writes: usize,
+}
+
+pub struct MockBridge {
+    address: SocketAddr,
+    state: Arc<Mutex<MockState>>,
+    server: Arc<Server>,
+    thread: Option<JoinHandle<()>>,
+    /// the DTLS server's port, once there's an area to stream to
+    stream_port: Option<u16>,
+    stream_stop: Arc<AtomicBool>,
+    stream_thread: Option<JoinHandle<()>>,
+}
+
+fn light(name: &str, modelid: &str, state: LightState) -> Light {
+    Light {
+        name: name.to_string(),
+        modelid: modelid.to_string(),
+        swversion: "1.50.2_r30933".to_string(),
+        uniqueid: format!("00:17:88:01:00:00:00:{:02x}-0b", thread_rng().gen::<u8>()),
+        state,
+    }
+}
+
+fn default_lights() -> BTreeMap<usize, Light> {
+    let colour = LightState {
+        on: true,
+        bri: Some(254),
+        hue: Some(8418),
+        sat: Some(140),
+        ct: Some(366),
+        xy: Some((0.4573, 0.41)),
+    };
+    let white = LightState {
+        on: false,
+        bri: Some(127),
+        hue: None,
+        sat: None,
+        ct: None,
+        xy: None,
+    };
+
+    BTreeMap::from([
+        (1, light("Desk", "LCT015", colour)),
+        (2, light("Backdrop", "LCT001", colour)),
+        (3, light("Hallway", "LWB010", white)),
+    ])
+}
+
+impl MockBridge {
+    /// Starts a bridge listening on a random local port. It stops when dropped.
+    pub fn start() -> Self {
+        let server = Arc::new(Server::http("127.0.0.1:0").expect("failed to start mock bridge"));
+        let address = server.server_addr().to_ip().unwrap();
+
+        let id: String = (0..6)
+            .map(|_| thread_rng().sample(Alphanumeric) as char)
+            .collect();
+        let state = Arc::new(Mutex::new(MockState {
+            info: BridgeInfo {
+                name: format!("Mock Bridge {}", id),
+                bridgeid: format!("001788FFFE{}", id.to_uppercase()),
+            },
+            link_button_until: None,
+            users: HashSet::new(),
+            lights: default_lights(),
+            color_modes: BTreeMap::from([(1, "xy"), (2, "hs")]),
+            groups: BTreeMap::new(),
+            scenes: BTreeMap::new(),
+            frames: vec![],
+            next_error: None,
+            latency: Duration::ZERO,
+            light_writes: 0,
+            group_writes: 0,
+        }));
+
+        let thread = {
+            let server = server.clone();
+            let state = state.clone();
+            thread::spawn(move || {
+                for request in server.incoming_requests() {
+                    handle(&state, request);
+                }
+            })
+        };
+
+        Self {
+            address,
+            state,
+            server,
+            thread: Some(thread),
+            stream_port: None,
+            stream_stop: Arc::new(AtomicBool::new(false)),
+            stream_thread: None,
+        }
+    }
+
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    pub fn base_url(&self) -> String {
+        crate::hue::base_url(self.address)
+    }
+
+    pub fn info(&self) -> BridgeInfo {
+        self.state.lock().unwrap().info.clone()
+    }
+
+    pub fn press_link_button(&self) {
+        self.state.lock().unwrap().link_button_until = Some(Instant::now() + LINK_BUTTON_WINDOW);
+    }
+
+    pub fn is_user(&self, username: &str) -> bool {
+        self.state.lock().unwrap().users.contains(username)
+    }
+
+    /// skips the link button dance for tests that just need to talk to lights
+    pub fn registered_bridge(&self) -> crate::hue::Bridge {
+        let username = "mock-user".to_string();
+        self.state.lock().unwrap().users.insert(username.clone());
+        let mut bridge = UnauthBridge::new(self.base_url())
+            .with_user(username)
+            .with_clientkey(Some(CLIENTKEY.to_string()));
+        if let Some(port) = self.stream_port {
+            bridge.stream_port = port;
+        }
+        bridge
+    }
+
+    /// Adds a group of `type`, like "Room" or "Zone", and returns its ID.
+    pub fn add_group(&self, name: &str, r#type: &'static str, lights: &[usize]) -> usize {
+        let mut state = self.state.lock().unwrap();
+        let id = state.groups.keys().last().map_or(1, |id| id + 1);
+        state.groups.insert(
+            id,
+            MockGroup {
+                name: name.to_string(),
+                r#type,
+                lights: lights.to_vec(),
+                streaming: false,
+            },
+        );
+        id
+    }
+
+    /// Adds an entertainment area and returns its group ID. Bridges handed out after this
+    /// can stream to it.
+    pub fn add_entertainment_area(&mut self, name: &str, lights: &[usize]) -> usize {
+        if self.stream_port.is_none() {
+            let socket = UdpSocket::bind("127.0.0.1:0").expect("failed to start stream server");
+            self.stream_port = Some(socket.local_addr().unwrap().port());
+            let state = self.state.clone();
+            let stop = self.stream_stop.clone();
+            self.stream_thread = Some(thread::spawn(move || serve_streams(socket, state, stop)));
+        }
+        self.add_group(name, "Entertainment", lights)
+    }
+
+    /// Saves a scene for `group`, or for just its lights if there's no group, and returns
+    /// its ID.
+    pub fn add_scene(
+        &self,
+        name: &str,
+        group: Option<usize>,
+        lightstates: BTreeMap<usize, LightState>,
+    ) -> String {
+        let mut state = self.state.lock().unwrap();
+        let id = format!("scene-{}", state.scenes.len() + 1);
+        state.scenes.insert(
+            id.clone(),
+            MockScene {
+                name: name.to_string(),
+                group,
+                lightstates,
+            },
+        );
+        id
+    }
+
+    pub fn is_streaming(&self, group: usize) -> bool {
+        self.state.lock().unwrap().groups[&group].streaming
+    }
+
+    /// Waits for at least `count` frames to have been streamed, and returns them all.
+    pub fn wait_for_frames(&self, count: usize) -> Vec<Vec<StreamedColor>> {
+        wait_for(|| {
+            let frames = self.state.lock().unwrap().frames.clone();
+            (frames.len() >= count).then_some(frames)
+        })
+        .unwrap_or_else(|| {
+            panic!(
+                "only {} frames came",
+                self.state.lock().unwrap().frames.len()
+            )
+        })
+    }
+
+    /// a registered bridge, as setup would have handed it to the app
+    pub fn validated_bridge(&self) -> ValidatedBridge {
+        ValidatedBridge::from_bridge(
+            self.registered_bridge(),
+            self.address,
+            "twitchbrite#mock".to_string(),
+            self.info().bridgeid,
+        )
+        .unwrap()
+    }
+
+    pub fn light_ids(&self) -> Vec<usize> {
+        self.state.lock().unwrap().lights.keys().copied().collect()
+    }
+
+    pub fn light_state(&self, id: usize) -> Option<LightState> {
+        self.state.lock().unwrap().lights.get(&id).map(|l| l.state)
+    }
+
+    /// Waits for commands sent in the background to get light `id` into a state `done` likes.
+    pub fn wait_for_light(&self, id: usize, done: impl Fn(&LightState) -> bool) -> LightState {
+        wait_for(|| Some(self.light_state(id).expect("no such light")).filter(&done))
+            .unwrap_or_else(|| panic!("light {} ended up {:?}", id, self.light_state(id)))
+    }
+
+    /// how many light state changes the bridge has been sent
+    pub fn light_writes(&self) -> usize {
+        self.state.lock().unwrap().light_writes
+    }
+
+    /// how many group actions the bridge has been sent
+    pub fn group_writes(&self) -> usize {
+        self.state.lock().unwrap().group_writes
+    }
+
+    /// the next request gets this error instead of its usual response
+    pub fn fail_next(&self, code: usize, description: &str) {
+        self.state.lock().unwrap().next_error = Some((code, description.to_string()));
+    }
+
+    /// every response is delayed by this much
+    pub fn set_latency(&self, latency: Duration) {
+        self.state.lock().unwrap().latency = latency;
+    }
+}
+
+impl Drop for MockBridge {
+    fn drop(&mut self) {
+        self.server.unblock();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+        self.stream_stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.stream_thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Takes one stream at a time, like the bridge does, until `stop` is set. Clients have to
+/// know a registered user and the key it was given.
+fn serve_streams(socket: UdpSocket, state: Arc<Mutex<MockState>>, stop: Arc<AtomicBool>) {
+    socket.set_read_timeout(Some(STREAM_POLL)).unwrap();
+    let mut context = SslContext::builder(SslMethod::dtls()).unwrap();
+    context.set_cipher_list(entertainment::CIPHER).unwrap();
+    let users = state.clone();
+    context.set_psk_server_callback(move |_, identity, psk_out| {
+        let identity = String::from_utf8_lossy(identity.unwrap_or_default()).to_string();
+        if !users.lock().unwrap().users.contains(&identity) {
+            return Ok(0);
+        }
+        let key: Vec<u8> = (0..CLIENTKEY.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&CLIENTKEY[i..i + 2], 16).unwrap())
+            .collect();
+        psk_out[..key.len()].copy_from_slice(&key);
+        Ok(key.len())
+    });
+    let context = context.build();
+
+    let mut buf = [0; 2048];
+    while !stop.load(Ordering::Relaxed) {
+        let peer = match socket.peek_from(&mut buf) {
+            Ok((_, peer)) => peer,
+            Err(_) => continue,
+        };
+        let datagrams = Datagrams::new(socket.try_clone().unwrap(), peer);
+        if let Some(stream) = accept(&context, datagrams, &stop) {
+            receive_frames(stream, &state, &stop);
+        }
+    }
+}
+
+fn accept(
+    context: &SslContext,
+    datagrams: Datagrams,
+    stop: &AtomicBool,
+) -> Option<SslStream<Datagrams>> {
+    let mut handshake = Ssl::new(context).unwrap().accept(datagrams);
+    loop {
+        match handshake {
+            Ok(stream) => return Some(stream),
+            Err(HandshakeError::WouldBlock(mid)) if !stop.load(Ordering::Relaxed) => {
+                handshake = mid.handshake()
+            }
+            Err(_) => return None,
+        }
+    }
+}
+
+/// Records frames until the client closes the stream or goes quiet for good.
+fn receive_frames(mut stream: SslStream<Datagrams>, state: &Mutex<MockState>, stop: &AtomicBool) {
+    let mut buf = [0; 2048];
+    while !stop.load(Ordering::Relaxed) {
+        match stream.read(&mut buf) {
+            Ok(0) => return,
+            Ok(len) => {
+                let mut state = state.lock().unwrap();
+                // a real bridge ignores streams to areas that aren't streaming
+                if state.groups.values().any(|group| group.streaming) {
+                    state.frames.push(decode_frame(&buf[..len]));
+                }
+            }
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+            Err(_) => return,
+        }
+    }
+}
+
+/// The lights and colours in a v1 stream message.
+fn decode_frame(message: &[u8]) -> Vec<StreamedColor> {
+    assert_eq!(&message[..9], b"HueStream", "not a stream message");
+    let channel = |bytes: &[u8], i: usize| u16::from_be_bytes([bytes[i], bytes[i + 1]]);
+    message[16..]
+        .chunks(9)
+        .map(|light| {
+            (
+                channel(light, 1) as usize,
+                [channel(light, 3), channel(light, 5), channel(light, 7)],
+            )
+        })
+        .collect()
+}
+
+fn error(code: usize, address: &str, description: &str) -> Value {
+    json!([BridgeError {
+        error: BridgeErrorInner {
+            r#type: code,
+            address: address.to_string(),
+            description: description.to_string(),
+        },
+    }])
+}
+
+fn to_value<T: Serialize>(t: T) -> Value {
+    serde_json::to_value(t).unwrap()
+}
+
+fn handle(state: &Mutex<MockState>, mut request: Request) {
+    let mut body = String::new();
+    let _ = request.as_reader().read_to_string(&mut body);
+
+    let latency = state.lock().unwrap().latency;
+    thread::sleep(latency);
+
+    let url = request.url().to_string();
+    let path: Vec<&str> = url.trim_matches('/').split('/').collect();
+    let response = route(&mut state.lock().unwrap(), request.method(), &path, &body);
+
+    let header = Header::from_bytes("Content-Type", "application/json").unwrap();
+    let _ = request.respond(Response::from_string(response.to_string()).with_header(header));
+}
+
+fn route(state: &mut MockState, method: &Method, path: &[&str], body: &str) -> Value {
+    if let Some((code, description)) = state.next_error.take() {
+        return error(code, &format!("/{}", path.join("/")), &description);
+    }
+
+    match (method, path) {
+        (Method::Get, ["api", "config"]) => to_value(&state.info),
+
+        (Method::Post, ["api"]) => {
+            let pressed = state
+                .link_button_until
+                .is_some_and(|until| Instant::now() < until);
+            if !pressed {
+                return error(101, "", "link button not pressed");
+            }
+
+            let username: String = (0..40)
+                .map(|_| thread_rng().sample(Alphanumeric) as char)
+                .collect();
+            state.users.insert(username.clone());
+            let wants_key = serde_json::from_str::<Value>(body)
+                .is_ok_and(|body| body["generateclientkey"] == json!(true));
+            match wants_key {
+                true => json!([{ "success": { "username": username, "clientkey": CLIENTKEY } }]),
+                false => json!([{ "success": { "username": username } }]),
+            }
+        }
+
+        (_, ["api", user, rest @ ..]) if !state.users.contains(*user) => {
+            error(1, &format!("/{}", rest.join("/")), "unauthorized user")
+        }
+
+        (Method::Get, ["api", _, "config"]) => to_value(&state.info),
+
+        (Method::Get, ["api", _, "lights"]) => {
+            let lights: BTreeMap<_, _> = state
+                .lights
+                .keys()
+                .map(|id| (id.to_string(), light_value(state, *id)))
+                .collect();
+            to_value(lights)
+        }
+
+        (Method::Get, ["api", _, "lights", id]) => {
+            match id.parse().ok().filter(|id| state.lights.contains_key(id)) {
+                Some(id) => light_value(state, id),
+                None => error(3, &format!("/lights/{}", id), "resource not available"),
+            }
+        }
+
+        (Method::Put, ["api", _, "lights", id, "state"]) => {
+            let (id, light) = match id
+                .parse()
+                .ok()
+                .and_then(|id: usize| Some((id, state.lights.get_mut(&id)?)))
+            {
+                Some(light) => light,
+                None => return error(3, &format!("/lights/{}", id), "resource not available"),
+            };
+            let command: CommandLight = match serde_json::from_str(body) {
+                Ok(command) => command,
+                Err(_) => {
+                    return error(
+                        2,
+                        &format!("/lights/{}/state", id),
+                        "body contains invalid json",
+                    )
+                }
+            };
+
+            apply(&mut light.state, &command);
+            if let Some(mode) = color_mode(&command) {
+                state.color_modes.insert(id, mode);
+            }
+            state.light_writes += 1;
+            success(&format!("/lights/{}/state", id), &command)
+        }
+
+        (Method::Get, ["api", _, "groups"]) => {
+            let groups: BTreeMap<_, _> = state
+                .groups
+                .iter()
+                .map(|(id, group)| {
+                    let lights: Vec<String> = group.lights.iter().map(usize::to_string).collect();
+                    let mut value = json!({
+                        "name": group.name,
+                        "type": group.r#type,
+                        "lights": lights,
+                    });
+                    if group.r#type == "Entertainment" {
+                        value["stream"] = json!({ "active": group.streaming });
+                    }
+                    (id.to_string(), value)
+                })
+                .collect();
+            to_value(groups)
+        }
+
+        (Method::Put, ["api", _, "groups", id]) => {
+            let address = format!("/groups/{}/stream/active", id);
+            let group = match id
+                .parse()
+                .ok()
+                .and_then(|id: usize| state.groups.get_mut(&id))
+            {
+                Some(group) => group,
+                None => return error(3, &format!("/groups/{}", id), "resource not available"),
+            };
+            let active = match serde_json::from_str::<Value>(body) {
+                Ok(body) => body["stream"]["active"].as_bool(),
+                Err(_) => None,
+            };
+            match (active, group.r#type) {
+                (Some(active), "Entertainment") => {
+                    group.streaming = active;
+                    json!([{ "success": { address: active } }])
+                }
+                _ => error(7, &address, "invalid value for parameter, active"),
+            }
+        }
+
+        (Method::Get, ["api", _, "scenes"]) => {
+            let scenes: BTreeMap<_, _> = state
+                .scenes
+                .iter()
+                .map(|(id, scene)| {
+                    let lights: Vec<String> =
+                        scene.lightstates.keys().map(usize::to_string).collect();
+                    let mut value = json!({
+                        "name": scene.name,
+                        "type": "LightScene",
+                        "lights": lights,
+                    });
+                    if let Some(group) = scene.group {
+                        value["type"] = json!("GroupScene");
+                        value["group"] = json!(group.to_string());
+                    }
+                    (id.clone(), value)
+                })
+                .collect();
+            to_value(scenes)
+        }
+
+        (Method::Put, ["api", _, "groups", id, "action"]) => {
+            let address = format!("/groups/{}/action", id);
+            let members: Vec<usize> = match id.parse() {
+                Ok(0) => state.lights.keys().copied().collect(),
+                Ok(id) => match state.groups.get(&id) {
+                    Some(group) => group.lights.clone(),
+                    None => return error(3, &address, "resource not available"),
+                },
+                Err(_) => return error(3, &address, "resource not available"),
+            };
+            let command: CommandLight = match serde_json::from_str(body) {
+                Ok(command) => command,
+                Err(_) => return error(2, &address, "body contains invalid json"),
+            };
+
+            // a scene sets each of its lights its own way, and ignores the rest of the group
+            let commands: Vec<(usize, CommandLight)> = match &command.scene {
+                Some(scene) => match state.scenes.get(scene) {
+                    Some(scene) => scene
+                        .lightstates
+                        .iter()
+                        .map(|(id, state)| (*id, scene_command(state)))
+                        .collect(),
+                    None => return error(7, &format!("{}/scene", address), "invalid value"),
+                },
+                None => members.iter().map(|id| (*id, command.clone())).collect(),
+            };
+            for (id, command) in commands {
+                if let Some(light) = state.lights.get_mut(&id) {
+                    apply(&mut light.state, &command);
+                    if let Some(mode) = color_mode(&command) {
+                        state.color_modes.insert(id, mode);
+                    }
+                }
+            }
+            state.group_writes += 1;
+            success(&address, &command)
+        }
+
+        _ => error(
+            4,
+            &format!("/{}", path.join("/")),
+            "method not available for resource",
+        ),
+    }
+}
+
+/// A light as the bridge reports it, colour mode included.
+fn light_value(state: &MockState, id: usize) -> Value {
+    let mut light = to_value(&state.lights[&id]);
+    if let Some(mode) = state.color_modes.get(&id) {
+        light["state"]["colormode"] = json!(mode);
+    }
+    light
+}
+
+/// The mode a command leaves a light in. Like a real bridge, xy beats ct beats hue/sat.
+fn color_mode(command: &CommandLight) -> Option<&'static str> {
+    if command.xy.is_some() {
+        Some("xy")
+    } else if command.ct.is_some() {
+        Some("ct")
+    } else if command.hue.is_some() || command.sat.is_some() {
+        Some("hs")
+    } else {
+        None
+    }
+}
+
+/// one `success` per attribute set
+fn success(address: &str, command: &CommandLight) -> Value {
+    let success: Vec<Value> = to_value(command)
+        .as_object()
+        .unwrap()
+        .iter()
+        .map(|(key, value)| json!({ "success": { format!("{}/{}", address, key): value } }))
+        .collect();
+    Value::Array(success)
+}
+
+/// what recalling a scene does to one of its lights
+fn scene_command(state: &LightState) -> CommandLight {
+    CommandLight {
+        on: Some(state.on),
+        bri: state.bri,
+        hue: state.hue,
+        sat: state.sat,
+        ct: state.ct,
+        xy: state.xy,
+        ..CommandLight::default()
+    }
+}
+
+fn apply(state: &mut LightState, command: &CommandLight) {
+    if let Some(on) = command.on {
+        state.on = on;
+    }
+    if command.bri.is_some() {
+        state.bri = command.bri;
+    }
+    if command.hue.is_some() {
+        state.hue = command.hue;
+    }
+    if command.sat.is_some() {
+        state.sat = command.sat;
+    }
+    if command.ct.is_some() {
+        state.ct = command.ct;
+    }
+    if command.xy.is_some() {
+        state.xy = command.xy;
+    }
+}
@@ -0,0 +1,480 @@
+//! A small Hue v1 REST client. hueclient's types are reused as-is, but its `Bridge` always
+//! talks to `http://{ip}`, which makes it impossible to point at anything but real hardware.
+//! Everything here takes a base URL instead.
+//!
+//! Errors stay as hueclient's `HueError` so callers can match on the bridge's error codes.
+#![allow(clippy::result_large_err)]
+
+pub mod entertainment;
+pub mod groups;
+#[cfg(test)]
+pub mod mock;
+pub mod queue;
+
+use hueclient::{CommandLight, HueError, IdentifiedLight, Light, LightState};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+use std::time::Duration;
+
+/// bridges serve the v1 API over plain http on port 80
+pub const HUE_HTTP_PORT: u16 = 80;
+/// and take Entertainment API streams over DTLS on this UDP port
+pub const ENTERTAINMENT_PORT: u16 = 2100;
+
+const PROBE_TIMEOUT: Duration = Duration::from_secs(3);
+const N_UPNP_URL: &str = "https://discovery.meethue.com/";
+/// https://developers.meethue.com/develop/application-design-guidance/hue-bridge-discovery/
+/// suggests waiting up to 5 seconds for SSDP replies
+const SSDP_TIMEOUT: Duration = Duration::from_secs(5);
+const SSDP_MAX_BRIDGES: usize = 16;
+
+pub fn base_url(address: SocketAddr) -> String {
+    format!("http://{}", address)
+}
+
+/// The unauthenticated subset of `/api/config`, which every bridge answers.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BridgeInfo {
+    pub name: String,
+    pub bridgeid: String,
+}
+
+/// A bridge that answered on the network, but that we're not necessarily registered with.
+#[derive(Debug, Clone)]
+pub struct DiscoveredBridge {
+    pub address: SocketAddr,
+    pub info: BridgeInfo,
+}
+
+/// Where to look for bridges.
+#[derive(Debug, Clone)]
+pub enum Discovery {
+    /// the Hue discovery service and SSDP
+    Network,
+    /// only these addresses, e.g. a mock bridge in tests
+    #[cfg_attr(not(test), allow(dead_code))]
+    Addresses(Vec<SocketAddr>),
+}
+
+impl Discovery {
+    /// Every bridge that answers, each asked for its ID so the same bridge isn't listed twice.
+    pub fn discover_bridges(&self) -> Vec<DiscoveredBridge> {
+        let addresses: Vec<SocketAddr> = match self {
+            Discovery::Network => discover_network()
+                .into_iter()
+                .map(|ip| SocketAddr::new(ip, HUE_HTTP_PORT))
+                .collect(),
+            Discovery::Addresses(addresses) => addresses.clone(),
+        };
+
+        let mut bridges: Vec<DiscoveredBridge> = vec![];
+        for address in addresses {
+            if let Ok(info) = fetch_bridge_info(&base_url(address)) {
+                if !bridges.iter().any(|b| b.info.bridgeid == info.bridgeid) {
+                    bridges.push(DiscoveredBridge { address, info });
+                }
+            }
+        }
+        bridges
+    }
+}
+
+/// Asks whatever is at `base_url` whether it's a Hue bridge, and which one.
+pub fn fetch_bridge_info(base_url: &str) -> anyhow::Result<BridgeInfo> {
+    let client = reqwest::blocking::Client::builder()
+        .timeout(PROBE_TIMEOUT)
+        .build()?;
+    let info = client
+        .get(&format!("{}/api/config", base_url))
+        .send()?
+        .json()?;
+    Ok(info)
+}
+
+fn discover_network() -> BTreeSet<IpAddr> {
+    let mut addresses = BTreeSet::new();
+    if let Ok(found) = discover_n_upnp() {
+        addresses.extend(found);
+    }
+    if let Ok(found) = ssdp_probe::ssdp_probe_v4(br"IpBridge", SSDP_MAX_BRIDGES, SSDP_TIMEOUT) {
+        addresses.extend(found.into_iter().map(IpAddr::from));
+    }
+    addresses
+}
+
+/// Bridges report themselves to Philips' discovery service, which lists every bridge
+/// behind the same public IP.
+fn discover_n_upnp() -> anyhow::Result<Vec<IpAddr>> {
+    #[derive(Deserialize)]
+    struct Entry {
+        internalipaddress: IpAddr,
+    }
+
+    let client = reqwest::blocking::Client::builder()
+        .timeout(PROBE_TIMEOUT)
+        .build()?;
+    let entries: Vec<Entry> = client.get(N_UPNP_URL).send()?.json()?;
+    Ok(entries.into_iter().map(|e| e.internalipaddress).collect())
+}
+
+/// A bridge we don't have a username for yet.
+#[derive(Debug, Clone)]
+pub struct UnauthBridge {
+    pub base_url: String,
+    client: reqwest::blocking::Client,
+}
+
+impl UnauthBridge {
+    pub fn new(base_url: impl Into<String>) -> Self {
+        Self {
+            base_url: base_url.into(),
+            client: reqwest::blocking::Client::new(),
+        }
+    }
+
+    pub fn with_user(self, username: impl Into<String>) -> Bridge {
+        Bridge {
+            base_url: self.base_url,
+            username: username.into(),
+            clientkey: None,
+            stream_port: ENTERTAINMENT_PORT,
+            client: self.client,
+        }
+    }
+
+    /// Fails with error 101 until the bridge's link button has been pressed. Bridges new
+    /// enough to stream hand out a clientkey too.
+    pub fn register_user(&self, devicetype: &str) -> hueclient::Result<Bridge> {
+        #[derive(Serialize)]
+        struct PostApi<'a> {
+            devicetype: &'a str,
+            generateclientkey: bool,
+        }
+        #[derive(Deserialize)]
+        struct Success {
+            success: Username,
+        }
+        #[derive(Deserialize)]
+        struct Username {
+            username: String,
+            clientkey: Option<String>,
+        }
+
+        let url = format!("{}/api", self.base_url);
+        let resp: BridgeResponse<Success> = self
+            .client
+            .post(&url)
+            .json(&PostApi {
+                devicetype,
+                generateclientkey: true,
+            })
+            .send()?
+            .json()?;
+
+        let user = resp.get()?.success;
+        Ok(self
+            .clone()
+            .with_user(user.username)
+            .with_clientkey(user.clientkey))
+    }
+}
+
+/// Which of a light's colour attributes it's actually showing.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ColorMode {
+    Hs,
+    Xy,
+    Ct,
+}
+
+/// A light's state with the colour mode, which hueclient's `LightState` leaves out.
+/// Without it there's no telling whether hue/sat, xy or ct is the real colour.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FullLightState {
+    pub on: bool,
+    pub bri: Option<u8>,
+    pub hue: Option<u16>,
+    pub sat: Option<u8>,
+    pub ct: Option<u16>,
+    pub xy: Option<(f32, f32)>,
+    /// missing on white-only lights
+    pub colormode: Option<ColorMode>,
+}
+
+impl From<FullLightState> for LightState {
+    fn from(state: FullLightState) -> Self {
+        LightState {
+            on: state.on,
+            bri: state.bri,
+            hue: state.hue,
+            sat: state.sat,
+            ct: state.ct,
+            xy: state.xy,
+        }
+    }
+}
+
+/// A bridge and a username it (presumably) accepts.
+#[derive(Debug, Clone)]
+pub struct Bridge {
+    pub base_url: String,
+    pub username: String,
+    /// the key for Entertainment API streams, hex encoded. Missing for users registered
+    /// before twitchbrite asked for one
+    pub clientkey: Option<String>,
+    /// where the bridge takes Entertainment API streams
+    pub stream_port: u16,
+    client: reqwest::blocking::Client,
+}
+
+impl Bridge {
+    pub fn with_clientkey(self, clientkey: Option<String>) -> Self {
+        Self { clientkey, ..self }
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}/api/{}/{}", self.base_url, self.username, path)
+    }
+
+    /// sorted by ID
+    pub fn get_all_lights(&self) -> hueclient::Result<Vec<IdentifiedLight>> {
+        let resp: BridgeResponse<HashMap<String, Light>> =
+            self.client.get(&self.url("lights")).send()?.json()?;
+
+        let mut lights = vec![];
+        for (id, light) in resp.get()? {
+            let id = usize::from_str(&id).map_err(|_| protocol_err("light ID isn't a number"))?;
+            lights.push(IdentifiedLight { id, light });
+        }
+        lights.sort_by_key(|light| light.id);
+        Ok(lights)
+    }
+
+    /// by light ID
+    pub fn get_light_states(&self) -> hueclient::Result<BTreeMap<usize, FullLightState>> {
+        #[derive(Deserialize)]
+        struct StateOnly {
+            state: FullLightState,
+        }
+
+        let resp: BridgeResponse<HashMap<String, StateOnly>> =
+            self.client.get(&self.url("lights")).send()?.json()?;
+
+        let mut states = BTreeMap::new();
+        for (id, light) in resp.get()? {
+            let id = usize::from_str(&id).map_err(|_| protocol_err("light ID isn't a number"))?;
+            states.insert(id, light.state);
+        }
+        Ok(states)
+    }
+
+    pub fn set_light_state(
+        &self,
+        light: usize,
+        command: &CommandLight,
+    ) -> hueclient::Result<Value> {
+        self.put(&format!("lights/{}/state", light), command)
+    }
+
+    /// Every light in the group at once. Group 0 is every light the bridge knows.
+    pub fn set_group_state(
+        &self,
+        group: usize,
+        command: &CommandLight,
+    ) -> hueclient::Result<Value> {
+        self.put(&format!("groups/{}/action", group), command)
+    }
+
+    fn put(&self, path: &str, body: &impl Serialize) -> hueclient::Result<Value> {
+        // one entry per attribute set, so a PUT that sets nothing gets back `[]`
+        let value: Value = self.client.put(&self.url(path)).json(body).send()?.json()?;
+
+        // some attributes can fail while the rest succeed, which is still worth reporting
+        let failed = value
+            .as_array()
+            .into_iter()
+            .flatten()
+            .find_map(|entry| serde_json::from_value::<BridgeError>(entry.clone()).ok());
+        match failed {
+            Some(BridgeError { error }) => Err(error.into()),
+            None => Ok(value),
+        }
+    }
+}
+
+fn protocol_err(msg: &str) -> HueError {
+    HueError::ProtocolError {
+        msg: msg.to_string(),
+    }
+}
+
+/// Errors come back as `[{"error": {...}}]` with a 200, so every response has to be checked.
+/// `Errors` goes first, since untagged variants are tried in order and a `T` like `Value`
+/// would happily swallow an error. That makes an empty array `Errors` too.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum BridgeResponse<T> {
+    Errors(Vec<BridgeError>),
+    Element(T),
+    List(Vec<T>),
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BridgeError {
+    pub error: BridgeErrorInner,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BridgeErrorInner {
+    pub r#type: usize,
+    pub address: String,
+    pub description: String,
+}
+
+impl From<BridgeErrorInner> for HueError {
+    fn from(error: BridgeErrorInner) -> Self {
+        HueError::BridgeError {
+            code: error.r#type,
+            msg: error.description,
+        }
+    }
+}
+
+impl<T> BridgeResponse<T> {
+    fn get(self) -> hueclient::Result<T> {
+        match self {
+            BridgeResponse::Element(t) => Ok(t),
+            BridgeResponse::List(mut ts) => ts
+                .pop()
+                .ok_or_else(|| protocol_err("expected a non-empty array")),
+            BridgeResponse::Errors(mut es) => match es.pop() {
+                Some(BridgeError { error }) => Err(error.into()),
+                None => Err(protocol_err("expected a non-empty array")),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hue::mock::MockBridge;
+
+    #[test]
+    fn register_user_waits_for_link_button() {
+        let mock = MockBridge::start();
+        let unauth = UnauthBridge::new(mock.base_url());
+
+        match unauth.register_user("twitchbrite#test") {
+            Err(HueError::BridgeError { code: 101, .. }) => {}
+            other => panic!("expected link button error, got {:?}", other),
+        }
+
+        mock.press_link_button();
+        let bridge = unauth.register_user("twitchbrite#test").unwrap();
+        assert!(mock.is_user(&bridge.username));
+    }
+
+    #[test]
+    fn lights_need_a_registered_user() {
+        let mock = MockBridge::start();
+        let bridge = UnauthBridge::new(mock.base_url()).with_user("nobody");
+
+        match bridge.get_all_lights() {
+            Err(HueError::BridgeError { code: 1, .. }) => {}
+            other => panic!("expected unauthorized user error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn set_light_state_updates_the_bridge() {
+        let mock = MockBridge::start();
+        let bridge = mock.registered_bridge();
+
+        let lights = bridge.get_all_lights().unwrap();
+        assert_eq!(lights.len(), mock.light_ids().len());
+
+        let command = CommandLight::default().on().with_bri(42).with_xy(0.3, 0.4);
+        bridge.set_light_state(lights[0].id, &command).unwrap();
+
+        let state = mock.light_state(lights[0].id).unwrap();
+        assert!(state.on);
+        assert_eq!(state.bri, Some(42));
+        assert_eq!(state.xy, Some((0.3, 0.4)));
+    }
+
+    #[test]
+    fn commands_that_change_nothing_are_fine() {
+        let mock = MockBridge::start();
+        let bridge = mock.registered_bridge();
+
+        let resp = bridge.set_light_state(1, &CommandLight::default()).unwrap();
+        assert_eq!(resp, serde_json::json!([]));
+
+        // but there's nothing to read out of an empty array
+        let empty: BridgeResponse<HashMap<String, Value>> = serde_json::from_str("[]").unwrap();
+        assert!(matches!(empty.get(), Err(HueError::ProtocolError { .. })));
+    }
+
+    #[test]
+    fn light_states_come_with_their_colour_mode() {
+        let mock = MockBridge::start();
+        let bridge = mock.registered_bridge();
+
+        let states = bridge.get_light_states().unwrap();
+        assert_eq!(states[&1].colormode, Some(ColorMode::Xy));
+        assert_eq!(states[&3].colormode, None);
+
+        bridge
+            .set_light_state(1, &CommandLight::default().with_ct(300))
+            .unwrap();
+        let states = bridge.get_light_states().unwrap();
+        assert_eq!(states[&1].colormode, Some(ColorMode::Ct));
+    }
+
+    #[test]
+    fn injected_errors_come_back_as_bridge_errors() {
+        let mock = MockBridge::start();
+        let bridge = mock.registered_bridge();
+
+        mock.fail_next(
+            201,
+            "parameter, bri, is not modifiable. Device is set to off.",
+        );
+        match bridge.set_light_state(1, &CommandLight::default().with_bri(1)) {
+            Err(HueError::BridgeError { code: 201, .. }) => {}
+            other => panic!("expected injected error, got {:?}", other),
+        }
+
+        // only the next request fails
+        bridge
+            .set_light_state(1, &CommandLight::default().on())
+            .unwrap();
+    }
+
+    #[test]
+    fn probing_gives_up_on_slow_bridges() {
+        let mock = MockBridge::start();
+        assert_eq!(
+            fetch_bridge_info(&mock.base_url()).unwrap().bridgeid,
+            mock.info().bridgeid
+        );
+
+        mock.set_latency(PROBE_TIMEOUT + Duration::from_secs(1));
+        assert!(fetch_bridge_info(&mock.base_url()).is_err());
+    }
+
+    #[test]
+    fn discovery_dedupes_by_bridge_id() {
+        let a = MockBridge::start();
+        let b = MockBridge::start();
+
+        let discovery = Discovery::Addresses(vec![a.address(), a.address(), b.address()]);
+        let bridges = discovery.discover_bridges();
+        assert_eq!(bridges.len(), 2);
+    }
+}
@@ -0,0 +1,563 @@
+//! An output queue in front of each bridge. Bridges cope with about 10 light commands a
+//! second and 1 group command a second; past that, commands back up inside the bridge and the
+//! lights lag by seconds. So commands wait here for their turn instead, where a newer command
+//! for a light replaces the one still waiting, and the same command for every light goes out
+//! as a single group command. Commands for a room or zone go out as one group command too.
+//! https://developers.meethue.com/develop/hue-api/lights-api/#set-light-state (see "Limits")
+
+use crate::config::ValidatedBridge;
+use crate::hue::groups::ALL_LIGHTS;
+use crate::lights::snapshot::Snapshots;
+use crate::lights::LightEvent;
+use crate::rate_limit::TokenBucket;
+use crate::shutdown;
+use crate::tasks::Task;
+use crossbeam_channel::{Receiver, RecvTimeoutError, Sender};
+use hueclient::CommandLight;
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const LIGHT_RATE: f32 = 10.0;
+const LIGHT_BURST: f32 = 5.0;
+const GROUP_RATE: f32 = 1.0;
+const GROUP_BURST: f32 = 1.0;
+/// how often the queue wakes up to check whether it should stop
+const WAKE_INTERVAL: Duration = Duration::from_millis(250);
+
+enum Write {
+    Light(usize, CommandLight),
+    Group(usize, CommandLight),
+}
+
+/// How backed up a bridge's queue is.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct QueueStats {
+    /// commands waiting to go out
+    pub depth: usize,
+    /// commands replaced by newer ones before they went out
+    pub dropped: u64,
+}
+
+/// Where light commands for one bridge go. Cheap to clone, and the queue stops once every
+/// clone is gone.
+#[derive(Clone)]
+pub struct BridgeQueue {
+    bridge: ValidatedBridge,
+    snapshots: Snapshots,
+    writes_tx: Sender<Write>,
+}
+
+impl BridgeQueue {
+    pub fn start(
+        bridge: ValidatedBridge,
+        snapshots: Snapshots,
+        events_tx: Sender<LightEvent>,
+    ) -> Self {
+        let (queue, task) = Self::new(bridge, snapshots, events_tx);
+        task.spawn(());
+        queue
+    }
+
+    fn new(
+        bridge: ValidatedBridge,
+        snapshots: Snapshots,
+        events_tx: Sender<LightEvent>,
+    ) -> (Self, QueueTask) {
+        let (writes_tx, writes_rx) = crossbeam_channel::unbounded();
+        let task = QueueTask::new(bridge.clone(), writes_rx, events_tx);
+        let queue = Self {
+            bridge,
+            snapshots,
+            writes_tx,
+        };
+        (queue, task)
+    }
+
+    /// for reading from, since only writes need queueing
+    pub fn bridge(&self) -> &ValidatedBridge {
+        &self.bridge
+    }
+
+    /// what this bridge's lights looked like before anything was queued for them
+    pub fn snapshots(&self) -> &Snapshots {
+        &self.snapshots
+    }
+
+    pub fn set_light(&self, light: usize, command: CommandLight) {
+        let _ = self.writes_tx.send(Write::Light(light, command));
+    }
+
+    /// Every light in a room, zone or other group at once, or every light on the bridge for
+    /// group 0.
+    pub fn set_group(&self, group: usize, command: CommandLight) {
+        let _ = self.writes_tx.send(Write::Group(group, command));
+    }
+}
+
+/// Commands waiting their turn, at most one per ID, oldest first.
+#[derive(Default)]
+struct Pending {
+    order: VecDeque<usize>,
+    commands: HashMap<usize, CommandLight>,
+}
+
+impl Pending {
+    /// Returns true if this replaced a command that was already waiting.
+    fn push(&mut self, id: usize, command: CommandLight) -> bool {
+        match self.commands.remove(&id) {
+            Some(older) => {
+                self.commands.insert(id, merge(older, command));
+                true
+            }
+            None => {
+                self.order.push_back(id);
+                self.commands.insert(id, command);
+                false
+            }
+        }
+    }
+
+    fn remove(&mut self, id: usize) -> Option<CommandLight> {
+        self.order.retain(|waiting| *waiting != id);
+        self.commands.remove(&id)
+    }
+
+    fn pop(&mut self) -> Option<(usize, CommandLight)> {
+        let id = self.order.pop_front()?;
+        self.commands.remove(&id).map(|command| (id, command))
+    }
+
+    fn len(&self) -> usize {
+        self.order.len()
+    }
+
+    /// The command, if every ID in `ids` (and nothing else) is waiting on the same one.
+    fn shared_by_exactly(&self, ids: &BTreeSet<usize>) -> Option<CommandLight> {
+        if ids.is_empty()
+            || self.len() != ids.len()
+            || !ids.iter().all(|id| self.commands.contains_key(id))
+        {
+            return None;
+        }
+        let mut commands = self
+            .commands
+            .values()
+            .map(|command| serde_json::to_value(command).ok());
+        let first = commands.next()??;
+        match commands.all(|command| command.as_ref() == Some(&first)) {
+            true => self.commands.values().next().cloned(),
+            false => None,
+        }
+    }
+
+    fn clear(&mut self) {
+        self.order.clear();
+        self.commands.clear();
+    }
+}
+
+/// What `older` then `newer` would have done, as one command. A newer colour replaces the
+/// older one outright, since the bridge would pick xy over ct over hue/sat if they were mixed.
+fn merge(older: CommandLight, newer: CommandLight) -> CommandLight {
+    let colored = |command: &CommandLight| {
+        command.hue.is_some()
+            || command.sat.is_some()
+            || command.ct.is_some()
+            || command.xy.is_some()
+    };
+    let color = match colored(&newer) {
+        true => &newer,
+        false => &older,
+    };
+    CommandLight {
+        on: newer.on.or(older.on),
+        bri: newer.bri.or(older.bri),
+        hue: color.hue,
+        sat: color.sat,
+        ct: color.ct,
+        xy: color.xy,
+        transitiontime: newer.transitiontime.or(older.transitiontime),
+        alert: newer.alert.or(older.alert),
+        scene: newer.scene.or(older.scene),
+    }
+}
+
+/// Sends queued commands as fast as the bridge can take them, until shutdown or until nobody
+/// can queue any more.
+struct QueueTask {
+    bridge: ValidatedBridge,
+    writes_rx: Receiver<Write>,
+    events_tx: Sender<LightEvent>,
+    lights: Pending,
+    groups: Pending,
+    light_bucket: TokenBucket,
+    group_bucket: TokenBucket,
+    /// every light on the bridge, for spotting commands that could go to group 0 instead
+    all_lights: BTreeSet<usize>,
+    /// the lights in every other group, for keeping group and light commands in order
+    group_members: HashMap<usize, BTreeSet<usize>>,
+    stats: QueueStats,
+    reported: QueueStats,
+    /// so a bridge that's gone away is reported once, not once per command
+    failing: bool,
+}
+
+impl QueueTask {
+    fn new(
+        bridge: ValidatedBridge,
+        writes_rx: Receiver<Write>,
+        events_tx: Sender<LightEvent>,
+    ) -> Self {
+        let now = Instant::now();
+        Self {
+            bridge,
+            writes_rx,
+            events_tx,
+            lights: Pending::default(),
+            groups: Pending::default(),
+            light_bucket: TokenBucket::new(LIGHT_RATE, LIGHT_BURST, now),
+            group_bucket: TokenBucket::new(GROUP_RATE, GROUP_BURST, now),
+            all_lights: BTreeSet::new(),
+            group_members: HashMap::new(),
+            stats: QueueStats::default(),
+            reported: QueueStats::default(),
+            failing: false,
+        }
+    }
+
+    /// the lights in `group`, if we know them
+    fn members(&self, group: usize) -> Option<&BTreeSet<usize>> {
+        match group {
+            ALL_LIGHTS => Some(&self.all_lights),
+            _ => self.group_members.get(&group),
+        }
+    }
+
+    /// Queues `write`, keeping lights and groups in order: whichever command came last for a
+    /// light is the one it ends up following.
+    fn push(&mut self, write: Write) {
+        let superseded = match write {
+            Write::Light(id, command) => {
+                // a waiting group command would otherwise go out after this one and undo it
+                let groups: Vec<usize> = self.groups.order.iter().copied().collect();
+                for group in groups {
+                    if self
+                        .members(group)
+                        .is_some_and(|members| members.contains(&id))
+                    {
+                        self.expand(group);
+                    }
+                }
+                self.lights.push(id, command)
+            }
+            Write::Group(id, command) => {
+                // lights with commands waiting take this on top, whichever goes out first
+                let members = self.members(id).cloned().unwrap_or_default();
+                for light in members {
+                    if let Some(waiting) = self.lights.commands.remove(&light) {
+                        let merged = merge(waiting, command.clone());
+                        self.lights.commands.insert(light, merged);
+                    }
+                }
+                self.groups.push(id, command)
+            }
+        };
+        if superseded {
+            self.stats.dropped += 1;
+        }
+    }
+
+    /// Turns a waiting group command into one command per light.
+    fn expand(&mut self, group: usize) {
+        let members = self.members(group).cloned().unwrap_or_default();
+        if let Some(command) = self.groups.remove(group) {
+            for light in members {
+                self.lights.push(light, command.clone());
+            }
+        }
+    }
+
+    /// how long until something waiting could go out
+    fn wait(&mut self, now: Instant) -> Duration {
+        let mut wait = WAKE_INTERVAL;
+        if self.lights.len() > 0 {
+            wait = wait.min(self.light_bucket.wait(now));
+        }
+        if self.groups.len() > 0 {
+            wait = wait.min(self.group_bucket.wait(now));
+        }
+        wait
+    }
+
+    /// Sends whatever the buckets allow.
+    fn flush(&mut self, now: Instant) {
+        if let Some(command) = self.lights.shared_by_exactly(&self.all_lights) {
+            if self.group_bucket.try_take(now) {
+                self.lights.clear();
+                let result = self.bridge.set_group_state(ALL_LIGHTS, &command);
+                self.check(result);
+            }
+        }
+        while self.groups.len() > 0 && self.group_bucket.try_take(now) {
+            if let Some((id, command)) = self.groups.pop() {
+                let result = self.bridge.set_group_state(id, &command);
+                self.check(result);
+            }
+        }
+        while self.lights.len() > 0 && self.light_bucket.try_take(now) {
+            if let Some((id, command)) = self.lights.pop() {
+                let result = self.bridge.set_light_state(id, &command);
+                self.check(result);
+            }
+        }
+        self.stats.depth = self.lights.len() + self.groups.len();
+    }
+
+    fn check<T>(&mut self, result: hueclient::Result<T>) {
+        match result {
+            Ok(_) => self.failing = false,
+            Err(e) if !self.failing => {
+                self.failing = true;
+                let _ = self.events_tx.send(LightEvent::BridgeFailed {
+                    bridge: self.bridge.id().to_string(),
+                    reason: e.to_string(),
+                });
+            }
+            Err(_) => {}
+        }
+    }
+
+    fn report(&mut self) {
+        if self.stats != self.reported {
+            self.reported = self.stats;
+            let _ = self.events_tx.send(LightEvent::Queue {
+                bridge: self.bridge.id().to_string(),
+                stats: self.stats,
+            });
+        }
+    }
+}
+
+impl Task for QueueTask {
+    type Result = ();
+    type OnCompleteParams = ();
+
+    fn run_task(mut self) -> anyhow::Result<()> {
+        // without the list, commands just go out one light at a time
+        if let Ok(lights) = self.bridge.get_all_lights() {
+            self.all_lights = lights.iter().map(|light| light.id).collect();
+        }
+        // and without this, a light command can be undone by a group command sent before it
+        if let Ok(groups) = self.bridge.get_groups() {
+            self.group_members = groups
+                .into_iter()
+                .map(|group| (group.id, group.lights.into_iter().collect()))
+                .collect();
+        }
+
+        // once nobody can queue anything, what's already waiting still goes out, since that's
+        // often lights being put back after an effect
+        let mut open = true;
+        loop {
+            let wait = self.wait(Instant::now());
+            match open {
+                true => match self.writes_rx.recv_timeout(wait) {
+                    Ok(write) => self.push(write),
+                    Err(RecvTimeoutError::Timeout) => {}
+                    Err(RecvTimeoutError::Disconnected) => open = false,
+                },
+                false => thread::sleep(wait),
+            }
+            while let Ok(write) = self.writes_rx.try_recv() {
+                self.push(write);
+            }
+            if shutdown::requested() {
+                return Ok(());
+            }
+
+            self.flush(Instant::now());
+            self.report();
+            if !open && self.stats.depth == 0 {
+                return Ok(());
+            }
+        }
+    }
+
+    fn on_complete(_r: anyhow::Result<()>, _p: ()) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::harness::wait_until;
+    use crate::hue::mock::MockBridge;
+
+    fn bri(bri: u8) -> CommandLight {
+        CommandLight::default().with_bri(bri)
+    }
+
+    /// The queue and its task, which isn't running, so tests hand it writes and flush it when
+    /// they choose. It knows every light on the bridge, for spotting group 0 commands.
+    fn queue(mock: &MockBridge, events_tx: Sender<LightEvent>) -> (BridgeQueue, QueueTask) {
+        let (queue, mut task) =
+            BridgeQueue::new(mock.validated_bridge(), Snapshots::default(), events_tx);
+        task.all_lights = mock.light_ids().into_iter().collect();
+        (queue, task)
+    }
+
+    #[test]
+    fn newer_commands_replace_waiting_ones() {
+        let mock = MockBridge::start();
+        let (events_tx, events) = crossbeam_channel::unbounded();
+        let (queue, mut task) = queue(&mock, events_tx);
+
+        queue.set_light(1, CommandLight::default().off());
+        for level in 1..=5 {
+            queue.set_light(1, bri(level));
+        }
+        while let Ok(write) = task.writes_rx.try_recv() {
+            task.push(write);
+        }
+        task.flush(Instant::now());
+        task.report();
+
+        assert_eq!(mock.light_writes(), 1);
+        let state = mock.light_state(1).unwrap();
+        // merged, so the first command's `off` still happened
+        assert_eq!((state.on, state.bri), (false, Some(5)));
+        assert_eq!(
+            events.try_recv(),
+            Ok(LightEvent::Queue {
+                bridge: mock.info().bridgeid,
+                stats: QueueStats {
+                    depth: 0,
+                    dropped: 5
+                }
+            })
+        );
+    }
+
+    #[test]
+    fn newer_colours_replace_older_ones() {
+        let xy = CommandLight::default().with_xy(0.15, 0.06).with_bri(254);
+        let hs = CommandLight::default().with_hue(8418).with_sat(140);
+        let merged = merge(xy, hs.clone());
+        assert_eq!(
+            (merged.hue, merged.sat, merged.xy, merged.bri),
+            (Some(8418), Some(140), None, Some(254))
+        );
+        assert_eq!(merge(hs, CommandLight::default().off()).hue, Some(8418));
+    }
+
+    #[test]
+    fn keeps_to_the_rate_limit() {
+        let mock = MockBridge::start();
+        let (queue, mut task) = queue(&mock, crossbeam_channel::unbounded().0);
+        let start = Instant::now();
+        task.light_bucket = TokenBucket::new(20.0, 1.0, start);
+        thread::spawn(move || task.run_task());
+
+        for (i, id) in [1, 2, 3, 1, 2].into_iter().enumerate() {
+            queue.set_light(id, bri(i as u8 + 1));
+        }
+        let bri = |id| mock.light_state(id).unwrap().bri;
+        assert!(
+            wait_until(|| (bri(1), bri(2), bri(3)) == (Some(4), Some(5), Some(3))),
+            "gave up waiting for every light"
+        );
+        // at least three writes: one straight away, then one every 50ms
+        assert!(start.elapsed() >= Duration::from_millis(100));
+        assert!(mock.light_writes() <= 5);
+    }
+
+    #[test]
+    fn sends_the_same_command_for_every_light_as_one() {
+        let mock = MockBridge::start();
+        let (queue, mut task) = queue(&mock, crossbeam_channel::unbounded().0);
+
+        for id in mock.light_ids() {
+            queue.set_light(id, bri(42));
+        }
+        while let Ok(write) = task.writes_rx.try_recv() {
+            task.push(write);
+        }
+        task.flush(Instant::now());
+        assert_eq!((mock.group_writes(), mock.light_writes()), (1, 0));
+
+        // not quite everything
+        for id in mock.light_ids().into_iter().skip(1) {
+            queue.set_light(id, bri(7));
+        }
+        while let Ok(write) = task.writes_rx.try_recv() {
+            task.push(write);
+        }
+        task.flush(Instant::now());
+        assert_eq!((mock.group_writes(), mock.light_writes()), (1, 2));
+    }
+
+    #[test]
+    fn whatever_came_last_wins() {
+        let mock = MockBridge::start();
+        let (queue, mut task) = queue(&mock, crossbeam_channel::unbounded().0);
+        let flush = |task: &mut QueueTask, at: Instant| {
+            while let Ok(write) = task.writes_rx.try_recv() {
+                task.push(write);
+            }
+            task.flush(at);
+        };
+        let start = Instant::now();
+
+        // a light, then everything
+        queue.set_light(1, CommandLight::default().with_hue(100).with_bri(3));
+        queue.set_group(ALL_LIGHTS, bri(9));
+        flush(&mut task, start);
+        let state = mock.light_state(1).unwrap();
+        assert_eq!((state.hue, state.bri), (Some(100), Some(9)));
+
+        // everything, then a light
+        queue.set_group(ALL_LIGHTS, bri(20));
+        queue.set_light(1, bri(4));
+        flush(&mut task, start + Duration::from_secs(1));
+        let brightness: Vec<_> = mock
+            .light_ids()
+            .into_iter()
+            .map(|id| mock.light_state(id).unwrap().bri)
+            .collect();
+        assert_eq!(brightness, vec![Some(4), Some(20), Some(20)]);
+    }
+
+    #[test]
+    fn reports_failures_once() {
+        let mock = MockBridge::start();
+        let (events_tx, events) = crossbeam_channel::unbounded();
+        let (queue, mut task) = queue(&mock, events_tx);
+        drop(mock);
+
+        queue.set_light(1, bri(1));
+        queue.set_light(2, bri(1));
+        while let Ok(write) = task.writes_rx.try_recv() {
+            task.push(write);
+        }
+        task.flush(Instant::now());
+        let failures = events
+            .try_iter()
+            .filter(|event| matches!(event, LightEvent::BridgeFailed { .. }))
+            .count();
+        assert_eq!(failures, 1);
+    }
+
+    #[test]
+    fn stops_once_nobody_can_queue_anything() {
+        let mock = MockBridge::start();
+        let (queue, task) = queue(&mock, crossbeam_channel::unbounded().0);
+        let thread = thread::spawn(move || task.run_task());
+        // but not before what's already queued has gone out
+        for id in [1, 2, 3, 1, 2, 3] {
+            queue.set_light(id, bri(id as u8 * 10));
+        }
+        drop(queue);
+        thread.join().unwrap().unwrap();
+        assert_eq!(mock.light_state(3).unwrap().bri, Some(30));
+    }
+}
@@ -0,0 +1,454 @@
+//! Colour science: getting between the sRGB the TUI and chat think in and what the bulbs
+//! speak, which is CIE xy plus brightness, hue/sat, or colour temperature in mirek. Each
+//! family of colour bulbs can only show the colours inside its own triangle (gamut) of xy
+//! space, so colours are clamped to the light's gamut, found by its model ID.
+//! https://developers.meethue.com/develop/application-design-guidance/color-conversion-formulas-rgb-to-xy-and-back/
+
+use crate::hue::{ColorMode, FullLightState};
+use crate::lights::Rgb;
+use hueclient::CommandLight;
+
+/// the warmest and coolest whites the bulbs do, 2000K and about 6500K
+pub const WARMEST_MIREK: u16 = 500;
+pub const COOLEST_MIREK: u16 = 153;
+/// the sRGB white point, D65, which is where black ends up too
+const WHITE_POINT: (f32, f32) = (0.3127, 0.3290);
+
+/// A colour by hue in degrees, then saturation and value from 0 to 1.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Hsv {
+    pub hue: f32,
+    pub sat: f32,
+    pub value: f32,
+}
+
+impl Hsv {
+    pub fn rgb(&self) -> Rgb {
+        let h = self.hue.rem_euclid(360.0) / 60.0;
+        let x = 1.0 - (h % 2.0 - 1.0).abs();
+        let (r, g, b) = match h as u8 {
+            0 => (1.0, x, 0.0),
+            1 => (x, 1.0, 0.0),
+            2 => (0.0, 1.0, x),
+            3 => (0.0, x, 1.0),
+            4 => (x, 0.0, 1.0),
+            _ => (1.0, 0.0, x),
+        };
+        // desaturating mixes in white
+        let byte = |c: f32| ((1.0 - self.sat + c * self.sat) * self.value * 255.0).round() as u8;
+        Rgb(byte(r), byte(g), byte(b))
+    }
+}
+
+impl From<Rgb> for Hsv {
+    fn from(rgb: Rgb) -> Self {
+        let [r, g, b] = [rgb.0, rgb.1, rgb.2].map(|c| c as f32 / 255.0);
+        let max = r.max(g).max(b);
+        let min = r.min(g).min(b);
+        let delta = max - min;
+
+        let hue = if delta == 0.0 {
+            0.0
+        } else if max == r {
+            60.0 * ((g - b) / delta).rem_euclid(6.0)
+        } else if max == g {
+            60.0 * ((b - r) / delta + 2.0)
+        } else {
+            60.0 * ((r - g) / delta + 4.0)
+        };
+        Self {
+            hue,
+            sat: if max == 0.0 { 0.0 } else { delta / max },
+            value: max,
+        }
+    }
+}
+
+fn to_linear(c: f32) -> f32 {
+    match c <= 0.04045 {
+        true => c / 12.92,
+        false => ((c + 0.055) / 1.055).powf(2.4),
+    }
+}
+
+fn from_linear(c: f32) -> f32 {
+    match c <= 0.0031308 {
+        true => 12.92 * c,
+        false => 1.055 * c.powf(1.0 / 2.4) - 0.055,
+    }
+}
+
+impl Rgb {
+    /// The colour's CIE xy, and its brightness from 0 to 1. The brightness is the brightest
+    /// channel's, like hue/sat commands use, rather than the luminance, which would leave
+    /// pure blue barely lit.
+    pub fn xy(&self) -> ((f32, f32), f32) {
+        let [r, g, b] = [self.0, self.1, self.2].map(|c| to_linear(c as f32 / 255.0));
+        let x = 0.4124564 * r + 0.3575761 * g + 0.1804375 * b;
+        let y = 0.2126729 * r + 0.7151522 * g + 0.0721750 * b;
+        let z = 0.0193339 * r + 0.119192 * g + 0.9503041 * b;
+        let sum = x + y + z;
+        let brightness = self.0.max(self.1).max(self.2) as f32 / 255.0;
+        match sum > 0.0 {
+            true => ((x / sum, y / sum), brightness),
+            false => (WHITE_POINT, brightness),
+        }
+    }
+
+    /// The brightest sRGB colour at `xy`, dimmed to `brightness` from 0 to 1. Colours sRGB
+    /// can't show come out as the nearest it can.
+    pub fn from_xy((x, y): (f32, f32), brightness: f32) -> Self {
+        // at luminance 1, since it's scaled to the brightest channel anyway
+        let y = y.max(f32::EPSILON);
+        let (big_x, big_z) = (x / y, (1.0 - x - y) / y);
+        let linear = [
+            3.2404542 * big_x - 1.5371385 - 0.4985314 * big_z,
+            -0.969266 * big_x + 1.8760108 + 0.041556 * big_z,
+            0.0556434 * big_x - 0.2040259 + 1.0572252 * big_z,
+        ]
+        .map(|c| c.max(0.0));
+        let max = linear[0].max(linear[1]).max(linear[2]);
+        let [r, g, b] = linear.map(|c| {
+            let c = if max > 0.0 { c / max } else { 1.0 };
+            (from_linear(c) * brightness.clamp(0.0, 1.0) * 255.0).round() as u8
+        });
+        Rgb(r, g, b)
+    }
+}
+
+/// The white at `mirek` (a million over the temperature in kelvin), along the Planckian
+/// locus as Kim et al. approximate it.
+pub fn mirek_to_xy(mirek: u16) -> (f32, f32) {
+    let t = 1_000_000.0 / mirek.clamp(COOLEST_MIREK, WARMEST_MIREK) as f32;
+    let x = match t <= 4000.0 {
+        true => -0.2661239e9 / t.powi(3) - 0.2343589e6 / t.powi(2) + 0.8776956e3 / t + 0.17991,
+        false => -3.0258469e9 / t.powi(3) + 2.107038e6 / t.powi(2) + 0.2226347e3 / t + 0.24039,
+    };
+    let y = if t <= 2222.0 {
+        -1.1063814 * x.powi(3) - 1.3481102 * x.powi(2) + 2.185558 * x - 0.20219683
+    } else if t <= 4000.0 {
+        -0.9549476 * x.powi(3) - 1.374186 * x.powi(2) + 2.09137 * x - 0.16748867
+    } else {
+        3.081758 * x.powi(3) - 5.873387 * x.powi(2) + 3.75113 * x - 0.37001483
+    };
+    (x, y)
+}
+
+/// The nearest white the bulbs do to `xy`, by McCamy's approximation. Anything that isn't
+/// close to white gets a rough answer.
+pub fn xy_to_mirek((x, y): (f32, f32)) -> u16 {
+    let n = (x - 0.3320) / (0.1858 - y);
+    let kelvin = 449.0 * n.powi(3) + 3525.0 * n.powi(2) + 6823.3 * n + 5520.33;
+    match kelvin > 0.0 {
+        true => (1_000_000.0 / kelvin)
+            .round()
+            .clamp(COOLEST_MIREK as f32, WARMEST_MIREK as f32) as u16,
+        false => WARMEST_MIREK,
+    }
+}
+
+/// The triangle of xy a family of bulbs can show, by its red, green and blue corners.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Gamut {
+    pub red: (f32, f32),
+    pub green: (f32, f32),
+    pub blue: (f32, f32),
+}
+
+/// the first LivingColors and LightStrips
+pub const GAMUT_A: Gamut = Gamut {
+    red: (0.704, 0.296),
+    green: (0.2151, 0.7106),
+    blue: (0.138, 0.08),
+};
+/// the first few generations of Hue bulbs
+pub const GAMUT_B: Gamut = Gamut {
+    red: (0.675, 0.322),
+    green: (0.409, 0.518),
+    blue: (0.167, 0.04),
+};
+/// everything since
+pub const GAMUT_C: Gamut = Gamut {
+    red: (0.6915, 0.3038),
+    green: (0.17, 0.7),
+    blue: (0.1532, 0.0475),
+};
+
+const GAMUT_A_MODELS: &[&str] = &[
+    "LLC001", "LLC005", "LLC006", "LLC007", "LLC010", "LLC011", "LLC012", "LLC013", "LLC014",
+    "LST001",
+];
+const GAMUT_B_MODELS: &[&str] = &["LCT001", "LCT002", "LCT003", "LCT007", "LLM001"];
+
+/// how far along from `a` to `b` the nearest point to `p` is, from 0 to 1
+fn along(p: (f32, f32), a: (f32, f32), b: (f32, f32)) -> (f32, f32) {
+    let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+    let length = dx * dx + dy * dy;
+    let t = match length > 0.0 {
+        true => (((p.0 - a.0) * dx + (p.1 - a.1) * dy) / length).clamp(0.0, 1.0),
+        false => 0.0,
+    };
+    (a.0 + t * dx, a.1 + t * dy)
+}
+
+/// which side of the line from `a` to `b` `p` is on
+fn side(p: (f32, f32), a: (f32, f32), b: (f32, f32)) -> f32 {
+    (b.0 - a.0) * (p.1 - a.1) - (b.1 - a.1) * (p.0 - a.0)
+}
+
+impl Gamut {
+    /// Models that aren't known to be older are taken to be gamut C, like every colour
+    /// light made since.
+    pub fn for_model(model: &str) -> Self {
+        if GAMUT_A_MODELS.contains(&model) {
+            GAMUT_A
+        } else if GAMUT_B_MODELS.contains(&model) {
+            GAMUT_B
+        } else {
+            GAMUT_C
+        }
+    }
+
+    pub fn contains(&self, xy: (f32, f32)) -> bool {
+        // a little leeway, so points clamped onto an edge count as inside
+        const LEEWAY: f32 = 1e-6;
+        let sides = [
+            side(xy, self.red, self.green),
+            side(xy, self.green, self.blue),
+            side(xy, self.blue, self.red),
+        ];
+        sides.iter().all(|s| *s >= -LEEWAY) || sides.iter().all(|s| *s <= LEEWAY)
+    }
+
+    /// `xy`, or the nearest colour the bulbs can show if they can't show it.
+    pub fn clamp(&self, xy: (f32, f32)) -> (f32, f32) {
+        if self.contains(xy) {
+            return xy;
+        }
+        let distance = |p: &(f32, f32)| (p.0 - xy.0).hypot(p.1 - xy.1);
+        [
+            along(xy, self.red, self.green),
+            along(xy, self.green, self.blue),
+            along(xy, self.blue, self.red),
+        ]
+        .into_iter()
+        .min_by(|a, b| distance(a).total_cmp(&distance(b)))
+        .unwrap_or(xy)
+    }
+}
+
+/// What a light can be told to show.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum LightKind {
+    Color(Gamut),
+    /// whites from warm to cool
+    Ambiance,
+    /// brightness only
+    Dimmable,
+}
+
+impl LightKind {
+    /// Going by what the light reports: colour lights report xy or hue, ambiance lights only
+    /// a colour temperature, and dimmable lights neither.
+    pub fn of(model: &str, state: &FullLightState) -> Self {
+        if state.xy.is_some() || state.hue.is_some() {
+            LightKind::Color(Gamut::for_model(model))
+        } else if state.ct.is_some() {
+            LightKind::Ambiance
+        } else {
+            LightKind::Dimmable
+        }
+    }
+
+    /// The nearest a light like this gets to `color`.
+    pub fn command(&self, color: Rgb) -> CommandLight {
+        let (xy, brightness) = color.xy();
+        // 0 isn't off, just very dim, so black is as close as the lights get
+        let command = CommandLight::default()
+            .on()
+            .with_bri((brightness * 254.0).round().max(1.0) as u8);
+        match self {
+            LightKind::Color(gamut) => {
+                let (x, y) = gamut.clamp(xy);
+                command.with_xy(x, y)
+            }
+            LightKind::Ambiance => command.with_ct(xy_to_mirek(xy)),
+            LightKind::Dimmable => command,
+        }
+    }
+}
+
+/// The colour a light is showing at full brightness, going by its colour mode. Lights that
+/// don't report one are white, or as warm as their colour temperature.
+pub fn shown(state: &FullLightState, gamut: Gamut) -> Rgb {
+    let by_xy = state.xy.map(|xy| Rgb::from_xy(gamut.clamp(xy), 1.0));
+    let by_hs = state.hue.zip(state.sat).map(|(hue, sat)| {
+        Hsv {
+            hue: hue as f32 / 65535.0 * 360.0,
+            sat: sat as f32 / 254.0,
+            value: 1.0,
+        }
+        .rgb()
+    });
+    let by_ct = state.ct.map(|ct| Rgb::from_xy(mirek_to_xy(ct), 1.0));
+    let by_mode = match state.colormode {
+        Some(ColorMode::Xy) => by_xy,
+        Some(ColorMode::Hs) => by_hs,
+        Some(ColorMode::Ct) => by_ct,
+        None => None,
+    };
+    by_mode
+        .or(by_xy)
+        .or(by_hs)
+        .or(by_ct)
+        .unwrap_or(Rgb(255, 255, 255))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: (f32, f32), b: (f32, f32)) -> bool {
+        (a.0 - b.0).abs() < 0.002 && (a.1 - b.1).abs() < 0.002
+    }
+
+    fn state(colormode: Option<ColorMode>) -> FullLightState {
+        FullLightState {
+            on: true,
+            bri: Some(254),
+            hue: Some(0),
+            sat: Some(254),
+            ct: Some(153),
+            xy: Some((0.17, 0.7)),
+            colormode,
+        }
+    }
+
+    #[test]
+    fn goes_between_rgb_and_hsv() {
+        let orange = Hsv::from(Rgb(255, 128, 0));
+        assert_eq!(orange.hue.round(), 30.0);
+        assert_eq!((orange.sat, orange.value), (1.0, 1.0));
+        assert_eq!(orange.rgb(), Rgb(255, 128, 0));
+
+        let dark_grey = Hsv {
+            hue: 200.0,
+            sat: 0.0,
+            value: 0.25,
+        };
+        assert_eq!(dark_grey.rgb(), Rgb(64, 64, 64));
+    }
+
+    #[test]
+    fn goes_between_rgb_and_xy() {
+        // the sRGB primaries and white point
+        assert!(close(Rgb(255, 0, 0).xy().0, (0.64, 0.33)));
+        assert!(close(Rgb(0, 255, 0).xy().0, (0.30, 0.60)));
+        assert!(close(Rgb(0, 0, 255).xy().0, (0.15, 0.06)));
+        assert!(close(Rgb(255, 255, 255).xy().0, WHITE_POINT));
+        assert_eq!(Rgb(0, 0, 0).xy(), (WHITE_POINT, 0.0));
+        assert_eq!(Rgb(0, 0, 128).xy().1, 128.0 / 255.0);
+
+        for rgb in [
+            Rgb(255, 0, 0),
+            Rgb(255, 128, 0),
+            Rgb(128, 0, 255),
+            Rgb(0, 255, 255),
+        ] {
+            let (xy, brightness) = rgb.xy();
+            let back = Rgb::from_xy(xy, brightness);
+            let off_by = |a: u8, b: u8| (a as i16 - b as i16).abs();
+            assert!(
+                off_by(back.0, rgb.0) <= 1 && off_by(back.1, rgb.1) <= 1,
+                "{:?} came back as {:?}",
+                rgb,
+                back
+            );
+            assert!(off_by(back.2, rgb.2) <= 1);
+        }
+    }
+
+    #[test]
+    fn goes_between_xy_and_colour_temperature() {
+        // about 6500K, sRGB's white
+        assert_eq!(xy_to_mirek(WHITE_POINT), 154);
+        let warm = mirek_to_xy(WARMEST_MIREK);
+        assert!(close(warm, (0.5267, 0.4133)));
+        assert_eq!(xy_to_mirek(warm), 500);
+        assert!(xy_to_mirek(mirek_to_xy(366)).abs_diff(366) <= 1);
+        // out of range either way
+        assert_eq!(mirek_to_xy(100), mirek_to_xy(COOLEST_MIREK));
+        assert_eq!(xy_to_mirek((0.6, 0.4)), WARMEST_MIREK);
+        assert_eq!(xy_to_mirek((0.25, 0.25)), COOLEST_MIREK);
+    }
+
+    #[test]
+    fn clamps_to_the_gamut() {
+        assert_eq!(Gamut::for_model("LLC010"), GAMUT_A);
+        assert_eq!(Gamut::for_model("LCT001"), GAMUT_B);
+        assert_eq!(Gamut::for_model("LCT015"), GAMUT_C);
+        assert_eq!(Gamut::for_model("LCX004"), GAMUT_C);
+
+        let warm_white = (0.4573, 0.41);
+        assert_eq!(GAMUT_B.clamp(warm_white), warm_white);
+        // blue's just outside gamut B, so it goes to the nearest point on its edge
+        let blue = Rgb(0, 0, 255).xy().0;
+        assert!(!GAMUT_B.contains(blue));
+        let clamped = GAMUT_B.clamp(blue);
+        assert!(GAMUT_B.contains(clamped));
+        assert!(close(clamped, (0.1716, 0.0491)));
+        // and green's past its green corner, so it goes to the corner
+        assert_eq!(GAMUT_B.clamp(Rgb(0, 255, 0).xy().0), GAMUT_B.green);
+    }
+
+    #[test]
+    fn speaks_each_lights_language() {
+        let colour = state(Some(ColorMode::Xy));
+        let blue = Rgb(0, 0, 128);
+        let command = LightKind::of("LCT001", &colour).command(blue);
+        assert_eq!(command.bri, Some(127));
+        assert!(GAMUT_B.contains(command.xy.unwrap()));
+        assert_eq!((command.hue, command.ct), (None, None));
+
+        let ambiance = FullLightState {
+            hue: None,
+            sat: None,
+            xy: None,
+            ..colour
+        };
+        let command = LightKind::of("LTW001", &ambiance).command(Rgb(255, 255, 255));
+        assert_eq!((command.ct, command.xy), (Some(154), None));
+
+        let dimmable = FullLightState {
+            ct: None,
+            ..ambiance
+        };
+        assert_eq!(LightKind::of("LWB010", &dimmable), LightKind::Dimmable);
+        let command = LightKind::Dimmable.command(Rgb(255, 0, 0));
+        assert_eq!(
+            (command.on, command.bri, command.ct),
+            (Some(true), Some(254), None)
+        );
+    }
+
+    #[test]
+    fn shows_what_the_light_shows() {
+        // the same light showing red, cool white or green, depending on its mode
+        assert_eq!(shown(&state(Some(ColorMode::Hs)), GAMUT_C), Rgb(255, 0, 0));
+        let white = shown(&state(Some(ColorMode::Ct)), GAMUT_C);
+        assert!(white.0 > 240 && white.1 > 240 && white.2 > 240);
+        let green = shown(&state(Some(ColorMode::Xy)), GAMUT_C);
+        assert!(green.1 == 255 && green.0 < 100);
+        // a light that asked for more green than it has shows what it can instead
+        assert_ne!(shown(&state(Some(ColorMode::Xy)), GAMUT_B), green);
+
+        let dimmable = FullLightState {
+            hue: None,
+            sat: None,
+            ct: None,
+            xy: None,
+            ..state(None)
+        };
+        assert_eq!(shown(&dimmable, GAMUT_C), Rgb(255, 255, 255));
+    }
+}
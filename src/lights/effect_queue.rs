@@ -0,0 +1,519 @@
+//! Decides what plays when effects are triggered faster than they can play, say a raid in
+//! the middle of a sub train. One effect plays at a time. Each one that's triggered says how
+//! much it matters, how long it's willing to wait, and what to do if something's already
+//! playing.
+
+use crate::commands::{parse_effect, parse_scene};
+use crate::lights::effects::Effect;
+use crate::lights::LightAction;
+use crate::twitch::TwitchEvent;
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// What to do with an effect that's triggered while another is playing.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Policy {
+    /// cut off the playing effect if it matters less, otherwise wait like `Queue`
+    Preempt,
+    /// wait for a turn, the ones that matter most first
+    Queue,
+    /// play now or not at all
+    DropIfBusy,
+    /// make the same effect play for longer if it's playing or waiting, otherwise wait like
+    /// `Queue`
+    Merge,
+}
+
+impl fmt::Display for Policy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Policy::Preempt => "preempt",
+            Policy::Queue => "queue",
+            Policy::DropIfBusy => "drop if busy",
+            Policy::Merge => "merge",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// An effect someone or something triggered.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EffectRequest {
+    pub effect: Effect,
+    /// higher goes first
+    pub priority: u8,
+    /// how long it may wait for its turn before it's dropped
+    pub max_wait: Duration,
+    pub policy: Policy,
+    /// what triggered it, e.g. "raid from dwbrite"
+    pub reason: String,
+}
+
+/// How one kind of trigger plays its effect.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EffectRule {
+    /// as it would follow !effect, e.g. "pulse purple", or "scene Relax" to recall a scene
+    /// saved on the bridges, or empty to do nothing. Chat commands say their own
+    pub effect: String,
+    pub priority: u8,
+    pub max_wait_secs: u64,
+    pub policy: Policy,
+}
+
+impl EffectRule {
+    fn new(effect: &str, priority: u8, max_wait_secs: u64, policy: Policy) -> Self {
+        Self {
+            effect: effect.to_string(),
+            priority,
+            max_wait_secs,
+            policy,
+        }
+    }
+
+    /// `effect` played by this rule's priority, wait and policy
+    pub fn request(&self, effect: Effect, reason: String) -> EffectRequest {
+        EffectRequest {
+            effect,
+            priority: self.priority,
+            max_wait: Duration::from_secs(self.max_wait_secs),
+            policy: self.policy,
+            reason,
+        }
+    }
+
+    /// None if there's nothing to do, or it doesn't parse
+    fn trigger(&self, reason: String) -> Option<Triggered> {
+        let action = match self.effect.split_once(' ') {
+            Some(("scene", name)) => parse_scene(name),
+            _ => parse_effect(&self.effect),
+        };
+        match action {
+            Ok(LightAction::Effect(effect)) => {
+                Some(Triggered::Effect(self.request(effect, reason)))
+            }
+            Ok(LightAction::Scene(name)) => Some(Triggered::Scene(name)),
+            _ => None,
+        }
+    }
+}
+
+/// What a Twitch event sets off.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Triggered {
+    Effect(EffectRequest),
+    /// recalled straight away, since it's over as soon as it's done
+    Scene(String),
+}
+
+/// A room, zone or other group that effects play on as a whole, instead of on every light.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EffectTarget {
+    pub bridge: String,
+    pub group: usize,
+    /// what it was called when it was picked
+    pub name: String,
+}
+
+/// Which effects Twitch events play, and how all effects get along.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EffectsConfig {
+    /// for !effect
+    pub command: EffectRule,
+    pub follow: EffectRule,
+    pub subscribe: EffectRule,
+    pub gift: EffectRule,
+    pub cheer: EffectRule,
+    pub raid: EffectRule,
+    /// where effects play, or every light if it's not set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target: Option<EffectTarget>,
+}
+
+impl Default for EffectsConfig {
+    fn default() -> Self {
+        Self {
+            command: EffectRule::new("", 1, 30, Policy::Queue),
+            follow: EffectRule::new("flash white", 2, 10, Policy::DropIfBusy),
+            // one long pulse for a sub train rather than a queue of them
+            subscribe: EffectRule::new("pulse purple", 5, 60, Policy::Merge),
+            gift: EffectRule::new("pulse purple", 5, 60, Policy::Merge),
+            cheer: EffectRule::new("strobe yellow", 4, 60, Policy::Queue),
+            raid: EffectRule::new("cycle", 9, 60, Policy::Preempt),
+            target: None,
+        }
+    }
+}
+
+impl EffectsConfig {
+    /// What a Twitch event plays or recalls, if anything.
+    pub fn trigger(&self, event: &TwitchEvent) -> Option<Triggered> {
+        let anonymous = || "someone anonymous".to_string();
+        let (rule, reason) = match event {
+            TwitchEvent::Follow { user } => (&self.follow, format!("follow from {}", user)),
+            TwitchEvent::Subscribe { user, .. } => (&self.subscribe, format!("sub from {}", user)),
+            TwitchEvent::GiftSub { gifter, count, .. } => (
+                &self.gift,
+                format!(
+                    "{} gift sub(s) from {}",
+                    count,
+                    gifter.clone().unwrap_or_else(anonymous)
+                ),
+            ),
+            TwitchEvent::Cheer { user, bits, .. } => (
+                &self.cheer,
+                format!(
+                    "{} bits from {}",
+                    bits,
+                    user.clone().unwrap_or_else(anonymous)
+                ),
+            ),
+            TwitchEvent::Raid { from, .. } => (&self.raid, format!("raid from {}", from)),
+            _ => return None,
+        };
+        rule.trigger(reason)
+    }
+}
+
+/// What the app should do about the effects it's playing.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum EffectOrder {
+    Start {
+        id: u64,
+        effect: Effect,
+    },
+    Stop {
+        id: u64,
+    },
+    /// play this many more cycles
+    Extend {
+        id: u64,
+        cycles: u32,
+    },
+}
+
+struct Playing {
+    id: u64,
+    request: EffectRequest,
+}
+
+struct Waiting {
+    request: EffectRequest,
+    since: Instant,
+}
+
+/// One effect's line in the queue panel.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueuedEffect {
+    pub reason: String,
+    pub effect: Effect,
+    pub priority: u8,
+    pub policy: Policy,
+    /// until it's dropped, or zero for the one that's playing
+    pub time_left: Duration,
+}
+
+/// The queue as the UI sees it.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EffectsStatus {
+    pub playing: Option<QueuedEffect>,
+    /// in the order they'll play
+    pub waiting: Vec<QueuedEffect>,
+    /// cut off, turned away while busy or given up waiting
+    pub dropped: u64,
+}
+
+#[derive(Default)]
+pub struct EffectQueue {
+    playing: Option<Playing>,
+    /// oldest first
+    waiting: Vec<Waiting>,
+    next_id: u64,
+    dropped: u64,
+}
+
+impl EffectQueue {
+    pub fn submit(&mut self, request: EffectRequest, now: Instant) -> Vec<EffectOrder> {
+        let playing = match &mut self.playing {
+            Some(playing) => playing,
+            None => return self.start(request),
+        };
+
+        match request.policy {
+            Policy::Preempt if request.priority > playing.request.priority => {
+                let stop = EffectOrder::Stop { id: playing.id };
+                self.dropped += 1;
+                let mut orders = vec![stop];
+                orders.extend(self.start(request));
+                orders
+            }
+            Policy::DropIfBusy => {
+                self.dropped += 1;
+                vec![]
+            }
+            Policy::Merge if playing.request.effect.kind == request.effect.kind => {
+                let cycles = request.effect.cycles.unwrap_or(0);
+                let effect = &mut playing.request.effect;
+                effect.cycles = effect.cycles.map(|n| n + cycles);
+                vec![EffectOrder::Extend {
+                    id: playing.id,
+                    cycles,
+                }]
+            }
+            Policy::Merge => {
+                let same = self
+                    .waiting
+                    .iter_mut()
+                    .find(|waiting| waiting.request.effect.kind == request.effect.kind);
+                match same {
+                    Some(waiting) => {
+                        let effect = &mut waiting.request.effect;
+                        effect.cycles =
+                            effect.cycles.zip(request.effect.cycles).map(|(a, b)| a + b);
+                    }
+                    None => self.waiting.push(Waiting {
+                        request,
+                        since: now,
+                    }),
+                }
+                vec![]
+            }
+            _ => {
+                self.waiting.push(Waiting {
+                    request,
+                    since: now,
+                });
+                vec![]
+            }
+        }
+    }
+
+    /// The effect with this ID has stopped playing, so the next one can start.
+    pub fn finished(&mut self, id: u64, now: Instant) -> Vec<EffectOrder> {
+        if self.playing.as_ref().map(|playing| playing.id) != Some(id) {
+            return vec![];
+        }
+        self.playing = None;
+        self.expire(now);
+        match self.next() {
+            Some(index) => {
+                let next = self.waiting.remove(index);
+                self.start(next.request)
+            }
+            None => vec![],
+        }
+    }
+
+    /// Drops effects that have waited too long. Returns true if there were any.
+    pub fn expire(&mut self, now: Instant) -> bool {
+        let before = self.waiting.len();
+        self.waiting.retain(|waiting| {
+            now.saturating_duration_since(waiting.since) <= waiting.request.max_wait
+        });
+        let expired = before - self.waiting.len();
+        self.dropped += expired as u64;
+        expired > 0
+    }
+
+    pub fn status(&self, now: Instant) -> EffectsStatus {
+        let line = |request: &EffectRequest, time_left| QueuedEffect {
+            reason: request.reason.clone(),
+            effect: request.effect,
+            priority: request.priority,
+            policy: request.policy,
+            time_left,
+        };
+
+        let mut order: Vec<&Waiting> = self.waiting.iter().collect();
+        order.sort_by_key(|waiting| Reverse(waiting.request.priority));
+        EffectsStatus {
+            playing: self
+                .playing
+                .as_ref()
+                .map(|playing| line(&playing.request, Duration::ZERO)),
+            waiting: order
+                .into_iter()
+                .map(|waiting| {
+                    let waited = now.saturating_duration_since(waiting.since);
+                    line(
+                        &waiting.request,
+                        waiting.request.max_wait.saturating_sub(waited),
+                    )
+                })
+                .collect(),
+            dropped: self.dropped,
+        }
+    }
+
+    /// the waiting effect that matters most, oldest first among equals
+    fn next(&self) -> Option<usize> {
+        let priority = self.waiting.iter().map(|w| w.request.priority).max()?;
+        self.waiting
+            .iter()
+            .position(|waiting| waiting.request.priority == priority)
+    }
+
+    fn start(&mut self, request: EffectRequest) -> Vec<EffectOrder> {
+        let id = self.next_id;
+        self.next_id += 1;
+        let effect = request.effect;
+        self.playing = Some(Playing { id, request });
+        vec![EffectOrder::Start { id, effect }]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lights::effects::EffectKind;
+    use crate::lights::Rgb;
+
+    const PURPLE: Rgb = Rgb(128, 0, 255);
+
+    fn request(kind: EffectKind, priority: u8, policy: Policy) -> EffectRequest {
+        EffectRequest {
+            effect: Effect::from(kind),
+            priority,
+            max_wait: Duration::from_secs(30),
+            policy,
+            reason: format!("{}", kind),
+        }
+    }
+
+    fn started(orders: &[EffectOrder]) -> Vec<EffectKind> {
+        orders
+            .iter()
+            .filter_map(|order| match order {
+                EffectOrder::Start { effect, .. } => Some(effect.kind),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn plays_the_most_important_next() {
+        let now = Instant::now();
+        let mut queue = EffectQueue::default();
+        let first = queue.submit(request(EffectKind::Flash(PURPLE), 1, Policy::Queue), now);
+        assert_eq!(started(&first), vec![EffectKind::Flash(PURPLE)]);
+
+        queue.submit(request(EffectKind::Strobe(PURPLE), 1, Policy::Queue), now);
+        queue.submit(request(EffectKind::ColorCycle, 5, Policy::Queue), now);
+        queue.submit(request(EffectKind::Chase(PURPLE), 5, Policy::Queue), now);
+        let order: Vec<_> = queue
+            .status(now)
+            .waiting
+            .iter()
+            .map(|waiting| waiting.effect.kind)
+            .collect();
+        assert_eq!(
+            order,
+            vec![
+                EffectKind::ColorCycle,
+                EffectKind::Chase(PURPLE),
+                EffectKind::Strobe(PURPLE)
+            ]
+        );
+
+        // finishing something that's no longer playing changes nothing
+        assert!(queue.finished(7, now).is_empty());
+        let next = queue.finished(0, now);
+        assert_eq!(started(&next), vec![EffectKind::ColorCycle]);
+    }
+
+    #[test]
+    fn preempts_only_what_matters_less() {
+        let now = Instant::now();
+        let mut queue = EffectQueue::default();
+        queue.submit(request(EffectKind::Pulse(PURPLE), 5, Policy::Queue), now);
+
+        let raid = queue.submit(request(EffectKind::ColorCycle, 9, Policy::Preempt), now);
+        assert_eq!(raid[0], EffectOrder::Stop { id: 0 });
+        assert_eq!(started(&raid), vec![EffectKind::ColorCycle]);
+
+        // a second raid doesn't cut off the first
+        let again = queue.submit(request(EffectKind::Chase(PURPLE), 9, Policy::Preempt), now);
+        assert!(again.is_empty());
+        assert_eq!(queue.status(now).waiting.len(), 1);
+        assert_eq!(queue.status(now).dropped, 1);
+    }
+
+    #[test]
+    fn drops_if_busy() {
+        let now = Instant::now();
+        let mut queue = EffectQueue::default();
+        let follow = request(EffectKind::Flash(PURPLE), 1, Policy::DropIfBusy);
+        assert_eq!(queue.submit(follow.clone(), now).len(), 1);
+        assert!(queue.submit(follow, now).is_empty());
+        assert_eq!(queue.status(now).dropped, 1);
+        assert!(queue.status(now).waiting.is_empty());
+    }
+
+    #[test]
+    fn merges_into_the_same_effect() {
+        let now = Instant::now();
+        let mut queue = EffectQueue::default();
+        let sub = request(EffectKind::Pulse(PURPLE), 5, Policy::Merge);
+        queue.submit(sub.clone(), now);
+
+        assert_eq!(
+            queue.submit(sub.clone(), now),
+            vec![EffectOrder::Extend { id: 0, cycles: 3 }]
+        );
+        assert_eq!(queue.status(now).playing.unwrap().effect.cycles, Some(6));
+
+        // something else is playing, so it merges into the one that's waiting instead
+        queue.submit(request(EffectKind::ColorCycle, 9, Policy::Preempt), now);
+        queue.submit(sub.clone(), now);
+        queue.submit(sub, now);
+        let waiting = queue.status(now).waiting;
+        assert_eq!(waiting.len(), 1);
+        assert_eq!(waiting[0].effect.cycles, Some(6));
+    }
+
+    #[test]
+    fn gives_up_waiting_eventually() {
+        let start = Instant::now();
+        let mut queue = EffectQueue::default();
+        queue.submit(request(EffectKind::ColorCycle, 5, Policy::Queue), start);
+        queue.submit(request(EffectKind::Flash(PURPLE), 1, Policy::Queue), start);
+
+        let later = start + Duration::from_secs(20);
+        assert_eq!(
+            queue.status(later).waiting[0].time_left,
+            Duration::from_secs(10)
+        );
+        assert!(!queue.expire(later));
+
+        let too_late = start + Duration::from_secs(31);
+        assert!(queue.finished(0, too_late).is_empty());
+        assert_eq!(queue.status(too_late).dropped, 1);
+    }
+
+    #[test]
+    fn events_can_recall_scenes() {
+        let mut config = EffectsConfig::default();
+        config.raid.effect = "scene Raid party".to_string();
+        let raid = TwitchEvent::Raid {
+            from: "dwbrite".to_string(),
+            viewers: 12,
+        };
+        assert_eq!(
+            config.trigger(&raid),
+            Some(Triggered::Scene("Raid party".to_string()))
+        );
+
+        let follow = TwitchEvent::Follow {
+            user: "viewer".to_string(),
+        };
+        match config.trigger(&follow) {
+            Some(Triggered::Effect(request)) => assert_eq!(request.reason, "follow from viewer"),
+            other => panic!("expected the follow's flash, got {:?}", other),
+        }
+        config.follow.effect = String::new();
+        assert_eq!(config.trigger(&follow), None);
+    }
+}
@@ -0,0 +1,480 @@
+//! Effects as keyframed timelines. Each effect becomes one timeline per light, which the
+//! scheduler samples to find out what every light should be doing at any moment.
+
+use crate::hue::FullLightState;
+use crate::lights::color::{self, Gamut};
+use crate::lights::Rgb;
+use std::fmt;
+use std::time::Duration;
+
+/// What a light should look like. Brightness 0 is off.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Target {
+    pub color: Rgb,
+    /// 0 to 1
+    pub brightness: f32,
+}
+
+impl Target {
+    pub fn new(color: Rgb, brightness: f32) -> Self {
+        Self {
+            color,
+            brightness: brightness.clamp(0.0, 1.0),
+        }
+    }
+
+    /// Roughly what a light is showing now, so effects can start and end there, and the TUI
+    /// can show it. White-only lights come out white.
+    pub fn from_state(state: &FullLightState, gamut: Gamut) -> Self {
+        let brightness = match (state.on, state.bri) {
+            (false, _) => 0.0,
+            (true, Some(bri)) => bri as f32 / 254.0,
+            (true, None) => 1.0,
+        };
+        Self::new(color::shown(state, gamut), brightness)
+    }
+
+    /// The colour dimmed to the brightness, for outputs that only take a colour.
+    pub fn rgb(&self) -> Rgb {
+        let dim = |channel: u8| (channel as f32 * self.brightness).round() as u8;
+        let Rgb(r, g, b) = self.color;
+        Rgb(dim(r), dim(g), dim(b))
+    }
+
+    fn lerp(&self, to: &Target, p: f32) -> Self {
+        let channel = |a: u8, b: u8| (a as f32 + (b as f32 - a as f32) * p).round() as u8;
+        let (a, b) = (self.color, to.color);
+        Self::new(
+            Rgb(channel(a.0, b.0), channel(a.1, b.1), channel(a.2, b.2)),
+            self.brightness + (to.brightness - self.brightness) * p,
+        )
+    }
+}
+
+/// How a keyframe is approached from the one before it.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Easing {
+    Linear,
+    EaseIn,
+    EaseOut,
+    EaseInOut,
+    /// holds the previous keyframe, then jumps
+    Step,
+}
+
+impl Easing {
+    /// `p` and the result both go from 0 to 1
+    pub fn apply(&self, p: f32) -> f32 {
+        let p = p.clamp(0.0, 1.0);
+        match self {
+            Easing::Linear => p,
+            Easing::EaseIn => p * p,
+            Easing::EaseOut => 1.0 - (1.0 - p) * (1.0 - p),
+            Easing::EaseInOut => (1.0 - (p * std::f32::consts::PI).cos()) / 2.0,
+            Easing::Step => match p >= 1.0 {
+                true => 1.0,
+                false => 0.0,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Keyframe {
+    /// how far into the cycle, from 0 to 1
+    pub at: f32,
+    pub target: Target,
+    pub easing: Easing,
+}
+
+fn key(at: f32, target: Target, easing: Easing) -> Keyframe {
+    Keyframe { at, target, easing }
+}
+
+/// A band of `lit` passing over a light `distance` of the way along, from 0 to 1, so lights
+/// further along light up later in the cycle.
+fn sweep(start: Target, lit: Target, distance: f32) -> Vec<Keyframe> {
+    use Easing::*;
+    const BAND: f32 = 0.3;
+    let from = distance.clamp(0.0, 1.0) * (1.0 - BAND);
+    vec![
+        key(0.0, start, Step),
+        key(from, start, Step),
+        key(from + BAND / 2.0, lit, EaseOut),
+        key(from + BAND, start, EaseIn),
+        key(1.0, start, Step),
+    ]
+}
+
+/// One light's part in an effect: keyframes over a cycle, played `cycles` times.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Timeline {
+    /// sorted by `at`, starting at 0 and ending at 1
+    pub keyframes: Vec<Keyframe>,
+    pub cycle: Duration,
+    /// None to go until stopped
+    pub cycles: Option<u32>,
+}
+
+impl Timeline {
+    pub fn length(&self) -> Option<Duration> {
+        self.cycles.map(|cycles| self.cycle * cycles)
+    }
+
+    /// Where the light should be `elapsed` into the effect. Past the end, that's the last
+    /// keyframe.
+    pub fn sample(&self, elapsed: Duration) -> Target {
+        let last = self.keyframes[self.keyframes.len() - 1];
+        if self.length().is_some_and(|length| elapsed >= length) || self.cycle.is_zero() {
+            return last.target;
+        }
+
+        let t = (elapsed.as_secs_f32() / self.cycle.as_secs_f32()).fract();
+        let next = self
+            .keyframes
+            .iter()
+            .position(|keyframe| keyframe.at > t)
+            .unwrap_or(self.keyframes.len() - 1);
+        if next == 0 {
+            return self.keyframes[0].target;
+        }
+
+        let (from, to) = (&self.keyframes[next - 1], &self.keyframes[next]);
+        let span = to.at - from.at;
+        let p = match span > 0.0 {
+            true => (t - from.at) / span,
+            false => 1.0,
+        };
+        from.target.lerp(&to.target, to.easing.apply(p))
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum EffectKind {
+    /// straight to the colour, then fading back to how things were
+    Flash(Rgb),
+    /// a quick swell and a slower fall
+    Pulse(Rgb),
+    /// on, off, on, off
+    Strobe(Rgb),
+    /// round the colour wheel
+    ColorCycle,
+    /// to the colour, staying there
+    FadeTo(Rgb),
+    /// slow and even, in and out
+    Breathe(Rgb),
+    /// the colour passing from light to light
+    Chase(Rgb),
+    /// the colour sweeping across the room from left to right
+    Wave(Rgb),
+    /// the colour spreading out from the middle of the room
+    Ripple(Rgb),
+}
+
+impl EffectKind {
+    pub const NAMES: &'static [&'static str] = &[
+        "flash", "pulse", "strobe", "cycle", "fade", "breathe", "chase", "wave", "ripple",
+    ];
+
+    /// by one of `NAMES`, with the colour for effects that take one
+    pub fn from_name(name: &str, color: Rgb) -> Option<Self> {
+        Some(match name {
+            "flash" => EffectKind::Flash(color),
+            "pulse" => EffectKind::Pulse(color),
+            "strobe" => EffectKind::Strobe(color),
+            "cycle" => EffectKind::ColorCycle,
+            "fade" => EffectKind::FadeTo(color),
+            "breathe" => EffectKind::Breathe(color),
+            "chase" => EffectKind::Chase(color),
+            "wave" => EffectKind::Wave(color),
+            "ripple" => EffectKind::Ripple(color),
+            _ => return None,
+        })
+    }
+
+    /// how long one cycle takes, and how many there are
+    fn default_timing(&self) -> (Duration, Option<u32>) {
+        let millis = Duration::from_millis;
+        match self {
+            EffectKind::Flash(_) => (millis(800), Some(1)),
+            EffectKind::Pulse(_) => (millis(1200), Some(3)),
+            EffectKind::Strobe(_) => (millis(200), Some(15)),
+            EffectKind::ColorCycle => (millis(6000), Some(2)),
+            EffectKind::FadeTo(_) => (millis(2000), Some(1)),
+            EffectKind::Breathe(_) => (millis(4000), Some(3)),
+            EffectKind::Chase(_) => (millis(1500), Some(4)),
+            EffectKind::Wave(_) => (millis(2000), Some(3)),
+            EffectKind::Ripple(_) => (millis(2000), Some(3)),
+        }
+    }
+}
+
+/// An effect and how long it goes on for.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Effect {
+    pub kind: EffectKind,
+    pub cycle: Duration,
+    pub cycles: Option<u32>,
+}
+
+impl From<EffectKind> for Effect {
+    fn from(kind: EffectKind) -> Self {
+        let (cycle, cycles) = kind.default_timing();
+        Self {
+            kind,
+            cycle,
+            cycles,
+        }
+    }
+}
+
+impl Effect {
+    /// The timeline for light `index` of `count`, which starts out at `start` and sits at
+    /// `position` in the room (see [`Layout`](crate::lights::layout::Layout)).
+    pub fn timeline(
+        &self,
+        start: Target,
+        index: usize,
+        count: usize,
+        (x, y): (f32, f32),
+    ) -> Timeline {
+        use Easing::*;
+        let full = |color| Target::new(color, 1.0);
+        let dim = |color| Target::new(color, 0.15);
+        let off = Target::new(start.color, 0.0);
+
+        let keyframes = match self.kind {
+            EffectKind::Flash(color) => vec![
+                key(0.0, full(color), Step),
+                key(0.2, full(color), Step),
+                key(1.0, start, EaseOut),
+            ],
+            EffectKind::Pulse(color) => vec![
+                key(0.0, dim(color), Step),
+                key(0.25, full(color), EaseOut),
+                key(1.0, dim(color), EaseIn),
+            ],
+            EffectKind::Strobe(color) => vec![
+                key(0.0, full(color), Step),
+                key(0.5, off, Step),
+                key(1.0, off, Step),
+            ],
+            EffectKind::ColorCycle => {
+                let wheel = [0.0, 60.0, 120.0, 180.0, 240.0, 300.0, 360.0];
+                wheel
+                    .iter()
+                    .map(|&degrees| {
+                        let target = full(Rgb::from_hsv(degrees, 1.0));
+                        key(degrees / 360.0, target, Linear)
+                    })
+                    .collect()
+            }
+            EffectKind::FadeTo(color) => {
+                vec![key(0.0, start, Step), key(1.0, full(color), EaseInOut)]
+            }
+            EffectKind::Breathe(color) => vec![
+                key(0.0, dim(color), Step),
+                key(0.5, full(color), EaseInOut),
+                key(1.0, dim(color), EaseInOut),
+            ],
+            EffectKind::Chase(color) => {
+                // each light gets an equal slice of the cycle to be lit in
+                let slice = 1.0 / count.max(1) as f32;
+                let lit = index as f32 * slice;
+                vec![
+                    key(0.0, start, Step),
+                    key(lit, full(color), Step),
+                    key(lit + slice, start, Step),
+                    key(1.0, start, Step),
+                ]
+            }
+            EffectKind::Wave(color) => sweep(start, full(color), x),
+            EffectKind::Ripple(color) => {
+                // as far from the middle as a corner is counts as all the way out
+                let distance = (x - 0.5).hypot(y - 0.5) / 0.5f32.hypot(0.5);
+                sweep(start, full(color), distance)
+            }
+        };
+
+        Timeline {
+            keyframes,
+            cycle: self.cycle,
+            cycles: self.cycles,
+        }
+    }
+}
+
+impl fmt::Display for EffectKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EffectKind::Flash(color) => write!(f, "flash {}", color),
+            EffectKind::Pulse(color) => write!(f, "pulse {}", color),
+            EffectKind::Strobe(color) => write!(f, "strobe {}", color),
+            EffectKind::ColorCycle => write!(f, "cycle through the colours"),
+            EffectKind::FadeTo(color) => write!(f, "fade to {}", color),
+            EffectKind::Breathe(color) => write!(f, "breathe {}", color),
+            EffectKind::Chase(color) => write!(f, "chase {}", color),
+            EffectKind::Wave(color) => write!(f, "wave {}", color),
+            EffectKind::Ripple(color) => write!(f, "ripple {}", color),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hue::ColorMode;
+    use crate::lights::color::GAMUT_C;
+
+    const RED: Rgb = Rgb(255, 0, 0);
+    const BLUE: Rgb = Rgb(0, 0, 255);
+
+    fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    #[test]
+    fn eases() {
+        for easing in [
+            Easing::Linear,
+            Easing::EaseIn,
+            Easing::EaseOut,
+            Easing::EaseInOut,
+        ] {
+            assert_eq!(easing.apply(0.0), 0.0);
+            assert!((easing.apply(1.0) - 1.0).abs() < 1e-6);
+        }
+        assert!(Easing::EaseIn.apply(0.5) < 0.5);
+        assert!(Easing::EaseOut.apply(0.5) > 0.5);
+        assert!((Easing::EaseInOut.apply(0.5) - 0.5).abs() < 1e-6);
+        assert_eq!(Easing::Step.apply(0.99), 0.0);
+    }
+
+    #[test]
+    fn samples_between_keyframes() {
+        let start = Target::new(RED, 0.0);
+        let effect = Effect {
+            kind: EffectKind::FadeTo(BLUE),
+            cycle: ms(1000),
+            cycles: Some(1),
+        };
+        let timeline = effect.timeline(start, 0, 1, (0.5, 0.5));
+
+        assert_eq!(timeline.sample(ms(0)), start);
+        let halfway = timeline.sample(ms(500));
+        assert!((halfway.brightness - 0.5).abs() < 0.01);
+        assert_eq!(halfway.color, Rgb(128, 0, 128));
+        // and it stays there once it's done
+        assert_eq!(timeline.sample(ms(5000)), Target::new(BLUE, 1.0));
+    }
+
+    #[test]
+    fn repeats_cycles() {
+        let start = Target::new(RED, 1.0);
+        let effect = Effect {
+            kind: EffectKind::Strobe(BLUE),
+            cycle: ms(200),
+            cycles: Some(3),
+        };
+        let timeline = effect.timeline(start, 0, 1, (0.5, 0.5));
+
+        assert_eq!(timeline.sample(ms(50)).brightness, 1.0);
+        assert_eq!(timeline.sample(ms(150)).brightness, 0.0);
+        assert_eq!(timeline.sample(ms(450)).brightness, 1.0);
+        assert_eq!(timeline.length(), Some(ms(600)));
+    }
+
+    #[test]
+    fn flashes_end_where_they_started() {
+        let start = Target::new(RED, 0.5);
+        let timeline = Effect::from(EffectKind::Flash(BLUE)).timeline(start, 0, 1, (0.5, 0.5));
+        assert_eq!(timeline.sample(ms(0)), Target::new(BLUE, 1.0));
+        assert_eq!(timeline.sample(ms(800)), start);
+    }
+
+    #[test]
+    fn chases_light_one_light_at_a_time() {
+        let start = Target::new(RED, 0.0);
+        let effect = Effect {
+            kind: EffectKind::Chase(BLUE),
+            cycle: ms(300),
+            cycles: None,
+        };
+        let lit = |elapsed| {
+            (0..3)
+                .filter(|&i| {
+                    effect
+                        .timeline(start, i, 3, (0.5, 0.5))
+                        .sample(elapsed)
+                        .brightness
+                        > 0.0
+                })
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(lit(ms(50)), vec![0]);
+        assert_eq!(lit(ms(150)), vec![1]);
+        assert_eq!(lit(ms(250)), vec![2]);
+        assert_eq!(lit(ms(350)), vec![0]);
+    }
+
+    #[test]
+    fn sweeps_across_the_room() {
+        let start = Target::new(RED, 0.0);
+        let effect = Effect {
+            kind: EffectKind::Wave(BLUE),
+            cycle: ms(1000),
+            cycles: None,
+        };
+        let brightness = |x, elapsed| {
+            effect
+                .timeline(start, 0, 1, (x, 0.5))
+                .sample(elapsed)
+                .brightness
+        };
+
+        // the band reaches the left wall first and the right wall last
+        assert!(brightness(0.0, ms(150)) > 0.9);
+        assert_eq!(brightness(1.0, ms(150)), 0.0);
+        assert_eq!(brightness(0.0, ms(850)), 0.0);
+        assert!(brightness(1.0, ms(850)) > 0.9);
+
+        let ripple = Effect {
+            kind: EffectKind::Ripple(BLUE),
+            ..effect
+        };
+        let middle = ripple.timeline(start, 0, 1, (0.5, 0.5));
+        let corner = ripple.timeline(start, 0, 1, (0.0, 1.0));
+        assert!(middle.sample(ms(150)).brightness > 0.9);
+        assert_eq!(corner.sample(ms(150)).brightness, 0.0);
+        assert!(corner.sample(ms(850)).brightness > 0.9);
+    }
+
+    #[test]
+    fn reads_light_states() {
+        let state = FullLightState {
+            on: true,
+            bri: Some(127),
+            hue: Some(0),
+            sat: Some(254),
+            ct: None,
+            xy: Some((0.3127, 0.329)),
+            colormode: Some(ColorMode::Hs),
+        };
+        let target = Target::from_state(&state, GAMUT_C);
+        assert_eq!(target.color, RED);
+        assert!((target.brightness - 0.5).abs() < 0.01);
+        assert_eq!(target.rgb(), Rgb(128, 0, 0));
+
+        // the same light showing white by xy instead
+        let xy = FullLightState {
+            colormode: Some(ColorMode::Xy),
+            ..state
+        };
+        assert_eq!(Target::from_state(&xy, GAMUT_C).color, Rgb(255, 255, 255));
+
+        let off = FullLightState { on: false, ..state };
+        assert_eq!(Target::from_state(&off, GAMUT_C).brightness, 0.0);
+        assert_eq!(Target::from_state(&off, GAMUT_C).rgb(), Rgb(0, 0, 0));
+    }
+}
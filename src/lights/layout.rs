@@ -0,0 +1,98 @@
+//! Where the lights are in the room, so effects can move across it.
+
+use serde::{Deserialize, Serialize};
+
+/// One light's spot, from (0, 0) at the top left of the room to (1, 1) at the bottom right,
+/// the same way round as the terminal.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LightPosition {
+    pub bridge: String,
+    pub light: usize,
+    pub x: f32,
+    pub y: f32,
+    /// the part of the room it's in, like "desk" or "backdrop"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub zone: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Layout {
+    pub lights: Vec<LightPosition>,
+}
+
+impl Layout {
+    /// Where light `index` of the bridge's `count` is. Lights nobody has placed are spread
+    /// out across the middle of the room.
+    pub fn position(&self, bridge: &str, light: usize, index: usize, count: usize) -> (f32, f32) {
+        match self.get(bridge, light) {
+            Some(placed) => (placed.x, placed.y),
+            None => ((index as f32 + 0.5) / count.max(1) as f32, 0.5),
+        }
+    }
+
+    pub fn get(&self, bridge: &str, light: usize) -> Option<&LightPosition> {
+        self.lights
+            .iter()
+            .find(|placed| placed.bridge == bridge && placed.light == light)
+    }
+
+    /// The light's spot, placing it at `position` first if nobody has yet.
+    pub fn entry(&mut self, bridge: &str, light: usize, (x, y): (f32, f32)) -> &mut LightPosition {
+        let index = match self
+            .lights
+            .iter()
+            .position(|placed| placed.bridge == bridge && placed.light == light)
+        {
+            Some(index) => index,
+            None => {
+                self.lights.push(LightPosition {
+                    bridge: bridge.to_string(),
+                    light,
+                    x,
+                    y,
+                    zone: None,
+                });
+                self.lights.len() - 1
+            }
+        };
+        &mut self.lights[index]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spreads_out_lights_nobody_placed() {
+        let layout = Layout {
+            lights: vec![LightPosition {
+                bridge: "bridge".to_string(),
+                light: 2,
+                x: 0.1,
+                y: 0.9,
+                zone: None,
+            }],
+        };
+        assert_eq!(layout.position("bridge", 2, 1, 4), (0.1, 0.9));
+        assert_eq!(layout.position("bridge", 1, 0, 4), (0.125, 0.5));
+        assert_eq!(layout.position("other", 2, 3, 4), (0.875, 0.5));
+    }
+
+    #[test]
+    fn keeps_zones_through_the_config() {
+        let mut layout = Layout::default();
+        layout.entry("bridge", 1, (0.25, 0.5)).zone = Some("desk".to_string());
+        layout.entry("bridge", 1, (0.9, 0.9)).x = 0.3;
+        layout.entry("bridge", 2, (0.75, 0.5));
+
+        let toml = toml::to_string(&layout).unwrap();
+        let read: Layout = toml::from_str(&toml).unwrap();
+        assert_eq!(read, layout);
+        assert_eq!(read.lights.len(), 2);
+        assert_eq!(read.position("bridge", 1, 0, 2), (0.3, 0.5));
+        assert_eq!(read.lights[0].zone.as_deref(), Some("desk"));
+        assert_eq!(read.lights[1].zone, None);
+    }
+}
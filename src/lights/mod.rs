@@ -0,0 +1,305 @@
+//! What chat (and later, everything else) can ask the lights to do, and the tasks that do it.
+
+pub mod color;
+pub mod effect_queue;
+pub mod effects;
+pub mod layout;
+pub mod rainbow;
+pub mod scheduler;
+pub mod snapshot;
+
+use crate::hue::queue::{BridgeQueue, QueueStats};
+use crate::lights::color::{Hsv, LightKind};
+use crate::lights::effect_queue::EffectsStatus;
+use crate::lights::effects::Effect;
+use crate::tasks::Task;
+use anyhow::anyhow;
+use crossbeam_channel::Sender;
+use hueclient::CommandLight;
+use rand::{thread_rng, Rng};
+use std::fmt;
+
+/// A colour as chat would name it.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Rgb(pub u8, pub u8, pub u8);
+
+const NAMED_COLOURS: &[(&str, Rgb)] = &[
+    ("red", Rgb(255, 0, 0)),
+    ("orange", Rgb(255, 128, 0)),
+    ("yellow", Rgb(255, 220, 0)),
+    ("green", Rgb(0, 255, 0)),
+    ("cyan", Rgb(0, 255, 255)),
+    ("blue", Rgb(0, 0, 255)),
+    ("purple", Rgb(128, 0, 255)),
+    ("magenta", Rgb(255, 0, 255)),
+    ("pink", Rgb(255, 105, 180)),
+    ("white", Rgb(255, 255, 255)),
+];
+
+impl Rgb {
+    /// A colour name like "purple", or hex like "#8000ff".
+    pub fn parse(s: &str) -> Option<Self> {
+        let s = s.trim().to_lowercase();
+        if let Some((_, rgb)) = NAMED_COLOURS.iter().find(|(name, _)| *name == s) {
+            return Some(*rgb);
+        }
+
+        let hex = s.strip_prefix('#').unwrap_or(&s);
+        if hex.len() != 6 || !hex.is_ascii() {
+            return None;
+        }
+        let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+        Some(Rgb(channel(0)?, channel(2)?, channel(4)?))
+    }
+
+    pub fn random() -> Self {
+        // fully saturated, since party lights in pastel aren't much of a party
+        Self::from_hsv(thread_rng().gen_range(0.0..360.0), 1.0)
+    }
+
+    /// At full value. `degrees` wraps around, `sat` goes from 0 to 1.
+    pub fn from_hsv(degrees: f32, sat: f32) -> Self {
+        Hsv {
+            hue: degrees,
+            sat,
+            value: 1.0,
+        }
+        .rgb()
+    }
+}
+
+impl fmt::Display for Rgb {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match NAMED_COLOURS.iter().find(|(_, rgb)| rgb == self) {
+            Some((name, _)) => write!(f, "{}", name),
+            None => write!(f, "#{:02x}{:02x}{:02x}", self.0, self.1, self.2),
+        }
+    }
+}
+
+/// Something to do to every light.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LightAction {
+    Color(Rgb),
+    On,
+    Off,
+    /// a different random colour on each light
+    Party,
+    Effect(Effect),
+    /// the TUI border's gradient across the room, until something else is asked for
+    Rainbow,
+    /// a scene saved on the bridges, by name
+    Scene(String),
+}
+
+impl fmt::Display for LightAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LightAction::Color(rgb) => write!(f, "turn the lights {}", rgb),
+            LightAction::On => write!(f, "turn the lights on"),
+            LightAction::Off => write!(f, "turn the lights off"),
+            LightAction::Party => write!(f, "party"),
+            LightAction::Effect(effect) => write!(f, "{}", effect.kind),
+            LightAction::Rainbow => write!(f, "turn on rainbow mode"),
+            LightAction::Scene(name) => write!(f, "recall the {} scene", name),
+        }
+    }
+}
+
+/// How light actions went, for whatever's on screen.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LightEvent {
+    /// the action couldn't even start, since the lights couldn't be read
+    Failed { action: LightAction, reason: String },
+    /// a bridge started turning commands down
+    BridgeFailed { bridge: String, reason: String },
+    /// how a bridge's queue is doing, whenever that changes
+    Queue { bridge: String, stats: QueueStats },
+    /// an effect stopped playing, by its ID in the effect queue
+    EffectDone { id: u64 },
+    /// what's playing and waiting, whenever that changes
+    Effects(EffectsStatus),
+}
+
+/// Applies an action to every light on every bridge.
+pub struct LightTask {
+    queues: Vec<BridgeQueue>,
+    action: LightAction,
+}
+
+impl LightTask {
+    pub fn new(queues: Vec<BridgeQueue>, action: LightAction) -> Self {
+        Self { queues, action }
+    }
+
+    /// what to tell a light of `kind`, so colours stay within what it can show
+    fn command(&self, kind: LightKind) -> CommandLight {
+        match self.action {
+            LightAction::Color(rgb) => kind.command(rgb),
+            LightAction::On => CommandLight::default().on(),
+            LightAction::Off => CommandLight::default().off(),
+            LightAction::Party => kind.command(Rgb::random()),
+            // played frame by frame by an `EffectTask` or `RainbowTask` instead
+            LightAction::Effect(_) | LightAction::Rainbow => CommandLight::default(),
+            // recalled by `recall` instead
+            LightAction::Scene(_) => CommandLight::default(),
+        }
+    }
+
+    /// Recalls the scene on every bridge that has one by that name. It goes straight to the
+    /// bridge, since it's a single command and the queue would otherwise split it up among
+    /// lights that don't know what a scene is.
+    fn recall(&self, name: &str) -> anyhow::Result<()> {
+        let mut recalled = false;
+        for queue in &self.queues {
+            let bridge = queue.bridge();
+            let scene = bridge
+                .get_scenes()?
+                .into_iter()
+                .find(|scene| scene.name.eq_ignore_ascii_case(name));
+            if let Some(scene) = scene {
+                queue.snapshots().take(bridge)?;
+                bridge.recall_scene(&scene)?;
+                recalled = true;
+            }
+        }
+        match recalled {
+            true => Ok(()),
+            false => Err(anyhow!("there's no scene called {}", name)),
+        }
+    }
+}
+
+impl Task for LightTask {
+    type Result = ();
+    type OnCompleteParams = (LightAction, Sender<LightEvent>);
+
+    /// Only finds out which lights there are, saving how they were; the queues take it from
+    /// there.
+    fn run_task(self) -> anyhow::Result<()> {
+        if let LightAction::Scene(name) = &self.action {
+            return self.recall(name);
+        }
+        for queue in &self.queues {
+            let states = queue.snapshots().take(queue.bridge())?;
+            let kinds = scheduler::light_kinds(queue.bridge(), &states)?;
+            for id in states.into_keys() {
+                let kind = kinds.get(&id).copied().unwrap_or(LightKind::Dimmable);
+                queue.set_light(id, self.command(kind));
+            }
+        }
+        Ok(())
+    }
+
+    fn on_complete(r: anyhow::Result<()>, (action, events_tx): (LightAction, Sender<LightEvent>)) {
+        if let Err(e) = r {
+            let _ = events_tx.send(LightEvent::Failed {
+                action,
+                reason: format!("{:#}", e),
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hue::mock::MockBridge;
+    use crate::lights::color::{GAMUT_B, GAMUT_C};
+    use crate::lights::snapshot::Snapshots;
+    use hueclient::LightState;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn parses_colours() {
+        assert_eq!(Rgb::parse("Purple"), Some(Rgb(128, 0, 255)));
+        assert_eq!(Rgb::parse("#ff8000"), Some(Rgb(255, 128, 0)));
+        assert_eq!(Rgb::parse("00ff7f"), Some(Rgb(0, 255, 127)));
+        assert_eq!(Rgb::parse("#ff80"), None);
+        assert_eq!(Rgb::parse("plaid"), None);
+
+        assert_eq!(Rgb(128, 0, 255).to_string(), "purple");
+        assert_eq!(Rgb(0, 255, 127).to_string(), "#00ff7f");
+    }
+
+    #[test]
+    fn colours_every_light() {
+        let mock = MockBridge::start();
+        let queue = BridgeQueue::start(
+            mock.validated_bridge(),
+            Snapshots::default(),
+            crossbeam_channel::unbounded().0,
+        );
+        let task = LightTask::new(vec![queue], LightAction::Color(Rgb(0, 0, 255)));
+        task.run_task().unwrap();
+
+        // each as blue as it gets: the desk in its own gamut, and the hallway, which can't
+        // show colour, just on
+        let blue = Rgb(0, 0, 255).xy().0;
+        mock.wait_for_light(1, |state| state.xy == Some(GAMUT_C.clamp(blue)));
+        mock.wait_for_light(2, |state| state.xy == Some(GAMUT_B.clamp(blue)));
+        mock.wait_for_light(3, |state| state.on);
+        assert_eq!(mock.light_state(3).unwrap().xy, None);
+    }
+
+    #[test]
+    fn recalls_scenes_by_name() {
+        let mock = MockBridge::start();
+        let office = mock.add_group("Office", "Room", &[1, 2]);
+        let dim = LightState {
+            on: true,
+            bri: Some(20),
+            hue: None,
+            sat: None,
+            ct: Some(500),
+            xy: None,
+        };
+        mock.add_scene(
+            "Movie night",
+            Some(office),
+            BTreeMap::from([(1, dim), (2, dim)]),
+        );
+        let queue = BridgeQueue::start(
+            mock.validated_bridge(),
+            Snapshots::default(),
+            crossbeam_channel::unbounded().0,
+        );
+
+        let scene = LightAction::Scene("movie night".to_string());
+        LightTask::new(vec![queue.clone()], scene)
+            .run_task()
+            .unwrap();
+        assert_eq!(mock.light_state(2).unwrap().bri, Some(20));
+        assert!(!mock.light_state(3).unwrap().on);
+
+        let missing = LightAction::Scene("disco".to_string());
+        let failed = LightTask::new(vec![queue], missing).run_task();
+        assert_eq!(
+            failed.unwrap_err().to_string(),
+            "there's no scene called disco"
+        );
+    }
+
+    #[test]
+    fn reports_failures() {
+        let mock = MockBridge::start();
+        let bridge = mock.validated_bridge();
+        drop(mock);
+        let queue = BridgeQueue::start(
+            bridge,
+            Snapshots::default(),
+            crossbeam_channel::unbounded().0,
+        );
+        let task = LightTask::new(vec![queue], LightAction::Off);
+
+        let (events_tx, events) = crossbeam_channel::unbounded();
+        LightTask::on_complete(task.run_task(), (LightAction::Off, events_tx));
+        assert!(matches!(
+            events.try_recv(),
+            Ok(LightEvent::Failed {
+                action: LightAction::Off,
+                ..
+            })
+        ));
+    }
+}
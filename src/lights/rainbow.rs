@@ -0,0 +1,172 @@
+//! Rainbow mode: the gradient from the TUI's border, played across the room. Each light
+//! shows the colour the border would have at the light's spot, at the same moment, so the
+//! lights and the border move as one.
+
+use crate::hue::queue::BridgeQueue;
+use crate::lights::color::LightKind;
+use crate::lights::layout::Layout;
+use crate::lights::scheduler::{self, FRAME_INTERVAL};
+use crate::lights::{LightAction, LightEvent, Rgb};
+use crate::shutdown;
+use crate::tasks::Task;
+use crate::widgets::rainbow_border;
+use crate::widgets::unicorn_vomit;
+use crossbeam_channel::{Receiver, Sender};
+use hueclient::CommandLight;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tui::style::Color;
+
+/// The border's colour at (`x`, `y`) on the screen, from 0 to 1 each way, after `ticks`.
+pub fn color_at((x, y): (f32, f32), ticks: u64) -> Rgb {
+    match unicorn_vomit::calculate_color(x, y, rainbow_border::animation_time(ticks)) {
+        Color::Rgb(r, g, b) => Rgb(r, g, b),
+        _ => Rgb(255, 255, 255),
+    }
+}
+
+/// Keeps the lights on the gradient until stopped or shutdown. Bridges playing an effect are
+/// left to it until it's done.
+pub struct RainbowTask {
+    queues: Vec<BridgeQueue>,
+    layout: Layout,
+    /// the app's ticks, so the lights keep time with the border rather than a clock of
+    /// their own
+    ticks: Arc<AtomicU64>,
+    frame_interval: Duration,
+    cancel_ch: (Sender<()>, Receiver<()>),
+}
+
+impl RainbowTask {
+    pub fn new(queues: Vec<BridgeQueue>, layout: Layout, ticks: Arc<AtomicU64>) -> Self {
+        Self {
+            queues,
+            layout,
+            ticks,
+            frame_interval: FRAME_INTERVAL,
+            cancel_ch: crossbeam_channel::bounded(1),
+        }
+    }
+
+    /// stops the task when sent to
+    pub fn canceller(&self) -> Sender<()> {
+        self.cancel_ch.0.clone()
+    }
+}
+
+/// as near as a light of `kind` gets, eased over a frame so the steps don't show
+fn command(color: Rgb, kind: LightKind) -> CommandLight {
+    let mut command = kind.command(color);
+    command.transitiontime = Some((FRAME_INTERVAL.as_millis() / 100) as u16);
+    command
+}
+
+impl Task for RainbowTask {
+    type Result = ();
+    type OnCompleteParams = Sender<LightEvent>;
+
+    fn run_task(self) -> anyhow::Result<()> {
+        // (queue, light ID, kind, position) for every light
+        let mut lights = vec![];
+        for queue in &self.queues {
+            let states = queue.snapshots().take(queue.bridge())?;
+            let kinds = scheduler::light_kinds(queue.bridge(), &states)?;
+            for (i, id) in states.keys().enumerate() {
+                let kind = kinds.get(id).copied().unwrap_or(LightKind::Dimmable);
+                let position = self
+                    .layout
+                    .position(queue.bridge().id(), *id, i, states.len());
+                lights.push((queue, *id, kind, position));
+            }
+        }
+
+        loop {
+            let ticks = self.ticks.load(Ordering::Relaxed);
+            for (queue, id, kind, position) in &lights {
+                if !queue.snapshots().effect_playing() {
+                    queue.set_light(*id, command(color_at(*position, ticks), *kind));
+                }
+            }
+
+            if self.cancel_ch.1.recv_timeout(self.frame_interval).is_ok() || shutdown::requested() {
+                return Ok(());
+            }
+        }
+    }
+
+    fn on_complete(r: anyhow::Result<()>, events_tx: Sender<LightEvent>) {
+        if let Err(e) = r {
+            let _ = events_tx.send(LightEvent::Failed {
+                action: LightAction::Rainbow,
+                reason: format!("{:#}", e),
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hue::mock::MockBridge;
+    use crate::lights::color::GAMUT_C;
+    use crate::lights::layout::LightPosition;
+    use crate::lights::snapshot::Snapshots;
+    use crate::widgets::rainbow_border::RainbowBorderWidget;
+    use std::thread;
+    use tui::buffer::Buffer;
+    use tui::layout::Rect;
+    use tui::widgets::Widget;
+
+    #[test]
+    fn matches_the_border() {
+        let ticks = 1234;
+        let area = Rect::new(0, 0, 40, 10);
+        let mut buf = Buffer::empty(area);
+        RainbowBorderWidget {
+            border_animated: true,
+            ticks,
+        }
+        .render(area, &mut buf);
+
+        // the border samples each cell at its fraction of the way across and down
+        let (x, y) = (30, 0);
+        let position = (
+            x as f32 / area.right() as f32,
+            y as f32 / area.bottom() as f32,
+        );
+        let Rgb(r, g, b) = color_at(position, ticks);
+        assert_eq!(buf.get(x, y).bg, Color::Rgb(r, g, b));
+    }
+
+    #[test]
+    fn paints_the_gradient_across_the_room() {
+        let mock = MockBridge::start();
+        let bridge = mock.validated_bridge();
+        let layout = Layout {
+            lights: vec![LightPosition {
+                bridge: bridge.id().to_string(),
+                light: 1,
+                x: 0.2,
+                y: 0.7,
+                zone: None,
+            }],
+        };
+        let queue = BridgeQueue::start(
+            bridge,
+            Snapshots::default(),
+            crossbeam_channel::unbounded().0,
+        );
+        let ticks = Arc::new(AtomicU64::new(500));
+        let mut task = RainbowTask::new(vec![queue], layout, ticks);
+        task.frame_interval = Duration::from_millis(10);
+        let cancel = task.canceller();
+        let thread = thread::spawn(move || task.run_task());
+
+        // the desk's colour kept within its gamut
+        let expected = LightKind::Color(GAMUT_C).command(color_at((0.2, 0.7), 500));
+        mock.wait_for_light(1, |state| state.xy == expected.xy);
+        cancel.send(()).unwrap();
+        thread.join().unwrap().unwrap();
+    }
+}
@@ -0,0 +1,634 @@
+//! Plays effects on the bridges' lights, a frame at a time. Lights in an entertainment area
+//! are streamed to, if the bridge lets us; the rest go through the bridge's queue. Effects
+//! aimed at a room, zone or other group play on it as a whole, a group command a frame.
+
+use crate::config::ValidatedBridge;
+use crate::hue::entertainment::{EntertainmentStream, STREAM_INTERVAL};
+use crate::hue::queue::BridgeQueue;
+use crate::hue::Bridge;
+use crate::lights::color::{Gamut, LightKind, GAMUT_C};
+use crate::lights::effects::{Effect, Target, Timeline};
+use crate::lights::layout::Layout;
+use crate::lights::snapshot::{self, States};
+use crate::lights::{LightAction, LightEvent, Rgb};
+use crate::shutdown;
+use crate::tasks::Task;
+use anyhow::anyhow;
+use crossbeam_channel::{Receiver, Sender};
+use hueclient::CommandLight;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// the bridge queue only lets about 10 commands a second through anyway
+pub const FRAME_INTERVAL: Duration = Duration::from_millis(100);
+/// and about one group command a second, so effects on a group lose their finer detail
+pub const GROUP_FRAME_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Turns an effect's timelines into light commands for a single bridge.
+pub struct Scheduler {
+    queue: BridgeQueue,
+    /// by light ID, or by group ID when playing on a group
+    timelines: Vec<(usize, Timeline)>,
+    /// the group it's playing on, if it's playing on one
+    group: Option<usize>,
+    /// what each light (or the group) can show, keyed like `timelines`
+    kinds: HashMap<usize, LightKind>,
+    started: Instant,
+    /// what each light was last sent, so lights that aren't changing are left alone
+    sent: HashMap<usize, Target>,
+    /// for the lights in the bridge's entertainment area, if it has one
+    stream: Option<EntertainmentStream>,
+}
+
+impl Scheduler {
+    /// Reads where the lights are now, since that's where some effects start or end, and
+    /// where they go back to once the effect is `finish`ed. Lights are numbered left to right
+    /// across `layout`, so effects that go from light to light move across the room.
+    pub fn new(
+        queue: BridgeQueue,
+        effect: &Effect,
+        layout: &Layout,
+        now: Instant,
+    ) -> anyhow::Result<Self> {
+        let states = queue.snapshots().take(queue.bridge())?;
+        let kinds = light_kinds(queue.bridge(), &states)?;
+        queue.snapshots().effect_started(&states);
+
+        let count = states.len();
+        let mut placed: Vec<_> = states
+            .iter()
+            .enumerate()
+            .map(|(i, (id, state))| {
+                let position = layout.position(queue.bridge().id(), *id, i, count);
+                (*id, state, position)
+            })
+            .collect();
+        placed.sort_by(|(_, _, a), (_, _, b)| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+        let timelines = placed
+            .into_iter()
+            .enumerate()
+            .map(|(i, (id, state, position))| {
+                let start = Target::from_state(state, gamut(&kinds, id));
+                (id, effect.timeline(start, i, count, position))
+            })
+            .collect();
+
+        Ok(Self {
+            stream: open_stream(queue.bridge()),
+            queue,
+            timelines,
+            group: None,
+            kinds,
+            started: now,
+            sent: HashMap::new(),
+        })
+    }
+
+    /// Plays the effect on a room, zone or other group as if it were one light, starting from
+    /// what its first light is showing, and speaking to the group as if it were that light.
+    pub fn for_group(
+        queue: BridgeQueue,
+        group: usize,
+        effect: &Effect,
+        now: Instant,
+    ) -> anyhow::Result<Self> {
+        let members = queue
+            .bridge()
+            .get_groups()?
+            .into_iter()
+            .find(|found| found.id == group)
+            .ok_or_else(|| anyhow!("group {} isn't on the bridge any more", group))?
+            .lights;
+        let states: States = queue
+            .snapshots()
+            .take(queue.bridge())?
+            .into_iter()
+            .filter(|(id, _)| members.contains(id))
+            .collect();
+        let kinds = light_kinds(queue.bridge(), &states)?;
+        let kind = group_kind(kinds.values().copied());
+        // starting from how the most capable member looks, since that's who it's shaped for
+        let (lead, state) = states
+            .iter()
+            .max_by_key(|(id, _)| kinds.get(id).map_or(0, |kind| capability(*kind)))
+            .ok_or_else(|| anyhow!("group {} has no lights", group))?;
+        let start = Target::from_state(state, gamut(&kinds, *lead));
+        queue.snapshots().effect_started(&states);
+
+        Ok(Self {
+            timelines: vec![(group, effect.timeline(start, 0, 1, (0.5, 0.5)))],
+            kinds: HashMap::from([(group, kind)]),
+            queue,
+            group: Some(group),
+            stream: None,
+            started: now,
+            sent: HashMap::new(),
+        })
+    }
+
+    /// How often frames should go out: as often as the stream wants them, or as often as the
+    /// bridge's queue lets them through.
+    pub fn frame_interval(&self) -> Duration {
+        match (self.group, &self.stream) {
+            (Some(_), _) => GROUP_FRAME_INTERVAL,
+            (None, Some(_)) => STREAM_INTERVAL,
+            (None, None) => FRAME_INTERVAL,
+        }
+    }
+
+    fn is_streamed(&self, light: usize) -> bool {
+        self.stream
+            .as_ref()
+            .is_some_and(|stream| stream.area().lights.contains(&light))
+    }
+
+    pub fn is_done(&self, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.started);
+        self.timelines
+            .iter()
+            .all(|(_, timeline)| timeline.length().is_some_and(|length| elapsed >= length))
+    }
+
+    /// The commands that get the lights that aren't streamed to where they should be at `now`,
+    /// or the group's command when playing on a group.
+    pub fn frame(&mut self, now: Instant) -> Vec<(usize, CommandLight)> {
+        let elapsed = now.saturating_duration_since(self.started);
+        let interval = self.frame_interval();
+        let mut commands = vec![];
+        for (id, timeline) in &self.timelines {
+            if self.is_streamed(*id) {
+                continue;
+            }
+            let target = timeline.sample(elapsed);
+            if self.sent.get(id) != Some(&target) {
+                self.sent.insert(*id, target);
+                let kind = self.kinds.get(id).copied().unwrap_or(LightKind::Dimmable);
+                commands.push((*id, command(&target, interval, kind)));
+            }
+        }
+        commands
+    }
+
+    /// Every streamed light's colour at `now`. The stream gets all of them every frame,
+    /// since the bridge gives up on streams that go quiet.
+    pub fn stream_frame(&self, now: Instant) -> Vec<(usize, Rgb)> {
+        let elapsed = now.saturating_duration_since(self.started);
+        self.timelines
+            .iter()
+            .filter(|(id, _)| self.is_streamed(*id))
+            .map(|(id, timeline)| (*id, timeline.sample(elapsed).rgb()))
+            .collect()
+    }
+
+    /// Streams and queues the frame for `now`. If the bridge is behind, it skips to the latest
+    /// frame. If the stream breaks, its lights go through the queue from then on.
+    pub fn send_frame(&mut self, now: Instant) {
+        let colors = self.stream_frame(now);
+        if let Some(stream) = &mut self.stream {
+            if stream.send(&colors).is_err() {
+                self.stream = None;
+            }
+        }
+        for (id, command) in self.frame(now) {
+            match self.group {
+                Some(_) => self.queue.set_group(id, command),
+                None => self.queue.set_light(id, command),
+            }
+        }
+    }
+
+    /// Plays `cycles` more times than planned. Effects that go until stopped stay that way.
+    pub fn extend(&mut self, cycles: u32) {
+        for (_, timeline) in &mut self.timelines {
+            timeline.cycles = timeline.cycles.map(|n| n + cycles);
+        }
+    }
+
+    /// Puts the lights back how they were before the effect, unless another effect is still
+    /// playing on them.
+    pub fn finish(self) {
+        // the area ignores REST commands until it stops streaming
+        drop(self.stream);
+        for (id, state) in self.queue.snapshots().effect_finished() {
+            self.queue.set_light(id, snapshot::restore_command(&state));
+        }
+    }
+}
+
+/// Streams to the bridge's first entertainment area. Bridges without one, or that were set
+/// up before streaming was, or that won't shake hands, get REST. So do effects that start
+/// while another is already streaming to the area.
+fn open_stream(bridge: &ValidatedBridge) -> Option<EntertainmentStream> {
+    bridge.clientkey.as_ref()?;
+    let area = bridge.get_entertainment_areas().ok()?.into_iter().next()?;
+    EntertainmentStream::open(bridge, area).ok()
+}
+
+/// Asks the bridge what model each light is, so colours can be clamped to its gamut, and
+/// white lights aren't sent colours.
+pub(crate) fn light_kinds(
+    bridge: &Bridge,
+    states: &States,
+) -> anyhow::Result<HashMap<usize, LightKind>> {
+    Ok(bridge
+        .get_all_lights()?
+        .into_iter()
+        .filter_map(|found| {
+            let state = states.get(&found.id)?;
+            Some((found.id, LightKind::of(&found.light.modelid, state)))
+        })
+        .collect())
+}
+
+/// how much of a colour a kind of light can show, so colour beats white beats dimming
+fn capability(kind: LightKind) -> u8 {
+    match kind {
+        LightKind::Color(_) => 2,
+        LightKind::Ambiance => 1,
+        LightKind::Dimmable => 0,
+    }
+}
+
+/// What a group command's shaped for: the most capable of its members, so a colour light
+/// in with white ones still gets colour. The bridge keeps each member within its own gamut,
+/// so members that disagree on it get the widest, C, rather than being held to one's.
+fn group_kind(kinds: impl IntoIterator<Item = LightKind>) -> LightKind {
+    kinds
+        .into_iter()
+        .reduce(|a, b| match (a, b) {
+            (LightKind::Color(x), LightKind::Color(y)) if x != y => LightKind::Color(GAMUT_C),
+            _ if capability(b) > capability(a) => b,
+            _ => a,
+        })
+        .unwrap_or(LightKind::Dimmable)
+}
+
+/// the gamut to read a light's colour in, if it has colour at all
+fn gamut(kinds: &HashMap<usize, LightKind>, light: usize) -> Gamut {
+    match kinds.get(&light) {
+        Some(LightKind::Color(gamut)) => *gamut,
+        _ => GAMUT_C,
+    }
+}
+
+/// The bridge's version of `target` for a light of `kind`, eased over a frame `interval` long
+/// so the steps don't show.
+fn command(target: &Target, interval: Duration, kind: LightKind) -> CommandLight {
+    let mut command = match target.brightness > 0.0 {
+        true => {
+            let mut command = kind.command(target.color);
+            command.bri = Some((target.brightness * 254.0).round().max(1.0) as u8);
+            command
+        }
+        false => CommandLight::default().off(),
+    };
+    command.transitiontime = Some((interval.as_millis() / 100) as u16);
+    command
+}
+
+/// Changes to an effect that's already playing.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum EffectControl {
+    Stop,
+    /// play this many more cycles
+    Extend(u32),
+}
+
+/// Plays an effect through on every bridge at once, or until it's stopped or shutdown.
+pub struct EffectTask {
+    queues: Vec<BridgeQueue>,
+    effect: Effect,
+    layout: Layout,
+    /// the group to play on as a whole, instead of every light one by one
+    group: Option<usize>,
+    frame_interval: Duration,
+    control_ch: (Sender<EffectControl>, Receiver<EffectControl>),
+}
+
+impl EffectTask {
+    pub fn new(queues: Vec<BridgeQueue>, effect: Effect, layout: Layout) -> Self {
+        Self {
+            queues,
+            effect,
+            layout,
+            group: None,
+            frame_interval: FRAME_INTERVAL,
+            control_ch: crossbeam_channel::unbounded(),
+        }
+    }
+
+    /// Plays on a group as a whole instead. Group IDs are the bridge's own, so this is for a
+    /// task with just that bridge's queue.
+    pub fn on_group(self, group: Option<usize>) -> Self {
+        Self { group, ..self }
+    }
+
+    /// for stopping or extending the effect once it's playing
+    pub fn controller(&self) -> Sender<EffectControl> {
+        self.control_ch.0.clone()
+    }
+}
+
+impl Task for EffectTask {
+    type Result = ();
+    /// the action, the ID the effect queue knows it by, and where to say it's done
+    type OnCompleteParams = (LightAction, u64, Sender<LightEvent>);
+
+    fn run_task(self) -> anyhow::Result<()> {
+        let started = Instant::now();
+        let mut schedulers: Vec<Scheduler> = vec![];
+        for queue in self.queues {
+            let scheduler = match self.group {
+                Some(group) => Scheduler::for_group(queue, group, &self.effect, started),
+                None => Scheduler::new(queue, &self.effect, &self.layout, started),
+            };
+            match scheduler {
+                Ok(scheduler) => schedulers.push(scheduler),
+                Err(e) => {
+                    schedulers.into_iter().for_each(Scheduler::finish);
+                    return Err(e);
+                }
+            }
+        }
+
+        loop {
+            let now = Instant::now();
+            schedulers.iter_mut().for_each(|s| s.send_frame(now));
+            if schedulers.iter().all(|s| s.is_done(now)) || shutdown::requested() {
+                break;
+            }
+            let interval = schedulers
+                .iter()
+                .map(Scheduler::frame_interval)
+                .fold(self.frame_interval, Duration::min);
+            match self.control_ch.1.recv_timeout(interval) {
+                Ok(EffectControl::Stop) => break,
+                Ok(EffectControl::Extend(cycles)) => {
+                    schedulers.iter_mut().for_each(|s| s.extend(cycles))
+                }
+                // the task holds a sender itself, so this only ever times out
+                Err(_) => {}
+            }
+        }
+        schedulers.into_iter().for_each(Scheduler::finish);
+        Ok(())
+    }
+
+    fn on_complete(
+        r: anyhow::Result<()>,
+        (action, id, events_tx): (LightAction, u64, Sender<LightEvent>),
+    ) {
+        if let Err(e) = r {
+            let _ = events_tx.send(LightEvent::Failed {
+                action,
+                reason: format!("{:#}", e),
+            });
+        }
+        let _ = events_tx.send(LightEvent::EffectDone { id });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::harness::wait_until;
+    use crate::hue::mock::MockBridge;
+    use crate::lights::color::{GAMUT_A, GAMUT_B};
+    use crate::lights::effects::EffectKind;
+    use crate::lights::layout::LightPosition;
+    use crate::lights::snapshot::Snapshots;
+    use crate::lights::Rgb;
+
+    const BLUE: Rgb = Rgb(0, 0, 255);
+
+    fn fade_to_blue(millis: u64) -> Effect {
+        Effect {
+            kind: EffectKind::FadeTo(BLUE),
+            cycle: Duration::from_millis(millis),
+            cycles: Some(1),
+        }
+    }
+
+    fn queue(mock: &MockBridge) -> BridgeQueue {
+        BridgeQueue::start(
+            mock.validated_bridge(),
+            Snapshots::default(),
+            crossbeam_channel::unbounded().0,
+        )
+    }
+
+    #[test]
+    fn only_sends_what_changed() {
+        let mock = MockBridge::start();
+        let start = Instant::now();
+        let mut scheduler =
+            Scheduler::new(queue(&mock), &fade_to_blue(1000), &Layout::default(), start).unwrap();
+
+        assert_eq!(scheduler.frame(start).len(), 3);
+        assert!(scheduler.frame(start).is_empty());
+
+        let end = start + Duration::from_secs(1);
+        assert!(scheduler.is_done(end));
+        let commands: HashMap<_, _> = scheduler.frame(end).into_iter().collect();
+        assert_eq!(commands.len(), 3);
+        for command in commands.values() {
+            assert_eq!(command.on, Some(true));
+            assert_eq!(command.bri, Some(254));
+            assert_eq!(command.transitiontime, Some(1));
+            assert_eq!(command.hue, None);
+        }
+        // the same blue, as near as each light's gamut gets to it, and none for the hallway
+        // light, which is white
+        let blue = BLUE.xy().0;
+        assert_eq!(commands[&1].xy, Some(GAMUT_C.clamp(blue)));
+        assert_eq!(commands[&2].xy, Some(GAMUT_B.clamp(blue)));
+        assert_ne!(commands[&1].xy, commands[&2].xy);
+        assert_eq!((commands[&3].xy, commands[&3].ct), (None, None));
+        assert!(scheduler.frame(end + FRAME_INTERVAL).is_empty());
+    }
+
+    #[test]
+    fn goes_from_light_to_light_across_the_room() {
+        let mock = MockBridge::start();
+        let queue = queue(&mock);
+        let place = |light, x| LightPosition {
+            bridge: queue.bridge().id().to_string(),
+            light,
+            x,
+            y: 0.5,
+            zone: None,
+        };
+        // the hallway light is by the left wall, the desk by the right
+        let layout = Layout {
+            lights: vec![place(3, 0.0), place(2, 0.5), place(1, 1.0)],
+        };
+        let chase = Effect::from(EffectKind::Chase(BLUE));
+        let start = Instant::now();
+        let mut scheduler = Scheduler::new(queue, &chase, &layout, start).unwrap();
+
+        // blue, or just on for the hallway light, which is white
+        let lit = |commands: Vec<(usize, CommandLight)>| {
+            commands
+                .into_iter()
+                .filter(|(_, command)| {
+                    command.on == Some(true) && command.xy.is_none_or(|(x, _)| x < 0.2)
+                })
+                .map(|(id, _)| id)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            lit(scheduler.frame(start + Duration::from_millis(100))),
+            [3]
+        );
+        assert_eq!(
+            lit(scheduler.frame(start + Duration::from_millis(600))),
+            [2]
+        );
+        assert_eq!(
+            lit(scheduler.frame(start + Duration::from_millis(1100))),
+            [1]
+        );
+    }
+
+    #[test]
+    fn streams_to_the_entertainment_area() {
+        let mut mock = MockBridge::start();
+        let area = mock.add_entertainment_area("TV area", &[1, 2]);
+        let before = mock.registered_bridge().get_light_states().unwrap();
+        let mut task = EffectTask::new(vec![queue(&mock)], fade_to_blue(200), Layout::default());
+        task.frame_interval = Duration::from_millis(20);
+        task.run_task().unwrap();
+
+        // a frame every 20ms for 200ms, all of it streamed to the area's lights
+        let frames = mock.wait_for_frames(5);
+        assert!(frames.iter().all(|frame| frame.len() == 2));
+        assert!(frames.contains(&vec![(1, [0, 0, 65535]), (2, [0, 0, 65535])]));
+        assert!(!mock.is_streaming(area));
+
+        // the hallway light isn't in the area, so it went through REST, and the others were
+        // put back the same way once streaming stopped
+        mock.wait_for_light(3, |state| !state.on);
+        mock.wait_for_light(1, |state| state.xy == before[&1].xy);
+        mock.wait_for_light(2, |state| state.hue == before[&2].hue);
+    }
+
+    #[test]
+    fn effects_at_the_same_time_take_turns_streaming() {
+        let mut mock = MockBridge::start();
+        mock.add_entertainment_area("TV area", &[1, 2]);
+        let layout = Layout::default();
+        let start = Instant::now();
+
+        let first = Scheduler::new(queue(&mock), &fade_to_blue(200), &layout, start).unwrap();
+        assert_eq!(first.frame_interval(), STREAM_INTERVAL);
+        // the area's taken, so this one goes through REST
+        let second = Scheduler::new(queue(&mock), &fade_to_blue(200), &layout, start).unwrap();
+        assert_eq!(second.frame_interval(), FRAME_INTERVAL);
+        assert!(!second.is_streamed(1));
+
+        first.finish();
+        let third = Scheduler::new(queue(&mock), &fade_to_blue(200), &layout, start).unwrap();
+        assert_eq!(third.frame_interval(), STREAM_INTERVAL);
+        second.finish();
+        third.finish();
+    }
+
+    #[test]
+    fn plays_on_a_whole_group_at_once() {
+        let mock = MockBridge::start();
+        let office = mock.add_group("Office", "Room", &[1, 2]);
+        let start = Instant::now();
+        let mut scheduler =
+            Scheduler::for_group(queue(&mock), office, &fade_to_blue(200), start).unwrap();
+        assert_eq!(scheduler.frame_interval(), GROUP_FRAME_INTERVAL);
+        let end = scheduler.frame(start + Duration::from_millis(200));
+        assert_eq!(end.len(), 1);
+        // the desk and backdrop disagree on their gamut, so it's the widest
+        let blue = GAMUT_C.clamp(BLUE.xy().0);
+        assert_eq!((end[0].0, end[0].1.xy), (office, Some(blue)));
+        assert_eq!(end[0].1.transitiontime, Some(10));
+        scheduler.finish();
+
+        let before = mock.registered_bridge().get_light_states().unwrap();
+        let mut task = EffectTask::new(vec![queue(&mock)], fade_to_blue(200), Layout::default())
+            .on_group(Some(office));
+        task.frame_interval = Duration::from_millis(20);
+        task.run_task().unwrap();
+        mock.wait_for_light(1, |state| state.xy == before[&1].xy);
+        assert!(mock.group_writes() >= 1);
+        // the hallway isn't in the office, so it was left off
+        assert!(!mock.light_state(3).unwrap().on);
+    }
+
+    #[test]
+    fn shapes_group_commands_for_their_most_capable_member() {
+        let mock = MockBridge::start();
+        // the white hallway light comes first, but the backdrop can show colour
+        let mixed = mock.add_group("Mixed", "Zone", &[3, 2]);
+        let start = Instant::now();
+        let mut scheduler =
+            Scheduler::for_group(queue(&mock), mixed, &fade_to_blue(200), start).unwrap();
+        let end = scheduler.frame(start + Duration::from_millis(200));
+        assert_eq!(end[0].1.xy, Some(GAMUT_B.clamp(BLUE.xy().0)));
+        scheduler.finish();
+
+        assert_eq!(
+            group_kind([LightKind::Dimmable, LightKind::Ambiance]),
+            LightKind::Ambiance
+        );
+        assert_eq!(
+            group_kind([LightKind::Color(GAMUT_A), LightKind::Color(GAMUT_B)]),
+            LightKind::Color(GAMUT_C)
+        );
+    }
+
+    #[test]
+    fn turns_dark_targets_off() {
+        let off = command(
+            &Target::new(BLUE, 0.0),
+            FRAME_INTERVAL,
+            LightKind::Color(GAMUT_C),
+        );
+        assert_eq!((off.on, off.xy), (Some(false), None));
+    }
+
+    #[test]
+    fn plays_effects_through_then_puts_the_lights_back() {
+        let mock = MockBridge::start();
+        let before = mock.registered_bridge().get_light_states().unwrap();
+        let mut task = EffectTask::new(vec![queue(&mock)], fade_to_blue(200), Layout::default());
+        task.frame_interval = Duration::from_millis(20);
+        task.run_task().unwrap();
+
+        mock.wait_for_light(1, |state| state.xy == before[&1].xy);
+        mock.wait_for_light(3, |state| !state.on);
+        assert!(mock.light_writes() > 3);
+
+        // the effect coloured it by xy, but it was showing hue and saturation before
+        let bridge = mock.registered_bridge();
+        assert!(
+            wait_until(|| bridge.get_light_states().unwrap()[&2].colormode == before[&2].colormode),
+            "light 2 wasn't put back"
+        );
+    }
+
+    #[test]
+    fn stops_and_stretches_when_told() {
+        let mock = MockBridge::start();
+        let start = Instant::now();
+        let mut scheduler =
+            Scheduler::new(queue(&mock), &fade_to_blue(1000), &Layout::default(), start).unwrap();
+        scheduler.extend(2);
+        assert!(!scheduler.is_done(start + Duration::from_secs(2)));
+        assert!(scheduler.is_done(start + Duration::from_secs(3)));
+
+        let mut task = EffectTask::new(vec![queue(&mock)], fade_to_blue(60_000), Layout::default());
+        task.frame_interval = Duration::from_millis(20);
+        task.controller().send(EffectControl::Stop).unwrap();
+        let (events_tx, events) = crossbeam_channel::unbounded();
+        let action = LightAction::Effect(fade_to_blue(60_000));
+        EffectTask::on_complete(task.run_task(), (action, 4, events_tx));
+        assert_eq!(events.try_recv(), Ok(LightEvent::EffectDone { id: 4 }));
+    }
+}
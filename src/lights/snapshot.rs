@@ -0,0 +1,180 @@
+//! Remembers what the lights looked like before the app changed them, so they can be put back.
+
+use crate::hue::{Bridge, ColorMode, FullLightState};
+use hueclient::CommandLight;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+/// Light states by light ID.
+pub type States = BTreeMap<usize, FullLightState>;
+
+/// Saved states for one bridge's lights. Clones share them.
+#[derive(Debug, Clone, Default)]
+pub struct Snapshots {
+    saved: Arc<Mutex<Saved>>,
+}
+
+#[derive(Debug, Default)]
+struct Saved {
+    /// before the app first touched each light, for when it exits
+    originals: States,
+    /// before the first of the effects now running started
+    before_effects: States,
+    /// effects that have started and not finished
+    effects: usize,
+}
+
+impl Snapshots {
+    /// Reads every light, keeping the state of any the app hasn't touched before.
+    /// Call it before changing anything.
+    pub fn take(&self, bridge: &Bridge) -> anyhow::Result<States> {
+        let states = bridge.get_light_states()?;
+        let mut saved = self.saved.lock().unwrap();
+        for (id, state) in &states {
+            saved.originals.entry(*id).or_insert(*state);
+        }
+        Ok(states)
+    }
+
+    /// `states` is what the lights go back to once this effect, and any others that start
+    /// before it's done, have finished. An effect that starts mid-way through another one
+    /// doesn't get to put the lights back to the other effect's colours.
+    pub fn effect_started(&self, states: &States) {
+        let mut saved = self.saved.lock().unwrap();
+        if saved.effects == 0 {
+            saved.before_effects = states.clone();
+        }
+        saved.effects += 1;
+    }
+
+    pub fn effect_playing(&self) -> bool {
+        self.saved.lock().unwrap().effects > 0
+    }
+
+    /// What to put the lights back to, if this was the last effect running.
+    pub fn effect_finished(&self) -> States {
+        let mut saved = self.saved.lock().unwrap();
+        saved.effects = saved.effects.saturating_sub(1);
+        match saved.effects {
+            0 => std::mem::take(&mut saved.before_effects),
+            _ => States::new(),
+        }
+    }
+
+    /// Puts every light the app touched back how it found it, straight to the bridge rather
+    /// than through its queue, since this happens on the way out. Lights that couldn't be
+    /// restored stay saved.
+    pub fn restore(&self, bridge: &Bridge) -> anyhow::Result<()> {
+        let originals = std::mem::take(&mut self.saved.lock().unwrap().originals);
+        let mut failed = None;
+        for (id, state) in originals {
+            if let Err(e) = bridge.set_light_state(id, &restore_command(&state)) {
+                self.saved.lock().unwrap().originals.insert(id, state);
+                failed = Some(e);
+            }
+        }
+        match failed {
+            Some(e) => Err(e.into()),
+            None => Ok(()),
+        }
+    }
+}
+
+/// The command that puts a light back to `state`. A light that was off is only turned off,
+/// since the bridge won't change the colour of a light that's off.
+pub fn restore_command(state: &FullLightState) -> CommandLight {
+    if !state.on {
+        return CommandLight::default().off();
+    }
+
+    let mut command = CommandLight::default().on();
+    command.bri = state.bri;
+    match state.colormode {
+        Some(ColorMode::Hs) => {
+            command.hue = state.hue;
+            command.sat = state.sat;
+        }
+        Some(ColorMode::Xy) => command.xy = state.xy,
+        Some(ColorMode::Ct) => command.ct = state.ct,
+        None => {}
+    }
+    command
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hue::mock::MockBridge;
+
+    #[test]
+    fn puts_back_exactly_what_was_there() {
+        let mock = MockBridge::start();
+        let bridge = mock.registered_bridge();
+        let before = bridge.get_light_states().unwrap();
+
+        let snapshots = Snapshots::default();
+        snapshots.take(&bridge).unwrap();
+        for id in mock.light_ids() {
+            let command = CommandLight::default().on().with_bri(9).with_hue(100);
+            bridge.set_light_state(id, &command).unwrap();
+        }
+        // later snapshots don't replace what was there first
+        snapshots.take(&bridge).unwrap();
+
+        snapshots.restore(&bridge).unwrap();
+        let after = bridge.get_light_states().unwrap();
+        // showing xy again, whatever hue it was left with
+        assert_eq!(
+            (
+                after[&1].on,
+                after[&1].bri,
+                after[&1].xy,
+                after[&1].colormode
+            ),
+            (true, Some(254), before[&1].xy, Some(ColorMode::Xy))
+        );
+        assert_eq!(after[&2], before[&2]);
+        assert!(!after[&3].on);
+    }
+
+    #[test]
+    fn only_the_last_effect_restores() {
+        let state = |bri| FullLightState {
+            on: true,
+            bri: Some(bri),
+            hue: None,
+            sat: None,
+            ct: None,
+            xy: None,
+            colormode: None,
+        };
+        let snapshots = Snapshots::default();
+
+        snapshots.effect_started(&States::from([(1, state(10))]));
+        // started mid-way through the first, so it sees the first's colours
+        snapshots.effect_started(&States::from([(1, state(200))]));
+        assert!(snapshots.effect_finished().is_empty());
+        assert_eq!(snapshots.effect_finished()[&1].bri, Some(10));
+    }
+
+    #[test]
+    fn restores_colour_by_mode() {
+        let state = FullLightState {
+            on: true,
+            bri: Some(100),
+            hue: Some(8418),
+            sat: Some(140),
+            ct: Some(366),
+            xy: Some((0.4573, 0.41)),
+            colormode: Some(ColorMode::Ct),
+        };
+        let command = restore_command(&state);
+        assert_eq!(
+            (command.on, command.bri, command.ct, command.xy, command.hue),
+            (Some(true), Some(100), Some(366), None, None)
+        );
+
+        let off = restore_command(&FullLightState { on: false, ..state });
+        assert_eq!((off.on, off.bri, off.ct), (Some(false), None, None));
+    }
+}
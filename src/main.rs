@@ -1,19 +1,58 @@
 mod activities;
-mod config;
+mod commands;
+pub mod config;
+#[cfg(test)]
+mod harness;
+mod hue;
+mod lights;
+mod rate_limit;
+mod shutdown;
 mod tasks;
+mod twitch;
 pub mod widgets;
 
 use crate::widgets::unicorn_vomit;
 
-use crossbeam_channel::{Receiver, Sender, TryRecvError};
-use crossterm::terminal::enable_raw_mode;
-use std::time::Duration;
-use std::{io, thread};
+use crossbeam_channel::{Receiver, Sender};
+use crossterm::event::{self, EnableMouseCapture, Event, KeyCode, KeyEvent, KeyModifiers};
+use crossterm::execute;
+use crossterm::terminal::{enable_raw_mode, EnterAlternateScreen};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use std::{io, mem, thread};
 use tui::backend::{Backend, CrosstermBackend};
 
 use crate::activities::bridge_connect::BridgeConnect;
+use crate::activities::group_picker::GroupPicker;
+use crate::activities::layout_editor::LayoutEditor;
+use crate::activities::settings::Settings;
+use crate::activities::status::Status;
+use crate::activities::twitch_login::TwitchLogin;
 use crate::activities::Activity;
-use crate::Mode::Setup;
+use crate::commands::{CommandOutcome, Commands};
+use crate::config::{BridgeConfig, Config, TwitchTokens, ValidatedBridge};
+use crate::hue::queue::BridgeQueue;
+use crate::hue::Discovery;
+use crate::lights::effect_queue::{
+    EffectOrder, EffectQueue, EffectRequest, EffectTarget, EffectsStatus, Triggered,
+};
+use crate::lights::layout::Layout;
+use crate::lights::rainbow::RainbowTask;
+use crate::lights::scheduler::{EffectControl, EffectTask};
+use crate::lights::snapshot::Snapshots;
+use crate::lights::{LightAction, LightEvent, LightTask};
+use crate::shutdown::TerminalGuard;
+use crate::tasks::Task;
+use crate::twitch::auth::AuthClient;
+use crate::twitch::chat::{ChatHandle, ChatTask, Credentials, Reply};
+use crate::twitch::eventsub::{EventSubHandle, EventSubTask};
+use crate::twitch::helix::HelixClient;
+use crate::twitch::tokens::{TokenEvent, TokenTask};
+use crate::twitch::TwitchEvent;
+use crate::widgets::center_rect;
+use crate::widgets::help::HelpWidget;
 use tui::Terminal;
 
 // TODO: maybe add blocking for the (what should be) asynchronous parts like hue comms
@@ -21,119 +60,1079 @@ use tui::Terminal;
 pub struct GlobalState {
     ticks: u64,
     should_stop: bool,
+    show_help: bool,
+    #[allow(dead_code)]
     edge_animated: bool,
+    config: Config,
+    /// keyed by bridge ID
+    bridges: BTreeMap<String, ValidatedBridge>,
+    /// where setup looks for bridges
+    discovery: Discovery,
 }
 
-pub enum Mode<B: Backend> {
-    Setup {
-        activities: Vec<Box<dyn Activity<B>>>,
-    },
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Mode {
+    /// working through `setup_steps` until there's nothing left to set up
+    Setup,
+    /// the live view, where twitchbrite does its job
+    Running,
+    Settings,
 }
 
-impl<B: Backend> Mode<B> {
-    pub fn init_setup(app_tx: Sender<AppMsg>) -> Mode<B> {
-        Setup {
-            activities: vec![
-                Box::new(BridgeConnect::init(app_tx.clone())),
-                Box::new(BridgeConnect::init(app_tx.clone())),
-                Box::new(BridgeConnect::init(app_tx.clone())),
-            ],
-        }
-    }
+/// What an activity hands back to the app when it's done.
+pub enum Outcome {
+    Bridges(Vec<ValidatedBridge>),
+    TwitchLogin(TwitchTokens),
+}
+
+/// The parts of setup that can be run again from settings.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SetupStep {
+    Bridge,
+    TwitchLogin,
 }
 
 pub enum AppMsg {
-    Next,
+    /// the current activity is done, move on to whatever comes after it
+    Next(Outcome),
+    /// return to the previous activity on the history stack
+    Back,
+    OpenSettings,
+    RerunSetup(SetupStep),
+    OpenLayoutEditor,
+    /// keep where the lights are, and go back
+    SaveLayout(Layout),
+    OpenGroupPicker,
+    /// play effects on this group from now on, or on every light, and go back
+    SetEffectTarget(Option<EffectTarget>),
+    /// recall the scene by this name on every bridge that has one
+    RecallScene(String),
+    /// rainbow mode on if it's off, off if it's on
+    ToggleRainbow,
 }
 
+/// how long running tasks get to wrap up once we're quitting
+const TASK_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// keys that work no matter which activity is on screen
+const GLOBAL_KEYBINDINGS: [(&str, &str); 3] = [
+    ("ctrl+c, ctrl+q", "quit"),
+    ("?, f1", "toggle this help"),
+    ("esc", "back"),
+];
+
 pub struct TwitchBrite<B: Backend> {
     terminal: Terminal<B>,
     activity: Box<dyn Activity<B>>,
     state: GlobalState,
     channel: (Sender<AppMsg>, Receiver<AppMsg>),
-    mode: Mode<B>,
+    /// chat and friends, as they happen
+    twitch_ch: (Sender<TwitchEvent>, Receiver<TwitchEvent>),
+    /// everything EventSub tells us, kept apart from chat since the two overlap
+    eventsub_ch: (Sender<TwitchEvent>, Receiver<TwitchEvent>),
+    /// whether EventSub is up, and so the one to trust for subs, gifts and raids
+    eventsub_live: bool,
+    mode: Mode,
+    setup_steps: VecDeque<Box<dyn Activity<B>>>,
+    history_stack: Vec<(Mode, Box<dyn Activity<B>>)>,
+    /// the current chat connection, so logging in again doesn't leave two running
+    chat: Option<ChatHandle>,
+    eventsub: Option<EventSubHandle>,
+    /// stops the task keeping the Twitch tokens fresh
+    token_task: Option<Sender<()>>,
+    tokens_ch: (Sender<TokenEvent>, Receiver<TokenEvent>),
+    commands: Commands,
+    lights_ch: (Sender<LightEvent>, Receiver<LightEvent>),
+    /// keyed by bridge ID, started the first time a bridge is sent anything
+    queues: BTreeMap<String, BridgeQueue>,
+    /// keyed by bridge ID, kept when a bridge's queue is replaced so the lights can still be
+    /// put back on the way out
+    snapshots: BTreeMap<String, Snapshots>,
+    /// decides which triggered effect plays when
+    effects: EffectQueue,
+    /// for each effect that's playing, by its ID in `effects`
+    effect_controls: HashMap<u64, Sender<EffectControl>>,
+    /// what the activity was last told about `effects`
+    effects_shown: EffectsStatus,
+    /// `state.ticks`, for tasks that keep time with the UI
+    clock: Arc<AtomicU64>,
+    /// stops rainbow mode, if it's on
+    rainbow: Option<Sender<()>>,
 }
 
 impl<B: Backend> TwitchBrite<B> {
     pub fn with_backend(backend: B) -> anyhow::Result<()> {
+        let mut config = Config::load()?;
+        let bridges = load_saved_bridges(&config);
+        if config.identify_bridges(&bridges) {
+            config.save();
+        }
+
         let mut terminal = Terminal::new(backend)?;
 
+        shutdown::install_panic_hook();
+        shutdown::register_signals()?;
+
+        let _terminal_guard = TerminalGuard;
         enable_raw_mode()?; // TODO: this depends on crossterm - if the rest of the code is backend-agnostic, shouldn't this be, too?
+        execute!(io::stdout(), EnterAlternateScreen, EnableMouseCapture)?;
         terminal.clear()?;
 
-        let channel = crossbeam_channel::unbounded();
+        let mut app = Self::new(terminal, config, bridges, Discovery::Network);
+        app.connect_twitch();
+        app.run()?;
+
+        // let tasks know they should stop, and give them a moment to do so
+        shutdown::request();
+        shutdown::join_tasks(TASK_SHUTDOWN_TIMEOUT);
+
+        Ok(())
+    }
+
+    /// Builds the app without touching the real terminal, so tests can drive it by hand.
+    fn new(
+        terminal: Terminal<B>,
+        config: Config,
+        bridges: BTreeMap<String, ValidatedBridge>,
+        discovery: Discovery,
+    ) -> Self {
+        let channel: (Sender<AppMsg>, Receiver<AppMsg>) = crossbeam_channel::unbounded();
+        let state = GlobalState {
+            should_stop: false,
+            show_help: false,
+            ticks: 0,
+            edge_animated: true,
+            config,
+            bridges,
+            discovery,
+        };
 
-        let mut mode = Mode::init_setup(channel.0.clone());
-        let curr_activity = match &mut mode {
-            Mode::Setup { activities } => activities.remove(0),
+        let mut setup_steps = Self::setup_steps(channel.0.clone(), &state, None);
+        let (mode, activity) = match setup_steps.pop_front() {
+            Some(step) => (Mode::Setup, step),
+            None => (
+                Mode::Running,
+                Self::running_activity(channel.0.clone(), &state.bridges),
+            ),
         };
 
-        let mut app = Self {
+        Self {
             terminal,
-            activity: curr_activity,
-            state: GlobalState {
-                should_stop: false,
-                ticks: 0,
-                edge_animated: true,
-            },
+            activity,
+            state,
             channel,
-            // history_stack: vec![],
+            twitch_ch: crossbeam_channel::unbounded(),
+            eventsub_ch: crossbeam_channel::unbounded(),
+            eventsub_live: false,
             mode,
-        };
+            setup_steps,
+            history_stack: vec![],
+            chat: None,
+            eventsub: None,
+            token_task: None,
+            tokens_ch: crossbeam_channel::unbounded(),
+            commands: Commands::default(),
+            lights_ch: crossbeam_channel::unbounded(),
+            queues: BTreeMap::new(),
+            snapshots: BTreeMap::new(),
+            effects: EffectQueue::default(),
+            effect_controls: HashMap::new(),
+            effects_shown: EffectsStatus::default(),
+            clock: Arc::new(AtomicU64::new(0)),
+            rainbow: None,
+        }
+    }
 
+    /// The real-time loop: ~60 ticks per second until something asks us to stop.
+    fn run(&mut self) -> anyhow::Result<()> {
         loop {
-            app.update()?;
-            app.draw()?;
+            while event::poll(Duration::ZERO)? {
+                let event = event::read()?;
+                self.input(&event);
+            }
+
+            self.tick();
+            self.draw()?;
 
-            if app.state.should_stop {
-                break;
+            if self.state.should_stop || shutdown::requested() {
+                return Ok(());
             }
 
             thread::sleep(Duration::from_millis(16))
         }
+    }
+
+    /// Joins the configured channel's chat, if there is one, as the logged in user if there
+    /// is one. Logged in, it also keeps their tokens fresh and listens to EventSub.
+    /// Any earlier connection is dropped.
+    fn connect_twitch(&mut self) {
+        self.disconnect_twitch();
+
+        let twitch_config = self.state.config.twitch_config();
+        if let (Some(tokens), Some(client_id)) = (&twitch_config.tokens, twitch_config.client_id())
+        {
+            if let Ok(auth) = AuthClient::new(&twitch_config.auth_url, client_id) {
+                let task = TokenTask::new(auth, tokens.clone(), self.tokens_ch.0.clone());
+                self.token_task = Some(task.canceller());
+                task.spawn(self.tokens_ch.0.clone());
+            }
+
+            let helix = HelixClient::new(&twitch_config.helix_url, client_id, &tokens.access_token);
+            if let (Some(channel), Ok(helix)) = (twitch_config.channel(), helix) {
+                let eventsub = EventSubTask::new(
+                    &twitch_config.eventsub_url,
+                    helix,
+                    channel,
+                    tokens,
+                    self.eventsub_ch.0.clone(),
+                );
+                self.eventsub = Some(eventsub.controller());
+                eventsub.spawn(self.eventsub_ch.0.clone());
+            }
+        }
 
-        return Ok(());
+        let channel = match twitch_config.channel() {
+            Some(channel) => channel,
+            None => return,
+        };
+        let credentials = match &twitch_config.tokens {
+            Some(tokens) => Credentials::OAuth {
+                login: tokens.login.clone(),
+                token: tokens.access_token.clone(),
+            },
+            None => Credentials::Anonymous,
+        };
+
+        let chat = ChatTask::new(
+            &twitch_config.chat_host,
+            channel,
+            credentials,
+            self.twitch_ch.0.clone(),
+        );
+        self.chat = Some(chat.controller());
+        chat.spawn(self.twitch_ch.0.clone());
+    }
+
+    fn disconnect_twitch(&mut self) {
+        if let Some(chat) = self.chat.take() {
+            chat.stop();
+        }
+        if let Some(eventsub) = self.eventsub.take() {
+            eventsub.stop();
+        }
+        self.eventsub_live = false;
+        if let Some(token_task) = self.token_task.take() {
+            let _ = token_task.try_send(());
+        }
+    }
+
+    fn handle_token_event(&mut self, event: TokenEvent) {
+        match event {
+            TokenEvent::Refreshed(tokens) => {
+                if let Some(chat) = &self.chat {
+                    chat.set_credentials(Credentials::OAuth {
+                        login: tokens.login.clone(),
+                        token: tokens.access_token.clone(),
+                    });
+                }
+                if let Some(eventsub) = &self.eventsub {
+                    eventsub.set_token(tokens.access_token.clone());
+                }
+                self.state.config.twitch_config_mut().tokens = Some(tokens);
+                self.state.config.save();
+            }
+            // already on it
+            TokenEvent::Expired(_) if self.state.config.twitch_config().tokens.is_none() => {}
+            TokenEvent::Expired(reason) => {
+                // chat stays connected with the old login in the meantime
+                self.token_task = None;
+                self.state.config.twitch_config_mut().tokens = None;
+                self.state.config.save();
+
+                let login = TwitchLogin::with_reason(
+                    self.channel.0.clone(),
+                    self.state.config.twitch_config(),
+                    reason,
+                );
+                self.push(Mode::Setup, Box::new(login));
+            }
+        }
+    }
+
+    /// every bridge's queue, starting any that aren't running yet
+    fn queues(&mut self) -> Vec<BridgeQueue> {
+        let (queues, snapshots) = (&mut self.queues, &mut self.snapshots);
+        let events_tx = &self.lights_ch.0;
+        self.state
+            .bridges
+            .iter()
+            .map(|(id, bridge)| {
+                queues
+                    .entry(id.clone())
+                    .or_insert_with(|| {
+                        let snapshots = snapshots.entry(id.clone()).or_default().clone();
+                        BridgeQueue::start(bridge.clone(), snapshots, events_tx.clone())
+                    })
+                    .clone()
+            })
+            .collect()
+    }
+
+    /// The queues effects play through, and the group they play on if one's been picked.
+    /// A group on a bridge that isn't connected is ignored, so effects still play somewhere.
+    fn effect_queues(&mut self) -> (Vec<BridgeQueue>, Option<usize>) {
+        let queues = self.queues();
+        let target = match &self.state.config.effects_config().target {
+            Some(target) => target,
+            None => return (queues, None),
+        };
+        match queues
+            .iter()
+            .find(|queue| queue.bridge().id() == target.bridge)
+        {
+            Some(queue) => (vec![queue.clone()], Some(target.group)),
+            None => (queues, None),
+        }
+    }
+
+    /// Hands the effect to the effect queue, which decides whether it plays now, later or not
+    /// at all.
+    fn submit_effect(&mut self, request: EffectRequest) {
+        let orders = self.effects.submit(request, Instant::now());
+        self.follow_orders(orders);
     }
 
-    fn update(&mut self) -> anyhow::Result<()> {
+    fn follow_orders(&mut self, orders: Vec<EffectOrder>) {
+        for order in orders {
+            match order {
+                EffectOrder::Start { id, effect } => {
+                    let (queues, group) = self.effect_queues();
+                    let task = EffectTask::new(queues, effect, self.state.config.layout().clone())
+                        .on_group(group);
+                    self.effect_controls.insert(id, task.controller());
+                    task.spawn((LightAction::Effect(effect), id, self.lights_ch.0.clone()));
+                }
+                EffectOrder::Stop { id } => {
+                    if let Some(control) = self.effect_controls.remove(&id) {
+                        let _ = control.send(EffectControl::Stop);
+                    }
+                }
+                EffectOrder::Extend { id, cycles } => {
+                    if let Some(control) = self.effect_controls.get(&id) {
+                        let _ = control.send(EffectControl::Extend(cycles));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Lets the activity know about changes to the effect queue, including effects giving up.
+    fn show_effects(&mut self) {
+        let now = Instant::now();
+        self.effects.expire(now);
+        let status = self.effects.status(now);
+        if status != self.effects_shown {
+            self.activity
+                .handle_light_event(&LightEvent::Effects(status.clone()));
+            self.effects_shown = status;
+        }
+    }
+
+    /// Starts rainbow mode over, with the layout as it is now.
+    fn start_rainbow(&mut self) {
+        self.stop_rainbow();
+        let layout = self.state.config.layout().clone();
+        let task = RainbowTask::new(self.queues(), layout, self.clock.clone());
+        self.rainbow = Some(task.canceller());
+        task.spawn(self.lights_ch.0.clone());
+    }
+
+    fn stop_rainbow(&mut self) {
+        if let Some(cancel) = self.rainbow.take() {
+            let _ = cancel.send(());
+        }
+    }
+
+    /// Plays whatever effect, or recalls whatever scene, the config says `event` does.
+    fn trigger_effect(&mut self, event: &TwitchEvent) {
+        match self.state.config.effects_config().trigger(event) {
+            Some(Triggered::Effect(request)) => self.submit_effect(request),
+            Some(Triggered::Scene(name)) => self.run_action(LightAction::Scene(name)),
+            None => {}
+        }
+    }
+
+    /// Does something to every light straight away, ending rainbow mode since it would undo
+    /// it.
+    fn run_action(&mut self, action: LightAction) {
+        self.stop_rainbow();
+        LightTask::new(self.queues(), action.clone()).spawn((action, self.lights_ch.0.clone()));
+    }
+
+    fn run_command(&mut self, outcome: CommandOutcome) {
+        match &outcome.result {
+            Ok(LightAction::Effect(effect)) => {
+                let rule = &self.state.config.effects_config().command;
+                let reason = format!("!{} from {}", outcome.command, outcome.message.display_name);
+                self.submit_effect(rule.request(*effect, reason));
+            }
+            Ok(LightAction::Rainbow) => self.start_rainbow(),
+            Ok(action) => self.run_action(action.clone()),
+            Err(_) => {}
+        }
+
+        let replies = &self.state.config.twitch_config().replies;
+        if let (Some(chat), Some(text)) = (&self.chat, replies.render(&outcome)) {
+            let id = &outcome.message.id;
+            chat.reply(Reply {
+                parent: Some(id.clone()).filter(|_| !id.is_empty()),
+                text,
+            });
+        }
+        self.activity.handle_command(&outcome);
+    }
+
+    /// Everything that still needs doing before twitchbrite can run, plus `rerun` if the user
+    /// asked to go through that step again.
+    fn setup_steps(
+        app_tx: Sender<AppMsg>,
+        state: &GlobalState,
+        rerun: Option<SetupStep>,
+    ) -> VecDeque<Box<dyn Activity<B>>> {
+        let mut steps: VecDeque<Box<dyn Activity<B>>> = VecDeque::new();
+        if state.bridges.is_empty() || rerun == Some(SetupStep::Bridge) {
+            steps.push_back(Box::new(BridgeConnect::with_discovery(
+                app_tx.clone(),
+                state.discovery.clone(),
+            )));
+        }
+        if state.config.twitch_config().tokens.is_none() || rerun == Some(SetupStep::TwitchLogin) {
+            steps.push_back(Box::new(TwitchLogin::init(
+                app_tx,
+                state.config.twitch_config(),
+            )));
+        }
+        steps
+    }
+
+    fn running_activity(
+        app_tx: Sender<AppMsg>,
+        bridges: &BTreeMap<String, ValidatedBridge>,
+    ) -> Box<dyn Activity<B>> {
+        let status = bridges.values().map(|bridge| {
+            format!(
+                "Connected to Hue bridge {} at {}",
+                bridge.id(),
+                bridge.address()
+            )
+        });
+        Box::new(Status::init(app_tx, status))
+    }
+
+    /// Hands an input event to the app-wide keybindings, then to the current activity.
+    fn input(&mut self, event: &Event) {
+        if !self.handle_global_event(event) {
+            self.activity.handle_event(event);
+        }
+    }
+
+    fn tick(&mut self) {
         self.state.ticks += 1;
+        self.clock.store(self.state.ticks, Ordering::Relaxed);
 
-        // new screens can use event::poll() and event::read() for input
         self.activity.update(self.state.ticks);
 
+        while let Ok(event) = self.twitch_ch.1.try_recv() {
+            let on_eventsub = matches!(
+                event,
+                TwitchEvent::Subscribe { .. }
+                    | TwitchEvent::GiftSub { .. }
+                    | TwitchEvent::Raid { .. }
+            );
+            // so they're not counted twice
+            if !(self.eventsub_live && on_eventsub) {
+                self.activity.handle_twitch_event(&event);
+                self.trigger_effect(&event);
+            }
+
+            if let TwitchEvent::Message(msg) = &event {
+                if let Some(outcome) = self.commands.handle(msg, Instant::now()) {
+                    self.run_command(outcome);
+                }
+            }
+        }
+
+        while let Ok(event) = self.eventsub_ch.1.try_recv() {
+            match event {
+                TwitchEvent::EventSubConnected { .. } => self.eventsub_live = true,
+                TwitchEvent::EventSubDisconnected { .. } => self.eventsub_live = false,
+                _ => {}
+            }
+            self.activity.handle_twitch_event(&event);
+            self.trigger_effect(&event);
+        }
+
+        while let Ok(event) = self.tokens_ch.1.try_recv() {
+            self.handle_token_event(event);
+        }
+
+        while let Ok(event) = self.lights_ch.1.try_recv() {
+            match event {
+                LightEvent::EffectDone { id } => {
+                    self.effect_controls.remove(&id);
+                    let orders = self.effects.finished(id, Instant::now());
+                    self.follow_orders(orders);
+                }
+                event => self.activity.handle_light_event(&event),
+            }
+        }
+        self.show_effects();
+
         if let Ok(x) = self.channel.1.try_recv() {
             self.handle_message(x);
         }
-
-        Ok(())
     }
 
     fn draw(&mut self) -> anyhow::Result<()> {
+        let help = match self.state.show_help {
+            true => Some(HelpWidget {
+                global: GLOBAL_KEYBINDINGS.to_vec(),
+                activity: self.activity.keybindings(),
+            }),
+            false => None,
+        };
+
         self.terminal.draw(|f| {
             self.activity.render(self.state.ticks, f);
+
+            if let Some(help) = help {
+                f.render_widget(help, center_rect(f.size(), 48, 14));
+            }
         })?;
 
         Ok(())
     }
 
+    /// Keybindings that apply to every activity. Returns true if the event was consumed.
+    fn handle_global_event(&mut self, event: &Event) -> bool {
+        let key = match event {
+            Event::Key(key) => key,
+            _ => return false,
+        };
+
+        match key {
+            KeyEvent {
+                code: KeyCode::Char('c') | KeyCode::Char('q'),
+                modifiers: KeyModifiers::CONTROL,
+            } => self.state.should_stop = true,
+
+            KeyEvent {
+                code: KeyCode::Char('?') | KeyCode::F(1),
+                ..
+            } => self.state.show_help = !self.state.show_help,
+
+            KeyEvent {
+                code: KeyCode::Esc, ..
+            } => match self.state.show_help {
+                true => self.state.show_help = false,
+                false => self.channel.0.send(AppMsg::Back).unwrap(),
+            },
+
+            _ => return false,
+        }
+
+        true
+    }
+
     fn handle_message(&mut self, msg: AppMsg) {
         match msg {
-            AppMsg::Next => {
-                match &mut self.mode {
-                    Mode::Setup { activities } => {
-                        if activities.is_empty() {
-                            // TODO: go to the next mode
-                        } else {
-                            self.activity = activities.remove(0);
-                        }
-                    }
+            AppMsg::Next(outcome) => {
+                self.apply(outcome);
+                self.next();
+            }
+            AppMsg::Back => self.back(),
+            AppMsg::OpenSettings => {
+                let settings = Box::new(Settings::init(self.channel.0.clone()));
+                self.push(Mode::Settings, settings);
+            }
+            AppMsg::RerunSetup(step) => {
+                self.setup_steps =
+                    Self::setup_steps(self.channel.0.clone(), &self.state, Some(step));
+                if let Some(step) = self.setup_steps.pop_front() {
+                    self.push(Mode::Setup, step);
+                }
+            }
+            AppMsg::OpenLayoutEditor => {
+                let editor = Box::new(LayoutEditor::init(
+                    self.channel.0.clone(),
+                    self.state.bridges.values().cloned().collect(),
+                    self.state.config.layout().clone(),
+                ));
+                self.push(Mode::Settings, editor);
+            }
+            AppMsg::SaveLayout(layout) => {
+                self.state.config.set_layout(layout);
+                self.state.config.save();
+                // so the rainbow's spread across the new layout
+                if self.rainbow.is_some() {
+                    self.stop_rainbow();
+                    self.start_rainbow();
+                }
+                self.back();
+            }
+            AppMsg::OpenGroupPicker => {
+                let picker = Box::new(GroupPicker::init(
+                    self.channel.0.clone(),
+                    self.state.bridges.values().cloned().collect(),
+                    self.state.config.effects_config().target.clone(),
+                ));
+                self.push(Mode::Settings, picker);
+            }
+            AppMsg::SetEffectTarget(target) => {
+                self.state.config.effects_config_mut().target = target;
+                self.state.config.save();
+                self.back();
+            }
+            AppMsg::RecallScene(name) => self.run_action(LightAction::Scene(name)),
+            AppMsg::ToggleRainbow => match self.rainbow {
+                Some(_) => self.stop_rainbow(),
+                None => self.start_rainbow(),
+            },
+        }
+    }
+
+    /// Takes whatever an activity produced into the shared state.
+    fn apply(&mut self, outcome: Outcome) {
+        match outcome {
+            Outcome::Bridges(bridges) => {
+                // keep the credentials around so we don't have to register again next launch
+                for bridge in bridges {
+                    self.state
+                        .config
+                        .set_bridge_config(BridgeConfig::from_validated_bridge(&bridge));
+                    // the old queue would keep using the old credentials
+                    self.queues.remove(bridge.id());
+                    self.state.bridges.insert(bridge.id().to_string(), bridge);
                 }
+                self.state.config.save();
+            }
+            Outcome::TwitchLogin(tokens) => {
+                self.state.config.twitch_config_mut().tokens = Some(tokens);
+                self.state.config.save();
+                self.connect_twitch();
+            }
+        }
+    }
+
+    fn next(&mut self) {
+        match self.mode {
+            Mode::Setup => match self.setup_steps.pop_front() {
+                Some(step) => self.push(Mode::Setup, step),
+                None => self.start_running(),
+            },
+            Mode::Running | Mode::Settings => {}
+        }
+    }
+
+    /// Shows `activity`, keeping the current one on the history stack to come back to.
+    fn push(&mut self, mode: Mode, activity: Box<dyn Activity<B>>) {
+        let previous = mem::replace(&mut self.activity, activity);
+        self.history_stack.push((self.mode, previous));
+        self.mode = mode;
+    }
+
+    fn back(&mut self) {
+        let (mode, activity) = match self.history_stack.pop() {
+            Some(previous) => previous,
+            None => return,
+        };
+
+        let current = mem::replace(&mut self.activity, activity);
+        if self.mode == Mode::Setup {
+            match mode {
+                // stepping back within setup, so this step still needs doing
+                Mode::Setup => self.setup_steps.push_front(current),
+                // backing out of setup entirely
+                _ => self.setup_steps.clear(),
+            }
+        }
+        self.mode = mode;
+    }
+
+    /// Setup is done, so there's nothing to go back to.
+    fn start_running(&mut self) {
+        self.history_stack.clear();
+        self.setup_steps.clear();
+        self.activity = Self::running_activity(self.channel.0.clone(), &self.state.bridges);
+        self.mode = Mode::Running;
+    }
+}
+
+/// Puts back every light the app changed, whether it's quitting normally or unwinding from a
+/// panic. Tasks get to stop first, so a late effect frame can't undo it.
+impl<B: Backend> Drop for TwitchBrite<B> {
+    fn drop(&mut self) {
+        if thread::panicking() {
+            shutdown::join_tasks(TASK_SHUTDOWN_TIMEOUT);
+        }
+        for (id, snapshots) in &self.snapshots {
+            if let Some(bridge) = self.state.bridges.get(id) {
+                let _ = snapshots.restore(bridge);
             }
         }
     }
 }
 
+/// Rebuilds every bridge from saved credentials that the bridge still accepts.
+fn load_saved_bridges(config: &Config) -> BTreeMap<String, ValidatedBridge> {
+    config
+        .bridge_configs()
+        .filter_map(|bridge_config| bridge_config.validate().ok())
+        .map(|bridge| (bridge.id().to_string(), bridge))
+        .collect()
+}
+
 fn main() -> anyhow::Result<()> {
     let stdout = io::stdout();
     TwitchBrite::with_backend(CrosstermBackend::new(stdout))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::harness::Harness;
+    use crate::hue::mock::MockBridge;
+    use crate::lights::color::{Gamut, GAMUT_B, GAMUT_C};
+    use crate::lights::Rgb;
+    use crate::twitch::mock::MOCK_LOGIN;
+    use crate::twitch::{ChatMessage, SubTier};
+    use serde_json::json;
+
+    #[test]
+    fn setup_hands_over_to_running() {
+        let mock = MockBridge::start();
+        mock.press_link_button();
+
+        let mut app = Harness::new(80, 24, Discovery::Addresses(vec![mock.address()]));
+        assert_eq!(app.mode(), Mode::Setup);
+        app.tick();
+        assert!(app.shows("welcome to twitchbrite"));
+
+        app.tick_until(|app| app.shows(&app.auth.user_code()));
+        assert_eq!(app.mode(), Mode::Setup);
+
+        app.auth.authorize();
+        app.tick_until(|app| app.mode() == Mode::Running);
+        app.tick();
+        let connected = format!(
+            "Connected to Hue bridge {} at {}",
+            mock.info().bridgeid,
+            mock.address()
+        );
+        assert!(app.shows(&connected));
+    }
+
+    #[test]
+    fn help_overlay_toggles() {
+        let mock = MockBridge::start();
+        let mut app = Harness::with_bridges(80, 24, vec![mock.validated_bridge()]);
+        app.tick();
+        assert!(!app.shows("toggle this help"));
+
+        app.key(KeyCode::Char('?'));
+        app.tick();
+        assert!(app.shows("toggle this help"));
+        assert!(app.shows("settings"));
+
+        // esc closes the overlay rather than going back
+        app.key(KeyCode::Esc);
+        app.tick();
+        assert!(!app.shows("toggle this help"));
+        assert_eq!(app.mode(), Mode::Running);
+    }
+
+    #[test]
+    fn places_the_lights_from_settings() {
+        let mock = MockBridge::start();
+        let mut app = Harness::with_bridges(80, 24, vec![mock.validated_bridge()]);
+        app.send(AppMsg::OpenSettings);
+        app.tick();
+        app.key(KeyCode::Down);
+        app.key(KeyCode::Enter);
+        app.tick_until(|app| app.shows("Backdrop"));
+
+        app.key(KeyCode::Right);
+        app.key(KeyCode::Enter);
+        app.tick_until(|app| app.shows("Set up the Hue bridge again"));
+        let placed = app.config().layout().get(&mock.info().bridgeid, 1);
+        assert_eq!(placed.map(|placed| placed.x), Some(0.2));
+    }
+
+    #[test]
+    fn effects_play_on_the_group_picked_in_settings() {
+        let mock = MockBridge::start();
+        let office = mock.add_group("Office", "Room", &[1, 2]);
+        let mut app = Harness::with_bridges(80, 24, vec![mock.validated_bridge()]);
+        app.send(AppMsg::OpenSettings);
+        app.tick();
+        app.key(KeyCode::Down);
+        app.key(KeyCode::Down);
+        app.key(KeyCode::Enter);
+        app.tick_until(|app| app.shows("Office · room"));
+
+        app.key(KeyCode::Down);
+        app.key(KeyCode::Enter);
+        app.tick_until(|app| app.shows("Set up the Hue bridge again"));
+        let target = app.config().effects_config().target.clone();
+        assert_eq!(target.map(|target| target.group), Some(office));
+
+        app.eventsub_event(TwitchEvent::Raid {
+            from: "dwbrite".to_string(),
+            viewers: 12,
+        });
+        app.tick_until(|_| mock.group_writes() > 0);
+        // the hallway isn't in the office
+        assert!(!mock.light_state(3).unwrap().on);
+    }
+
+    #[test]
+    fn settings_and_back() {
+        let mock = MockBridge::start();
+        let mut app = Harness::with_bridges(80, 24, vec![mock.validated_bridge()]);
+
+        app.key(KeyCode::Char('s'));
+        app.tick();
+        assert_eq!(app.mode(), Mode::Settings);
+        assert!(app.shows("Set up the Hue bridge again"));
+
+        app.key(KeyCode::Esc);
+        app.tick();
+        assert_eq!(app.mode(), Mode::Running);
+
+        // same thing, straight through the channel
+        app.send(AppMsg::OpenSettings);
+        app.tick();
+        assert_eq!(app.mode(), Mode::Settings);
+        app.send(AppMsg::Back);
+        app.tick();
+        assert_eq!(app.mode(), Mode::Running);
+    }
+
+    #[test]
+    fn rerunning_setup_can_be_backed_out_of() {
+        let mock = MockBridge::start();
+        let mut app = Harness::with_bridges(80, 24, vec![mock.validated_bridge()]);
+
+        app.send(AppMsg::OpenSettings);
+        app.tick();
+        app.send(AppMsg::RerunSetup(SetupStep::Bridge));
+        app.tick();
+        assert_eq!(app.mode(), Mode::Setup);
+
+        app.key(KeyCode::Esc);
+        app.tick();
+        assert_eq!(app.mode(), Mode::Settings);
+    }
+
+    #[test]
+    fn rendering_only_depends_on_ticks() {
+        let mock = MockBridge::start();
+        let mut a = Harness::with_bridges(80, 24, vec![mock.validated_bridge()]);
+        let mut b = Harness::with_bridges(80, 24, vec![mock.validated_bridge()]);
+
+        a.ticks(10);
+        b.ticks(10);
+        assert_eq!(a.buffer(), b.buffer());
+
+        // the border is animated while running
+        b.tick();
+        assert_ne!(a.buffer(), b.buffer());
+    }
+
+    #[test]
+    fn twitch_events_show_up_while_running() {
+        let mock = MockBridge::start();
+        let mut app = Harness::with_bridges(80, 24, vec![mock.validated_bridge()]);
+
+        app.twitch(TwitchEvent::Raid {
+            from: "Raider".to_string(),
+            viewers: 42,
+        });
+        app.tick();
+        app.tick();
+        assert!(app.shows("Raider is raiding with 42 viewers"));
+    }
+
+    #[test]
+    fn eventsub_takes_over_from_chat() {
+        let mock = MockBridge::start();
+        let mut app = Harness::with_bridges(80, 24, vec![mock.validated_bridge()]);
+
+        let session = app.eventsub.accept();
+        session.welcome(10);
+        app.tick_until(|app| app.shows("Listening for"));
+
+        let raid = json!({ "from_broadcaster_user_name": "Raider", "viewers": 42 });
+        session.notification("a", "channel.raid", raid);
+        app.tick_until(|app| app.shows("Raider is raiding with 42 viewers"));
+
+        // chat's copy of the same raid
+        app.twitch(TwitchEvent::Raid {
+            from: "ChatRaider".to_string(),
+            viewers: 42,
+        });
+        app.ticks(2);
+        assert!(!app.shows("ChatRaider"));
+    }
+
+    #[test]
+    fn refreshed_twitch_tokens_are_saved() {
+        let mock = MockBridge::start();
+        let mut app = Harness::with_bridges(80, 24, vec![mock.validated_bridge()]);
+        let old = app.config().twitch_config().tokens.clone().unwrap();
+        // the tokens were fine at startup
+        app.tick_until(|app| app.auth.validations() == 1);
+
+        app.auth.expire_tokens_in(60);
+        app.restart_twitch();
+        app.tick_until(|app| app.config().twitch_config().tokens.as_ref() != Some(&old));
+
+        let tokens = app.config().twitch_config().tokens.clone().unwrap();
+        assert!(app.auth.is_valid(&tokens.access_token));
+        assert_eq!(app.mode(), Mode::Running);
+    }
+
+    #[test]
+    fn an_expired_twitch_login_goes_back_to_logging_in() {
+        let mock = MockBridge::start();
+        let mut app = Harness::with_bridges(80, 24, vec![mock.validated_bridge()]);
+        let tokens = app.config().twitch_config().tokens.clone().unwrap();
+        app.tick_until(|app| app.auth.validations() == 1);
+
+        app.auth.refuse_refresh();
+        app.auth.revoke(&tokens.access_token);
+        app.restart_twitch();
+        app.tick_until(|app| app.shows("log in to twitch"));
+        assert_eq!(app.mode(), Mode::Setup);
+        assert!(app.config().twitch_config().tokens.is_none());
+
+        app.tick_until(|app| app.shows(&app.auth.user_code()));
+        assert!(app.shows("Couldn't refresh the Twitch login"));
+    }
+
+    #[test]
+    fn chat_commands_change_the_lights() {
+        let mock = MockBridge::start();
+        let mut app = Harness::with_bridges(80, 24, vec![mock.validated_bridge()]);
+        let message = |text: &str| {
+            TwitchEvent::Message(ChatMessage {
+                id: "msg".to_string(),
+                channel: MOCK_LOGIN.to_string(),
+                user_id: "1234".to_string(),
+                login: "viewer".to_string(),
+                display_name: "Viewer".to_string(),
+                text: text.to_string(),
+                badges: BTreeMap::new(),
+                bits: 0,
+            })
+        };
+
+        app.twitch(message("!color blue"));
+        app.tick_until(|app| app.shows("!color from Viewer: turn the lights blue"));
+        // the colour lights, as blue as each can show
+        let blue = Rgb(0, 0, 255).xy().0;
+        app.tick_until(|_| {
+            let shows =
+                |id, gamut: Gamut| mock.light_state(id).unwrap().xy == Some(gamut.clamp(blue));
+            shows(1, GAMUT_C) && shows(2, GAMUT_B)
+        });
+        assert_eq!(
+            app.chat.expect("@reply-parent-msg-id"),
+            format!(
+                "@reply-parent-msg-id=msg PRIVMSG #{} :effect queued: turn the lights blue",
+                MOCK_LOGIN
+            )
+        );
+
+        app.twitch(message("!lights party"));
+        app.ticks(2);
+        assert!(app.shows("!lights from Viewer rejected: needs to be a subscriber"));
+        assert!(app
+            .chat
+            .expect("@reply-parent-msg-id")
+            .ends_with(":you need to be a subscriber to use !lights"));
+
+        // quitting puts the lights back how they were
+        drop(app);
+        let backdrop = mock.light_state(2).unwrap();
+        assert_eq!((backdrop.hue, backdrop.sat), (Some(8418), Some(140)));
+        assert!(!mock.light_state(3).unwrap().on);
+    }
+
+    #[test]
+    fn chat_can_recall_scenes() {
+        let mock = MockBridge::start();
+        let mut night = mock.light_state(3).unwrap();
+        night.on = true;
+        mock.add_scene("Night light", None, BTreeMap::from([(3, night)]));
+        let mut app = Harness::with_bridges(80, 24, vec![mock.validated_bridge()]);
+
+        app.twitch(TwitchEvent::Message(ChatMessage {
+            id: "msg".to_string(),
+            channel: MOCK_LOGIN.to_string(),
+            user_id: "1234".to_string(),
+            login: "viewer".to_string(),
+            display_name: "Viewer".to_string(),
+            text: "!scene night light".to_string(),
+            badges: BTreeMap::from([("subscriber".to_string(), "1".to_string())]),
+            bits: 0,
+        }));
+        app.tick_until(|app| app.shows("!scene from Viewer: recall the night light scene"));
+        app.tick_until(|_| mock.light_state(3).unwrap().on);
+
+        // and it's put back on the way out, like anything else
+        drop(app);
+        assert!(!mock.light_state(3).unwrap().on);
+    }
+
+    #[test]
+    fn raids_cut_in_and_subs_wait_their_turn() {
+        let mock = MockBridge::start();
+        let mut app = Harness::with_bridges(80, 24, vec![mock.validated_bridge()]);
+        let sub = |user: &str| TwitchEvent::Subscribe {
+            user: user.to_string(),
+            tier: SubTier::Tier1,
+            months: 1,
+            message: None,
+        };
+
+        app.eventsub_event(sub("viewer"));
+        app.tick_until(|app| app.shows("▶ sub from viewer: pulse purple"));
+
+        app.eventsub_event(TwitchEvent::Raid {
+            from: "dwbrite".to_string(),
+            viewers: 12,
+        });
+        app.eventsub_event(sub("other"));
+        app.tick_until(|app| app.shows("▶ raid from dwbrite: cycle through the colours"));
+        assert!(app.shows(" effects · 1 dropped "));
+        assert!(app.shows("1. sub from other: pulse purple (priority 5, merge, "));
+    }
+
+    #[test]
+    fn r_turns_on_rainbow_mode() {
+        let mock = MockBridge::start();
+        let mut app = Harness::with_bridges(80, 24, vec![mock.validated_bridge()]);
+        app.key(KeyCode::Char('r'));
+        // the hallway light was off
+        app.tick_until(|_| mock.light_state(3).unwrap().on);
+        app.key(KeyCode::Char('r'));
+    }
+
+    #[test]
+    fn ctrl_q_quits() {
+        let mut app = Harness::new(80, 24, Discovery::Addresses(vec![]));
+        app.ctrl('q');
+        assert!(app.should_stop());
+    }
+}
@@ -0,0 +1,178 @@
+//! Keeps to limits like "at most 20 messages in any 30 seconds" or "about 10 a second".
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// A sliding window: something may happen as long as fewer than `max` things happened in the
+/// last `window`.
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    max: usize,
+    window: Duration,
+    /// oldest first
+    recent: VecDeque<Instant>,
+}
+
+impl RateLimiter {
+    pub fn new(max: usize, window: Duration) -> Self {
+        Self {
+            max,
+            window,
+            recent: VecDeque::new(),
+        }
+    }
+
+    pub fn set_max(&mut self, max: usize) {
+        self.max = max;
+    }
+
+    /// How long until something may happen, zero if it may happen now.
+    pub fn wait(&mut self, now: Instant) -> Duration {
+        while let Some(&oldest) = self.recent.front() {
+            if now.saturating_duration_since(oldest) < self.window {
+                break;
+            }
+            self.recent.pop_front();
+        }
+
+        if self.max == 0 {
+            // nothing may happen at all, so check back in a window in case the limit's changed
+            return self.window;
+        }
+        if self.recent.len() < self.max {
+            return Duration::ZERO;
+        }
+        // once enough of the window has passed that we're back under the limit
+        let freed_by = self.recent[self.recent.len() - self.max];
+        (freed_by + self.window).saturating_duration_since(now)
+    }
+
+    /// Counts something against the limit, returning false (and counting nothing) if it
+    /// would go over.
+    pub fn try_acquire(&mut self, now: Instant) -> bool {
+        if !self.wait(now).is_zero() {
+            return false;
+        }
+        self.recent.push_back(now);
+        true
+    }
+}
+
+/// Tokens trickle in at `rate` a second, up to `burst` saved up, and each use takes one.
+/// Smoother than a window for steady streams like light commands.
+#[derive(Debug, Clone)]
+pub struct TokenBucket {
+    rate: f32,
+    burst: f32,
+    tokens: f32,
+    refilled: Instant,
+}
+
+impl TokenBucket {
+    /// starts out full
+    pub fn new(rate: f32, burst: f32, now: Instant) -> Self {
+        Self {
+            rate,
+            burst,
+            tokens: burst,
+            refilled: now,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.refilled).as_secs_f32();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.burst);
+        self.refilled = now;
+    }
+
+    /// How long until there's a token, zero if there's one now.
+    pub fn wait(&mut self, now: Instant) -> Duration {
+        self.refill(now);
+        match self.tokens >= 1.0 {
+            true => Duration::ZERO,
+            false => Duration::from_secs_f32((1.0 - self.tokens) / self.rate),
+        }
+    }
+
+    /// Takes a token if there is one.
+    pub fn try_take(&mut self, now: Instant) -> bool {
+        self.refill(now);
+        if self.tokens < 1.0 {
+            return false;
+        }
+        self.tokens -= 1.0;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allows_up_to_the_limit_per_window() {
+        let start = Instant::now();
+        let at = |millis| start + Duration::from_millis(millis);
+        let mut limiter = RateLimiter::new(2, Duration::from_secs(1));
+
+        assert!(limiter.try_acquire(at(0)));
+        assert!(limiter.try_acquire(at(400)));
+        assert!(!limiter.try_acquire(at(500)));
+        assert_eq!(limiter.wait(at(500)), Duration::from_millis(500));
+
+        // the first one has left the window, the second hasn't
+        assert!(limiter.try_acquire(at(1000)));
+        assert!(!limiter.try_acquire(at(1000)));
+        assert!(limiter.try_acquire(at(1400)));
+    }
+
+    #[test]
+    fn raising_the_limit_takes_effect_at_once() {
+        let now = Instant::now();
+        let mut limiter = RateLimiter::new(1, Duration::from_secs(30));
+        assert!(limiter.try_acquire(now));
+        assert!(!limiter.try_acquire(now));
+
+        limiter.set_max(3);
+        assert!(limiter.try_acquire(now));
+        assert!(limiter.try_acquire(now));
+        assert!(!limiter.try_acquire(now));
+
+        // and lowering it waits for enough to leave the window
+        limiter.set_max(1);
+        assert_eq!(limiter.wait(now), Duration::from_secs(30));
+    }
+
+    #[test]
+    fn a_limit_of_nothing_lets_nothing_through() {
+        let now = Instant::now();
+        let mut limiter = RateLimiter::new(0, Duration::from_secs(30));
+        assert!(!limiter.try_acquire(now));
+        assert_eq!(limiter.wait(now), Duration::from_secs(30));
+
+        limiter.set_max(1);
+        assert!(limiter.try_acquire(now));
+        limiter.set_max(0);
+        assert!(!limiter.try_acquire(now + Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn buckets_refill_steadily() {
+        let start = Instant::now();
+        let at = |millis| start + Duration::from_millis(millis);
+        let mut bucket = TokenBucket::new(10.0, 2.0, start);
+
+        assert!(bucket.try_take(at(0)));
+        assert!(bucket.try_take(at(0)));
+        assert!(!bucket.try_take(at(0)));
+        let wait = bucket.wait(at(50)).as_secs_f32();
+        assert!((wait - 0.05).abs() < 0.001, "{}", wait);
+        assert!(bucket.try_take(at(100)));
+        assert!(!bucket.try_take(at(100)));
+
+        // saving up only goes so far
+        assert!(bucket.try_take(at(5000)));
+        assert!(bucket.try_take(at(5000)));
+        assert!(!bucket.try_take(at(5000)));
+    }
+}
@@ -0,0 +1,100 @@
+use crossterm::event::DisableMouseCapture;
+use crossterm::execute;
+use crossterm::terminal::{disable_raw_mode, LeaveAlternateScreen};
+use std::io;
+use std::panic;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, LazyLock, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// Set once the app is on its way out, whether by keybinding or signal.
+static REQUESTED: LazyLock<Arc<AtomicBool>> = LazyLock::new(|| Arc::new(AtomicBool::new(false)));
+
+/// Threads spawned through `Task::spawn`, so they can be waited on before exiting.
+static TASKS: Mutex<Vec<JoinHandle<()>>> = Mutex::new(Vec::new());
+
+pub fn request() {
+    REQUESTED.store(true, Ordering::SeqCst);
+}
+
+/// Long-running tasks should check this and return early when it's set.
+pub fn requested() -> bool {
+    REQUESTED.load(Ordering::SeqCst)
+}
+
+/// SIGINT/SIGTERM (and friends) request a shutdown instead of killing us outright,
+/// so the main loop gets a chance to restore the terminal.
+pub fn register_signals() -> io::Result<()> {
+    for signal in signal_hook::consts::TERM_SIGNALS {
+        signal_hook::flag::register(*signal, Arc::clone(&REQUESTED))?;
+    }
+    Ok(())
+}
+
+/// Restores the terminal before the default hook prints the panic message,
+/// otherwise it'd be swallowed by the alternate screen. Only the thread this is installed on
+/// takes the app down with it; a task that panics just ends.
+pub fn install_panic_hook() {
+    let default_hook = panic::take_hook();
+    let main = thread::current().id();
+    panic::set_hook(Box::new(move |info| {
+        if thread::current().id() == main {
+            request();
+            restore_terminal();
+        }
+        default_hook(info);
+    }));
+}
+
+/// Undoes everything the app does to the terminal on startup. Safe to call more than once.
+pub fn restore_terminal() {
+    let _ = execute!(io::stdout(), DisableMouseCapture, LeaveAlternateScreen);
+    let _ = disable_raw_mode();
+}
+
+/// Restores the terminal when dropped, so early returns and unwinding clean up too.
+pub struct TerminalGuard;
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        restore_terminal();
+    }
+}
+
+pub fn track(handle: JoinHandle<()>) {
+    let mut tasks = TASKS.lock().unwrap();
+    tasks.retain(|task| !task.is_finished());
+    tasks.push(handle);
+}
+
+/// Waits up to `timeout` for tracked tasks to finish. Anything still running after that
+/// (e.g. stuck on a blocking request) is left to die with the process.
+pub fn join_tasks(timeout: Duration) {
+    let deadline = Instant::now() + timeout;
+    let mut tasks = std::mem::take(&mut *TASKS.lock().unwrap());
+
+    while !tasks.is_empty() && Instant::now() < deadline {
+        let (finished, running): (Vec<_>, Vec<_>) =
+            tasks.into_iter().partition(|task| task.is_finished());
+        for task in finished {
+            let _ = task.join();
+        }
+        tasks = running;
+
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn panicking_tasks_dont_take_the_app_down() {
+        install_panic_hook();
+        let task = thread::spawn(|| panic!("a task gave up"));
+        assert!(task.join().is_err());
+        assert!(!requested());
+    }
+}
@@ -1,7 +1,9 @@
+use crate::shutdown;
 use std::thread;
 
 /// A task is a long-running activity meant to run on another thread.
 /// basically it's async for when you don't want to build an executor in your "game" loop.
+/// Tasks that loop should check `shutdown::requested()` and return when it's set.
 pub trait Task {
     type Result;
     type OnCompleteParams;
@@ -12,6 +14,7 @@ pub trait Task {
         Self: Sized + Send + 'static,
         Self::OnCompleteParams: Sized + Send + 'static,
     {
-        thread::spawn(move || Self::on_complete(self.run_task(), p));
+        let handle = thread::spawn(move || Self::on_complete(self.run_task(), p));
+        shutdown::track(handle);
     }
 }
@@ -0,0 +1,197 @@
+//! Twitch's OAuth endpoints: the device code flow for logging in, plus validating and
+//! refreshing the tokens it hands out.
+//! https://dev.twitch.tv/docs/authentication/getting-tokens-oauth/#device-code-grant-flow
+
+use anyhow::{anyhow, bail};
+use serde::Deserialize;
+use serde_json::Value;
+use std::fmt;
+use std::time::Duration;
+
+pub const TWITCH_AUTH_URL: &str = "https://id.twitch.tv/oauth2";
+
+/// twitchbrite's own client ID, if it was built with one
+pub const BUILT_IN_CLIENT_ID: Option<&str> = option_env!("TWITCHBRITE_CLIENT_ID");
+
+/// reading chat, talking back in it, and everything EventSub tells us about
+pub const SCOPES: &[&str] = &[
+    "chat:read",
+    "chat:edit",
+    "bits:read",
+    "channel:read:subscriptions",
+    "channel:read:redemptions",
+    "channel:read:hype_train",
+    "moderator:read:followers",
+];
+
+const DEVICE_GRANT: &str = "urn:ietf:params:oauth:grant-type:device_code";
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// What the user needs to log in on another device.
+#[derive(Debug, Clone, Deserialize)]
+pub struct DeviceCode {
+    pub device_code: String,
+    pub user_code: String,
+    pub verification_uri: String,
+    /// seconds until the codes stop working
+    pub expires_in: u64,
+    /// seconds to wait between polls
+    pub interval: u64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct TokenResponse {
+    pub access_token: String,
+    pub refresh_token: String,
+    /// seconds until the access token expires
+    pub expires_in: u64,
+}
+
+/// Who a token belongs to, and how much longer it's good for.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Validation {
+    pub login: String,
+    pub user_id: String,
+    /// seconds
+    pub expires_in: u64,
+}
+
+/// Where the device code flow is at.
+#[derive(Debug)]
+pub enum DevicePoll {
+    /// the user hasn't finished logging in yet
+    Pending,
+    /// polling too often, so back off
+    SlowDown,
+    /// the code expired before the user logged in, so start over
+    Expired,
+    Denied,
+    Done(TokenResponse),
+}
+
+/// Twitch answered, and the answer was no. Unlike a network error, trying again won't help.
+#[derive(Debug)]
+pub struct Rejected(String);
+
+impl fmt::Display for Rejected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Twitch said: {}", self.0)
+    }
+}
+
+impl std::error::Error for Rejected {}
+
+#[derive(Clone)]
+pub struct AuthClient {
+    base_url: String,
+    client_id: String,
+    client: reqwest::blocking::Client,
+}
+
+impl AuthClient {
+    pub fn new(base_url: impl Into<String>, client_id: impl Into<String>) -> anyhow::Result<Self> {
+        Ok(Self {
+            base_url: base_url.into(),
+            client_id: client_id.into(),
+            client: reqwest::blocking::Client::builder()
+                .timeout(REQUEST_TIMEOUT)
+                .build()?,
+        })
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}/{}", self.base_url, path)
+    }
+
+    pub fn request_device_code(&self) -> anyhow::Result<DeviceCode> {
+        let scopes = SCOPES.join(" ");
+        let resp = self
+            .client
+            .post(&self.url("device"))
+            .form(&[("client_id", &self.client_id), ("scopes", &scopes)])
+            .send()?;
+        json_or_error(resp)
+    }
+
+    pub fn poll_device_code(&self, device_code: &str) -> anyhow::Result<DevicePoll> {
+        let scopes = SCOPES.join(" ");
+        let resp = self
+            .client
+            .post(&self.url("token"))
+            .form(&[
+                ("client_id", self.client_id.as_str()),
+                ("scopes", &scopes),
+                ("device_code", device_code),
+                ("grant_type", DEVICE_GRANT),
+            ])
+            .send()?;
+
+        if resp.status().is_success() {
+            return Ok(DevicePoll::Done(resp.json()?));
+        }
+
+        let body: Value = resp.json()?;
+        match error_message(&body).as_deref() {
+            Some("authorization_pending") => Ok(DevicePoll::Pending),
+            Some("slow_down") => Ok(DevicePoll::SlowDown),
+            // Twitch says "invalid device code" once a code has expired
+            Some("expired_token") | Some("invalid device code") => Ok(DevicePoll::Expired),
+            Some("access_denied") => Ok(DevicePoll::Denied),
+            _ => Err(twitch_error(&body)),
+        }
+    }
+
+    /// None if the token has expired or been revoked.
+    pub fn validate(&self, access_token: &str) -> anyhow::Result<Option<Validation>> {
+        let resp = self
+            .client
+            .get(&self.url("validate"))
+            .header("Authorization", format!("OAuth {}", access_token))
+            .send()?;
+        if resp.status() == reqwest::StatusCode::UNAUTHORIZED {
+            return Ok(None);
+        }
+        json_or_error(resp).map(Some)
+    }
+
+    /// Trades a refresh token for a new pair of tokens. The old refresh token may stop working.
+    pub fn refresh(&self, refresh_token: &str) -> anyhow::Result<TokenResponse> {
+        let resp = self
+            .client
+            .post(&self.url("token"))
+            .form(&[
+                ("client_id", self.client_id.as_str()),
+                ("grant_type", "refresh_token"),
+                ("refresh_token", refresh_token),
+            ])
+            .send()?;
+        json_or_error(resp)
+    }
+}
+
+/// Twitch puts the error in `message`, where the OAuth spec says `error`.
+fn error_message(body: &Value) -> Option<String> {
+    body.get("message")
+        .or_else(|| body.get("error"))
+        .and_then(Value::as_str)
+        .map(str::to_string)
+}
+
+fn twitch_error(body: &Value) -> anyhow::Error {
+    match error_message(body) {
+        Some(message) => anyhow::Error::new(Rejected(message)),
+        None => anyhow!("Twitch sent something unexpected: {}", body),
+    }
+}
+
+fn json_or_error<T: serde::de::DeserializeOwned>(
+    resp: reqwest::blocking::Response,
+) -> anyhow::Result<T> {
+    if resp.status().is_success() {
+        return Ok(resp.json()?);
+    }
+    match resp.json::<Value>() {
+        Ok(body) => Err(twitch_error(&body)),
+        Err(_) => bail!("Twitch answered with an error and no explanation"),
+    }
+}
@@ -0,0 +1,843 @@
+//! Reads a channel's chat over TMI, Twitch's IRC interface, and turns it into `TwitchEvent`s.
+//! Logged in, it can also reply.
+
+use crate::rate_limit::RateLimiter;
+use crate::shutdown;
+use crate::tasks::Task;
+use crate::twitch::irc::Message;
+use crate::twitch::{ChatMessage, SubTier, TwitchEvent};
+use anyhow::{anyhow, bail, Context};
+use crossbeam_channel::{Receiver, Sender};
+use rand::{thread_rng, Rng};
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::{Duration, Instant};
+
+/// plain-text IRC, since TLS would need a whole TLS stack for what's public chat anyway
+pub const TMI_HOST: &str = "irc.chat.twitch.tv:6667";
+
+/// twitch.tv/tags gets us badges, IDs and the like, twitch.tv/commands gets us USERNOTICE etc.
+const CAPABILITIES: &str = "CAP REQ :twitch.tv/tags twitch.tv/commands";
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+/// how often the read loop wakes up to check whether it should stop
+const READ_TIMEOUT: Duration = Duration::from_millis(250);
+/// Twitch pings about every five minutes, so this much silence means the connection is dead
+const IDLE_TIMEOUT: Duration = Duration::from_secs(6 * 60);
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(60);
+/// a session that lasted this long was working, so the next reconnect starts from scratch
+const STABLE_SESSION: Duration = Duration::from_secs(60);
+/// https://dev.twitch.tv/docs/irc/#rate-limits
+const MESSAGE_WINDOW: Duration = Duration::from_secs(30);
+const REGULAR_MESSAGE_LIMIT: usize = 20;
+/// for the broadcaster and the channel's moderators
+const MODERATOR_MESSAGE_LIMIT: usize = 100;
+/// replies held back by the rate limit past this many are dropped, oldest first, since
+/// they'd be stale by the time they went out
+const MAX_PENDING_REPLIES: usize = 20;
+
+pub enum Credentials {
+    /// read-only, as one of the `justinfan` users Twitch lets anyone log in as
+    Anonymous,
+    OAuth {
+        login: String,
+        token: String,
+    },
+}
+
+/// Twitch turned down our credentials, so there's no point reconnecting with them.
+#[derive(Debug)]
+pub struct LoginFailed(String);
+
+impl fmt::Display for LoginFailed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Twitch chat login failed: {}", self.0)
+    }
+}
+
+impl std::error::Error for LoginFailed {}
+
+/// A message for the channel, threaded under the message with ID `parent` if there is one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reply {
+    pub parent: Option<String>,
+    pub text: String,
+}
+
+impl Reply {
+    fn line(&self, channel: &str) -> String {
+        // one message per line, so the text can't be allowed to start another
+        let text = self.text.replace(['\r', '\n'], " ");
+        match &self.parent {
+            Some(parent) => format!(
+                "@reply-parent-msg-id={} PRIVMSG #{} :{}",
+                parent, channel, text
+            ),
+            None => format!("PRIVMSG #{} :{}", channel, text),
+        }
+    }
+}
+
+/// Stays in a channel's chat until shutdown or cancellation, reconnecting whenever the
+/// connection drops. If Twitch turns the login down, it waits for new credentials first.
+pub struct ChatTask {
+    host: String,
+    channel: String,
+    credentials: Credentials,
+    events_tx: Sender<TwitchEvent>,
+    reconnect_delay: Duration,
+    cancel_ch: (Sender<()>, Receiver<()>),
+    /// credentials to use from the next connection on
+    credentials_ch: (Sender<Credentials>, Receiver<Credentials>),
+    replies_ch: (Sender<Reply>, Receiver<Reply>),
+    /// replies waiting on the rate limit
+    pending: VecDeque<Reply>,
+    limiter: RateLimiter,
+}
+
+/// Controls a `ChatTask` from outside once it's running.
+#[derive(Clone)]
+pub struct ChatHandle {
+    cancel_tx: Sender<()>,
+    credentials_tx: Sender<Credentials>,
+    replies_tx: Sender<Reply>,
+}
+
+impl ChatHandle {
+    /// disconnects and stops the task
+    pub fn stop(&self) {
+        let _ = self.cancel_tx.try_send(());
+    }
+
+    /// Logs in with `credentials` from the next reconnect on. The current connection is left
+    /// alone, since Twitch doesn't drop it when the old token expires.
+    pub fn set_credentials(&self, credentials: Credentials) {
+        let _ = self.credentials_tx.send(credentials);
+    }
+
+    /// Sends `reply` as soon as the rate limit allows. Anonymous chat can't talk, so it's
+    /// dropped there.
+    pub fn reply(&self, reply: Reply) {
+        let _ = self.replies_tx.send(reply);
+    }
+}
+
+impl ChatTask {
+    pub fn new(
+        host: impl Into<String>,
+        channel: &str,
+        credentials: Credentials,
+        events_tx: Sender<TwitchEvent>,
+    ) -> Self {
+        Self {
+            host: host.into(),
+            channel: channel.trim_start_matches('#').to_lowercase(),
+            credentials,
+            events_tx,
+            reconnect_delay: RECONNECT_DELAY,
+            cancel_ch: crossbeam_channel::bounded(1),
+            credentials_ch: crossbeam_channel::unbounded(),
+            replies_ch: crossbeam_channel::unbounded(),
+            pending: VecDeque::new(),
+            limiter: RateLimiter::new(REGULAR_MESSAGE_LIMIT, MESSAGE_WINDOW),
+        }
+    }
+
+    pub fn controller(&self) -> ChatHandle {
+        ChatHandle {
+            cancel_tx: self.cancel_ch.0.clone(),
+            credentials_tx: self.credentials_ch.0.clone(),
+            replies_tx: self.replies_ch.0.clone(),
+        }
+    }
+
+    fn stopping(&self) -> bool {
+        shutdown::requested() || !self.cancel_ch.1.is_empty()
+    }
+
+    /// The next credentials `set_credentials` is given, or None if told to stop first.
+    fn wait_for_credentials(&self) -> Option<Credentials> {
+        loop {
+            crossbeam_channel::select! {
+                recv(self.credentials_ch.1) -> credentials => return credentials.ok(),
+                recv(self.cancel_ch.1) -> _ => return None,
+                default(READ_TIMEOUT) => {
+                    if shutdown::requested() {
+                        return None;
+                    }
+                }
+            }
+        }
+    }
+
+    fn nick(&self) -> String {
+        match &self.credentials {
+            Credentials::Anonymous => format!("justinfan{}", thread_rng().gen_range(1000..100000)),
+            Credentials::OAuth { login, .. } => login.to_lowercase(),
+        }
+    }
+
+    /// One connection, from login until it drops. Only returns Ok when asked to stop.
+    fn session(&mut self) -> anyhow::Result<()> {
+        let stream = connect(&self.host)?;
+        stream.set_read_timeout(Some(READ_TIMEOUT))?;
+        let mut writer = stream.try_clone()?;
+
+        let nick = self.nick();
+        send(&mut writer, CAPABILITIES)?;
+        if let Credentials::OAuth { token, .. } = &self.credentials {
+            send(&mut writer, &format!("PASS oauth:{}", token))?;
+        }
+        send(&mut writer, &format!("NICK {}", nick))?;
+        send(&mut writer, &format!("JOIN #{}", self.channel))?;
+
+        let mut reader = BufReader::new(stream);
+        let mut line = vec![];
+        let mut last_heard = Instant::now();
+        loop {
+            if self.stopping() {
+                return Ok(());
+            }
+            self.send_replies(&mut writer)?;
+
+            // a timeout can leave half a line in `line`, which the next read finishes
+            match reader.read_until(b'\n', &mut line) {
+                Ok(_) if !line.ends_with(b"\n") => bail!("The server closed the connection."),
+                Ok(_) => {
+                    last_heard = Instant::now();
+                    let text = String::from_utf8_lossy(&line).into_owned();
+                    line.clear();
+                    if let Some(msg) = Message::parse(&text) {
+                        self.handle(&msg, &nick, &mut writer)?;
+                    }
+                }
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                    ) =>
+                {
+                    if last_heard.elapsed() > IDLE_TIMEOUT {
+                        bail!(
+                            "Heard nothing from the server in {}s.",
+                            IDLE_TIMEOUT.as_secs()
+                        );
+                    }
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    /// Sends whatever the rate limit allows of the queued replies.
+    fn send_replies(&mut self, writer: &mut TcpStream) -> io::Result<()> {
+        self.pending.extend(self.replies_ch.1.try_iter());
+        if let Credentials::Anonymous = self.credentials {
+            self.pending.clear();
+        }
+        while self.pending.len() > MAX_PENDING_REPLIES {
+            self.pending.pop_front();
+        }
+
+        while !self.pending.is_empty() && self.limiter.try_acquire(Instant::now()) {
+            if let Some(reply) = self.pending.pop_front() {
+                send(writer, &reply.line(&self.channel))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn handle(&mut self, msg: &Message, nick: &str, writer: &mut TcpStream) -> anyhow::Result<()> {
+        let event = match msg.command.as_str() {
+            "PING" => {
+                send(
+                    writer,
+                    &format!("PONG :{}", msg.param(0).unwrap_or("tmi.twitch.tv")),
+                )?;
+                None
+            }
+            "RECONNECT" => bail!("The server asked us to reconnect."),
+            "NOTICE" => {
+                let notice = msg.param(1).unwrap_or("");
+                if notice == "Login authentication failed" || notice == "Improperly formatted auth"
+                {
+                    return Err(LoginFailed(notice.to_string()).into());
+                }
+                None
+            }
+            "JOIN" if msg.nick() == Some(nick) => Some(TwitchEvent::Joined {
+                channel: self.channel.clone(),
+            }),
+            // how Twitch sees us in the channel, sent on joining and after everything we say
+            "USERSTATE" => {
+                self.limiter.set_max(message_limit(msg));
+                None
+            }
+            "PRIVMSG" => chat_message(msg).map(TwitchEvent::Message),
+            "USERNOTICE" => user_notice(msg),
+            "CLEARCHAT" => Some(TwitchEvent::ClearChat {
+                user: msg.param(1).map(str::to_string),
+            }),
+            _ => None,
+        };
+
+        if let Some(event) = event {
+            // nobody listening just means the app is on its way out
+            let _ = self.events_tx.send(event);
+        }
+        Ok(())
+    }
+}
+
+impl Task for ChatTask {
+    type Result = ();
+    type OnCompleteParams = Sender<TwitchEvent>;
+
+    fn run_task(mut self) -> anyhow::Result<()> {
+        let mut delay = self.reconnect_delay;
+        loop {
+            if let Some(credentials) = self.credentials_ch.1.try_iter().last() {
+                self.credentials = credentials;
+            }
+
+            let started = Instant::now();
+            let error = match self.session() {
+                Ok(()) => return Ok(()),
+                Err(e) => e,
+            };
+
+            let _ = self.events_tx.send(TwitchEvent::Disconnected {
+                reason: format!("{:#}", error),
+            });
+
+            // the same login would only be turned down again, so hold off until the tokens
+            // have been refreshed or someone's logged in again
+            if error.is::<LoginFailed>() {
+                match self.wait_for_credentials() {
+                    Some(credentials) => self.credentials = credentials,
+                    None => return Ok(()),
+                }
+                delay = self.reconnect_delay;
+                continue;
+            }
+
+            if started.elapsed() > STABLE_SESSION {
+                delay = self.reconnect_delay;
+            }
+            // wait before reconnecting, or bail early if we're told to stop
+            if self.cancel_ch.1.recv_timeout(delay).is_ok() || shutdown::requested() {
+                return Ok(());
+            }
+            delay = (delay * 2).min(MAX_RECONNECT_DELAY);
+        }
+    }
+
+    fn on_complete(r: anyhow::Result<()>, p: Sender<TwitchEvent>) {
+        if let Err(e) = r {
+            let _ = p.send(TwitchEvent::Disconnected {
+                reason: format!("{:#}", e),
+            });
+        }
+    }
+}
+
+/// Connects to the first address `host` resolves to that answers.
+pub fn connect(host: &str) -> anyhow::Result<TcpStream> {
+    let mut last_error = anyhow!("{} didn't resolve to any address", host);
+    for address in host
+        .to_socket_addrs()
+        .with_context(|| format!("Couldn't resolve {}", host))?
+    {
+        match TcpStream::connect_timeout(&address, CONNECT_TIMEOUT) {
+            Ok(stream) => return Ok(stream),
+            Err(e) => {
+                last_error = anyhow::Error::new(e).context(format!("Couldn't connect to {}", host))
+            }
+        }
+    }
+    Err(last_error)
+}
+
+fn send(writer: &mut TcpStream, line: &str) -> io::Result<()> {
+    writer.write_all(format!("{}\r\n", line).as_bytes())
+}
+
+/// the name to show for whoever sent `msg`
+fn display_name(msg: &Message) -> Option<String> {
+    msg.tag("display-name")
+        .or_else(|| msg.tag("login"))
+        .or_else(|| msg.nick())
+        .map(str::to_string)
+}
+
+fn number_tag(msg: &Message, key: &str) -> Option<u32> {
+    msg.tag(key).and_then(|value| value.parse().ok())
+}
+
+/// `broadcaster/1,subscriber/12` into a map of badge to version
+fn badges(msg: &Message) -> BTreeMap<String, String> {
+    msg.tag("badges")
+        .unwrap_or("")
+        .split(',')
+        .filter_map(|badge| badge.split_once('/'))
+        .map(|(name, version)| (name.to_string(), version.to_string()))
+        .collect()
+}
+
+/// how many messages we may send per `MESSAGE_WINDOW`, going by our USERSTATE
+fn message_limit(userstate: &Message) -> usize {
+    let badges = badges(userstate);
+    match badges.contains_key("broadcaster") || badges.contains_key("moderator") {
+        true => MODERATOR_MESSAGE_LIMIT,
+        false => REGULAR_MESSAGE_LIMIT,
+    }
+}
+
+fn chat_message(msg: &Message) -> Option<ChatMessage> {
+    let login = msg.nick()?.to_string();
+    Some(ChatMessage {
+        id: msg.tag("id").unwrap_or("").to_string(),
+        channel: msg.param(0)?.trim_start_matches('#').to_string(),
+        user_id: msg.tag("user-id").unwrap_or("").to_string(),
+        display_name: display_name(msg).unwrap_or_else(|| login.clone()),
+        login,
+        text: msg.param(1)?.to_string(),
+        badges: badges(msg),
+        bits: number_tag(msg, "bits").unwrap_or(0),
+    })
+}
+
+/// https://dev.twitch.tv/docs/irc/tags#usernotice-tags
+fn user_notice(msg: &Message) -> Option<TwitchEvent> {
+    let tier = || SubTier::from_plan(msg.tag("msg-param-sub-plan")?);
+    // anonymous gifts come from this account
+    let gifter = || display_name(msg).filter(|_| msg.tag("login") != Some("ananonymousgifter"));
+
+    match msg.tag("msg-id")? {
+        "sub" | "resub" => Some(TwitchEvent::Subscribe {
+            user: display_name(msg)?,
+            tier: tier()?,
+            months: number_tag(msg, "msg-param-cumulative-months").unwrap_or(1),
+            message: msg.param(1).map(str::to_string),
+        }),
+
+        // a community gift is announced once as a whole, then once per recipient,
+        // so the per-recipient notices are skipped to avoid counting the subs twice
+        "subgift" | "anonsubgift" if msg.tag("msg-param-community-gift-id").is_some() => None,
+        "subgift" | "anonsubgift" => Some(TwitchEvent::GiftSub {
+            gifter: gifter(),
+            recipient: msg
+                .tag("msg-param-recipient-display-name")
+                .or_else(|| msg.tag("msg-param-recipient-user-name"))
+                .map(str::to_string),
+            tier: tier()?,
+            count: 1,
+        }),
+        "submysterygift" | "anonsubmysterygift" => Some(TwitchEvent::GiftSub {
+            gifter: gifter(),
+            recipient: None,
+            tier: tier()?,
+            count: number_tag(msg, "msg-param-mass-gift-count").unwrap_or(1),
+        }),
+
+        "raid" => Some(TwitchEvent::Raid {
+            from: msg
+                .tag("msg-param-displayName")
+                .or_else(|| msg.tag("msg-param-login"))?
+                .to_string(),
+            viewers: number_tag(msg, "msg-param-viewerCount").unwrap_or(0),
+        }),
+
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::harness::PATIENCE;
+    use crate::twitch::mock::FakeIrcServer;
+    use std::thread::{self, JoinHandle};
+
+    struct Running {
+        events: Receiver<TwitchEvent>,
+        handle: ChatHandle,
+        thread: JoinHandle<anyhow::Result<()>>,
+    }
+
+    impl Running {
+        fn next_event(&self) -> TwitchEvent {
+            self.events.recv_timeout(PATIENCE).expect("no event")
+        }
+
+        fn stop(self) -> anyhow::Result<()> {
+            self.handle.stop();
+            self.thread.join().unwrap()
+        }
+    }
+
+    fn start(server: &FakeIrcServer, credentials: Credentials) -> Running {
+        let (events_tx, events) = crossbeam_channel::unbounded();
+        let mut task = ChatTask::new(
+            server.address().to_string(),
+            "#DwBrite",
+            credentials,
+            events_tx,
+        );
+        task.reconnect_delay = Duration::from_millis(10);
+        let handle = task.controller();
+        let thread = thread::spawn(move || task.run_task());
+
+        Running {
+            events,
+            handle,
+            thread,
+        }
+    }
+
+    /// waits for the client to log in, then has the server confirm the join
+    fn join(server: &FakeIrcServer, chat: &Running) {
+        assert_eq!(server.recv(), CAPABILITIES);
+        let nick = loop {
+            let line = server.recv();
+            if let Some(nick) = line.strip_prefix("NICK ") {
+                break nick.to_string();
+            }
+        };
+        assert_eq!(server.recv(), "JOIN #dwbrite");
+        confirm_join(server, chat, &nick);
+    }
+
+    fn confirm_join(server: &FakeIrcServer, chat: &Running, nick: &str) {
+        server.send(&format!(":{0}!{0}@{0}.tmi.twitch.tv JOIN #dwbrite", nick));
+        assert_eq!(
+            chat.next_event(),
+            TwitchEvent::Joined {
+                channel: "dwbrite".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn logs_in_anonymously() {
+        let server = FakeIrcServer::start();
+        let chat = start(&server, Credentials::Anonymous);
+
+        assert_eq!(server.recv(), CAPABILITIES);
+        let nick = server.recv();
+        assert!(nick.starts_with("NICK justinfan"), "{}", nick);
+        assert_eq!(server.recv(), "JOIN #dwbrite");
+
+        chat.stop().unwrap();
+    }
+
+    #[test]
+    fn logs_in_with_a_token() {
+        let server = FakeIrcServer::start();
+        let chat = start(
+            &server,
+            Credentials::OAuth {
+                login: "DwBrite".to_string(),
+                token: "abc123".to_string(),
+            },
+        );
+
+        assert_eq!(server.recv(), CAPABILITIES);
+        assert_eq!(server.recv(), "PASS oauth:abc123");
+        assert_eq!(server.recv(), "NICK dwbrite");
+        assert_eq!(server.recv(), "JOIN #dwbrite");
+        confirm_join(&server, &chat, "dwbrite");
+
+        chat.stop().unwrap();
+    }
+
+    #[test]
+    fn waits_for_new_credentials_when_login_fails() {
+        let server = FakeIrcServer::start();
+        let credentials = |token: &str| Credentials::OAuth {
+            login: "dwbrite".to_string(),
+            token: token.to_string(),
+        };
+        let chat = start(&server, credentials("expired"));
+        server.expect("JOIN");
+
+        server.send(":tmi.twitch.tv NOTICE * :Login authentication failed");
+        match chat.next_event() {
+            TwitchEvent::Disconnected { reason } => {
+                assert_eq!(
+                    reason,
+                    "Twitch chat login failed: Login authentication failed"
+                )
+            }
+            event => panic!("expected a disconnect, got {:?}", event),
+        }
+        assert!(!chat.thread.is_finished());
+
+        chat.handle.set_credentials(credentials("refreshed"));
+        server.expect("PASS oauth:refreshed");
+        confirm_join(&server, &chat, "dwbrite");
+
+        chat.stop().unwrap();
+    }
+
+    #[test]
+    fn answers_pings() {
+        let server = FakeIrcServer::start();
+        let chat = start(&server, Credentials::Anonymous);
+        join(&server, &chat);
+
+        server.send("PING :tmi.twitch.tv");
+        assert_eq!(server.recv(), "PONG :tmi.twitch.tv");
+
+        chat.stop().unwrap();
+    }
+
+    #[test]
+    fn reconnects() {
+        let server = FakeIrcServer::start();
+        let chat = start(&server, Credentials::Anonymous);
+        join(&server, &chat);
+
+        server.send(":tmi.twitch.tv RECONNECT");
+        assert!(matches!(
+            chat.next_event(),
+            TwitchEvent::Disconnected { .. }
+        ));
+        join(&server, &chat);
+
+        server.disconnect();
+        assert!(matches!(
+            chat.next_event(),
+            TwitchEvent::Disconnected { .. }
+        ));
+        join(&server, &chat);
+
+        chat.stop().unwrap();
+    }
+
+    #[test]
+    fn reconnects_with_new_credentials() {
+        let server = FakeIrcServer::start();
+        let credentials = |token: &str| Credentials::OAuth {
+            login: "dwbrite".to_string(),
+            token: token.to_string(),
+        };
+        let chat = start(&server, credentials("old"));
+        server.expect("PASS oauth:old");
+        confirm_join(&server, &chat, "dwbrite");
+
+        // no reconnect just for new credentials
+        chat.handle.set_credentials(credentials("new"));
+        server.send("PING :tmi.twitch.tv");
+        server.expect("PONG");
+
+        server.disconnect();
+        assert!(matches!(
+            chat.next_event(),
+            TwitchEvent::Disconnected { .. }
+        ));
+        server.expect("PASS oauth:new");
+        confirm_join(&server, &chat, "dwbrite");
+
+        chat.stop().unwrap();
+    }
+
+    fn oauth() -> Credentials {
+        Credentials::OAuth {
+            login: "dwbrite".to_string(),
+            token: "abc123".to_string(),
+        }
+    }
+
+    fn reply(parent: &str, text: &str) -> Reply {
+        Reply {
+            parent: Some(parent.to_string()),
+            text: text.to_string(),
+        }
+    }
+
+    #[test]
+    fn replies_in_threads() {
+        let server = FakeIrcServer::start();
+        let chat = start(&server, oauth());
+        join(&server, &chat);
+
+        chat.handle
+            .reply(reply("b34ccfc7", "effect queued\r\nPRIVMSG #dwbrite :hi"));
+        assert_eq!(
+            server.recv(),
+            "@reply-parent-msg-id=b34ccfc7 PRIVMSG #dwbrite :effect queued  PRIVMSG #dwbrite :hi"
+        );
+        chat.handle.reply(Reply {
+            parent: None,
+            text: "hello".to_string(),
+        });
+        assert_eq!(server.recv(), "PRIVMSG #dwbrite :hello");
+
+        chat.stop().unwrap();
+    }
+
+    #[test]
+    fn holds_replies_back_to_the_rate_limit() {
+        let server = FakeIrcServer::start();
+        let (events_tx, _events) = crossbeam_channel::unbounded();
+        let mut task = ChatTask::new(server.address().to_string(), "dwbrite", oauth(), events_tx);
+        let window = Duration::from_millis(500);
+        task.limiter = RateLimiter::new(2, window);
+        let handle = task.controller();
+        let thread = thread::spawn(move || task.run_task());
+        server.expect("JOIN");
+
+        let start = Instant::now();
+        for i in 0..3 {
+            handle.reply(reply("abc", &i.to_string()));
+        }
+        server.expect("@reply-parent-msg-id=abc PRIVMSG #dwbrite :0");
+        server.expect("@reply-parent-msg-id=abc PRIVMSG #dwbrite :1");
+        server.expect("@reply-parent-msg-id=abc PRIVMSG #dwbrite :2");
+        assert!(start.elapsed() >= window);
+
+        handle.stop();
+        thread.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn moderators_get_a_higher_limit() {
+        let userstate = |badges: &str| {
+            Message::parse(&format!(
+                "@badges={};mod=0 :tmi.twitch.tv USERSTATE #dwbrite",
+                badges
+            ))
+            .unwrap()
+        };
+        assert_eq!(message_limit(&userstate("")), REGULAR_MESSAGE_LIMIT);
+        assert_eq!(message_limit(&userstate("vip/1")), REGULAR_MESSAGE_LIMIT);
+        assert_eq!(
+            message_limit(&userstate("moderator/1")),
+            MODERATOR_MESSAGE_LIMIT
+        );
+        assert_eq!(
+            message_limit(&userstate("broadcaster/1,subscriber/0")),
+            MODERATOR_MESSAGE_LIMIT
+        );
+    }
+
+    #[test]
+    fn parses_chat_into_events() {
+        let server = FakeIrcServer::start();
+        let chat = start(&server, Credentials::Anonymous);
+        join(&server, &chat);
+
+        server.send(
+            "@badge-info=subscriber/14;badges=moderator/1,subscriber/12;bits=100;color=#FF69B4;\
+             display-name=Someone;id=b34ccfc7-4977-403a-8a94-33c6bac34fb8;mod=1;user-id=1337 \
+             :someone!someone@someone.tmi.twitch.tv PRIVMSG #dwbrite :!lights party",
+        );
+        assert_eq!(
+            chat.next_event(),
+            TwitchEvent::Message(ChatMessage {
+                id: "b34ccfc7-4977-403a-8a94-33c6bac34fb8".to_string(),
+                channel: "dwbrite".to_string(),
+                user_id: "1337".to_string(),
+                login: "someone".to_string(),
+                display_name: "Someone".to_string(),
+                text: "!lights party".to_string(),
+                badges: BTreeMap::from([
+                    ("moderator".to_string(), "1".to_string()),
+                    ("subscriber".to_string(), "12".to_string()),
+                ]),
+                bits: 100,
+            })
+        );
+
+        server.send(
+            "@display-name=ronni;login=ronni;msg-id=resub;msg-param-cumulative-months=6;\
+             msg-param-sub-plan=Prime;system-msg=ronni\\shas\\ssubscribed\\sfor\\s6\\smonths! \
+             :tmi.twitch.tv USERNOTICE #dwbrite :Great stream -- keep it up!",
+        );
+        assert_eq!(
+            chat.next_event(),
+            TwitchEvent::Subscribe {
+                user: "ronni".to_string(),
+                tier: SubTier::Prime,
+                months: 6,
+                message: Some("Great stream -- keep it up!".to_string()),
+            }
+        );
+
+        server.send(
+            "@display-name=newbie;login=newbie;msg-id=sub;msg-param-sub-plan=1000 \
+             :tmi.twitch.tv USERNOTICE #dwbrite",
+        );
+        assert_eq!(
+            chat.next_event(),
+            TwitchEvent::Subscribe {
+                user: "newbie".to_string(),
+                tier: SubTier::Tier1,
+                months: 1,
+                message: None,
+            }
+        );
+
+        server.send(
+            "@display-name=AnAnonymousGifter;login=ananonymousgifter;msg-id=submysterygift;\
+             msg-param-mass-gift-count=5;msg-param-sub-plan=2000 :tmi.twitch.tv USERNOTICE #dwbrite",
+        );
+        assert_eq!(
+            chat.next_event(),
+            TwitchEvent::GiftSub {
+                gifter: None,
+                recipient: None,
+                tier: SubTier::Tier2,
+                count: 5,
+            }
+        );
+
+        // one of the five above, which was already counted
+        server.send(
+            "@display-name=AnAnonymousGifter;login=ananonymousgifter;msg-id=subgift;\
+             msg-param-community-gift-id=123;msg-param-recipient-display-name=Lucky;\
+             msg-param-sub-plan=2000 :tmi.twitch.tv USERNOTICE #dwbrite",
+        );
+        server.send(
+            "@display-name=Generous;login=generous;msg-id=subgift;\
+             msg-param-recipient-display-name=Lucky;msg-param-sub-plan=3000 \
+             :tmi.twitch.tv USERNOTICE #dwbrite",
+        );
+        assert_eq!(
+            chat.next_event(),
+            TwitchEvent::GiftSub {
+                gifter: Some("Generous".to_string()),
+                recipient: Some("Lucky".to_string()),
+                tier: SubTier::Tier3,
+                count: 1,
+            }
+        );
+
+        server.send(
+            "@display-name=Raider;login=raider;msg-id=raid;msg-param-displayName=Raider;\
+             msg-param-login=raider;msg-param-viewerCount=42 :tmi.twitch.tv USERNOTICE #dwbrite",
+        );
+        assert_eq!(
+            chat.next_event(),
+            TwitchEvent::Raid {
+                from: "Raider".to_string(),
+                viewers: 42,
+            }
+        );
+
+        server.send("@ban-duration=600 :tmi.twitch.tv CLEARCHAT #dwbrite :troll");
+        assert_eq!(
+            chat.next_event(),
+            TwitchEvent::ClearChat {
+                user: Some("troll".to_string())
+            }
+        );
+        server.send(":tmi.twitch.tv CLEARCHAT #dwbrite");
+        assert_eq!(chat.next_event(), TwitchEvent::ClearChat { user: None });
+
+        chat.stop().unwrap();
+    }
+}
@@ -0,0 +1,714 @@
+//! Follows, channel point redemptions and everything else chat doesn't say, from EventSub over
+//! a WebSocket. https://dev.twitch.tv/docs/eventsub/handling-websocket-events/
+
+use crate::config::TwitchTokens;
+use crate::shutdown;
+use crate::tasks::Task;
+use crate::twitch::chat::connect;
+use crate::twitch::helix::HelixClient;
+use crate::twitch::{HypeTrainStage, SubTier, TwitchEvent};
+use anyhow::{anyhow, bail, Context};
+use crossbeam_channel::{Receiver, Sender};
+use serde_json::{json, Value};
+use std::collections::{HashSet, VecDeque};
+use std::io;
+use std::net::TcpStream;
+use std::time::{Duration, Instant};
+use tungstenite::http::Uri;
+use tungstenite::stream::MaybeTlsStream;
+use tungstenite::{Message, WebSocket};
+
+pub const EVENTSUB_URL: &str = "wss://eventsub.wss.twitch.tv/ws";
+
+/// (type, version) of everything twitchbrite listens for
+const SUBSCRIPTIONS: &[(&str, &str)] = &[
+    ("channel.follow", "2"),
+    ("channel.subscribe", "1"),
+    ("channel.subscription.message", "1"),
+    ("channel.subscription.gift", "1"),
+    ("channel.cheer", "1"),
+    ("channel.raid", "1"),
+    ("channel.channel_points_custom_reward_redemption.add", "1"),
+    ("channel.hype_train.begin", "1"),
+    ("channel.hype_train.progress", "1"),
+    ("channel.hype_train.end", "1"),
+    ("stream.online", "1"),
+    ("stream.offline", "1"),
+];
+
+/// Twitch sends the welcome right away, and closes the connection if nothing's subscribed
+/// within 10 seconds
+const WELCOME_TIMEOUT: Duration = Duration::from_secs(10);
+/// how often the read loop wakes up to check whether it should stop
+const READ_TIMEOUT: Duration = Duration::from_millis(250);
+/// extra leeway on top of the keepalive timeout Twitch asks for
+const KEEPALIVE_SLACK: Duration = Duration::from_secs(2);
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(60);
+/// a session that lasted this long was working, so the next reconnect starts from scratch
+const STABLE_SESSION: Duration = Duration::from_secs(60);
+/// how many message IDs to remember, since Twitch may send a message more than once
+const REMEMBERED_MESSAGES: usize = 1000;
+
+type Socket = WebSocket<MaybeTlsStream<TcpStream>>;
+
+/// One WebSocket connection, once Twitch has welcomed it.
+struct Session {
+    socket: Socket,
+    id: String,
+    keepalive: Duration,
+    last_heard: Instant,
+}
+
+/// Stays subscribed to the channel's events until shutdown or cancellation, reconnecting
+/// whenever the connection drops.
+pub struct EventSubTask {
+    url: String,
+    helix: HelixClient,
+    /// the channel's owner, looked up once if it's not the logged in user
+    broadcaster: Broadcaster,
+    user_id: String,
+    events_tx: Sender<TwitchEvent>,
+    reconnect_delay: Duration,
+    keepalive_slack: Duration,
+    seen: (HashSet<String>, VecDeque<String>),
+    cancel_ch: (Sender<()>, Receiver<()>),
+    /// access tokens to use from the next connection on
+    token_ch: (Sender<String>, Receiver<String>),
+}
+
+enum Broadcaster {
+    Login(String),
+    Id(String),
+}
+
+/// Controls an `EventSubTask` from outside once it's running.
+#[derive(Clone)]
+pub struct EventSubHandle {
+    cancel_tx: Sender<()>,
+    token_tx: Sender<String>,
+}
+
+impl EventSubHandle {
+    /// disconnects and stops the task
+    pub fn stop(&self) {
+        let _ = self.cancel_tx.try_send(());
+    }
+
+    /// Subscribes with `access_token` from the next reconnect on. Existing subscriptions
+    /// outlive the token they were made with.
+    pub fn set_token(&self, access_token: String) {
+        let _ = self.token_tx.send(access_token);
+    }
+}
+
+impl EventSubTask {
+    pub fn new(
+        url: impl Into<String>,
+        helix: HelixClient,
+        channel: &str,
+        tokens: &TwitchTokens,
+        events_tx: Sender<TwitchEvent>,
+    ) -> Self {
+        let channel = channel.trim_start_matches('#').to_lowercase();
+        let broadcaster = match channel == tokens.login.to_lowercase() {
+            true => Broadcaster::Id(tokens.user_id.clone()),
+            false => Broadcaster::Login(channel),
+        };
+
+        Self {
+            url: url.into(),
+            helix,
+            broadcaster,
+            user_id: tokens.user_id.clone(),
+            events_tx,
+            reconnect_delay: RECONNECT_DELAY,
+            keepalive_slack: KEEPALIVE_SLACK,
+            seen: (HashSet::new(), VecDeque::new()),
+            cancel_ch: crossbeam_channel::bounded(1),
+            token_ch: crossbeam_channel::unbounded(),
+        }
+    }
+
+    pub fn controller(&self) -> EventSubHandle {
+        EventSubHandle {
+            cancel_tx: self.cancel_ch.0.clone(),
+            token_tx: self.token_ch.0.clone(),
+        }
+    }
+
+    fn stopping(&self) -> bool {
+        shutdown::requested() || !self.cancel_ch.1.is_empty()
+    }
+
+    fn broadcaster_id(&mut self) -> anyhow::Result<String> {
+        if let Broadcaster::Login(login) = &self.broadcaster {
+            let id = self.helix.user_id(login)?;
+            self.broadcaster = Broadcaster::Id(id);
+        }
+        match &self.broadcaster {
+            Broadcaster::Id(id) => Ok(id.clone()),
+            Broadcaster::Login(_) => unreachable!(),
+        }
+    }
+
+    /// Connects to `url` and waits for Twitch's welcome.
+    fn open(&self, url: &str) -> anyhow::Result<Session> {
+        let uri: Uri = url
+            .parse()
+            .with_context(|| format!("{} isn't a WebSocket URL", url))?;
+        let host = uri.host().with_context(|| format!("{} has no host", url))?;
+        let port = match (uri.port_u16(), uri.scheme_str()) {
+            (Some(port), _) => port,
+            (None, Some("ws")) => 80,
+            (None, _) => 443,
+        };
+
+        let stream = connect(&format!("{}:{}", host, port))?;
+        // the same socket, for changing the timeout once the TLS stream has it
+        let timeouts = stream.try_clone()?;
+        timeouts.set_read_timeout(Some(WELCOME_TIMEOUT))?;
+        let (mut socket, _) = tungstenite::client_tls(url, stream)
+            .map_err(|e| anyhow!("WebSocket handshake with {} failed: {}", url, e))?;
+
+        let welcome = match socket.read_message() {
+            Ok(Message::Text(text)) => serde_json::from_str::<Value>(&text)?,
+            Ok(other) => bail!("Expected a welcome from Twitch, got {:?}", other),
+            Err(tungstenite::Error::Io(e)) if is_timeout(&e) => {
+                bail!("Twitch didn't say welcome")
+            }
+            Err(e) => return Err(e.into()),
+        };
+        if welcome["metadata"]["message_type"] != "session_welcome" {
+            bail!("Expected a welcome from Twitch, got {}", welcome);
+        }
+
+        let session = &welcome["payload"]["session"];
+        timeouts.set_read_timeout(Some(READ_TIMEOUT))?;
+        Ok(Session {
+            socket,
+            id: session["id"]
+                .as_str()
+                .context("Twitch's welcome had no session ID")?
+                .to_string(),
+            keepalive: Duration::from_secs(
+                session["keepalive_timeout_seconds"].as_u64().unwrap_or(10),
+            ),
+            last_heard: Instant::now(),
+        })
+    }
+
+    /// Subscribes the session to everything in `SUBSCRIPTIONS`, returning how many worked.
+    /// Fails if none did, since Twitch hangs up on sessions without subscriptions anyway.
+    fn subscribe(&mut self, session_id: &str) -> anyhow::Result<usize> {
+        let broadcaster_id = self.broadcaster_id()?;
+
+        let mut subscribed = 0;
+        let mut last_error = None;
+        for (kind, version) in SUBSCRIPTIONS {
+            let condition = match *kind {
+                "channel.follow" => json!({
+                    "broadcaster_user_id": broadcaster_id,
+                    "moderator_user_id": self.user_id,
+                }),
+                "channel.raid" => json!({ "to_broadcaster_user_id": broadcaster_id }),
+                _ => json!({ "broadcaster_user_id": broadcaster_id }),
+            };
+
+            match self.helix.subscribe(kind, version, condition, session_id) {
+                Ok(()) => subscribed += 1,
+                Err(e) => {
+                    let _ = self.events_tx.send(TwitchEvent::SubscriptionFailed {
+                        kind: kind.to_string(),
+                        reason: format!("{:#}", e),
+                    });
+                    last_error = Some(e);
+                }
+            }
+        }
+
+        match (subscribed, last_error) {
+            (0, Some(e)) => Err(e.context("Couldn't subscribe to any Twitch events")),
+            (n, _) => Ok(n),
+        }
+    }
+
+    /// Reads events until the connection drops. Only returns Ok when asked to stop.
+    fn listen(&mut self, mut session: Session) -> anyhow::Result<()> {
+        loop {
+            if self.stopping() {
+                let _ = session.socket.close(None);
+                return Ok(());
+            }
+
+            match session.socket.read_message() {
+                Ok(Message::Text(text)) => {
+                    session.last_heard = Instant::now();
+                    if let Some(url) = self.handle(&text)? {
+                        // the subscriptions move over to the new session by themselves
+                        let new = self.open(&url)?;
+                        self.drain(&mut session);
+                        let _ = session.socket.close(None);
+                        session = new;
+                    }
+                }
+                Ok(Message::Close(frame)) => match frame {
+                    Some(frame) => bail!("Twitch closed the connection: {}", frame.reason),
+                    None => bail!("Twitch closed the connection."),
+                },
+                Ok(_) => session.last_heard = Instant::now(),
+                Err(tungstenite::Error::Io(e)) if is_timeout(&e) => {
+                    let timeout = session.keepalive + self.keepalive_slack;
+                    if session.last_heard.elapsed() > timeout {
+                        bail!(
+                            "Heard nothing from Twitch in {}s.",
+                            timeout.as_secs_f32().round()
+                        );
+                    }
+                }
+                Err(e) => return Err(e.into()),
+            }
+
+            // answers pings
+            let _ = session.socket.write_pending();
+        }
+    }
+
+    /// Handles whatever the old session still had buffered after a reconnect.
+    fn drain(&mut self, session: &mut Session) {
+        while let Ok(Message::Text(text)) = session.socket.read_message() {
+            let _ = self.handle(&text);
+        }
+    }
+
+    /// Returns a URL to reconnect to, if Twitch asked for that.
+    fn handle(&mut self, text: &str) -> anyhow::Result<Option<String>> {
+        let msg: Value = serde_json::from_str(text)?;
+        let metadata = &msg["metadata"];
+
+        if let Some(id) = metadata["message_id"].as_str() {
+            if !self.remember(id) {
+                return Ok(None);
+            }
+        }
+
+        match metadata["message_type"].as_str() {
+            Some("notification") => {
+                let kind = metadata["subscription_type"].as_str().unwrap_or_default();
+                if let Some(event) = event(kind, &msg["payload"]["event"]) {
+                    let _ = self.events_tx.send(event);
+                }
+            }
+            Some("session_reconnect") => {
+                let url = msg["payload"]["session"]["reconnect_url"]
+                    .as_str()
+                    .context("Twitch asked to reconnect without saying where")?;
+                return Ok(Some(url.to_string()));
+            }
+            Some("revocation") => {
+                let subscription = &msg["payload"]["subscription"];
+                let _ = self.events_tx.send(TwitchEvent::SubscriptionFailed {
+                    kind: subscription["type"]
+                        .as_str()
+                        .unwrap_or_default()
+                        .to_string(),
+                    reason: format!(
+                        "Twitch revoked it: {}",
+                        subscription["status"].as_str().unwrap_or("no reason given")
+                    ),
+                });
+            }
+            // keepalives only need to be heard
+            _ => {}
+        }
+        Ok(None)
+    }
+
+    /// Returns false if the message ID has been seen before.
+    fn remember(&mut self, id: &str) -> bool {
+        let (seen, order) = &mut self.seen;
+        if !seen.insert(id.to_string()) {
+            return false;
+        }
+        order.push_back(id.to_string());
+        if order.len() > REMEMBERED_MESSAGES {
+            if let Some(oldest) = order.pop_front() {
+                seen.remove(&oldest);
+            }
+        }
+        true
+    }
+}
+
+impl Task for EventSubTask {
+    type Result = ();
+    type OnCompleteParams = Sender<TwitchEvent>;
+
+    fn run_task(mut self) -> anyhow::Result<()> {
+        let mut delay = self.reconnect_delay;
+        loop {
+            if let Some(token) = self.token_ch.1.try_iter().last() {
+                self.helix.set_access_token(token);
+            }
+
+            let started = Instant::now();
+            let url = self.url.clone();
+            let result = self.open(&url).and_then(|session| {
+                let subscriptions = self.subscribe(&session.id)?;
+                let _ = self
+                    .events_tx
+                    .send(TwitchEvent::EventSubConnected { subscriptions });
+                self.listen(session)
+            });
+            let error = match result {
+                Ok(()) => return Ok(()),
+                Err(e) => e,
+            };
+
+            let _ = self.events_tx.send(TwitchEvent::EventSubDisconnected {
+                reason: format!("{:#}", error),
+            });
+
+            if started.elapsed() > STABLE_SESSION {
+                delay = self.reconnect_delay;
+            }
+            // wait before reconnecting, or bail early if we're told to stop
+            if self.cancel_ch.1.recv_timeout(delay).is_ok() || shutdown::requested() {
+                return Ok(());
+            }
+            delay = (delay * 2).min(MAX_RECONNECT_DELAY);
+        }
+    }
+
+    fn on_complete(r: anyhow::Result<()>, p: Sender<TwitchEvent>) {
+        if let Err(e) = r {
+            let _ = p.send(TwitchEvent::EventSubDisconnected {
+                reason: format!("{:#}", e),
+            });
+        }
+    }
+}
+
+fn is_timeout(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+    )
+}
+
+fn text(event: &Value, key: &str) -> Option<String> {
+    event[key]
+        .as_str()
+        .filter(|s| !s.is_empty())
+        .map(str::to_string)
+}
+
+fn number(event: &Value, key: &str) -> u32 {
+    event[key].as_u64().unwrap_or(0) as u32
+}
+
+/// Turns a notification's event into a `TwitchEvent`, if it's one twitchbrite cares about.
+/// https://dev.twitch.tv/docs/eventsub/eventsub-reference/#events
+fn event(kind: &str, event: &Value) -> Option<TwitchEvent> {
+    let user = || text(event, "user_name").or_else(|| text(event, "user_login"));
+    let tier = || SubTier::from_plan(event["tier"].as_str()?);
+    let hype_train = |stage| TwitchEvent::HypeTrain {
+        stage,
+        level: number(event, "level"),
+        progress: number(event, "progress"),
+        goal: number(event, "goal"),
+    };
+
+    Some(match kind {
+        "channel.follow" => TwitchEvent::Follow { user: user()? },
+        // gifted subs are announced as a whole by channel.subscription.gift
+        "channel.subscribe" if event["is_gift"] == true => return None,
+        "channel.subscribe" => TwitchEvent::Subscribe {
+            user: user()?,
+            tier: tier()?,
+            months: 1,
+            message: None,
+        },
+        "channel.subscription.message" => TwitchEvent::Subscribe {
+            user: user()?,
+            tier: tier()?,
+            months: number(event, "cumulative_months").max(1),
+            message: text(&event["message"], "text"),
+        },
+        "channel.subscription.gift" => TwitchEvent::GiftSub {
+            gifter: user().filter(|_| event["is_anonymous"] != true),
+            recipient: None,
+            tier: tier()?,
+            count: number(event, "total").max(1),
+        },
+        "channel.cheer" => TwitchEvent::Cheer {
+            user: user().filter(|_| event["is_anonymous"] != true),
+            bits: number(event, "bits"),
+            message: text(event, "message").unwrap_or_default(),
+        },
+        "channel.raid" => TwitchEvent::Raid {
+            from: text(event, "from_broadcaster_user_name")
+                .or_else(|| text(event, "from_broadcaster_user_login"))?,
+            viewers: number(event, "viewers"),
+        },
+        "channel.channel_points_custom_reward_redemption.add" => TwitchEvent::Redemption {
+            user: user()?,
+            reward: text(&event["reward"], "title")?,
+            cost: number(&event["reward"], "cost"),
+            input: text(event, "user_input"),
+        },
+        "channel.hype_train.begin" => hype_train(HypeTrainStage::Began),
+        "channel.hype_train.progress" => hype_train(HypeTrainStage::Progressed),
+        "channel.hype_train.end" => hype_train(HypeTrainStage::Ended),
+        "stream.online" => TwitchEvent::StreamOnline,
+        "stream.offline" => TwitchEvent::StreamOffline,
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::harness::PATIENCE;
+    use crate::twitch::mock::{MockEventSub, MOCK_LOGIN, MOCK_USER_ID};
+    use std::thread::{self, JoinHandle};
+
+    struct Running {
+        events: Receiver<TwitchEvent>,
+        handle: EventSubHandle,
+        thread: JoinHandle<anyhow::Result<()>>,
+    }
+
+    impl Running {
+        fn next_event(&self) -> TwitchEvent {
+            self.events.recv_timeout(PATIENCE).expect("no event")
+        }
+
+        fn stop(self) -> anyhow::Result<()> {
+            self.handle.stop();
+            self.thread.join().unwrap()
+        }
+    }
+
+    fn tokens() -> TwitchTokens {
+        TwitchTokens {
+            access_token: "access".to_string(),
+            refresh_token: "refresh".to_string(),
+            user_id: MOCK_USER_ID.to_string(),
+            login: MOCK_LOGIN.to_string(),
+        }
+    }
+
+    fn start_with(
+        mock: &MockEventSub,
+        channel: &str,
+        tweak: impl FnOnce(&mut EventSubTask),
+    ) -> Running {
+        let helix = HelixClient::new(mock.helix_url(), "client-id", "access").unwrap();
+        let (events_tx, events) = crossbeam_channel::unbounded();
+        let mut task = EventSubTask::new(mock.ws_url(), helix, channel, &tokens(), events_tx);
+        task.reconnect_delay = Duration::from_millis(10);
+        tweak(&mut task);
+        let handle = task.controller();
+        let thread = thread::spawn(move || task.run_task());
+
+        Running {
+            events,
+            handle,
+            thread,
+        }
+    }
+
+    fn start(mock: &MockEventSub) -> Running {
+        start_with(mock, MOCK_LOGIN, |_| {})
+    }
+
+    fn connected(running: &Running) {
+        assert_eq!(
+            running.next_event(),
+            TwitchEvent::EventSubConnected {
+                subscriptions: SUBSCRIPTIONS.len()
+            }
+        );
+    }
+
+    #[test]
+    fn subscribes_to_everything_once_welcomed() {
+        let mock = MockEventSub::start();
+        let running = start(&mock);
+
+        let session = mock.accept();
+        session.welcome(10);
+        connected(&running);
+
+        let subscriptions = mock.subscriptions();
+        let kinds: Vec<&str> = subscriptions
+            .iter()
+            .map(|s| s["type"].as_str().unwrap())
+            .collect();
+        let expected: Vec<&str> = SUBSCRIPTIONS.iter().map(|(kind, _)| *kind).collect();
+        assert_eq!(kinds, expected);
+        for subscription in &subscriptions {
+            assert_eq!(subscription["transport"]["session_id"], session.id.as_str());
+        }
+        assert_eq!(
+            subscriptions[0]["condition"],
+            json!({ "broadcaster_user_id": MOCK_USER_ID, "moderator_user_id": MOCK_USER_ID })
+        );
+        assert_eq!(
+            subscriptions[5]["condition"],
+            json!({ "to_broadcaster_user_id": MOCK_USER_ID })
+        );
+
+        running.stop().unwrap();
+    }
+
+    #[test]
+    fn looks_up_other_channels() {
+        let mock = MockEventSub::start();
+        let running = start_with(&mock, "#SomeoneElse", |_| {});
+
+        mock.accept().welcome(10);
+        connected(&running);
+        assert_eq!(
+            mock.subscriptions()[1]["condition"]["broadcaster_user_id"],
+            mock.user_id("someoneelse")
+        );
+
+        running.stop().unwrap();
+    }
+
+    #[test]
+    fn turns_notifications_into_events_once() {
+        let mock = MockEventSub::start();
+        let running = start(&mock);
+        let session = mock.accept();
+        session.welcome(10);
+        connected(&running);
+
+        let follow = json!({ "user_login": "someone", "user_name": "Someone" });
+        session.notification("a", "channel.follow", follow.clone());
+        session.notification("a", "channel.follow", follow);
+        session.notification(
+            "b",
+            "channel.channel_points_custom_reward_redemption.add",
+            json!({
+                "user_name": "Someone",
+                "user_input": "",
+                "reward": { "title": "Lights!", "cost": 500 },
+            }),
+        );
+
+        assert_eq!(
+            running.next_event(),
+            TwitchEvent::Follow {
+                user: "Someone".to_string()
+            }
+        );
+        assert_eq!(
+            running.next_event(),
+            TwitchEvent::Redemption {
+                user: "Someone".to_string(),
+                reward: "Lights!".to_string(),
+                cost: 500,
+                input: None,
+            }
+        );
+
+        running.stop().unwrap();
+    }
+
+    #[test]
+    fn reconnects_when_keepalives_stop() {
+        let mock = MockEventSub::start();
+        let running = start_with(&mock, MOCK_LOGIN, |task| {
+            task.keepalive_slack = Duration::ZERO
+        });
+
+        mock.accept().welcome(1);
+        connected(&running);
+        assert!(matches!(
+            running.next_event(),
+            TwitchEvent::EventSubDisconnected { .. }
+        ));
+
+        // a new session, so everything's subscribed again
+        mock.accept().welcome(10);
+        connected(&running);
+        assert_eq!(mock.subscriptions().len(), 2 * SUBSCRIPTIONS.len());
+
+        running.stop().unwrap();
+    }
+
+    #[test]
+    fn follows_session_reconnects() {
+        let mock = MockEventSub::start();
+        let running = start(&mock);
+        let old = mock.accept();
+        old.welcome(10);
+        connected(&running);
+
+        old.reconnect(&mock.ws_url());
+        let new = mock.accept();
+        new.welcome(10);
+        new.notification("c", "stream.online", json!({ "type": "live" }));
+        assert_eq!(running.next_event(), TwitchEvent::StreamOnline);
+
+        // the subscriptions carried over
+        assert_eq!(mock.subscriptions().len(), SUBSCRIPTIONS.len());
+        running.stop().unwrap();
+    }
+
+    #[test]
+    fn parses_events() {
+        let gift = json!({
+            "user_name": "Gifter",
+            "is_anonymous": false,
+            "total": 5,
+            "tier": "2000",
+        });
+        assert_eq!(
+            event("channel.subscription.gift", &gift),
+            Some(TwitchEvent::GiftSub {
+                gifter: Some("Gifter".to_string()),
+                recipient: None,
+                tier: SubTier::Tier2,
+                count: 5,
+            })
+        );
+
+        let gifted = json!({ "user_name": "Lucky", "tier": "1000", "is_gift": true });
+        assert_eq!(event("channel.subscribe", &gifted), None);
+
+        let cheer =
+            json!({ "is_anonymous": true, "user_name": null, "bits": 100, "message": "Cheer100" });
+        assert_eq!(
+            event("channel.cheer", &cheer),
+            Some(TwitchEvent::Cheer {
+                user: None,
+                bits: 100,
+                message: "Cheer100".to_string(),
+            })
+        );
+
+        let raid = json!({ "from_broadcaster_user_name": "Raider", "viewers": 42 });
+        assert_eq!(
+            event("channel.raid", &raid),
+            Some(TwitchEvent::Raid {
+                from: "Raider".to_string(),
+                viewers: 42,
+            })
+        );
+
+        let end = json!({ "level": 3 });
+        assert_eq!(
+            event("channel.hype_train.end", &end),
+            Some(TwitchEvent::HypeTrain {
+                stage: HypeTrainStage::Ended,
+                level: 3,
+                progress: 0,
+                goal: 0,
+            })
+        );
+    }
+}
@@ -0,0 +1,113 @@
+//! The bits of Twitch's Helix API twitchbrite needs: looking up users, and creating EventSub
+//! subscriptions. https://dev.twitch.tv/docs/api/reference/
+
+use anyhow::{anyhow, Context};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::time::Duration;
+
+pub const HELIX_URL: &str = "https://api.twitch.tv/helix";
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Clone)]
+pub struct HelixClient {
+    base_url: String,
+    client_id: String,
+    access_token: String,
+    client: reqwest::blocking::Client,
+}
+
+#[derive(Deserialize)]
+struct User {
+    id: String,
+}
+
+#[derive(Deserialize)]
+struct Users {
+    data: Vec<User>,
+}
+
+impl HelixClient {
+    pub fn new(
+        base_url: impl Into<String>,
+        client_id: impl Into<String>,
+        access_token: impl Into<String>,
+    ) -> anyhow::Result<Self> {
+        Ok(Self {
+            base_url: base_url.into(),
+            client_id: client_id.into(),
+            access_token: access_token.into(),
+            client: reqwest::blocking::Client::builder()
+                .timeout(REQUEST_TIMEOUT)
+                .build()?,
+        })
+    }
+
+    pub fn set_access_token(&mut self, access_token: String) {
+        self.access_token = access_token;
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}/{}", self.base_url, path)
+    }
+
+    fn check(resp: reqwest::blocking::Response) -> anyhow::Result<reqwest::blocking::Response> {
+        if resp.status().is_success() {
+            return Ok(resp);
+        }
+        let status = resp.status();
+        let message = resp
+            .json::<Value>()
+            .ok()
+            .and_then(|body| body.get("message")?.as_str().map(str::to_string))
+            .unwrap_or_else(|| status.to_string());
+        Err(anyhow!("Twitch said: {}", message))
+    }
+
+    /// the user ID behind a login name
+    pub fn user_id(&self, login: &str) -> anyhow::Result<String> {
+        let resp = self
+            .client
+            .get(&self.url("users"))
+            .query(&[("login", login)])
+            .header("Client-Id", &self.client_id)
+            .bearer_auth(&self.access_token)
+            .send()?;
+        let users: Users = Self::check(resp)?.json()?;
+        users
+            .data
+            .into_iter()
+            .next()
+            .map(|user| user.id)
+            .with_context(|| format!("There's no Twitch user called {}", login))
+    }
+
+    /// Has Twitch send events of `kind` to the WebSocket session `session_id`.
+    pub fn subscribe(
+        &self,
+        kind: &str,
+        version: &str,
+        condition: Value,
+        session_id: &str,
+    ) -> anyhow::Result<()> {
+        let body = json!({
+            "type": kind,
+            "version": version,
+            "condition": condition,
+            "transport": {
+                "method": "websocket",
+                "session_id": session_id,
+            },
+        });
+        let resp = self
+            .client
+            .post(&self.url("eventsub/subscriptions"))
+            .header("Client-Id", &self.client_id)
+            .bearer_auth(&self.access_token)
+            .json(&body)
+            .send()?;
+        Self::check(resp)?;
+        Ok(())
+    }
+}
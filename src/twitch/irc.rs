@@ -0,0 +1,126 @@
+//! Just enough IRC to read Twitch chat: parses lines like
+//! `@badges=moderator/1;color= :nick!nick@nick.tmi.twitch.tv PRIVMSG #channel :hello`.
+
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    pub tags: HashMap<String, String>,
+    pub prefix: Option<String>,
+    pub command: String,
+    /// the trailing parameter (after " :") is the last one, with its spaces intact
+    pub params: Vec<String>,
+}
+
+impl Message {
+    pub fn parse(line: &str) -> Option<Self> {
+        let mut rest = line.trim_end_matches(['\r', '\n']);
+
+        let mut tags = HashMap::new();
+        if let Some(tagged) = rest.strip_prefix('@') {
+            let (raw_tags, after) = tagged.split_once(' ')?;
+            for tag in raw_tags.split(';') {
+                let (key, value) = tag.split_once('=').unwrap_or((tag, ""));
+                tags.insert(key.to_string(), unescape_tag(value));
+            }
+            rest = after.trim_start();
+        }
+
+        let mut prefix = None;
+        if let Some(prefixed) = rest.strip_prefix(':') {
+            let (p, after) = prefixed.split_once(' ')?;
+            prefix = Some(p.to_string());
+            rest = after.trim_start();
+        }
+
+        let (middle, trailing) = match rest.split_once(" :") {
+            Some((middle, trailing)) => (middle, Some(trailing)),
+            None => (rest, None),
+        };
+        let mut words = middle.split(' ').filter(|w| !w.is_empty());
+        let command = words.next()?.to_string();
+        let mut params: Vec<String> = words.map(str::to_string).collect();
+        params.extend(trailing.map(str::to_string));
+
+        Some(Self {
+            tags,
+            prefix,
+            command,
+            params,
+        })
+    }
+
+    pub fn tag(&self, key: &str) -> Option<&str> {
+        self.tags
+            .get(key)
+            .map(String::as_str)
+            .filter(|v| !v.is_empty())
+    }
+
+    pub fn param(&self, i: usize) -> Option<&str> {
+        self.params.get(i).map(String::as_str)
+    }
+
+    /// the nick in `nick!user@host`
+    pub fn nick(&self) -> Option<&str> {
+        let prefix = self.prefix.as_deref()?;
+        Some(prefix.split_once('!').map_or(prefix, |(nick, _)| nick))
+    }
+}
+
+/// https://ircv3.net/specs/extensions/message-tags#escaping-values
+fn unescape_tag(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some(':') => out.push(';'),
+            Some('s') => out.push(' '),
+            Some('r') => out.push('\r'),
+            Some('n') => out.push('\n'),
+            Some(other) => out.push(other),
+            None => {}
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_a_tagged_privmsg() {
+        let line = "@badges=broadcaster/1,subscriber/12;display-name=Dw\\sBrite;id=abc \
+                    :dwbrite!dwbrite@dwbrite.tmi.twitch.tv PRIVMSG #dwbrite :!lights party time\r\n";
+        let msg = Message::parse(line).unwrap();
+
+        assert_eq!(msg.command, "PRIVMSG");
+        assert_eq!(msg.nick(), Some("dwbrite"));
+        assert_eq!(msg.params, ["#dwbrite", "!lights party time"]);
+        assert_eq!(msg.tag("display-name"), Some("Dw Brite"));
+        assert_eq!(msg.tag("badges"), Some("broadcaster/1,subscriber/12"));
+        assert_eq!(msg.tag("color"), None);
+    }
+
+    #[test]
+    fn parses_untagged_commands() {
+        let ping = Message::parse("PING :tmi.twitch.tv").unwrap();
+        assert_eq!(ping.command, "PING");
+        assert_eq!(ping.params, ["tmi.twitch.tv"]);
+        assert_eq!(ping.prefix, None);
+
+        let welcome = Message::parse(":tmi.twitch.tv 001 justinfan1 :Welcome, GLHF!").unwrap();
+        assert_eq!(welcome.command, "001");
+        assert_eq!(welcome.nick(), Some("tmi.twitch.tv"));
+    }
+
+    #[test]
+    fn unescapes_tag_values() {
+        assert_eq!(unescape_tag("a\\sb\\:c\\\\d\\"), "a b;c\\d");
+    }
+}
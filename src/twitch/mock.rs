@@ -0,0 +1,631 @@
+//! Local stand-ins for Twitch, so tests don't need the internet or an account.
+//! `FakeIrcServer` is TMI, scripted line by line: it records whatever the client sends and
+//! writes back whatever the test tells it to. `MockTwitchAuth` is the OAuth server, and
+//! `MockEventSub` is EventSub's WebSocket along with the Helix endpoints it needs.
+
+use crate::harness::{wait_until, PATIENCE};
+use crossbeam_channel::{Receiver, Sender};
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io::{BufRead, BufReader, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use tiny_http::{Header, Method, Request, Response, Server};
+use tungstenite::{Message, WebSocket};
+
+pub struct FakeIrcServer {
+    address: SocketAddr,
+    received: Receiver<String>,
+    /// the most recent connection, which is the one `send` writes to
+    client: Arc<Mutex<Option<TcpStream>>>,
+    stopped: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl FakeIrcServer {
+    /// Listens on a random local port, taking one client at a time. It stops when dropped.
+    pub fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("failed to start fake IRC server");
+        let address = listener.local_addr().unwrap();
+        let (received_tx, received) = crossbeam_channel::unbounded();
+        let client = Arc::new(Mutex::new(None));
+        let stopped = Arc::new(AtomicBool::new(false));
+
+        let thread = {
+            let client = client.clone();
+            let stopped = stopped.clone();
+            thread::spawn(move || {
+                for stream in listener.incoming() {
+                    if stopped.load(Ordering::SeqCst) {
+                        break;
+                    }
+                    if let Ok(stream) = stream {
+                        *client.lock().unwrap() = Some(stream.try_clone().unwrap());
+                        read_lines(stream, received_tx.clone());
+                    }
+                }
+            })
+        };
+
+        Self {
+            address,
+            received,
+            client,
+            stopped,
+            thread: Some(thread),
+        }
+    }
+
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    /// the next line the client sent, without its line ending
+    pub fn recv(&self) -> String {
+        self.received
+            .recv_timeout(PATIENCE)
+            .expect("the client didn't send anything")
+    }
+
+    /// skips lines until one starts with `prefix`
+    pub fn expect(&self, prefix: &str) -> String {
+        loop {
+            let line = self.recv();
+            if line.starts_with(prefix) {
+                return line;
+            }
+        }
+    }
+
+    /// writes a line to the connected client, waiting for one to connect if need be
+    pub fn send(&self, line: &str) {
+        let sent = wait_until(|| match self.client.lock().unwrap().as_mut() {
+            Some(client) => {
+                client
+                    .write_all(format!("{}\r\n", line).as_bytes())
+                    .unwrap();
+                true
+            }
+            None => false,
+        });
+        assert!(sent, "no client connected");
+    }
+
+    /// drops the current client, as if the connection had died
+    pub fn disconnect(&self) {
+        if let Some(client) = self.client.lock().unwrap().take() {
+            let _ = client.shutdown(Shutdown::Both);
+        }
+    }
+}
+
+fn read_lines(stream: TcpStream, received_tx: Sender<String>) {
+    thread::spawn(move || {
+        for line in BufReader::new(stream).lines() {
+            match line {
+                Ok(line) => {
+                    let _ = received_tx.send(line.trim_end_matches('\r').to_string());
+                }
+                Err(_) => break,
+            }
+        }
+    });
+}
+
+impl Drop for FakeIrcServer {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        self.disconnect();
+        // wake the listener up so it notices
+        let _ = TcpStream::connect(self.address);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+pub const MOCK_CLIENT_ID: &str = "mock-client-id";
+pub const MOCK_LOGIN: &str = "dwbrite";
+pub const MOCK_USER_ID: &str = "12826";
+
+struct AuthState {
+    device_code: Option<String>,
+    user_code: String,
+    authorized: bool,
+    /// what the next token polls get instead of their usual answer, e.g. "slow_down"
+    poll_errors: VecDeque<String>,
+    polls: usize,
+    access_tokens: HashSet<String>,
+    refresh_tokens: HashSet<String>,
+    refresh_works: bool,
+    /// what new and validated tokens say about their expiry, in seconds
+    expires_in: u64,
+    validations: usize,
+}
+
+pub struct MockTwitchAuth {
+    address: SocketAddr,
+    state: Arc<Mutex<AuthState>>,
+    server: Arc<Server>,
+    thread: Option<JoinHandle<()>>,
+}
+
+fn token() -> String {
+    (0..30)
+        .map(|_| thread_rng().sample(Alphanumeric) as char)
+        .collect::<String>()
+        .to_lowercase()
+}
+
+impl MockTwitchAuth {
+    /// Starts listening on a random local port. It stops when dropped.
+    pub fn start() -> Self {
+        let server =
+            Arc::new(Server::http("127.0.0.1:0").expect("failed to start mock Twitch auth"));
+        let address = server.server_addr().to_ip().unwrap();
+        let state = Arc::new(Mutex::new(AuthState {
+            device_code: None,
+            user_code: "ABCDEFGH".to_string(),
+            authorized: false,
+            poll_errors: VecDeque::new(),
+            polls: 0,
+            access_tokens: HashSet::new(),
+            refresh_tokens: HashSet::new(),
+            refresh_works: true,
+            expires_in: 14124,
+            validations: 0,
+        }));
+
+        let thread = {
+            let server = server.clone();
+            let state = state.clone();
+            thread::spawn(move || {
+                for request in server.incoming_requests() {
+                    handle_auth(&state, request);
+                }
+            })
+        };
+
+        Self {
+            address,
+            state,
+            server,
+            thread: Some(thread),
+        }
+    }
+
+    pub fn base_url(&self) -> String {
+        format!("http://{}/oauth2", self.address)
+    }
+
+    pub fn user_code(&self) -> String {
+        self.state.lock().unwrap().user_code.clone()
+    }
+
+    /// as if the user had entered the code and accepted
+    pub fn authorize(&self) {
+        self.state.lock().unwrap().authorized = true;
+    }
+
+    pub fn fail_next_poll(&self, message: &str) {
+        self.state
+            .lock()
+            .unwrap()
+            .poll_errors
+            .push_back(message.to_string());
+    }
+
+    /// how many times the device code has been polled for a token
+    pub fn polls(&self) -> usize {
+        self.state.lock().unwrap().polls
+    }
+
+    /// tokens that skip the login dance, as (access token, refresh token)
+    pub fn issue_tokens(&self) -> (String, String) {
+        let mut state = self.state.lock().unwrap();
+        let (access, refresh) = (token(), token());
+        state.access_tokens.insert(access.clone());
+        state.refresh_tokens.insert(refresh.clone());
+        (access, refresh)
+    }
+
+    /// Tokens say they expire this soon from now on, the ones already issued included.
+    pub fn expire_tokens_in(&self, seconds: u64) {
+        self.state.lock().unwrap().expires_in = seconds;
+    }
+
+    /// as if the user had disconnected twitchbrite from their Twitch account
+    pub fn revoke(&self, access_token: &str) {
+        self.state
+            .lock()
+            .unwrap()
+            .access_tokens
+            .remove(access_token);
+    }
+
+    /// Turns down every refresh from now on.
+    pub fn refuse_refresh(&self) {
+        self.state.lock().unwrap().refresh_works = false;
+    }
+
+    /// how many times a token has been validated
+    pub fn validations(&self) -> usize {
+        self.state.lock().unwrap().validations
+    }
+
+    pub fn is_valid(&self, access_token: &str) -> bool {
+        self.state
+            .lock()
+            .unwrap()
+            .access_tokens
+            .contains(access_token)
+    }
+}
+
+impl Drop for MockTwitchAuth {
+    fn drop(&mut self) {
+        self.server.unblock();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// `a=1&b=two%20words` into a map
+fn parse_form(body: &str) -> HashMap<String, String> {
+    fn decode(s: &str) -> String {
+        let bytes = s.as_bytes();
+        let mut out = vec![];
+        let mut i = 0;
+        while i < bytes.len() {
+            match bytes[i] {
+                b'+' => out.push(b' '),
+                b'%' if i + 2 < bytes.len() => {
+                    let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).unwrap_or("");
+                    out.push(u8::from_str_radix(hex, 16).unwrap_or(b'?'));
+                    i += 2;
+                }
+                b => out.push(b),
+            }
+            i += 1;
+        }
+        String::from_utf8_lossy(&out).into_owned()
+    }
+
+    body.split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .map(|(k, v)| (decode(k), decode(v)))
+        .collect()
+}
+
+fn handle_auth(state: &Mutex<AuthState>, mut request: Request) {
+    let mut body = String::new();
+    let _ = request.as_reader().read_to_string(&mut body);
+    let form = parse_form(&body);
+    let authorization = request
+        .headers()
+        .iter()
+        .find(|h| h.field.equiv("Authorization"))
+        .map(|h| h.value.as_str().to_string());
+
+    let url = request.url().to_string();
+    let (status, response) = route_auth(
+        &mut state.lock().unwrap(),
+        request.method(),
+        &url,
+        &form,
+        authorization.as_deref(),
+    );
+
+    let header = Header::from_bytes("Content-Type", "application/json").unwrap();
+    let _ = request.respond(
+        Response::from_string(response.to_string())
+            .with_status_code(status)
+            .with_header(header),
+    );
+}
+
+fn auth_error(status: u16, message: &str) -> (u16, Value) {
+    (status, json!({ "status": status, "message": message }))
+}
+
+fn route_auth(
+    state: &mut AuthState,
+    method: &Method,
+    url: &str,
+    form: &HashMap<String, String>,
+    authorization: Option<&str>,
+) -> (u16, Value) {
+    let field = |key: &str| form.get(key).map(String::as_str);
+
+    match (method, url) {
+        (Method::Post, "/oauth2/device") => {
+            if field("client_id") != Some(MOCK_CLIENT_ID) {
+                return auth_error(400, "invalid client");
+            }
+            let device_code = token();
+            state.device_code = Some(device_code.clone());
+            state.authorized = false;
+            (
+                200,
+                json!({
+                    "device_code": device_code,
+                    "expires_in": 1800,
+                    "interval": 0,
+                    "user_code": state.user_code,
+                    "verification_uri": format!("https://www.twitch.tv/activate?public=true&device-code={}", state.user_code),
+                }),
+            )
+        }
+
+        (Method::Post, "/oauth2/token") => match field("grant_type") {
+            Some("urn:ietf:params:oauth:grant-type:device_code") => {
+                state.polls += 1;
+                if let Some(message) = state.poll_errors.pop_front() {
+                    return auth_error(400, &message);
+                }
+                if field("device_code") != state.device_code.as_deref() {
+                    return auth_error(400, "invalid device code");
+                }
+                if !state.authorized {
+                    return auth_error(400, "authorization_pending");
+                }
+                state.device_code = None;
+                new_tokens(state)
+            }
+            Some("refresh_token") => {
+                let known = field("refresh_token")
+                    .map(|t| state.refresh_tokens.remove(t))
+                    .unwrap_or(false);
+                if !known || !state.refresh_works {
+                    return auth_error(400, "Invalid refresh token");
+                }
+                new_tokens(state)
+            }
+            _ => auth_error(400, "unsupported grant type"),
+        },
+
+        (Method::Get, "/oauth2/validate") => {
+            state.validations += 1;
+            let token = authorization.and_then(|a| a.strip_prefix("OAuth "));
+            match token {
+                Some(token) if state.access_tokens.contains(token) => (
+                    200,
+                    json!({
+                        "client_id": MOCK_CLIENT_ID,
+                        "login": MOCK_LOGIN,
+                        "scopes": crate::twitch::auth::SCOPES,
+                        "user_id": MOCK_USER_ID,
+                        "expires_in": state.expires_in,
+                    }),
+                ),
+                _ => auth_error(401, "invalid access token"),
+            }
+        }
+
+        _ => auth_error(404, "not found"),
+    }
+}
+
+fn new_tokens(state: &mut AuthState) -> (u16, Value) {
+    let (access, refresh) = (token(), token());
+    state.access_tokens.insert(access.clone());
+    state.refresh_tokens.insert(refresh.clone());
+    (
+        200,
+        json!({
+            "access_token": access,
+            "refresh_token": refresh,
+            "expires_in": state.expires_in,
+            "scope": crate::twitch::auth::SCOPES,
+            "token_type": "bearer",
+        }),
+    )
+}
+
+/// What `MockEventSub` gives every user it's asked to look up, other than the mock login.
+const LOOKED_UP_USER_ID: &str = "31337";
+
+pub struct MockEventSub {
+    ws_address: SocketAddr,
+    sessions: Receiver<MockEventSubSession>,
+    stopped: Arc<AtomicBool>,
+    listener: Option<JoinHandle<()>>,
+    helix: Arc<Server>,
+    helix_thread: Option<JoinHandle<()>>,
+    /// every subscription request's body, in order
+    subscriptions: Arc<Mutex<Vec<Value>>>,
+}
+
+/// One client connection to `MockEventSub`, scripted by the test.
+pub struct MockEventSubSession {
+    socket: Mutex<WebSocket<TcpStream>>,
+    pub id: String,
+}
+
+impl MockEventSub {
+    /// Starts the WebSocket and Helix servers on random local ports. They stop when dropped.
+    pub fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("failed to start mock EventSub");
+        let ws_address = listener.local_addr().unwrap();
+        let (sessions_tx, sessions) = crossbeam_channel::unbounded();
+        let stopped = Arc::new(AtomicBool::new(false));
+
+        let listener = {
+            let stopped = stopped.clone();
+            thread::spawn(move || {
+                for stream in listener.incoming() {
+                    if stopped.load(Ordering::SeqCst) {
+                        break;
+                    }
+                    let socket = match stream.map(tungstenite::accept) {
+                        Ok(Ok(socket)) => socket,
+                        _ => continue,
+                    };
+                    let _ = sessions_tx.send(MockEventSubSession {
+                        socket: Mutex::new(socket),
+                        id: token(),
+                    });
+                }
+            })
+        };
+
+        let helix = Arc::new(Server::http("127.0.0.1:0").expect("failed to start mock Helix"));
+        let subscriptions = Arc::new(Mutex::new(vec![]));
+        let helix_thread = {
+            let helix = helix.clone();
+            let subscriptions = subscriptions.clone();
+            thread::spawn(move || {
+                for request in helix.incoming_requests() {
+                    handle_helix(&subscriptions, request);
+                }
+            })
+        };
+
+        Self {
+            ws_address,
+            sessions,
+            stopped,
+            listener: Some(listener),
+            helix,
+            helix_thread: Some(helix_thread),
+            subscriptions,
+        }
+    }
+
+    pub fn ws_url(&self) -> String {
+        format!("ws://{}/ws", self.ws_address)
+    }
+
+    pub fn helix_url(&self) -> String {
+        format!("http://{}", self.helix.server_addr().to_ip().unwrap())
+    }
+
+    /// the next client to connect
+    pub fn accept(&self) -> MockEventSubSession {
+        self.sessions
+            .recv_timeout(PATIENCE)
+            .expect("nobody connected to EventSub")
+    }
+
+    pub fn subscriptions(&self) -> Vec<Value> {
+        self.subscriptions.lock().unwrap().clone()
+    }
+
+    /// the ID Helix gives out for `login`
+    pub fn user_id(&self, login: &str) -> &'static str {
+        helix_user_id(login)
+    }
+}
+
+fn helix_user_id(login: &str) -> &'static str {
+    match login {
+        MOCK_LOGIN => MOCK_USER_ID,
+        _ => LOOKED_UP_USER_ID,
+    }
+}
+
+impl Drop for MockEventSub {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        // wake the listener up so it notices
+        let _ = TcpStream::connect(self.ws_address);
+        if let Some(thread) = self.listener.take() {
+            let _ = thread.join();
+        }
+        self.helix.unblock();
+        if let Some(thread) = self.helix_thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl MockEventSubSession {
+    pub fn send(&self, metadata: Value, payload: Value) {
+        let mut metadata = metadata;
+        metadata["message_timestamp"] = json!("2022-11-16T10:11:12.634234626Z");
+        let msg = json!({ "metadata": metadata, "payload": payload });
+        self.socket
+            .lock()
+            .unwrap()
+            .write_message(Message::Text(msg.to_string()))
+            .expect("the client hung up");
+    }
+
+    /// Welcomes the client, who should hear something at least every `keepalive` seconds.
+    pub fn welcome(&self, keepalive: u64) {
+        self.send(
+            json!({ "message_id": token(), "message_type": "session_welcome" }),
+            json!({ "session": {
+                "id": self.id,
+                "status": "connected",
+                "keepalive_timeout_seconds": keepalive,
+                "reconnect_url": null,
+                "connected_at": "2022-11-16T10:11:12.634234626Z",
+            }}),
+        );
+    }
+
+    /// Sends a notification with the given message ID, so tests can send duplicates.
+    pub fn notification(&self, message_id: &str, kind: &str, event: Value) {
+        self.send(
+            json!({
+                "message_id": message_id,
+                "message_type": "notification",
+                "subscription_type": kind,
+                "subscription_version": "1",
+            }),
+            json!({
+                "subscription": { "type": kind, "status": "enabled" },
+                "event": event,
+            }),
+        );
+    }
+
+    /// Asks the client to move to `url`.
+    pub fn reconnect(&self, url: &str) {
+        self.send(
+            json!({ "message_id": token(), "message_type": "session_reconnect" }),
+            json!({ "session": {
+                "id": self.id,
+                "status": "reconnecting",
+                "keepalive_timeout_seconds": null,
+                "reconnect_url": url,
+            }}),
+        );
+    }
+}
+
+fn handle_helix(subscriptions: &Mutex<Vec<Value>>, mut request: Request) {
+    let mut body = String::new();
+    let _ = request.as_reader().read_to_string(&mut body);
+    let url = request.url().to_string();
+
+    let (status, response) = match (request.method(), url.split_once('?')) {
+        (Method::Get, Some(("/users", query))) => {
+            let login = parse_form(query).remove("login").unwrap_or_default();
+            let id = helix_user_id(&login);
+            (200, json!({ "data": [{ "id": id, "login": login }] }))
+        }
+        _ if url == "/eventsub/subscriptions" && *request.method() == Method::Post => {
+            let subscription: Value = serde_json::from_str(&body).unwrap_or_default();
+            subscriptions.lock().unwrap().push(subscription.clone());
+            (202, json!({ "data": [subscription], "total": 1 }))
+        }
+        _ => (
+            404,
+            json!({ "error": "Not Found", "status": 404, "message": "not found" }),
+        ),
+    };
+
+    let header = Header::from_bytes("Content-Type", "application/json").unwrap();
+    let _ = request.respond(
+        Response::from_string(response.to_string())
+            .with_status_code(status)
+            .with_header(header),
+    );
+}
@@ -0,0 +1,144 @@
+//! Everything that happens on the Twitch side, turned into `TwitchEvent`s for the app.
+
+pub mod auth;
+pub mod chat;
+pub mod eventsub;
+pub mod helix;
+pub mod irc;
+#[cfg(test)]
+pub mod mock;
+pub mod tokens;
+
+use std::collections::BTreeMap;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SubTier {
+    Prime,
+    Tier1,
+    Tier2,
+    Tier3,
+}
+
+impl SubTier {
+    /// from the plan IDs Twitch uses everywhere: "Prime", "1000", "2000" and "3000"
+    pub fn from_plan(plan: &str) -> Option<Self> {
+        match plan {
+            "Prime" => Some(SubTier::Prime),
+            "1000" => Some(SubTier::Tier1),
+            "2000" => Some(SubTier::Tier2),
+            "3000" => Some(SubTier::Tier3),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            SubTier::Prime => "Prime",
+            SubTier::Tier1 => "Tier 1",
+            SubTier::Tier2 => "Tier 2",
+            SubTier::Tier3 => "Tier 3",
+        }
+    }
+}
+
+/// A chat message, along with what its tags say about who sent it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChatMessage {
+    /// needed to reply to this message
+    pub id: String,
+    pub channel: String,
+    pub user_id: String,
+    pub login: String,
+    pub display_name: String,
+    pub text: String,
+    /// badge name to version, e.g. "subscriber" => "12"
+    pub badges: BTreeMap<String, String>,
+    /// bits cheered along with the message, if any
+    pub bits: u32,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum HypeTrainStage {
+    Began,
+    Progressed,
+    Ended,
+}
+
+/// Something that happened on the channel.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TwitchEvent {
+    /// the chat connection is up and the channel has been joined
+    Joined {
+        channel: String,
+    },
+    /// the chat connection dropped, and will be retried
+    Disconnected {
+        reason: String,
+    },
+    /// EventSub is up, with this many kinds of event coming in
+    EventSubConnected {
+        subscriptions: usize,
+    },
+    /// the EventSub connection dropped, and will be retried
+    EventSubDisconnected {
+        reason: String,
+    },
+    /// Twitch won't send one kind of event, e.g. for lack of permission
+    SubscriptionFailed {
+        kind: String,
+        reason: String,
+    },
+
+    Message(ChatMessage),
+    /// a new subscription or a resub
+    Subscribe {
+        user: String,
+        tier: SubTier,
+        /// cumulative, so 1 for a new sub
+        months: u32,
+        message: Option<String>,
+    },
+    GiftSub {
+        /// None for anonymous gifts
+        gifter: Option<String>,
+        /// None when the subs are given to random viewers
+        recipient: Option<String>,
+        tier: SubTier,
+        count: u32,
+    },
+    Raid {
+        from: String,
+        viewers: u32,
+    },
+    /// a user's messages were removed, or all of chat if `user` is None
+    ClearChat {
+        user: Option<String>,
+    },
+
+    Follow {
+        user: String,
+    },
+    Cheer {
+        /// None for anonymous cheers
+        user: Option<String>,
+        bits: u32,
+        message: String,
+    },
+    /// channel points spent on a custom reward
+    Redemption {
+        user: String,
+        reward: String,
+        cost: u32,
+        /// what the viewer typed, for rewards that ask for it
+        input: Option<String>,
+    },
+    HypeTrain {
+        stage: HypeTrainStage,
+        level: u32,
+        /// towards the current level's goal
+        progress: u32,
+        goal: u32,
+    },
+    StreamOnline,
+    StreamOffline,
+}
@@ -0,0 +1,229 @@
+//! Keeps the saved Twitch tokens working: validates them, as Twitch asks apps to do at startup
+//! and hourly, and refreshes them before they expire.
+//! https://dev.twitch.tv/docs/authentication/validate-tokens/
+
+use crate::config::TwitchTokens;
+use crate::shutdown;
+use crate::tasks::Task;
+use crate::twitch::auth::{AuthClient, Rejected};
+use crossbeam_channel::{Receiver, Sender};
+use std::time::{Duration, Instant};
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(60 * 60);
+/// refresh once the access token has less than this left
+const REFRESH_MARGIN: Duration = Duration::from_secs(10 * 60);
+/// for when Twitch can't be reached at all
+const RETRY_DELAY: Duration = Duration::from_secs(60);
+/// how often waits wake up to check whether the task should stop
+const WAKE_INTERVAL: Duration = Duration::from_millis(250);
+
+pub enum TokenEvent {
+    /// new tokens to save, and to log in to chat with from now on
+    Refreshed(TwitchTokens),
+    /// the tokens stopped working and couldn't be refreshed, so the user has to log in again
+    Expired(String),
+}
+
+/// Checks on the tokens until shutdown, cancellation, or until they can't be saved.
+pub struct TokenTask {
+    auth: AuthClient,
+    tokens: TwitchTokens,
+    events_tx: Sender<TokenEvent>,
+    check_interval: Duration,
+    refresh_margin: Duration,
+    retry_delay: Duration,
+    cancel_ch: (Sender<()>, Receiver<()>),
+}
+
+impl TokenTask {
+    pub fn new(auth: AuthClient, tokens: TwitchTokens, events_tx: Sender<TokenEvent>) -> Self {
+        Self {
+            auth,
+            tokens,
+            events_tx,
+            check_interval: CHECK_INTERVAL,
+            refresh_margin: REFRESH_MARGIN,
+            retry_delay: RETRY_DELAY,
+            cancel_ch: crossbeam_channel::bounded(1),
+        }
+    }
+
+    /// stops the task when sent to
+    pub fn canceller(&self) -> Sender<()> {
+        self.cancel_ch.0.clone()
+    }
+
+    /// Validates the tokens, refreshing them if they're expired or about to be.
+    /// Returns how long they can be left alone for.
+    fn check(&mut self) -> anyhow::Result<Duration> {
+        let expires_in = match self.auth.validate(&self.tokens.access_token)? {
+            Some(validation)
+                if Duration::from_secs(validation.expires_in) > self.refresh_margin =>
+            {
+                validation.expires_in
+            }
+            // expired, revoked, or close enough
+            _ => {
+                let fresh = self.auth.refresh(&self.tokens.refresh_token)?;
+                self.tokens.access_token = fresh.access_token;
+                self.tokens.refresh_token = fresh.refresh_token;
+                let _ = self
+                    .events_tx
+                    .send(TokenEvent::Refreshed(self.tokens.clone()));
+                fresh.expires_in
+            }
+        };
+
+        // fresh tokens shorter-lived than the margin would otherwise be refreshed nonstop
+        let lifetime = Duration::from_secs(expires_in);
+        let until_refresh = match lifetime > self.refresh_margin {
+            true => lifetime - self.refresh_margin,
+            false => lifetime / 2,
+        };
+        Ok(self.check_interval.min(until_refresh))
+    }
+
+    /// Waits for `duration`, returning false early if the task should stop.
+    fn wait(&self, duration: Duration) -> bool {
+        let deadline = Instant::now() + duration;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return true;
+            }
+            if self
+                .cancel_ch
+                .1
+                .recv_timeout(remaining.min(WAKE_INTERVAL))
+                .is_ok()
+                || shutdown::requested()
+            {
+                return false;
+            }
+        }
+    }
+}
+
+impl Task for TokenTask {
+    type Result = ();
+    type OnCompleteParams = Sender<TokenEvent>;
+
+    fn run_task(mut self) -> anyhow::Result<()> {
+        loop {
+            let wait = match self.check() {
+                Ok(wait) => wait,
+                Err(e) if e.is::<Rejected>() => return Err(e),
+                // couldn't reach Twitch, which doesn't say anything about the tokens
+                Err(_) => self.retry_delay,
+            };
+
+            if !self.wait(wait) {
+                return Ok(());
+            }
+        }
+    }
+
+    fn on_complete(r: anyhow::Result<()>, p: Sender<TokenEvent>) {
+        if let Err(e) = r {
+            let _ = p.send(TokenEvent::Expired(format!(
+                "Couldn't refresh the Twitch login: {:#}",
+                e
+            )));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::harness::{wait_until, PATIENCE};
+    use crate::twitch::mock::{MockTwitchAuth, MOCK_CLIENT_ID, MOCK_LOGIN, MOCK_USER_ID};
+    use std::thread::{self, JoinHandle};
+
+    fn task(auth: &MockTwitchAuth) -> (TokenTask, Receiver<TokenEvent>) {
+        let (access_token, refresh_token) = auth.issue_tokens();
+        let tokens = TwitchTokens {
+            access_token,
+            refresh_token,
+            user_id: MOCK_USER_ID.to_string(),
+            login: MOCK_LOGIN.to_string(),
+        };
+        let client = AuthClient::new(auth.base_url(), MOCK_CLIENT_ID).unwrap();
+        let (events_tx, events_rx) = crossbeam_channel::unbounded();
+        (TokenTask::new(client, tokens, events_tx), events_rx)
+    }
+
+    fn spawn(task: TokenTask, events_tx: Sender<TokenEvent>) -> JoinHandle<()> {
+        thread::spawn(move || TokenTask::on_complete(task.run_task(), events_tx))
+    }
+
+    #[test]
+    fn validates_on_a_schedule() {
+        let auth = MockTwitchAuth::start();
+        let (mut task, events) = task(&auth);
+        task.check_interval = Duration::from_millis(20);
+        let cancel = task.canceller();
+        let thread = spawn(task, crossbeam_channel::unbounded().0);
+
+        assert!(wait_until(|| auth.validations() >= 3), "stopped validating");
+        cancel.send(()).unwrap();
+        thread.join().unwrap();
+
+        // the tokens were good all along
+        assert!(events.try_recv().is_err());
+    }
+
+    #[test]
+    fn refreshes_before_the_token_expires() {
+        let auth = MockTwitchAuth::start();
+        let (task, events) = task(&auth);
+        let old = task.tokens.clone();
+        auth.expire_tokens_in(60);
+        let cancel = task.canceller();
+        let thread = spawn(task, crossbeam_channel::unbounded().0);
+
+        let tokens = match events.recv_timeout(PATIENCE) {
+            Ok(TokenEvent::Refreshed(tokens)) => tokens,
+            _ => panic!("expected new tokens"),
+        };
+        assert!(auth.is_valid(&tokens.access_token));
+        assert_ne!(tokens.refresh_token, old.refresh_token);
+        assert_eq!(tokens.login, old.login);
+
+        cancel.send(()).unwrap();
+        thread.join().unwrap();
+    }
+
+    #[test]
+    fn gives_up_once_refreshing_fails() {
+        let auth = MockTwitchAuth::start();
+        let (task, _) = task(&auth);
+        auth.revoke(&task.tokens.access_token);
+        auth.refuse_refresh();
+
+        let (events_tx, events) = crossbeam_channel::unbounded();
+        spawn(task, events_tx).join().unwrap();
+        match events.try_recv() {
+            Ok(TokenEvent::Expired(reason)) => assert!(reason.contains("Invalid refresh token")),
+            _ => panic!("expected the login to expire"),
+        }
+    }
+
+    #[test]
+    fn keeps_trying_while_twitch_is_unreachable() {
+        let auth = MockTwitchAuth::start();
+        let (mut task, _) = task(&auth);
+        task.auth = AuthClient::new("http://127.0.0.1:1/oauth2", MOCK_CLIENT_ID).unwrap();
+        task.retry_delay = Duration::from_millis(10);
+        let cancel = task.canceller();
+
+        let (events_tx, events) = crossbeam_channel::unbounded();
+        let thread = spawn(task, events_tx);
+        thread::sleep(Duration::from_millis(100));
+        cancel.send(()).unwrap();
+        thread.join().unwrap();
+
+        // a network error doesn't mean the login is gone
+        assert!(events.try_recv().is_err());
+    }
+}
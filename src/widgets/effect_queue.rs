@@ -0,0 +1,124 @@
+use crate::lights::effect_queue::{EffectsStatus, QueuedEffect};
+use tui::buffer::Buffer;
+use tui::layout::Rect;
+use tui::style::{Color, Modifier, Style};
+use tui::text::{Span, Spans};
+use tui::widgets::{Block, Borders, Clear, Paragraph, Widget};
+
+/// The effect that's playing, and the ones waiting their turn.
+pub struct EffectQueueWidget<'a> {
+    pub status: &'a EffectsStatus,
+}
+
+impl EffectQueueWidget<'_> {
+    /// waiting effects past this are summed up in one line
+    const MAX_WAITING: usize = 3;
+
+    /// How tall it wants to be, or 0 if there's nothing to show.
+    pub fn height(&self) -> u16 {
+        let waiting = self.status.waiting.len();
+        match (&self.status.playing, waiting) {
+            (None, 0) => 0,
+            (_, waiting) if waiting > Self::MAX_WAITING => Self::MAX_WAITING as u16 + 4,
+            (_, waiting) => waiting as u16 + 3,
+        }
+    }
+
+    fn describe(effect: &QueuedEffect) -> String {
+        format!("{}: {}", effect.reason, effect.effect.kind)
+    }
+
+    fn lines(&self) -> Vec<Spans<'static>> {
+        let bold = Style::default().add_modifier(Modifier::BOLD);
+        let mut lines = vec![match &self.status.playing {
+            Some(playing) => Spans::from(vec![
+                Span::styled("▶ ", bold.fg(Color::Green)),
+                Span::raw(Self::describe(playing)),
+            ]),
+            None => Spans::from(Span::raw("  nothing playing")),
+        }];
+
+        for (i, waiting) in self.status.waiting.iter().enumerate() {
+            if i == Self::MAX_WAITING {
+                let more = self.status.waiting.len() - i;
+                lines.push(Spans::from(format!("  …and {} more", more)));
+                break;
+            }
+            lines.push(Spans::from(vec![
+                Span::styled(format!("{}.", i + 1), bold),
+                Span::raw(format!(
+                    " {} (priority {}, {}, {}s left)",
+                    Self::describe(waiting),
+                    waiting.priority,
+                    waiting.policy,
+                    waiting.time_left.as_secs()
+                )),
+            ]));
+        }
+        lines
+    }
+}
+
+impl Widget for EffectQueueWidget<'_> {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        Clear.render(area, buf);
+        let title = match self.status.dropped {
+            0 => " effects ".to_string(),
+            dropped => format!(" effects · {} dropped ", dropped),
+        };
+        let block = Block::default().borders(Borders::ALL).title(title);
+        let inner = block.inner(area);
+        block.render(area, buf);
+
+        Paragraph::new(self.lines()).render(inner, buf);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::harness::lines;
+    use crate::lights::effect_queue::Policy;
+    use crate::lights::effects::{Effect, EffectKind};
+    use crate::lights::Rgb;
+    use std::time::Duration;
+
+    fn queued(reason: &str, kind: EffectKind, secs: u64) -> QueuedEffect {
+        QueuedEffect {
+            reason: reason.to_string(),
+            effect: Effect::from(kind),
+            priority: 5,
+            policy: Policy::Merge,
+            time_left: Duration::from_secs(secs),
+        }
+    }
+
+    #[test]
+    fn lists_what_plays_next() {
+        let status = EffectsStatus {
+            playing: Some(queued("raid from dwbrite", EffectKind::ColorCycle, 0)),
+            waiting: vec![
+                queued("sub from viewer", EffectKind::Pulse(Rgb(128, 0, 255)), 42),
+                queued("sub from other", EffectKind::Flash(Rgb(255, 0, 0)), 50),
+                queued("a", EffectKind::ColorCycle, 1),
+                queued("b", EffectKind::ColorCycle, 1),
+            ],
+            dropped: 2,
+        };
+        let widget = EffectQueueWidget { status: &status };
+        let height = widget.height();
+        assert_eq!(height, 7);
+
+        let area = Rect::new(0, 0, 72, height);
+        let mut buf = Buffer::empty(area);
+        widget.render(area, &mut buf);
+        let lines = lines(&buf);
+        assert!(lines[0].contains(" effects · 2 dropped "));
+        assert!(lines[1].contains("▶ raid from dwbrite: cycle through the colours"));
+        assert!(lines[2].contains("1. sub from viewer: pulse purple (priority 5, merge, 42s left)"));
+        assert!(lines[5].contains("…and 1 more"));
+
+        let idle = EffectsStatus::default();
+        assert_eq!(EffectQueueWidget { status: &idle }.height(), 0);
+    }
+}
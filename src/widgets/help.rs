@@ -0,0 +1,62 @@
+use crate::widgets::BLACK;
+use tui::buffer::Buffer;
+use tui::layout::Rect;
+use tui::style::{Modifier, Style};
+use tui::text::{Span, Spans};
+use tui::widgets::{Block, Borders, Clear, Paragraph, Widget};
+
+/// An overlay listing the keybindings that are currently available.
+pub struct HelpWidget {
+    pub global: Vec<(&'static str, &'static str)>,
+    pub activity: Vec<(&'static str, &'static str)>,
+}
+
+impl HelpWidget {
+    fn lines(bindings: &[(&'static str, &'static str)]) -> Vec<Spans<'static>> {
+        let width = bindings
+            .iter()
+            .map(|(keys, _)| keys.len())
+            .max()
+            .unwrap_or(0);
+        bindings
+            .iter()
+            .map(|(keys, description)| {
+                Spans::from(vec![
+                    Span::styled(
+                        format!("{:>width$}", keys, width = width),
+                        Style::default().add_modifier(Modifier::BOLD),
+                    ),
+                    Span::raw(format!("  {}", description)),
+                ])
+            })
+            .collect()
+    }
+}
+
+impl Widget for HelpWidget {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        Clear.render(area, buf);
+        let block = Block::default()
+            .borders(Borders::ALL)
+            .title(" help ")
+            .style(Style::default().bg(BLACK));
+        let inner = block.inner(area);
+        block.render(area, buf);
+
+        let mut text = Self::lines(&self.global);
+        if !self.activity.is_empty() {
+            text.push(Spans::from(""));
+            text.extend(Self::lines(&self.activity));
+        }
+
+        Paragraph::new(text).render(
+            Rect::new(
+                inner.x + 1,
+                inner.y + 1,
+                inner.width.saturating_sub(2),
+                inner.height.saturating_sub(1),
+            ),
+            buf,
+        );
+    }
+}
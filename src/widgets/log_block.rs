@@ -65,6 +65,7 @@ pub enum LogEvent {
     PushItem(LogItem),
     PopItem,
     SetVariant(String, LogVariant), // use LogItem id
+    SetMessage(String, String),     // use LogItem id
 }
 
 #[derive(Debug, Clone)]
@@ -90,8 +91,8 @@ pub struct Log {
     tx: Sender<LogEvent>,
 }
 
-impl Log {
-    pub fn default() -> Self {
+impl Default for Log {
+    fn default() -> Self {
         let (tx, rx) = crossbeam_channel::unbounded();
         Log {
             title: "".to_string(),
@@ -109,7 +110,9 @@ impl Log {
             tx,
         }
     }
+}
 
+impl Log {
     pub fn new(title: String, margin: Margin) -> Self {
         let (tx, rx) = crossbeam_channel::unbounded();
         Log {
@@ -146,6 +149,13 @@ impl Log {
                         }
                     }
                 }
+                LogEvent::SetMessage(id, message) => {
+                    for item in &mut self.history.content {
+                        if item.id == id {
+                            item.message = message.clone();
+                        }
+                    }
+                }
             }
         }
     }
@@ -168,13 +178,6 @@ impl Log {
     }
 }
 
-#[derive(Debug, Clone)]
-pub struct LogWidget {
-    title: String,
-    margin: Margin,
-    history: LogHistory,
-}
-
 impl From<&mut LogHistory> for Vec<ListItem<'_>> {
     fn from(history: &mut LogHistory) -> Self {
         history
@@ -248,3 +251,65 @@ impl Widget for Log {
         Widget::render(log_list, self.calculate_inner(area), buf);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::harness::lines;
+
+    fn render(log: &Log, width: u16, height: u16) -> Vec<String> {
+        let mut buf = Buffer::empty(Rect::new(0, 0, width, height));
+        log.clone().render(buf.area, &mut buf);
+        lines(&buf)
+    }
+
+    #[test]
+    fn snapshot() {
+        let mut log = Log::default();
+        log.set_title(" log ".to_string());
+        let tx = log.sender();
+
+        tx.send(LogEvent::PushItem(LogItem::info("hello").0))
+            .unwrap();
+        let (waiting, id) = LogItem::task_waiting("working");
+        tx.send(LogEvent::PushItem(waiting)).unwrap();
+        tx.send(LogEvent::PushItem(LogItem::error("oh no").0))
+            .unwrap();
+        log.update();
+
+        // newest at the bottom
+        assert_eq!(
+            render(&log, 24, 7),
+            [
+                "┌ log ─────────────────┐",
+                "│                      │",
+                "│                      │",
+                "│ [i] hello            │",
+                "│ [|] working          │",
+                "│ [!] oh no            │",
+                "└──────────────────────┘",
+            ]
+        );
+
+        // the spinner turns every 8 updates
+        for _ in 0..8 {
+            log.update();
+        }
+        assert_eq!(render(&log, 24, 7)[4], "│ [/] working          │");
+
+        tx.send(LogEvent::PopItem).unwrap();
+        tx.send(LogEvent::SetVariant(id.clone(), LogVariant::TaskComplete))
+            .unwrap();
+        tx.send(LogEvent::SetMessage(id, "worked".to_string()))
+            .unwrap();
+        log.update();
+        assert_eq!(
+            render(&log, 24, 7)[3..6],
+            [
+                "│                      │",
+                "│ [i] hello            │",
+                "│ [=] worked           │",
+            ]
+        );
+    }
+}
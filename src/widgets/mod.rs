@@ -1,3 +1,5 @@
+pub mod effect_queue;
+pub mod help;
 pub mod log_block;
 pub mod rainbow_border;
 pub mod smart_text;
@@ -18,8 +20,8 @@ pub fn center_rect(parent_size: Rect, mut width: u16, mut height: u16) -> Rect {
         height = parent_size.height;
     }
 
-    let x = parent_size.width / 2 - width / 2;
-    let y = parent_size.height / 2 - height / 2;
+    let x = parent_size.x + parent_size.width / 2 - width / 2;
+    let y = parent_size.y + parent_size.height / 2 - height / 2;
 
     Rect::new(x, y, width, height)
 }
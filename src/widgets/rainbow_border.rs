@@ -5,6 +5,12 @@ use tui::layout::Rect;
 use tui::style::Style;
 use tui::widgets::Widget;
 
+/// The `t` the border is drawn with after `ticks`, for anything that wants to keep time
+/// with it.
+pub fn animation_time(ticks: u64) -> f32 {
+    ticks as f32 * 0.016
+}
+
 pub struct RainbowBorderWidget {
     pub border_animated: bool,
     pub ticks: u64,
@@ -21,7 +27,7 @@ impl Widget for RainbowBorderWidget {
 
         // set background to unicorn vomit
         if self.border_animated {
-            let state = self.ticks as f32 * 0.016;
+            let state = animation_time(self.ticks);
             let bg = unicorn_vomit::Background { state };
             bg.render(outer_rect, buf);
         } else {
@@ -33,3 +39,50 @@ impl Widget for RainbowBorderWidget {
         block.render(inner_rect, buf);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tui::style::Color;
+
+    fn render(border_animated: bool, ticks: u64) -> Buffer {
+        let mut buf = Buffer::empty(Rect::new(0, 0, 12, 6));
+        RainbowBorderWidget {
+            border_animated,
+            ticks,
+        }
+        .render(buf.area, &mut buf);
+        buf
+    }
+
+    #[test]
+    fn snapshot() {
+        let buf = render(false, 0);
+
+        let inner = Rect::new(2, 1, 8, 4);
+        for y in 0..buf.area.height {
+            for x in 0..buf.area.width {
+                let cell = buf.get(x, y);
+                if inner.intersects(Rect::new(x, y, 1, 1)) {
+                    assert_eq!(cell.bg, BLACK, "({}, {}) should be inside", x, y);
+                } else {
+                    assert_ne!(cell.bg, BLACK, "({}, {}) should be border", x, y);
+                }
+            }
+        }
+
+        assert_eq!(buf.get(0, 0).bg, Color::Rgb(73, 243, 96));
+        assert_eq!(buf.get(1, 1).bg, Color::Rgb(137, 202, 32));
+        assert_eq!(buf.get(11, 5).bg, Color::Rgb(10, 180, 159));
+        // the foreground is rainbow everywhere, so text drawn inside is colourful too
+        assert_eq!(buf.get(2, 1).fg, Color::Rgb(158, 181, 11));
+    }
+
+    #[test]
+    fn only_animates_when_asked() {
+        assert_eq!(render(false, 0), render(false, 100));
+
+        assert_eq!(render(true, 100), render(true, 100));
+        assert_ne!(render(true, 0), render(true, 100));
+    }
+}
@@ -22,7 +22,7 @@ fn split_span_every(span: Span, n: usize) -> Vec<Span> {
     }
     lines
         .iter()
-        .map(|s| Span::styled(s.clone(), span.style.clone()))
+        .map(|s| Span::styled(s.clone(), span.style))
         .collect()
 }
 
@@ -39,7 +39,7 @@ impl<'a> WrappingText<'a> {
         let mut x = 0;
         for span in spans.into().0 {
             // if text doesn't overflow overflow
-            if x + span.width() <= width as usize {
+            if x + span.width() <= width {
                 // append span to line
                 x += span.width();
                 line.push(span)
@@ -61,7 +61,7 @@ impl<'a> WrappingText<'a> {
                         wrapping_text.lines.push(Line(vec![newspan.clone()]))
                     } else {
                         // append span to line
-                        x += span.width();
+                        x += newspan.width();
                         line.push(newspan.clone())
                     }
                 }
@@ -89,6 +89,12 @@ pub struct SmartTextComponent<'a> {
     input_text: Vec<Spans<'a>>,
 }
 
+impl<'a> Default for SmartTextComponent<'a> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl<'a> SmartTextComponent<'a> {
     pub fn new() -> Self {
         Self {
@@ -133,17 +139,64 @@ impl<'a> Widget for SmartTextComponent<'a> {
                 }
             } else {
                 for line in wrapping_text.lines {
+                    if lines_used >= area.height as usize {
+                        return;
+                    }
+
                     line.render(
                         Rect::new(area.x, area.y + lines_used as u16, area.width, 1),
                         buf,
                     );
                     lines_used += 1;
-
-                    if lines_used > area.height as usize {
-                        return;
-                    }
                 }
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::harness::lines;
+    use tui::style::{Color, Style};
+
+    fn text() -> SmartTextComponent<'static> {
+        let mut text = SmartTextComponent::new();
+        text.append_line("short");
+        text.append_line("this line is too long to fit");
+        text.append_span(Span::styled(" and red", Style::default().fg(Color::Red)));
+        text.append_line("end");
+        text
+    }
+
+    #[test]
+    fn snapshot() {
+        let mut buf = Buffer::empty(Rect::new(0, 0, 10, 7));
+        text().render(buf.area, &mut buf);
+
+        assert_eq!(
+            lines(&buf),
+            [
+                "short     ",
+                "this line ",
+                "is too lon",
+                "g to fit a",
+                "nd red    ",
+                "end       ",
+                "          ",
+            ]
+        );
+        // spans keep their style across the wrap
+        assert_eq!(buf.get(9, 3).fg, Color::Red);
+        assert_eq!(buf.get(0, 4).fg, Color::Red);
+        assert_eq!(buf.get(0, 5).fg, Color::Reset);
+    }
+
+    #[test]
+    fn stops_at_the_bottom_of_its_area() {
+        let mut buf = Buffer::empty(Rect::new(0, 0, 10, 3));
+        text().render(buf.area, &mut buf);
+
+        assert_eq!(lines(&buf), ["short     ", "this line ", "is too lon"]);
+    }
+}